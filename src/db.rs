@@ -3,19 +3,144 @@ use duckdb::{AccessMode, Config, Connection};
 use std::path::Path;
 use tracing::info;
 
+use crate::timezone::DayTimezone;
+
+/// Threads DuckDB uses for the read-write import connection unless `--threads` says otherwise.
+pub const DEFAULT_THREADS: usize = 4;
+
+/// DuckDB resource settings (`--threads`, `--memory-limit`). Unset fields keep the defaults:
+/// `DEFAULT_THREADS` for imports, DuckDB's own choices otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct DbOptions {
+    pub threads: Option<usize>,
+    /// Passed through to DuckDB's `memory_limit`, e.g. `2GB` or `75%`.
+    pub memory_limit: Option<String>,
+}
+
+impl DbOptions {
+    fn apply(&self, mut config: Config) -> Result<Config> {
+        if let Some(threads) = self.threads {
+            config = config.threads(threads as i64)?;
+        }
+        if let Some(ref limit) = self.memory_limit {
+            config = config.with("memory_limit", limit)?;
+        }
+        Ok(config)
+    }
+}
+
 pub fn open_db(db_path: &Path) -> Result<Connection> {
-    let config = Config::default().access_mode(AccessMode::ReadWrite)?;
+    open_db_with_options(db_path, &DbOptions::default())
+}
+
+pub fn open_db_with_options(db_path: &Path, options: &DbOptions) -> Result<Connection> {
+    let options = DbOptions {
+        threads: Some(options.threads.unwrap_or(DEFAULT_THREADS)),
+        ..options.clone()
+    };
+    let config = options.apply(Config::default().access_mode(AccessMode::ReadWrite)?)?;
     let conn = Connection::open_with_flags(db_path, config)?;
-    conn.execute_batch("PRAGMA threads=4;")?;
     Ok(conn)
 }
 
 pub fn open_db_readonly(db_path: &Path) -> Result<Connection> {
-    let config = Config::default().access_mode(AccessMode::ReadOnly)?;
+    open_db_readonly_with_options(db_path, &DbOptions::default())
+}
+
+pub fn open_db_readonly_with_options(db_path: &Path, options: &DbOptions) -> Result<Connection> {
+    let config = options.apply(Config::default().access_mode(AccessMode::ReadOnly)?)?;
+    let conn = Connection::open_with_flags(db_path, config)?;
+    Ok(conn)
+}
+
+/// Read-only connection for untrusted SQL: DuckDB's external access is off, so table
+/// functions like `read_csv`, `read_parquet`, and `glob` cannot touch the filesystem or
+/// network, extensions cannot be autoloaded, and the configuration is locked so a query
+/// cannot turn any of this back on.
+pub fn open_db_readonly_restricted(db_path: &Path, options: &DbOptions) -> Result<Connection> {
+    let config = restrict(options.apply(Config::default().access_mode(AccessMode::ReadOnly)?)?)?;
     let conn = Connection::open_with_flags(db_path, config)?;
     Ok(conn)
 }
 
+/// Read-write counterpart of `open_db_readonly_restricted`, for `serve --maintenance`.
+pub fn open_db_restricted(db_path: &Path, options: &DbOptions) -> Result<Connection> {
+    let config = restrict(options.apply(Config::default().access_mode(AccessMode::ReadWrite)?)?)?;
+    let conn = Connection::open_with_flags(db_path, config)?;
+    Ok(conn)
+}
+
+fn restrict(config: Config) -> Result<Config> {
+    Ok(config
+        .enable_external_access(false)?
+        .enable_autoload_extension(false)?
+        .with("lock_configuration", "true")?)
+}
+
+/// Tables created by `ensure_schema`, plus the `daily_record_stats` aggregate (and its weekly
+/// and monthly rollups) built after import.
+pub const BASE_TABLES: &[&str] = &[
+    "records",
+    "record_metadata",
+    "audiogram_points",
+    "workouts",
+    "workout_metadata",
+    "workout_events",
+    "workout_statistics",
+    "activity_summaries",
+    "ecg_readings",
+    "ecg_samples",
+    "route_points",
+    "imports",
+    "annotations",
+    "clinical_records",
+    "source_aliases",
+    "daily_record_stats",
+    "weekly_record_stats",
+    "monthly_record_stats",
+];
+
+/// Returned by `verify_schema` when a database lacks tables this version expects,
+/// typically because it was created by an older release.
+#[derive(Debug)]
+pub struct SchemaError {
+    pub missing_tables: Vec<String>,
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Database is missing tables: {}. It was probably created by an older version; \
+             re-run `apple-health-mcp import` against it to upgrade the schema.",
+            self.missing_tables.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// Check that every table in `BASE_TABLES` exists, returning a `SchemaError` listing any that don't.
+pub fn verify_schema(conn: &Connection) -> Result<()> {
+    let mut stmt = conn
+        .prepare("SELECT table_name FROM information_schema.tables WHERE table_schema = 'main'")?;
+    let existing = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let missing_tables: Vec<String> = BASE_TABLES
+        .iter()
+        .filter(|t| !existing.iter().any(|e| e == *t))
+        .map(|t| t.to_string())
+        .collect();
+
+    if missing_tables.is_empty() {
+        Ok(())
+    } else {
+        Err(SchemaError { missing_tables }.into())
+    }
+}
+
 /// Create tables without PRIMARY KEY constraints so Appender can bulk-load.
 /// Deduplication happens in `deduplicate_tables()` after loading.
 pub fn ensure_schema(conn: &Connection) -> Result<()> {
@@ -32,7 +157,11 @@ pub fn ensure_schema(conn: &Connection) -> Result<()> {
             creation_date   TIMESTAMP,
             start_date      TIMESTAMP NOT NULL,
             end_date        TIMESTAMP NOT NULL,
-            import_id       VARCHAR NOT NULL
+            import_id       VARCHAR NOT NULL,
+            first_import_id VARCHAR,
+            last_import_id  VARCHAR,
+            value_si        DOUBLE,
+            value_text      VARCHAR
         );
 
         CREATE TABLE IF NOT EXISTS record_metadata (
@@ -41,6 +170,15 @@ pub fn ensure_schema(conn: &Connection) -> Result<()> {
             value           VARCHAR
         );
 
+        CREATE TABLE IF NOT EXISTS audiogram_points (
+            record_hash       VARCHAR NOT NULL,
+            frequency_hz      DOUBLE NOT NULL,
+            left_ear_db       DOUBLE,
+            right_ear_db      DOUBLE,
+            left_ear_masked   BOOLEAN,
+            right_ear_masked  BOOLEAN
+        );
+
         CREATE TABLE IF NOT EXISTS workouts (
             workout_hash         VARCHAR,
             activity_type        VARCHAR NOT NULL,
@@ -56,7 +194,18 @@ pub fn ensure_schema(conn: &Connection) -> Result<()> {
             creation_date        TIMESTAMP,
             start_date           TIMESTAMP NOT NULL,
             end_date             TIMESTAMP NOT NULL,
-            import_id            VARCHAR NOT NULL
+            import_id            VARCHAR NOT NULL,
+            first_import_id      VARCHAR,
+            last_import_id       VARCHAR,
+            total_distance_m     DOUBLE,
+            total_energy_kcal    DOUBLE,
+            route_file           VARCHAR
+        );
+
+        CREATE TABLE IF NOT EXISTS workout_metadata (
+            workout_hash    VARCHAR NOT NULL,
+            key             VARCHAR NOT NULL,
+            value           VARCHAR
         );
 
         CREATE TABLE IF NOT EXISTS workout_events (
@@ -89,7 +238,9 @@ pub fn ensure_schema(conn: &Connection) -> Result<()> {
             apple_exercise_time_goal DOUBLE,
             apple_stand_hours        DOUBLE,
             apple_stand_hours_goal   DOUBLE,
-            import_id                VARCHAR NOT NULL
+            import_id                VARCHAR NOT NULL,
+            first_import_id          VARCHAR,
+            last_import_id           VARCHAR
         );
 
         CREATE TABLE IF NOT EXISTS ecg_readings (
@@ -100,7 +251,9 @@ pub fn ensure_schema(conn: &Connection) -> Result<()> {
             sample_rate_hz   DOUBLE,
             symptoms         VARCHAR,
             software_version VARCHAR,
-            import_id        VARCHAR NOT NULL
+            import_id        VARCHAR NOT NULL,
+            first_import_id  VARCHAR,
+            last_import_id   VARCHAR
         );
 
         CREATE TABLE IF NOT EXISTS ecg_samples (
@@ -129,49 +282,233 @@ pub fn ensure_schema(conn: &Connection) -> Result<()> {
             imported_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
             record_count BIGINT,
             workout_count BIGINT,
-            duration_secs DOUBLE
+            duration_secs DOUBLE,
+            timezone     VARCHAR
+        );
+
+        -- Observations from export_cda.xml (lab results, vitals); record_type is the
+        -- HealthKit type when Apple gives one, otherwise the observation code (e.g. LOINC)
+        CREATE TABLE IF NOT EXISTS clinical_records (
+            record_hash     VARCHAR,
+            record_type     VARCHAR NOT NULL,
+            display_name    VARCHAR,
+            value           DOUBLE,
+            value_text      VARCHAR,
+            unit            VARCHAR,
+            effective_date  TIMESTAMP,
+            source          VARCHAR,
+            import_id       VARCHAR NOT NULL,
+            first_import_id VARCHAR,
+            last_import_id  VARCHAR
+        );
+
+        -- import --checkpoint: top-level export.xml elements whose rows are committed, so an
+        -- interrupted import resumes after them. Deleted once the import finishes.
+        CREATE TABLE IF NOT EXISTS import_progress (
+            import_id           VARCHAR NOT NULL,
+            export_dir          VARCHAR,
+            elements_committed  BIGINT NOT NULL DEFAULT 0,
+            updated_at          TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+
+        -- import --source-aliases: source names LIKE pattern are reported as canonical_name
+        CREATE TABLE IF NOT EXISTS source_aliases (
+            pattern         VARCHAR NOT NULL,
+            canonical_name  VARCHAR NOT NULL
+        );
+
+        -- User notes on date ranges (serve --maintenance, add_annotation); never touched by import
+        CREATE TABLE IF NOT EXISTS annotations (
+            annotation_id VARCHAR,
+            start_date    DATE NOT NULL,
+            end_date      DATE NOT NULL,
+            tag           VARCHAR NOT NULL,
+            note          VARCHAR,
+            created_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
         );
         ",
     )?;
     Ok(())
 }
 
+/// Columns added after the initial schema, applied in order by `migrate`. `ensure_schema`
+/// already creates new tables with them, so these only matter for older database files.
+/// Every step must be safe to run twice: a crash between a step and its `schema_version`
+/// row means the next `migrate` repeats it.
+const MIGRATIONS: &[(u32, &str)] = &[
+    (
+        2,
+        "-- Provenance columns filled during deduplication
+        ALTER TABLE records ADD COLUMN IF NOT EXISTS first_import_id VARCHAR;
+        ALTER TABLE records ADD COLUMN IF NOT EXISTS last_import_id VARCHAR;
+        ALTER TABLE workouts ADD COLUMN IF NOT EXISTS first_import_id VARCHAR;
+        ALTER TABLE workouts ADD COLUMN IF NOT EXISTS last_import_id VARCHAR;
+        ALTER TABLE ecg_readings ADD COLUMN IF NOT EXISTS first_import_id VARCHAR;
+        ALTER TABLE ecg_readings ADD COLUMN IF NOT EXISTS last_import_id VARCHAR;",
+    ),
+    (
+        3,
+        "-- Canonical-unit columns filled by normalize_units()
+        ALTER TABLE records ADD COLUMN IF NOT EXISTS value_si DOUBLE;
+        ALTER TABLE workouts ADD COLUMN IF NOT EXISTS total_distance_m DOUBLE;
+        ALTER TABLE workouts ADD COLUMN IF NOT EXISTS total_energy_kcal DOUBLE;",
+    ),
+    (
+        4,
+        "-- Raw value of category records whose value isn't numeric
+        ALTER TABLE records ADD COLUMN IF NOT EXISTS value_text VARCHAR;",
+    ),
+    (
+        5,
+        "-- GPX path from WorkoutRoute/FileReference, kept even if the file never imported
+        ALTER TABLE workouts ADD COLUMN IF NOT EXISTS route_file VARCHAR;",
+    ),
+    (
+        6,
+        "-- import --timezone used for daily_record_stats' day boundaries
+        ALTER TABLE imports ADD COLUMN IF NOT EXISTS timezone VARCHAR;",
+    ),
+    (
+        7,
+        "-- Provenance for purge_import, which keeps rows another import also carried
+        ALTER TABLE activity_summaries ADD COLUMN IF NOT EXISTS first_import_id VARCHAR;
+        ALTER TABLE activity_summaries ADD COLUMN IF NOT EXISTS last_import_id VARCHAR;
+        ALTER TABLE clinical_records ADD COLUMN IF NOT EXISTS first_import_id VARCHAR;
+        ALTER TABLE clinical_records ADD COLUMN IF NOT EXISTS last_import_id VARCHAR;",
+    ),
+];
+
+/// Schema version `migrate` brings a database to. Version 1 is the schema before
+/// `schema_version` existed.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].0;
+
+/// Highest version recorded in `schema_version`, or 0 for a database that predates it.
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+    let has_table: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM information_schema.tables
+         WHERE table_schema = 'main' AND table_name = 'schema_version'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_table {
+        return Ok(0);
+    }
+    let version: Option<u32> =
+        conn.query_row("SELECT MAX(version) FROM schema_version", [], |row| {
+            row.get(0)
+        })?;
+    Ok(version.unwrap_or(0))
+}
+
+/// Apply the `MIGRATIONS` newer than the database's `schema_version`, recording each one as
+/// it completes, and return the resulting version. Run after `ensure_schema`; opens no
+/// transaction of its own, so it can run inside the caller's.
+pub fn migrate(conn: &Connection) -> Result<u32> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version     INTEGER NOT NULL,
+            applied_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );",
+    )?;
+    let applied = schema_version(conn)?;
+    let mut current = applied;
+    for &(version, sql) in MIGRATIONS.iter().filter(|(v, _)| *v > applied) {
+        info!("Migrating schema to version {}", version);
+        conn.execute_batch(sql)?;
+        conn.execute("INSERT INTO schema_version (version) VALUES (?)", [version])?;
+        current = version;
+    }
+    Ok(current)
+}
+
+/// Bring the database file at `db_path` up to `SCHEMA_VERSION` before serving it. Sessions
+/// open it read-only, so this opens it read-write only when it is behind.
+pub fn migrate_file(db_path: &Path, options: &DbOptions) -> Result<()> {
+    let version = schema_version(&open_db_readonly_with_options(db_path, options)?)?;
+    if version >= SCHEMA_VERSION {
+        return Ok(());
+    }
+    let conn = open_db_with_options(db_path, options)?;
+    ensure_schema(&conn)?;
+    migrate(&conn)?;
+    Ok(())
+}
+
+/// Tables deduplicated with `first_import_id` / `last_import_id` provenance: table, key,
+/// and whether the newest import's copy wins (see `dedup_with_provenance_sql`).
+const PROVENANCE_TABLES: &[(&str, &str, bool)] = &[
+    ("records", "record_hash", false),
+    ("workouts", "workout_hash", false),
+    ("ecg_readings", "ecg_hash", false),
+    ("activity_summaries", "date_components", true),
+    ("clinical_records", "record_hash", false),
+];
+
+/// SQL replacing `table` with one row per `key`, recording the earliest and latest
+/// import that contained each row in `first_import_id` / `last_import_id`.
+/// Imports are ordered by `imports.imported_at`; the import in progress has no
+/// `imports` row yet and so counts as the newest. With `latest_wins`, the kept row is
+/// the newest import's copy (for rows whose values change between exports); otherwise
+/// the copies are identical and any one is kept.
+fn dedup_with_provenance_sql(table: &str, key: &str, latest_wins: bool) -> String {
+    let order = if latest_wins {
+        format!(
+            " LEFT JOIN imports i ON i.import_id = t.import_id
+              ORDER BY t.{key}, COALESCE(i.imported_at, now()::TIMESTAMP) DESC"
+        )
+    } else {
+        String::new()
+    };
+    format!(
+        "
+        CREATE OR REPLACE TABLE {table} AS
+        WITH seen AS (
+            SELECT {key} AS k, COALESCE(first_import_id, import_id) AS iid FROM {table}
+            UNION ALL
+            SELECT {key}, COALESCE(last_import_id, import_id) FROM {table}
+        ), span AS (
+            SELECT k,
+                arg_min(iid, COALESCE(i.imported_at, now()::TIMESTAMP)) AS first_id,
+                arg_max(iid, COALESCE(i.imported_at, now()::TIMESTAMP)) AS last_id
+            FROM seen LEFT JOIN imports i ON i.import_id = seen.iid
+            GROUP BY k
+        )
+        SELECT d.* REPLACE (span.first_id AS first_import_id, span.last_id AS last_import_id)
+        FROM (SELECT DISTINCT ON (t.{key}) t.* FROM {table} t{order}) d
+        JOIN span ON span.k IS NOT DISTINCT FROM d.{key};
+        "
+    )
+}
+
 /// Deduplicate all tables after bulk loading.
 /// Replaces each table with a deduplicated version using DISTINCT ON or GROUP BY.
+/// Records, workouts, ECG readings, activity summaries, and clinical records also keep their
+/// first/last import ids.
 pub fn deduplicate_tables(conn: &Connection) -> Result<()> {
     info!("Deduplicating tables...");
 
+    for (table, key, latest_wins) in PROVENANCE_TABLES {
+        conn.execute_batch(&dedup_with_provenance_sql(table, key, *latest_wins))?;
+    }
+
     conn.execute_batch(
         "
-        CREATE OR REPLACE TABLE records AS
-        SELECT * FROM (
-            SELECT DISTINCT ON (record_hash) *
-            FROM records
-        );
-
         CREATE OR REPLACE TABLE record_metadata AS
         SELECT * FROM (
             SELECT DISTINCT ON (record_hash, key) *
             FROM record_metadata
         );
 
-        CREATE OR REPLACE TABLE workouts AS
-        SELECT * FROM (
-            SELECT DISTINCT ON (workout_hash) *
-            FROM workouts
-        );
-
-        CREATE OR REPLACE TABLE activity_summaries AS
+        CREATE OR REPLACE TABLE audiogram_points AS
         SELECT * FROM (
-            SELECT DISTINCT ON (date_components) *
-            FROM activity_summaries
-            ORDER BY date_components, import_id DESC
+            SELECT DISTINCT ON (record_hash, frequency_hz) *
+            FROM audiogram_points
         );
 
-        CREATE OR REPLACE TABLE ecg_readings AS
+        CREATE OR REPLACE TABLE workout_metadata AS
         SELECT * FROM (
-            SELECT DISTINCT ON (ecg_hash) *
-            FROM ecg_readings
+            SELECT DISTINCT ON (workout_hash, key) *
+            FROM workout_metadata
         );
 
         CREATE OR REPLACE TABLE ecg_samples AS
@@ -204,13 +541,90 @@ pub fn deduplicate_tables(conn: &Connection) -> Result<()> {
     Ok(())
 }
 
+/// Apple distance units → meters.
+const DISTANCE_TO_M: &[(&str, f64)] = &[
+    ("m", 1.0),
+    ("cm", 0.01),
+    ("km", 1000.0),
+    ("ft", 0.3048),
+    ("yd", 0.9144),
+    ("mi", 1609.344),
+];
+
+/// Apple energy units → kilocalories (`Cal` is the dietary calorie, i.e. 1 kcal).
+const ENERGY_TO_KCAL: &[(&str, f64)] = &[
+    ("kcal", 1.0),
+    ("Cal", 1.0),
+    ("cal", 0.001),
+    ("kJ", 1.0 / 4.184),
+    ("J", 1.0 / 4184.0),
+];
+
+/// Other record units with a canonical form: mass → kg, time → s; rates, pressure,
+/// counts, and percentages are already canonical and map 1:1.
+const OTHER_TO_CANONICAL: &[(&str, f64)] = &[
+    ("kg", 1.0),
+    ("g", 0.001),
+    ("lb", 0.453_592_37),
+    ("s", 1.0),
+    ("min", 60.0),
+    ("hr", 3600.0),
+    ("ms", 0.001),
+    ("count/min", 1.0),
+    ("mmHg", 1.0),
+    ("count", 1.0),
+    ("%", 1.0),
+];
+
+/// `CASE <column> WHEN '<unit>' THEN <factor> ... END`, NULL for unknown units.
+fn unit_factor_sql(column: &str, tables: &[&[(&str, f64)]]) -> String {
+    let arms: String = tables
+        .iter()
+        .flat_map(|t| t.iter())
+        .map(|(unit, factor)| format!(" WHEN '{}' THEN {:?}", unit, factor))
+        .collect();
+    format!("CASE {}{} END", column, arms)
+}
+
+/// Fill the canonical-unit columns (`records.value_si`, `workouts.total_distance_m`,
+/// `workouts.total_energy_kcal`) from each row's Apple unit string so values can be
+/// summed across sources. Units not in the conversion tables stay NULL.
+pub fn normalize_units(conn: &Connection) -> Result<()> {
+    conn.execute_batch(&format!(
+        "
+        UPDATE records SET value_si = value * {record_factor};
+        UPDATE workouts SET
+            total_distance_m = total_distance * {distance_factor},
+            total_energy_kcal = total_energy_burned * {energy_factor};
+        ",
+        record_factor =
+            unit_factor_sql("unit", &[DISTANCE_TO_M, ENERGY_TO_KCAL, OTHER_TO_CANONICAL]),
+        distance_factor = unit_factor_sql("total_distance_unit", &[DISTANCE_TO_M]),
+        energy_factor = unit_factor_sql("total_energy_unit", &[ENERGY_TO_KCAL]),
+    ))?;
+    Ok(())
+}
+
+/// Rebuild `daily_record_stats` (and its rollups), splitting days in the timezone the most
+/// recent import was given (see `stored_timezone`), or UTC.
 pub fn rebuild_daily_stats(conn: &Connection) -> Result<()> {
-    conn.execute_batch(
+    let timezone = stored_timezone(conn)?;
+    rebuild_daily_stats_in(conn, timezone.as_ref())
+}
+
+/// Like `rebuild_daily_stats`, bucketing each record on its local date in `timezone`
+/// (see `local_time_sql`), or its UTC date for `None`.
+pub fn rebuild_daily_stats_in(conn: &Connection, timezone: Option<&DayTimezone>) -> Result<()> {
+    let date = format!(
+        "CAST({} AS DATE)",
+        local_time_sql(conn, timezone, "start_date")?
+    );
+    conn.execute_batch(&format!(
         "
         CREATE OR REPLACE TABLE daily_record_stats AS
         SELECT
             record_type,
-            CAST(start_date AS DATE) AS date,
+            {date} AS date,
             unit,
             COUNT(*) AS count,
             AVG(value) AS avg_value,
@@ -219,9 +633,197 @@ pub fn rebuild_daily_stats(conn: &Connection) -> Result<()> {
             SUM(value) AS sum_value
         FROM records
         WHERE value IS NOT NULL
-        GROUP BY record_type, CAST(start_date AS DATE), unit;
-        ",
+        GROUP BY record_type, {date}, unit;
+        "
+    ))?;
+    rebuild_rollups(conn)
+}
+
+/// SQL for the calendar day of the UTC timestamp `column` as `daily_record_stats` splits days,
+/// for queries that read `records` (or `workouts`) directly and must agree with it.
+pub fn local_date_expr(conn: &Connection, column: &str) -> Result<String> {
+    Ok(format!("CAST({} AS DATE)", local_time_expr(conn, column)?))
+}
+
+/// SQL for the UTC timestamp `column` as wall-clock time in the timezone the most recent
+/// import was given (see `stored_timezone`), or `column` itself without one.
+pub fn local_time_expr(conn: &Connection, column: &str) -> Result<String> {
+    let timezone = stored_timezone(conn)?;
+    local_time_sql(conn, timezone.as_ref(), column)
+}
+
+/// SQL for `column` shifted into `timezone` (`column + offset`), or `column` for `None`.
+/// Daylight-saving transitions are spelled out over the span of `records.start_date`.
+fn local_time_sql(
+    conn: &Connection,
+    timezone: Option<&DayTimezone>,
+    column: &str,
+) -> Result<String> {
+    let Some(tz) = timezone else {
+        return Ok(column.to_string());
+    };
+    let span: Option<(i64, i64)> = conn.query_row(
+        "SELECT CAST(epoch(MIN(start_date)) AS BIGINT), CAST(epoch(MAX(start_date)) AS BIGINT)
+         FROM records",
+        [],
+        |row| {
+            Ok(row
+                .get::<_, Option<i64>>(0)?
+                .zip(row.get::<_, Option<i64>>(1)?))
+        },
+    )?;
+    Ok(format!(
+        "({} + to_seconds({}))",
+        column,
+        tz.offset_seconds_sql(column, span)
+    ))
+}
+
+/// Timezone recorded by the most recent import given `--timezone`, if any.
+pub fn stored_timezone(conn: &Connection) -> Result<Option<DayTimezone>> {
+    let mut stmt = conn.prepare(
+        "SELECT timezone FROM imports WHERE timezone IS NOT NULL
+         ORDER BY imported_at DESC LIMIT 1",
     )?;
+    let mut rows = stmt.query([])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(DayTimezone::parse(&row.get::<_, String>(0)?)?)),
+        None => Ok(None),
+    }
+}
+
+/// Rebuild `weekly_record_stats` and `monthly_record_stats` from `daily_record_stats`: one row
+/// per record type and week (ISO, starting Monday) or month, keyed by its first day in
+/// `period`. `avg_value` is weighted by record count (`sum_value / count`), not an average of
+/// daily averages, so the rollups match aggregating the records directly.
+pub fn rebuild_rollups(conn: &Connection) -> Result<()> {
+    for (table, part) in [
+        ("weekly_record_stats", "week"),
+        ("monthly_record_stats", "month"),
+    ] {
+        conn.execute_batch(&format!(
+            "CREATE OR REPLACE TABLE {table} AS
+            SELECT
+                record_type,
+                DATE_TRUNC('{part}', date) AS period,
+                SUM(count) AS count,
+                SUM(sum_value) / SUM(count) AS avg_value,
+                MIN(min_value) AS min_value,
+                MAX(max_value) AS max_value,
+                SUM(sum_value) AS sum_value,
+                COUNT(DISTINCT date) AS days_with_data
+            FROM daily_record_stats
+            GROUP BY record_type, DATE_TRUNC('{part}', date);"
+        ))?;
+    }
+    Ok(())
+}
+
+/// Roll back one import: delete the rows only `import_id` contributed from every table that
+/// tracks imports, drop child rows (metadata, audiogram points, workout events/statistics,
+/// ECG samples, route points) left without a parent, remove the `imports` row, and rebuild
+/// `daily_record_stats`. Rows of `PROVENANCE_TABLES` also seen by another import are kept,
+/// with their provenance columns pointed at the remaining import; an activity summary kept
+/// this way keeps the values of the newest export that carried it. Returns rows deleted;
+/// errors if no row carries `import_id`.
+pub fn purge_import(conn: &Connection, import_id: &str) -> Result<u64> {
+    let tx = conn.unchecked_transaction()?;
+    let mut removed = 0usize;
+
+    for (table, _, _) in PROVENANCE_TABLES {
+        removed += tx.execute(
+            &format!(
+                "DELETE FROM {table}
+                 WHERE COALESCE(first_import_id, import_id) = $1
+                   AND COALESCE(last_import_id, import_id) = $1"
+            ),
+            [import_id],
+        )?;
+        tx.execute(
+            &format!(
+                "UPDATE {table} SET
+                     import_id = CASE WHEN import_id <> $1 THEN import_id
+                                      WHEN first_import_id = $1 THEN last_import_id
+                                      ELSE first_import_id END,
+                     first_import_id = CASE WHEN first_import_id = $1 THEN last_import_id
+                                            ELSE first_import_id END,
+                     last_import_id = CASE WHEN last_import_id = $1 THEN first_import_id
+                                           ELSE last_import_id END
+                 WHERE $1 IN (import_id, first_import_id, last_import_id)"
+            ),
+            [import_id],
+        )?;
+    }
+
+    for (child, parent, key) in [
+        ("record_metadata", "records", "record_hash"),
+        ("audiogram_points", "records", "record_hash"),
+        ("workout_metadata", "workouts", "workout_hash"),
+        ("workout_events", "workouts", "workout_hash"),
+        ("workout_statistics", "workouts", "workout_hash"),
+        ("ecg_samples", "ecg_readings", "ecg_hash"),
+    ] {
+        removed += tx.execute(
+            &format!(
+                "DELETE FROM {child} c
+                 WHERE NOT EXISTS (SELECT 1 FROM {parent} p WHERE p.{key} = c.{key})"
+            ),
+            [],
+        )?;
+    }
+
+    // A route point stays while its workout does, even if this import's copy won dedup
+    removed += tx.execute(
+        "DELETE FROM route_points rp
+         WHERE import_id = $1
+           AND NOT EXISTS (SELECT 1 FROM workouts w WHERE w.workout_hash = rp.workout_hash)",
+        [import_id],
+    )?;
+    removed += tx.execute("DELETE FROM imports WHERE import_id = ?", [import_id])?;
+    tx.execute(
+        "DELETE FROM import_progress WHERE import_id = ?",
+        [import_id],
+    )?;
+
+    if removed == 0 {
+        anyhow::bail!("No rows found for import {}", import_id);
+    }
+    rebuild_daily_stats(&tx)?;
+    tx.commit()?;
+
+    info!("Purged import {} ({} rows removed)", import_id, removed);
+    Ok(removed as u64)
+}
+
+/// Checkpoint the database so blocks freed by `deduplicate_tables`' `CREATE OR REPLACE TABLE`
+/// statements are reclaimed and the WAL is folded into the file. Logs the file size before
+/// and after; a no-op for in-memory databases.
+pub fn compact_db(conn: &Connection) -> Result<()> {
+    let path: Option<String> = conn.query_row(
+        "SELECT path FROM duckdb_databases() WHERE database_name = current_database()",
+        [],
+        |row| row.get(0),
+    )?;
+    let Some(path) = path else {
+        return Ok(());
+    };
+    // The WAL holds everything written since the last checkpoint, so count it too
+    let file_size = || {
+        [path.clone(), format!("{}.wal", path)]
+            .iter()
+            .filter_map(|p| std::fs::metadata(p).ok())
+            .map(|m| m.len())
+            .sum::<u64>()
+    };
+
+    let before = file_size();
+    conn.execute_batch("FORCE CHECKPOINT;")?;
+    let after = file_size();
+    info!(
+        "Compacted database: {:.1} MB -> {:.1} MB",
+        before as f64 / 1e6,
+        after as f64 / 1e6
+    );
     Ok(())
 }
 
@@ -241,6 +843,12 @@ mod tests {
         conn
     }
 
+    #[test]
+    fn compact_db_in_memory_is_noop() {
+        let conn = setup();
+        compact_db(&conn).unwrap();
+    }
+
     #[test]
     fn schema_creation() {
         let conn = setup();
@@ -251,9 +859,11 @@ mod tests {
                 |row| row.get(0),
             )
             .unwrap();
-        // records, record_metadata, workouts, workout_events, workout_statistics,
-        // activity_summaries, ecg_readings, ecg_samples, route_points, imports = 10
-        assert_eq!(count, 10);
+        // records, record_metadata, workouts, workout_metadata, workout_events,
+        // workout_statistics, activity_summaries, ecg_readings, ecg_samples,
+        // route_points, imports, import_progress, annotations, audiogram_points,
+        // clinical_records, source_aliases = 16
+        assert_eq!(count, 16);
     }
 
     #[test]
@@ -267,7 +877,76 @@ mod tests {
                 |row| row.get(0),
             )
             .unwrap();
-        assert_eq!(count, 10);
+        assert_eq!(count, 16);
+    }
+
+    #[test]
+    fn migrate_upgrades_v1_database() {
+        let conn = open_db_in_memory().unwrap();
+        // The tables migrations touch, as the first release created them (clinical_records
+        // came later, but `ensure_schema` creates it before `migrate` runs)
+        conn.execute_batch(
+            "CREATE TABLE records (record_hash VARCHAR, record_type VARCHAR, value DOUBLE);
+             CREATE TABLE workouts (workout_hash VARCHAR, activity_type VARCHAR);
+             CREATE TABLE ecg_readings (ecg_hash VARCHAR);
+             CREATE TABLE activity_summaries (date_components VARCHAR);
+             CREATE TABLE clinical_records (record_hash VARCHAR);
+             CREATE TABLE imports (import_id VARCHAR, export_dir VARCHAR NOT NULL);
+             CREATE TABLE schema_version (version INTEGER NOT NULL,
+                 applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP);
+             INSERT INTO schema_version (version) VALUES (1);",
+        )
+        .unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), 1);
+
+        assert_eq!(migrate(&conn).unwrap(), SCHEMA_VERSION);
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        let route_file_columns: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM information_schema.columns
+                 WHERE table_name = 'workouts' AND column_name = 'route_file'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(route_file_columns, 1);
+
+        // Running again applies nothing and records nothing
+        assert_eq!(migrate(&conn).unwrap(), SCHEMA_VERSION);
+        let rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM schema_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, MIGRATIONS.len() as i64 + 1);
+    }
+
+    #[test]
+    fn migrate_fresh_schema_records_latest_version() {
+        let conn = setup();
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+        assert_eq!(migrate(&conn).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn verify_schema_complete() {
+        let conn = setup();
+        rebuild_daily_stats(&conn).unwrap();
+        verify_schema(&conn).unwrap();
+    }
+
+    #[test]
+    fn verify_schema_reports_missing_tables() {
+        let conn = open_db_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE records (record_hash VARCHAR);")
+            .unwrap();
+
+        let err = verify_schema(&conn).unwrap_err();
+        let schema_err = err.downcast_ref::<SchemaError>().unwrap();
+        assert_eq!(schema_err.missing_tables.len(), BASE_TABLES.len() - 1);
+        assert!(!schema_err.missing_tables.contains(&"records".to_string()));
+        assert!(schema_err
+            .missing_tables
+            .contains(&"daily_record_stats".to_string()));
+        assert!(err.to_string().contains("re-run `apple-health-mcp import`"));
     }
 
     #[test]
@@ -276,29 +955,123 @@ mod tests {
         // Insert duplicate records
         conn.execute_batch(
             "
-            INSERT INTO records VALUES ('hash1', 'HeartRate', 72.0, 'count/min', 'Watch', '1.0', NULL, '2024-01-01 00:00:00', '2024-01-01 00:00:00', '2024-01-01 00:01:00', 'imp1');
-            INSERT INTO records VALUES ('hash1', 'HeartRate', 72.0, 'count/min', 'Watch', '1.0', NULL, '2024-01-01 00:00:00', '2024-01-01 00:00:00', '2024-01-01 00:01:00', 'imp1');
-            INSERT INTO records VALUES ('hash2', 'StepCount', 100.0, 'count', 'Phone', '1.0', NULL, '2024-01-01 00:00:00', '2024-01-01 00:00:00', '2024-01-01 00:01:00', 'imp1');
+            INSERT INTO records VALUES ('hash1', 'HeartRate', 72.0, 'count/min', 'Watch', '1.0', NULL, '2024-01-01 00:00:00', '2024-01-01 00:00:00', '2024-01-01 00:01:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('hash1', 'HeartRate', 72.0, 'count/min', 'Watch', '1.0', NULL, '2024-01-01 00:00:00', '2024-01-01 00:00:00', '2024-01-01 00:01:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('hash2', 'StepCount', 100.0, 'count', 'Phone', '1.0', NULL, '2024-01-01 00:00:00', '2024-01-01 00:00:00', '2024-01-01 00:01:00', 'imp1', NULL, NULL, NULL, NULL);
+            ",
+        )
+        .unwrap();
+
+        deduplicate_tables(&conn).unwrap();
+
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM records", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn deduplication_keeps_first_and_last_import() {
+        let conn = setup();
+        conn.execute_batch(
+            "
+            INSERT INTO imports VALUES ('imp1', '/tmp/export', '2024-01-01 00:00:00', 1, 0, 1.0, NULL);
+            INSERT INTO records VALUES ('hash1', 'HeartRate', 72.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-01 00:00:00', '2024-01-01 00:01:00', 'imp1', NULL, NULL, NULL, NULL);
             ",
         )
         .unwrap();
+        deduplicate_tables(&conn).unwrap();
 
+        // Second import (still in progress, so no imports row yet) sees hash1 again plus a new row
+        conn.execute_batch(
+            "
+            INSERT INTO records VALUES ('hash1', 'HeartRate', 72.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-01 00:00:00', '2024-01-01 00:01:00', 'imp2', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('hash2', 'StepCount', 100.0, 'count', 'Phone', NULL, NULL, NULL, '2024-02-01 00:00:00', '2024-02-01 00:01:00', 'imp2', NULL, NULL, NULL, NULL);
+            ",
+        )
+        .unwrap();
         deduplicate_tables(&conn).unwrap();
 
+        let provenance = |hash: &str| -> (String, String) {
+            conn.query_row(
+                "SELECT first_import_id, last_import_id FROM records WHERE record_hash = ?",
+                [hash],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            provenance("hash1"),
+            ("imp1".to_string(), "imp2".to_string())
+        );
+        assert_eq!(
+            provenance("hash2"),
+            ("imp2".to_string(), "imp2".to_string())
+        );
+
         let count: i64 = conn
             .query_row("SELECT COUNT(*) FROM records", [], |row| row.get(0))
             .unwrap();
         assert_eq!(count, 2);
     }
 
+    #[test]
+    fn normalize_units_converts_known_units() {
+        let conn = setup();
+        conn.execute_batch(
+            "
+            INSERT INTO workouts VALUES ('w1', 'Running', 30.0, 'min', 5.0, 'km', 1.0, 'Cal', 'Watch', NULL, NULL, NULL, '2024-01-01 10:00:00', '2024-01-01 10:30:00', 'imp1', NULL, NULL, NULL, NULL, NULL);
+            INSERT INTO workouts VALUES ('w2', 'Walking', 60.0, 'min', 2.0, 'mi', 418.4, 'kJ', 'Watch', NULL, NULL, NULL, '2024-01-02 10:00:00', '2024-01-02 11:00:00', 'imp1', NULL, NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('r1', 'DistanceWalkingRunning', 5.0, 'km', 'Watch', NULL, NULL, NULL, '2024-01-01 00:00:00', '2024-01-01 00:01:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('r2', 'ActiveEnergyBurned', 1.0, 'Cal', 'Watch', NULL, NULL, NULL, '2024-01-01 00:00:00', '2024-01-01 00:01:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('r3', 'Weird', 3.0, 'furlong', 'Watch', NULL, NULL, NULL, '2024-01-01 00:00:00', '2024-01-01 00:01:00', 'imp1', NULL, NULL, NULL, NULL);
+            ",
+        )
+        .unwrap();
+
+        normalize_units(&conn).unwrap();
+
+        let (distance_m, energy_kcal): (f64, f64) = conn
+            .query_row(
+                "SELECT total_distance_m, total_energy_kcal FROM workouts WHERE workout_hash = 'w1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(distance_m, 5000.0);
+        assert_eq!(energy_kcal, 1.0);
+
+        let (distance_m, energy_kcal): (f64, f64) = conn
+            .query_row(
+                "SELECT total_distance_m, total_energy_kcal FROM workouts WHERE workout_hash = 'w2'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert!((distance_m - 3218.688).abs() < 1e-9);
+        assert!((energy_kcal - 100.0).abs() < 1e-9);
+
+        let value_si = |hash: &str| -> Option<f64> {
+            conn.query_row(
+                "SELECT value_si FROM records WHERE record_hash = ?",
+                [hash],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(value_si("r1"), Some(5000.0));
+        assert_eq!(value_si("r2"), Some(1.0));
+        assert_eq!(value_si("r3"), None);
+    }
+
     #[test]
     fn daily_stats_aggregation() {
         let conn = setup();
         conn.execute_batch(
             "
-            INSERT INTO records VALUES ('h1', 'HeartRate', 72.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-01 08:00:00', '2024-01-01 08:01:00', 'imp1');
-            INSERT INTO records VALUES ('h2', 'HeartRate', 80.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-01 09:00:00', '2024-01-01 09:01:00', 'imp1');
-            INSERT INTO records VALUES ('h3', 'HeartRate', 65.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-02 08:00:00', '2024-01-02 08:01:00', 'imp1');
+            INSERT INTO records VALUES ('h1', 'HeartRate', 72.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-01 08:00:00', '2024-01-01 08:01:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('h2', 'HeartRate', 80.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-01 09:00:00', '2024-01-01 09:01:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('h3', 'HeartRate', 65.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-02 08:00:00', '2024-01-02 08:01:00', 'imp1', NULL, NULL, NULL, NULL);
             ",
         )
         .unwrap();
@@ -322,6 +1095,103 @@ mod tests {
         assert!((avg - 76.0).abs() < 0.01);
     }
 
+    #[test]
+    fn daily_stats_bucket_on_local_date() {
+        let conn = setup();
+        // 02:30 UTC on Jan 2 is 21:30 on Jan 1 in New York (UTC-5 in winter)
+        conn.execute_batch(
+            "
+            INSERT INTO records VALUES ('h1', 'HeartRate', 72.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-02 02:30:00', '2024-01-02 02:31:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('h2', 'HeartRate', 80.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-07-02 03:30:00', '2024-07-02 03:31:00', 'imp1', NULL, NULL, NULL, NULL);
+            ",
+        )
+        .unwrap();
+        let dates = |conn: &Connection| -> Vec<String> {
+            let mut stmt = conn
+                .prepare("SELECT CAST(date AS VARCHAR) FROM daily_record_stats ORDER BY date")
+                .unwrap();
+            stmt.query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<std::result::Result<_, _>>()
+                .unwrap()
+        };
+
+        rebuild_daily_stats(&conn).unwrap();
+        assert_eq!(dates(&conn), ["2024-01-02", "2024-07-02"]);
+
+        let fixed = DayTimezone::parse("-05:00").unwrap();
+        rebuild_daily_stats_in(&conn, Some(&fixed)).unwrap();
+        assert_eq!(dates(&conn), ["2024-01-01", "2024-07-01"]);
+
+        // Daylight saving time: 03:30 UTC in July is 23:30 EDT (UTC-4)
+        let named = DayTimezone::parse("America/New_York").unwrap();
+        rebuild_daily_stats_in(&conn, Some(&named)).unwrap();
+        assert_eq!(dates(&conn), ["2024-01-01", "2024-07-01"]);
+
+        // The timezone an import recorded is used by later rebuilds (e.g. purge)
+        conn.execute_batch(
+            "INSERT INTO imports VALUES ('imp1', '/tmp/export', '2024-08-01 00:00:00', 2, 0, 1.0, '-05:00');",
+        )
+        .unwrap();
+        assert_eq!(stored_timezone(&conn).unwrap(), Some(fixed));
+        rebuild_daily_stats(&conn).unwrap();
+        assert_eq!(dates(&conn), ["2024-01-01", "2024-07-01"]);
+    }
+
+    #[test]
+    fn monthly_rollup_matches_direct_aggregation() {
+        let conn = setup();
+        // Uneven counts per day, so an average of daily averages would differ
+        conn.execute_batch(
+            "
+            INSERT INTO records SELECT 'h' || i, 'HeartRate', 60.0 + i, 'count/min', 'Watch', NULL, NULL, NULL,
+                TIMESTAMP '2024-01-30 08:00:00' + INTERVAL (i % 5) DAY, TIMESTAMP '2024-01-30 08:01:00' + INTERVAL (i % 5) DAY,
+                'imp1', NULL, NULL, NULL, NULL
+            FROM range(23) t(i) WHERE i % 5 != 1 OR i < 7;
+            INSERT INTO records VALUES ('s1', 'StepCount', 500.0, 'count', 'Phone', NULL, NULL, NULL, '2024-02-10 08:00:00', '2024-02-10 09:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            ",
+        )
+        .unwrap();
+        rebuild_daily_stats(&conn).unwrap();
+
+        type Row = (String, String, i64, f64, f64, f64, f64, i64);
+        let rows = |sql: &str| -> Vec<Row> {
+            let mut stmt = conn.prepare(sql).unwrap();
+            stmt.query_map([], |r| {
+                Ok((
+                    r.get(0)?,
+                    r.get(1)?,
+                    r.get(2)?,
+                    r.get(3)?,
+                    r.get(4)?,
+                    r.get(5)?,
+                    r.get(6)?,
+                    r.get(7)?,
+                ))
+            })
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect()
+        };
+        let rollup = rows(
+            "SELECT record_type, CAST(period AS VARCHAR), CAST(count AS BIGINT), avg_value, min_value, \
+             max_value, sum_value, days_with_data FROM monthly_record_stats ORDER BY 1, 2",
+        );
+        let direct = rows(
+            "SELECT record_type, CAST(DATE_TRUNC('month', CAST(start_date AS DATE)) AS VARCHAR), COUNT(*), \
+             AVG(value), MIN(value), MAX(value), SUM(value), COUNT(DISTINCT CAST(start_date AS DATE)) \
+             FROM records GROUP BY 1, 2 ORDER BY 1, 2",
+        );
+
+        assert_eq!(rollup.len(), 3); // HeartRate in January and February, StepCount
+        assert_eq!(rollup.len(), direct.len());
+        for (r, d) in rollup.iter().zip(&direct) {
+            assert_eq!((&r.0, &r.1, r.2, r.7), (&d.0, &d.1, d.2, d.7));
+            assert!((r.3 - d.3).abs() < 1e-9, "{:?} vs {:?}", r, d);
+            assert_eq!((r.4, r.5, r.6), (d.4, d.5, d.6));
+        }
+    }
+
     #[test]
     fn open_db_in_memory_works() {
         let conn = open_db_in_memory().unwrap();
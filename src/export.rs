@@ -0,0 +1,486 @@
+use anyhow::{Context, Result};
+use duckdb::Connection;
+use std::fmt::Write as _;
+use std::path::Path;
+use tracing::info;
+
+/// Escape a value for use inside a single-quoted SQL string literal.
+fn sql_literal(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Stream records of one type to a CSV file via DuckDB's `COPY ... TO`, so rows
+/// never pass through Rust and memory stays flat regardless of result size.
+/// Returns the number of rows written.
+pub fn export_records_csv(
+    conn: &Connection,
+    record_type: &str,
+    start_date: Option<&str>,
+    end_date: Option<&str>,
+    out: &Path,
+) -> Result<u64> {
+    let exists: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM records WHERE record_type = ?",
+        [record_type],
+        |row| row.get(0),
+    )?;
+    if exists == 0 {
+        anyhow::bail!(
+            "Record type {} not found in database (use list_record_types to see available types)",
+            record_type
+        );
+    }
+
+    let mut query = format!(
+        "SELECT record_type, value, unit, source_name, source_version, device, start_date, end_date \
+         FROM records WHERE record_type = {}",
+        sql_literal(record_type)
+    );
+    if let Some(sd) = start_date {
+        query.push_str(&format!(" AND start_date >= {}", sql_literal(sd)));
+    }
+    if let Some(ed) = end_date {
+        query.push_str(&format!(" AND end_date <= {}", sql_literal(ed)));
+    }
+    query.push_str(" ORDER BY start_date");
+
+    let out_str = out.to_string_lossy();
+    let copy = format!(
+        "COPY ({}) TO {} (FORMAT CSV, HEADER)",
+        query,
+        sql_literal(&out_str)
+    );
+    let rows: i64 = conn
+        .query_row(&copy, [], |row| row.get(0))
+        .with_context(|| format!("Failed to export records to {}", out_str))?;
+
+    info!("Exported {} {} records to {}", rows, record_type, out_str);
+    Ok(rows as u64)
+}
+
+/// Write a workout's route as a GPX 1.1 document with one `<trkpt>` per route point.
+/// Speed, course and accuracy go into `<extensions>`, matching Apple's own exports,
+/// so the file round-trips through `import_gpx_files`. Returns the number of points.
+pub fn export_route_gpx(conn: &Connection, workout_hash: &str, out: &Path) -> Result<u64> {
+    let mut stmt = conn.prepare(
+        "SELECT latitude, longitude, elevation, strftime(timestamp, '%Y-%m-%dT%H:%M:%SZ'), \
+         speed, course, h_accuracy, v_accuracy \
+         FROM route_points WHERE workout_hash = ? ORDER BY timestamp",
+    )?;
+    let points = stmt
+        .query_map([workout_hash], |row| {
+            Ok((
+                row.get::<_, f64>(0)?,
+                row.get::<_, f64>(1)?,
+                row.get::<_, Option<f64>>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, Option<f64>>(4)?,
+                row.get::<_, Option<f64>>(5)?,
+                row.get::<_, Option<f64>>(6)?,
+                row.get::<_, Option<f64>>(7)?,
+            ))
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    if points.is_empty() {
+        anyhow::bail!("Workout {} has no route points", workout_hash);
+    }
+
+    let name: Option<String> = conn
+        .query_row(
+            "SELECT activity_type || ' ' || strftime(start_date, '%Y-%m-%d %H:%M') \
+             FROM workouts WHERE workout_hash = ?",
+            [workout_hash],
+            |row| row.get(0),
+        )
+        .ok();
+
+    let mut gpx = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <gpx version=\"1.1\" creator=\"apple-health-mcp\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n\
+         \x20\x20<trk>\n",
+    );
+    if let Some(name) = name {
+        writeln!(gpx, "    <name>{}</name>", quick_xml::escape::escape(&name))?;
+    }
+    gpx.push_str("    <trkseg>\n");
+    for (lat, lon, ele, time, speed, course, h_acc, v_acc) in &points {
+        writeln!(gpx, "      <trkpt lat=\"{}\" lon=\"{}\">", lat, lon)?;
+        if let Some(ele) = ele {
+            writeln!(gpx, "        <ele>{}</ele>", ele)?;
+        }
+        writeln!(gpx, "        <time>{}</time>", time)?;
+        let extensions = [
+            ("speed", speed),
+            ("course", course),
+            ("hAcc", h_acc),
+            ("vAcc", v_acc),
+        ];
+        if extensions.iter().any(|(_, v)| v.is_some()) {
+            gpx.push_str("        <extensions>\n");
+            for (tag, value) in extensions {
+                if let Some(v) = value {
+                    writeln!(gpx, "          <{tag}>{v}</{tag}>")?;
+                }
+            }
+            gpx.push_str("        </extensions>\n");
+        }
+        gpx.push_str("      </trkpt>\n");
+    }
+    gpx.push_str("    </trkseg>\n  </trk>\n</gpx>\n");
+
+    std::fs::write(out, gpx)
+        .with_context(|| format!("Failed to write GPX file {}", out.display()))?;
+
+    info!(
+        "Exported {} route points for workout {} to {}",
+        points.len(),
+        workout_hash,
+        out.display()
+    );
+    Ok(points.len() as u64)
+}
+
+/// Write a workout as a Garmin TCX v2 activity: one lap spanning the workout, with a
+/// `<Trackpoint>` per route point. Each trackpoint carries the most recent heart-rate
+/// sample recorded during the workout at or before its timestamp; `<HeartRateBpm>` is
+/// omitted when there is none. Returns the number of trackpoints.
+pub fn export_workout_tcx(conn: &Connection, workout_hash: &str, out: &Path) -> Result<u64> {
+    let workout = conn.query_row(
+        "SELECT activity_type, strftime(start_date, '%Y-%m-%dT%H:%M:%SZ'), \
+         epoch(end_date) - epoch(start_date), total_distance_m, total_energy_kcal \
+         FROM workouts WHERE workout_hash = ?",
+        [workout_hash],
+        |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, f64>(2)?,
+                row.get::<_, Option<f64>>(3)?,
+                row.get::<_, Option<f64>>(4)?,
+            ))
+        },
+    );
+    let (activity_type, start, total_secs, distance_m, energy_kcal) = match workout {
+        Ok(w) => w,
+        Err(duckdb::Error::QueryReturnedNoRows) => {
+            anyhow::bail!("Workout {} not found", workout_hash)
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut stmt = conn.prepare(
+        "WITH w AS (SELECT start_date, end_date FROM workouts WHERE workout_hash = ?), \
+         hr AS ( \
+            SELECT r.start_date, r.value FROM records r, w \
+            WHERE r.record_type = 'HKQuantityTypeIdentifierHeartRate' \
+            AND r.start_date >= w.start_date AND r.start_date <= w.end_date \
+         ) \
+         SELECT strftime(p.timestamp, '%Y-%m-%dT%H:%M:%SZ'), p.latitude, p.longitude, p.elevation, \
+                CAST(round(hr.value) AS INTEGER) \
+         FROM route_points p ASOF LEFT JOIN hr ON p.timestamp >= hr.start_date \
+         WHERE p.workout_hash = ? ORDER BY p.timestamp",
+    )?;
+    let points = stmt
+        .query_map([workout_hash, workout_hash], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, f64>(1)?,
+                row.get::<_, f64>(2)?,
+                row.get::<_, Option<f64>>(3)?,
+                row.get::<_, Option<i32>>(4)?,
+            ))
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    if points.is_empty() {
+        anyhow::bail!("Workout {} has no route points", workout_hash);
+    }
+
+    let sport = match activity_type.as_str() {
+        "HKWorkoutActivityTypeRunning" => "Running",
+        "HKWorkoutActivityTypeCycling" => "Biking",
+        _ => "Other",
+    };
+
+    let mut tcx = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <TrainingCenterDatabase xmlns=\"http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2\">\n\
+         \x20\x20<Activities>\n",
+    );
+    writeln!(tcx, "    <Activity Sport=\"{}\">", sport)?;
+    writeln!(tcx, "      <Id>{}</Id>", start)?;
+    writeln!(tcx, "      <Lap StartTime=\"{}\">", start)?;
+    writeln!(
+        tcx,
+        "        <TotalTimeSeconds>{}</TotalTimeSeconds>",
+        total_secs
+    )?;
+    writeln!(
+        tcx,
+        "        <DistanceMeters>{}</DistanceMeters>",
+        distance_m.unwrap_or(0.0)
+    )?;
+    writeln!(
+        tcx,
+        "        <Calories>{}</Calories>",
+        energy_kcal.unwrap_or(0.0).round() as u32
+    )?;
+    tcx.push_str("        <Intensity>Active</Intensity>\n");
+    tcx.push_str("        <TriggerMethod>Manual</TriggerMethod>\n");
+    tcx.push_str("        <Track>\n");
+    for (time, lat, lon, ele, bpm) in &points {
+        tcx.push_str("          <Trackpoint>\n");
+        writeln!(tcx, "            <Time>{}</Time>", time)?;
+        writeln!(
+            tcx,
+            "            <Position><LatitudeDegrees>{}</LatitudeDegrees><LongitudeDegrees>{}</LongitudeDegrees></Position>",
+            lat, lon
+        )?;
+        if let Some(ele) = ele {
+            writeln!(tcx, "            <AltitudeMeters>{}</AltitudeMeters>", ele)?;
+        }
+        if let Some(bpm) = bpm {
+            writeln!(
+                tcx,
+                "            <HeartRateBpm><Value>{}</Value></HeartRateBpm>",
+                bpm
+            )?;
+        }
+        tcx.push_str("          </Trackpoint>\n");
+    }
+    tcx.push_str("        </Track>\n      </Lap>\n    </Activity>\n  </Activities>\n</TrainingCenterDatabase>\n");
+
+    std::fs::write(out, tcx)
+        .with_context(|| format!("Failed to write TCX file {}", out.display()))?;
+
+    info!(
+        "Exported {} trackpoints for workout {} to {}",
+        points.len(),
+        workout_hash,
+        out.display()
+    );
+    Ok(points.len() as u64)
+}
+
+/// Write one ECG reading as CSV for cardiology tools: `#`-prefixed header lines with the
+/// hash, recorded date, classification, sample rate and device, then a
+/// `sample_idx,voltage_uv` row per sample. Returns the number of samples written.
+pub fn export_ecg_csv(conn: &Connection, ecg_hash: &str, out: &Path) -> Result<u64> {
+    let reading = conn.query_row(
+        "SELECT strftime(recorded_date, '%Y-%m-%dT%H:%M:%SZ'), classification, sample_rate_hz, device \
+         FROM ecg_readings WHERE ecg_hash = ?",
+        [ecg_hash],
+        |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<f64>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        },
+    );
+    let (recorded, classification, sample_rate_hz, device) = match reading {
+        Ok(r) => r,
+        Err(duckdb::Error::QueryReturnedNoRows) => {
+            anyhow::bail!(
+                "ECG reading {} not found (use list_ecg_readings to see available readings)",
+                ecg_hash
+            )
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut csv = String::new();
+    writeln!(csv, "# ecg_hash: {}", ecg_hash)?;
+    writeln!(csv, "# recorded_date: {}", recorded)?;
+    writeln!(
+        csv,
+        "# classification: {}",
+        classification.as_deref().unwrap_or("")
+    )?;
+    match sample_rate_hz {
+        Some(hz) => writeln!(csv, "# sample_rate_hz: {}", hz)?,
+        None => csv.push_str("# sample_rate_hz:\n"),
+    }
+    writeln!(csv, "# device: {}", device.as_deref().unwrap_or(""))?;
+    csv.push_str("sample_idx,voltage_uv\n");
+
+    let mut stmt = conn.prepare(
+        "SELECT sample_idx, voltage_uv FROM ecg_samples WHERE ecg_hash = ? ORDER BY sample_idx",
+    )?;
+    let mut rows = stmt.query([ecg_hash])?;
+    let mut samples = 0u64;
+    while let Some(row) = rows.next()? {
+        let (idx, uv): (i32, f64) = (row.get(0)?, row.get(1)?);
+        writeln!(csv, "{},{}", idx, uv)?;
+        samples += 1;
+    }
+
+    std::fs::write(out, csv)
+        .with_context(|| format!("Failed to write ECG file {}", out.display()))?;
+
+    info!(
+        "Exported {} samples for ECG {} to {}",
+        samples,
+        ecg_hash,
+        out.display()
+    );
+    Ok(samples)
+}
+
+/// Write every ECG reading to `<ecg_hash>.csv` in `out_dir` (created if missing, must be
+/// empty) using `export_ecg_csv`. Returns the number of readings and of samples written.
+pub fn export_all_ecg_csv(conn: &Connection, out_dir: &Path) -> Result<(usize, u64)> {
+    if out_dir.exists() && std::fs::read_dir(out_dir)?.next().is_some() {
+        anyhow::bail!(
+            "Refusing to export into non-empty directory {}",
+            out_dir.display()
+        );
+    }
+
+    let mut stmt = conn.prepare("SELECT ecg_hash FROM ecg_readings ORDER BY recorded_date")?;
+    let hashes = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    if hashes.is_empty() {
+        anyhow::bail!("No ECG readings in database");
+    }
+
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create directory {}", out_dir.display()))?;
+    let mut samples = 0;
+    for hash in &hashes {
+        samples += export_ecg_csv(conn, hash, &out_dir.join(format!("{}.csv", hash)))?;
+    }
+    Ok((hashes.len(), samples))
+}
+
+/// Dump the whole database with DuckDB's `EXPORT DATABASE`: `schema.sql` holds the
+/// `CREATE` statements, `load.sql` the load commands, plus one CSV per table. The
+/// dump can be restored elsewhere with `IMPORT DATABASE '<dir>'`.
+pub fn dump_database(conn: &Connection, out_dir: &Path) -> Result<()> {
+    if out_dir.exists() && std::fs::read_dir(out_dir)?.next().is_some() {
+        anyhow::bail!(
+            "Refusing to dump into non-empty directory {}",
+            out_dir.display()
+        );
+    }
+
+    let out_str = out_dir.to_string_lossy();
+    conn.execute_batch(&format!("EXPORT DATABASE {}", sql_literal(&out_str)))
+        .with_context(|| format!("Failed to dump database to {}", out_str))?;
+
+    info!("Dumped database to {}", out_str);
+    Ok(())
+}
+
+/// Copy every table in `BASE_TABLES` into a new SQLite file through DuckDB's `sqlite`
+/// extension (installed on first use), for tools that only read SQLite. TIMESTAMP and DATE
+/// columns become `YYYY-MM-DD[ HH:MM:SS]` text, which SQLite's date functions understand;
+/// DOUBLE maps to REAL and integers to INTEGER. Returns the number of tables written.
+pub fn export_sqlite(conn: &Connection, out: &Path) -> Result<usize> {
+    if out.exists() {
+        anyhow::bail!("Refusing to overwrite existing file {}", out.display());
+    }
+
+    conn.execute_batch("INSTALL sqlite; LOAD sqlite;")
+        .context("Failed to load DuckDB's sqlite extension")?;
+    let out_str = out.to_string_lossy();
+    // Attached databases inherit the access mode, and the CLI opens the source read-only
+    conn.execute_batch(&format!(
+        "ATTACH {} AS sqlite_out (TYPE SQLITE, READ_ONLY false)",
+        sql_literal(&out_str)
+    ))
+    .with_context(|| format!("Failed to create {}", out_str))?;
+
+    let result = copy_tables_to_sqlite(conn);
+    conn.execute_batch("DETACH sqlite_out")?;
+    let tables = result?;
+
+    info!("Exported {} tables to {}", tables, out_str);
+    Ok(tables)
+}
+
+fn copy_tables_to_sqlite(conn: &Connection) -> Result<usize> {
+    let mut tables = 0;
+    for table in crate::db::BASE_TABLES {
+        let mut stmt = conn.prepare(
+            "SELECT column_name, data_type FROM information_schema.columns \
+             WHERE table_schema = 'main' AND table_catalog = current_database() AND table_name = ? \
+             ORDER BY ordinal_position",
+        )?;
+        let columns: Vec<(String, String)> = stmt
+            .query_map([table], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<std::result::Result<_, _>>()?;
+        if columns.is_empty() {
+            continue;
+        }
+
+        let select = columns
+            .iter()
+            .map(|(name, data_type)| {
+                if data_type.starts_with("TIMESTAMP") || data_type == "DATE" {
+                    format!("CAST(\"{0}\" AS VARCHAR) AS \"{0}\"", name)
+                } else {
+                    format!("\"{}\"", name)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        conn.execute_batch(&format!(
+            "CREATE TABLE sqlite_out.{0} AS SELECT {1} FROM main.{0}",
+            table, select
+        ))
+        .with_context(|| format!("Failed to export table {}", table))?;
+        tables += 1;
+    }
+    Ok(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ensure_schema, open_db_in_memory};
+
+    #[test]
+    fn copy_tables_to_attached_database() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO records (record_hash, record_type, value, unit, source_name, start_date, end_date, import_id) \
+             VALUES ('rh1', 'HKQuantityTypeIdentifierHeartRate', 72.0, 'count/min', 'Apple Watch', \
+                     '2024-01-01 08:00:00', '2024-01-01 08:01:00', 'imp1');",
+        )
+        .unwrap();
+
+        // A DuckDB file stands in for the SQLite one, so the copy runs without the extension
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("copy.duckdb");
+        conn.execute_batch(&format!(
+            "ATTACH {} AS sqlite_out",
+            sql_literal(&out.to_string_lossy())
+        ))
+        .unwrap();
+        assert!(copy_tables_to_sqlite(&conn).unwrap() > 0);
+
+        let (count, start_date_type): (i64, String) = conn
+            .query_row(
+                "SELECT (SELECT COUNT(*) FROM sqlite_out.records), \
+                 (SELECT data_type FROM information_schema.columns \
+                  WHERE table_catalog = 'sqlite_out' AND table_name = 'records' \
+                  AND column_name = 'start_date')",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(start_date_type, "VARCHAR");
+        let start_date: String = conn
+            .query_row("SELECT start_date FROM sqlite_out.records", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(start_date, "2024-01-01 08:00:00");
+    }
+}
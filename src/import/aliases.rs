@@ -0,0 +1,88 @@
+use anyhow::{bail, Context, Result};
+use duckdb::Connection;
+use std::path::Path;
+use tracing::info;
+
+/// One line of a `--source-aliases` file: source names matching `pattern` (a SQL `LIKE`
+/// pattern, so `%` matches any run of characters) are reported as `canonical_name`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceAlias {
+    pub pattern: String,
+    pub canonical_name: String,
+}
+
+/// Parse `PATTERN=CANONICAL` lines, e.g. `%Apple Watch=Apple Watch`. Blank lines and lines
+/// starting with `#` are skipped.
+pub fn parse_source_aliases(text: &str) -> Result<Vec<SourceAlias>> {
+    let mut aliases = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (pattern, canonical_name) = line
+            .rsplit_once('=')
+            .with_context(|| format!("Line {}: expected PATTERN=CANONICAL", i + 1))?;
+        let (pattern, canonical_name) = (pattern.trim(), canonical_name.trim());
+        if pattern.is_empty() || canonical_name.is_empty() {
+            bail!(
+                "Line {}: pattern and canonical name must not be empty",
+                i + 1
+            );
+        }
+        aliases.push(SourceAlias {
+            pattern: pattern.to_string(),
+            canonical_name: canonical_name.to_string(),
+        });
+    }
+    Ok(aliases)
+}
+
+/// Store the aliases in `path` in `source_aliases`, replacing earlier mappings of the same
+/// patterns. Raw `source_name` values are left as imported; tools apply the mapping when
+/// they read. Returns the number of aliases loaded.
+pub fn load_source_aliases(conn: &Connection, path: &Path) -> Result<usize> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read source aliases {}", path.display()))?;
+    let aliases = parse_source_aliases(&text)?;
+
+    for alias in &aliases {
+        conn.execute(
+            "DELETE FROM source_aliases WHERE pattern = ?",
+            [&alias.pattern],
+        )?;
+        conn.execute(
+            "INSERT INTO source_aliases VALUES (?, ?)",
+            [&alias.pattern, &alias.canonical_name],
+        )?;
+    }
+    info!("Loaded {} source aliases", aliases.len());
+    Ok(aliases.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_aliases() {
+        let aliases =
+            parse_source_aliases("# devices\n%Apple Watch = Apple Watch\n\nJohn's iPhone=iPhone\n")
+                .unwrap();
+        assert_eq!(
+            aliases,
+            vec![
+                SourceAlias {
+                    pattern: "%Apple Watch".to_string(),
+                    canonical_name: "Apple Watch".to_string(),
+                },
+                SourceAlias {
+                    pattern: "John's iPhone".to_string(),
+                    canonical_name: "iPhone".to_string(),
+                },
+            ]
+        );
+        assert!(parse_source_aliases("no separator").is_err());
+        assert!(parse_source_aliases("=Apple Watch").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::{Context, Result};
+use duckdb::Connection;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use tracing::{info, warn};
+use zip::ZipArchive;
+
+use super::{cda, ecg, gpx, xml, ImportOptions};
+use crate::models::ImportStats;
+
+/// True if `path` is a zip archive (e.g. the `export.zip` from the Health app's share sheet).
+pub fn is_archive(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// Import `export.xml`, `export_cda.xml`, `electrocardiograms/*.csv`, and `workout-routes/*.gpx`
+/// straight out of an export archive, streaming each entry into the regular parsers without
+/// extracting it.
+/// Apple nests everything under `apple_health_export/`; the root is taken from wherever
+/// `export.xml` sits.
+pub fn import_archive(
+    conn: &Connection,
+    zip_path: &Path,
+    import_id: &str,
+    options: &ImportOptions,
+) -> Result<ImportStats> {
+    let file = File::open(zip_path).context("Failed to open export archive")?;
+    let mut archive = ZipArchive::new(file).context("Failed to read export archive")?;
+
+    let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+    names.sort();
+
+    let xml_name = names
+        .iter()
+        .filter(|n| n.rsplit('/').next() == Some("export.xml"))
+        .min_by_key(|n| n.len())
+        .cloned()
+        .context("No export.xml found in archive")?;
+    let root = &xml_name[..xml_name.len() - "export.xml".len()];
+
+    // Phase 1: Parse export.xml
+    info!("Phase 1: Parsing {} from archive...", xml_name);
+    let mut stats = {
+        let entry = archive.by_name(&xml_name)?;
+        xml::import_xml_reader_parallel(
+            conn,
+            BufReader::with_capacity(8 * 1024 * 1024, entry),
+            import_id,
+            options.parse_threads,
+            options.batch_size(),
+        )?
+    };
+    let workout_route_map = std::mem::take(&mut stats.workout_routes);
+
+    let cda_name = format!("{}export_cda.xml", root);
+    if names.contains(&cda_name) {
+        let entry = archive.by_name(&cda_name)?;
+        stats.clinical_records = cda::import_cda_reader(conn, BufReader::new(entry), import_id)?;
+        info!("Imported {} clinical records", stats.clinical_records);
+    }
+
+    // Phase 2: Parse ECG files
+    info!("Phase 2: Parsing ECG files...");
+    for name in entries_in(&names, &format!("{}electrocardiograms/", root), ".csv") {
+        let result = read_entry(&mut archive, name)
+            .and_then(|content| ecg::import_ecg_csv(conn, &content, import_id));
+        match result {
+            Ok(()) => stats.ecg_readings += 1,
+            Err(e) => {
+                warn!("Failed to import ECG file {}: {:?}", name, e);
+                stats.ecg_files_failed += 1;
+            }
+        }
+    }
+    info!(
+        "Imported {} ECG recordings ({} files failed)",
+        stats.ecg_readings, stats.ecg_files_failed
+    );
+
+    // Phase 3: Parse GPX routes
+    info!("Phase 3: Parsing GPX route files...");
+    let routes_prefix = format!("{}workout-routes/", root);
+    for name in entries_in(&names, &routes_prefix, ".gpx") {
+        // Same key format as the FileReference paths in export.xml
+        let route_key = format!("/workout-routes/{}", &name[routes_prefix.len()..]);
+        let workout_hash = workout_route_map.get(&route_key).map(String::as_str);
+        let result = archive
+            .by_name(name)
+            .map_err(anyhow::Error::from)
+            .and_then(|entry| {
+                gpx::import_gpx_reader(conn, BufReader::new(entry), import_id, workout_hash)
+            });
+        match result {
+            Ok(n) => {
+                stats.route_points += n;
+                stats.gpx_files += 1;
+                if let Some(wh) = workout_hash {
+                    gpx::check_route_window(
+                        conn,
+                        name,
+                        wh,
+                        import_id,
+                        options.strict_routes,
+                        &mut stats,
+                    )?;
+                }
+            }
+            Err(e) => {
+                warn!("Failed to import GPX file {}: {:?}", name, e);
+                stats.gpx_files_failed += 1;
+            }
+        }
+    }
+    info!(
+        "Imported {} route points from {} GPX files ({} failed)",
+        stats.route_points, stats.gpx_files, stats.gpx_files_failed
+    );
+
+    Ok(stats)
+}
+
+/// Files directly inside the `prefix` directory with the given extension, mirroring the
+/// non-recursive directory scans used for unpacked exports.
+fn entries_in<'a>(
+    names: &'a [String],
+    prefix: &'a str,
+    extension: &'a str,
+) -> impl Iterator<Item = &'a String> {
+    names.iter().filter(move |n| {
+        n.strip_prefix(prefix)
+            .is_some_and(|rest| !rest.contains('/') && rest.ends_with(extension))
+    })
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<String> {
+    let mut content = String::new();
+    archive
+        .by_name(name)?
+        .read_to_string(&mut content)
+        .context("Failed to read archive entry")?;
+    Ok(content)
+}
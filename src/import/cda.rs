@@ -0,0 +1,309 @@
+use anyhow::{Context, Result};
+use duckdb::Connection;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::reader::Reader;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use tracing::info;
+
+use crate::models::compute_hash;
+
+/// Import the clinical records (lab results, vitals) in `export_cda.xml`. A missing file
+/// is not an error: many exports don't include one.
+pub fn import_cda(conn: &Connection, cda_path: &Path, import_id: &str) -> Result<u64> {
+    if !cda_path.exists() {
+        info!("No export_cda.xml found, skipping clinical records");
+        return Ok(0);
+    }
+    let file = std::fs::File::open(cda_path).context("Failed to open export_cda.xml")?;
+    let count = import_cda_reader(conn, BufReader::new(file), import_id)?;
+    info!("Imported {} clinical records", count);
+    Ok(count)
+}
+
+#[derive(Default)]
+struct ClinicalRow {
+    code: Option<String>,
+    display_name: Option<String>,
+    value: Option<f64>,
+    value_text: Option<String>,
+    unit: Option<String>,
+    effective_date: Option<String>,
+    source: Option<String>,
+    // Apple's `<text>` block repeats the observation as HealthKit fields
+    hk_type: Option<String>,
+    text_value: Option<String>,
+    text_unit: Option<String>,
+}
+
+/// An element whose text we keep.
+#[derive(Clone, Copy)]
+enum TextField {
+    SourceName,
+    Type,
+    TextValue,
+    TextUnit,
+    Value,
+}
+
+fn attr_value(e: &BytesStart, name: &[u8]) -> Option<String> {
+    e.attributes().filter_map(|a| a.ok()).find_map(|a| {
+        if a.key.local_name().as_ref() == name {
+            a.unescape_value().ok().map(|v| v.into_owned())
+        } else {
+            None
+        }
+    })
+}
+
+/// Parse one CDA document and append a row to `clinical_records` for every `<observation>`
+/// with a code and a value. Nested observations (`entryRelationship`) become rows of their own.
+pub(crate) fn import_cda_reader<R: BufRead>(
+    conn: &Connection,
+    reader: R,
+    import_id: &str,
+) -> Result<u64> {
+    let mut xml = Reader::from_reader(reader);
+    xml.config_mut().trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut open: Vec<ClinicalRow> = Vec::new();
+    let mut rows: Vec<ClinicalRow> = Vec::new();
+    let mut in_text = false;
+    let mut in_effective_time = false;
+    let mut current_field: Option<TextField> = None;
+
+    loop {
+        let event = xml
+            .read_event_into(&mut buf)
+            .context("Malformed export_cda.xml")?;
+        match event {
+            Event::Eof => break,
+            Event::Start(ref e) if e.local_name().as_ref() == b"observation" => {
+                open.push(ClinicalRow::default());
+                in_text = false;
+            }
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let is_empty = matches!(event, Event::Empty(_));
+                let Some(row) = open.last_mut() else {
+                    buf.clear();
+                    continue;
+                };
+                match e.local_name().as_ref() {
+                    b"text" if !is_empty => in_text = true,
+                    b"sourceName" if in_text => current_field = Some(TextField::SourceName),
+                    b"type" if in_text => current_field = Some(TextField::Type),
+                    b"value" if in_text => current_field = Some(TextField::TextValue),
+                    b"unit" if in_text => current_field = Some(TextField::TextUnit),
+                    // Only the observation's own code; participants and values carry codes too
+                    b"code" if row.code.is_none() => {
+                        row.code = attr_value(e, b"code");
+                        row.display_name = attr_value(e, b"displayName");
+                    }
+                    b"effectiveTime" => {
+                        row.effective_date =
+                            attr_value(e, b"value").and_then(|v| clean_cda_time(&v));
+                        in_effective_time = !is_empty;
+                    }
+                    b"low" | b"center" if in_effective_time && row.effective_date.is_none() => {
+                        row.effective_date =
+                            attr_value(e, b"value").and_then(|v| clean_cda_time(&v));
+                    }
+                    b"value" => {
+                        // PQ: value + unit; CD/CO: a coded result; ST: the element text
+                        match attr_value(e, b"value") {
+                            Some(v) => match v.parse::<f64>() {
+                                Ok(n) => row.value = Some(n),
+                                Err(_) => row.value_text = Some(v),
+                            },
+                            None => {
+                                row.value_text =
+                                    attr_value(e, b"displayName").or_else(|| attr_value(e, b"code"))
+                            }
+                        }
+                        row.unit = attr_value(e, b"unit").filter(|u| u != "1");
+                        if !is_empty {
+                            current_field = Some(TextField::Value);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::Text(ref t) => {
+                if let (Some(field), Some(row)) = (current_field, open.last_mut()) {
+                    let text = t.unescape().unwrap_or_default().trim().to_string();
+                    match field {
+                        TextField::SourceName => row.source = Some(text),
+                        TextField::Type => row.hk_type = Some(text),
+                        TextField::TextValue => row.text_value = Some(text),
+                        TextField::TextUnit => row.text_unit = Some(text),
+                        TextField::Value => row.value_text = Some(text),
+                    }
+                }
+            }
+            Event::End(ref e) => {
+                match e.local_name().as_ref() {
+                    b"observation" => {
+                        if let Some(row) = open.pop() {
+                            rows.push(row);
+                        }
+                        in_text = false;
+                    }
+                    b"text" => in_text = false,
+                    b"effectiveTime" => in_effective_time = false,
+                    _ => {}
+                }
+                current_field = None;
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    let mut count = 0u64;
+    let mut appender = conn.appender("clinical_records")?;
+    for mut row in rows {
+        if row.value.is_none() && row.value_text.is_none() {
+            match row.text_value.as_deref().map(str::parse::<f64>) {
+                Some(Ok(n)) => row.value = Some(n),
+                Some(Err(_)) => row.value_text = row.text_value.take(),
+                None => {}
+            }
+        }
+        let Some(record_type) = row.hk_type.or(row.code) else {
+            continue;
+        };
+        if row.value.is_none() && row.value_text.is_none() {
+            continue;
+        }
+        let unit = row.unit.or(row.text_unit);
+        let value = row.value.map(|v| v.to_string());
+        let record_hash = compute_hash(&[
+            &record_type,
+            row.effective_date.as_deref().unwrap_or(""),
+            value.as_deref().or(row.value_text.as_deref()).unwrap_or(""),
+            unit.as_deref().unwrap_or(""),
+            row.source.as_deref().unwrap_or(""),
+        ]);
+        appender.append_row(duckdb::params![
+            record_hash,
+            record_type,
+            row.display_name,
+            row.value,
+            row.value_text,
+            unit,
+            row.effective_date,
+            row.source,
+            import_id,
+            None::<String>,
+            None::<String>,
+        ])?;
+        count += 1;
+    }
+    appender.flush()?;
+
+    Ok(count)
+}
+
+/// HL7 timestamps (`YYYYMMDD[HHMM[SS]][+ZZZZ]`) as `YYYY-MM-DD HH:MM:SS`, keeping the local
+/// time and dropping the offset.
+fn clean_cda_time(ts: &str) -> Option<String> {
+    let digits: String = ts
+        .split(['+', '-', '.'])
+        .next()?
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    if digits.len() < 8 {
+        return None;
+    }
+    let part = |range: std::ops::Range<usize>| digits.get(range).unwrap_or("00");
+    Some(format!(
+        "{}-{}-{} {}:{}:{}",
+        &digits[0..4],
+        &digits[4..6],
+        &digits[6..8],
+        part(8..10),
+        part(10..12),
+        part(12..14)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ensure_schema, open_db_in_memory};
+
+    const MINIMAL_CDA: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ClinicalDocument xmlns="urn:hl7-org:v3" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
+  <code code="34133-9" codeSystem="2.16.840.1.113883.6.1" displayName="Summary of episode note"/>
+  <recordTarget><patientRole><patient><name>Test User</name></patient></patientRole></recordTarget>
+  <component><structuredBody><component><section>
+    <code code="30954-2" codeSystem="2.16.840.1.113883.6.1" displayName="Results"/>
+    <entry>
+      <organizer classCode="BATTERY" moodCode="EVN">
+        <component>
+          <observation classCode="OBS" moodCode="EVN">
+            <code code="2345-7" codeSystem="2.16.840.1.113883.6.1" displayName="Glucose [Mass/volume] in Serum or Plasma"/>
+            <text>
+              <sourceName>City Lab</sourceName>
+            </text>
+            <statusCode code="completed"/>
+            <effectiveTime value="20240115083000+0100"/>
+            <value xsi:type="PQ" value="95" unit="mg/dL"/>
+            <participant typeCode="DEV">
+              <participantRole><code code="ANALYZER"/></participantRole>
+            </participant>
+          </observation>
+        </component>
+      </organizer>
+    </entry>
+  </section></component></structuredBody></component>
+</ClinicalDocument>"#;
+
+    #[test]
+    fn import_cda_lab_result() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+
+        let count = import_cda_reader(&conn, MINIMAL_CDA.as_bytes(), "test_import").unwrap();
+        assert_eq!(count, 1);
+
+        let row: (String, String, f64, String, String, String) = conn
+            .query_row(
+                "SELECT record_type, display_name, value, unit, CAST(effective_date AS VARCHAR), source \
+                 FROM clinical_records",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?, r.get(5)?)),
+            )
+            .unwrap();
+        assert_eq!(row.0, "2345-7");
+        assert_eq!(row.1, "Glucose [Mass/volume] in Serum or Plasma");
+        assert_eq!(row.2, 95.0);
+        assert_eq!(row.3, "mg/dL");
+        assert_eq!(row.4, "2024-01-15 08:30:00");
+        assert_eq!(row.5, "City Lab");
+    }
+
+    #[test]
+    fn import_cda_missing_file() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+
+        let missing = Path::new("/nonexistent/export_cda.xml");
+        assert_eq!(import_cda(&conn, missing, "test").unwrap(), 0);
+    }
+
+    #[test]
+    fn clean_cda_time_formats() {
+        assert_eq!(
+            clean_cda_time("20240115083000+0100").as_deref(),
+            Some("2024-01-15 08:30:00")
+        );
+        assert_eq!(
+            clean_cda_time("20240115").as_deref(),
+            Some("2024-01-15 00:00:00")
+        );
+        assert_eq!(clean_cda_time("2024"), None);
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::{bail, Context, Result};
+use duckdb::Connection;
+use tracing::info;
+
+use crate::models::{record_kind, RecordKind};
+
+/// Keep one record per `interval_secs` bucket for `record_type` (from `--downsample TYPE=INTERVAL`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownsampleRule {
+    pub record_type: String,
+    pub interval_secs: u64,
+}
+
+/// Parse `TYPE=INTERVAL`, e.g. `HKQuantityTypeIdentifierHeartRate=1m`. The interval is a
+/// number with an `s`, `m`/`min`, `h`, or `d` suffix (bare numbers are seconds).
+pub fn parse_downsample_rule(spec: &str) -> Result<DownsampleRule> {
+    let (record_type, interval) = spec
+        .split_once('=')
+        .context("Expected TYPE=INTERVAL, e.g. HKQuantityTypeIdentifierHeartRate=1m")?;
+    let record_type = record_type.trim();
+    if record_type.is_empty() {
+        bail!("Missing record type in {:?}", spec);
+    }
+
+    let interval = interval.trim();
+    let split = interval
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(interval.len());
+    let (number, unit) = interval.split_at(split);
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("Invalid interval {:?}", interval))?;
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" | "min" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => bail!("Unknown interval unit {:?} (use s, m, h, or d)", unit),
+    };
+    if number == 0 {
+        bail!("Interval must be greater than zero");
+    }
+
+    Ok(DownsampleRule {
+        record_type: record_type.to_string(),
+        interval_secs: number * multiplier,
+    })
+}
+
+/// Collapse this import's numeric records of each rule's type to one row per source, unit,
+/// and interval bucket: summed for cumulative types (steps, energy), averaged otherwise.
+/// Runs before deduplication; the bucket row's hash is derived from the bucket, so
+/// re-importing the same export with the same rule dedups as usual. Metadata of dropped
+/// records is removed unless another import still has the record. Returns rows removed.
+/// Opens no transaction of its own; `run_import` calls it inside the import's.
+pub fn downsample_records(
+    conn: &Connection,
+    import_id: &str,
+    rules: &[DownsampleRule],
+) -> Result<u64> {
+    let mut removed = 0u64;
+    for rule in rules {
+        let aggregate = match record_kind(&rule.record_type) {
+            RecordKind::Cumulative => "SUM(value)",
+            _ => "AVG(value)",
+        };
+        let interval_secs = rule.interval_secs as i64;
+
+        conn.execute(
+            &format!(
+                "CREATE OR REPLACE TEMP TABLE downsampled AS
+                 SELECT
+                     sha256(concat_ws('|', record_type, source_name, COALESCE(unit, ''),
+                                      CAST(bucket AS VARCHAR), 'downsample', CAST($1 AS VARCHAR))) AS record_hash,
+                     record_type, {aggregate} AS value, unit, source_name,
+                     arg_min(source_version, start_date) AS source_version,
+                     arg_min(device, start_date) AS device,
+                     MIN(creation_date) AS creation_date,
+                     bucket AS start_date, MAX(end_date) AS end_date
+                 FROM (
+                     SELECT *, time_bucket(to_seconds($1), start_date) AS bucket
+                     FROM records
+                     WHERE import_id = $2 AND record_type = $3 AND value IS NOT NULL
+                 )
+                 GROUP BY record_type, source_name, unit, bucket"
+            ),
+            duckdb::params![interval_secs, import_id, rule.record_type],
+        )?;
+        conn.execute(
+            "DELETE FROM record_metadata
+             WHERE record_hash IN (SELECT record_hash FROM records
+                                   WHERE import_id = $1 AND record_type = $2 AND value IS NOT NULL)
+               AND record_hash NOT IN (SELECT record_hash FROM records WHERE import_id <> $1)",
+            duckdb::params![import_id, rule.record_type],
+        )?;
+        let before = conn.execute(
+            "DELETE FROM records WHERE import_id = ? AND record_type = ? AND value IS NOT NULL",
+            duckdb::params![import_id, rule.record_type],
+        )?;
+        let after = conn.execute(
+            "INSERT INTO records (record_hash, record_type, value, unit, source_name, source_version,
+                                  device, creation_date, start_date, end_date, import_id)
+             SELECT record_hash, record_type, value, unit, source_name, source_version,
+                    device, creation_date, start_date, end_date, ? FROM downsampled",
+            duckdb::params![import_id],
+        )?;
+        conn.execute_batch("DROP TABLE downsampled")?;
+
+        info!(
+            "Downsampled {} to {}s buckets ({} records removed)",
+            rule.record_type,
+            rule.interval_secs,
+            before - after
+        );
+        removed += (before - after) as u64;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!(
+            parse_downsample_rule("HKQuantityTypeIdentifierHeartRate=1m").unwrap(),
+            DownsampleRule {
+                record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+                interval_secs: 60,
+            }
+        );
+        assert_eq!(parse_downsample_rule("X=90").unwrap().interval_secs, 90);
+        assert_eq!(parse_downsample_rule("X=5min").unwrap().interval_secs, 300);
+        assert_eq!(parse_downsample_rule("X=1h").unwrap().interval_secs, 3600);
+        assert!(parse_downsample_rule("HKQuantityTypeIdentifierHeartRate").is_err());
+        assert!(parse_downsample_rule("X=0m").is_err());
+        assert!(parse_downsample_rule("X=1w").is_err());
+        assert!(parse_downsample_rule("=1m").is_err());
+    }
+}
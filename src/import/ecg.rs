@@ -4,9 +4,17 @@ use std::fs;
 use std::path::Path;
 use tracing::info;
 
-use crate::models::compute_hash;
+use super::xml::{clean_date, wall_clock};
+use crate::models::{compute_hash, ImportStats};
 
-pub fn import_ecg_files(conn: &Connection, ecg_dir: &Path, import_id: &str) -> Result<u64> {
+/// Import every ECG CSV in `ecg_dir`. Files that fail to parse are logged and
+/// counted in `stats.ecg_files_failed` rather than aborting the import.
+pub fn import_ecg_files(
+    conn: &Connection,
+    ecg_dir: &Path,
+    import_id: &str,
+    stats: &mut ImportStats,
+) -> Result<u64> {
     if !ecg_dir.exists() {
         info!("No electrocardiograms directory found, skipping ECG import");
         return Ok(0);
@@ -25,25 +33,37 @@ pub fn import_ecg_files(conn: &Connection, ecg_dir: &Path, import_id: &str) -> R
             Ok(_) => count += 1,
             Err(e) => {
                 tracing::warn!("Failed to import ECG file {:?}: {:?}", path, e);
+                stats.ecg_files_failed += 1;
             }
         }
     }
+    stats.ecg_readings += count;
 
-    info!("Imported {} ECG recordings", count);
+    info!(
+        "Imported {} ECG recordings ({} files failed)",
+        count, stats.ecg_files_failed
+    );
     Ok(count)
 }
 
 pub(crate) fn import_single_ecg(conn: &Connection, path: &Path, import_id: &str) -> Result<()> {
     let content = fs::read_to_string(path).context("Failed to read ECG file")?;
+    import_ecg_csv(conn, &content, import_id)
+}
+
+/// Parse the contents of one ECG CSV and insert the reading and its voltage samples.
+pub(crate) fn import_ecg_csv(conn: &Connection, content: &str, import_id: &str) -> Result<()> {
     let mut lines = content.lines();
 
     // Parse header fields
-    let mut recorded_date = String::new();
+    let mut raw_recorded_date = String::new();
     let mut classification = None;
     let mut device = None;
     let mut sample_rate_hz: Option<f64> = None;
     let mut symptoms = None;
     let mut software_version = None;
+    // First line after the header block; voltage parsing resumes from here
+    let mut first_data_line = None;
 
     // Header lines are "Key,Value" pairs
     for line in lines.by_ref() {
@@ -60,15 +80,10 @@ pub(crate) fn import_single_ecg(conn: &Connection, path: &Path, import_id: &str)
             // Skip DOB for privacy
             continue;
         } else if line.starts_with("Recorded Date,") {
-            let raw = line.strip_prefix("Recorded Date,").unwrap_or("");
-            // Strip timezone suffix " +0000"
-            recorded_date = if let Some(pos) = raw.rfind(" +") {
-                raw[..pos].to_string()
-            } else if let Some(pos) = raw.rfind(" -") {
-                raw[..pos].to_string()
-            } else {
-                raw.to_string()
-            };
+            raw_recorded_date = line
+                .strip_prefix("Recorded Date,")
+                .unwrap_or("")
+                .to_string();
         } else if line.starts_with("Classification,") {
             classification = Some(
                 line.strip_prefix("Classification,")
@@ -102,16 +117,20 @@ pub(crate) fn import_single_ecg(conn: &Connection, path: &Path, import_id: &str)
             continue;
         } else {
             // First non-header line - this should be voltage data
-            // We need to parse from here
+            first_data_line = Some(line);
             break;
         }
     }
 
-    if recorded_date.is_empty() {
+    if raw_recorded_date.is_empty() {
         anyhow::bail!("No recorded date found in ECG file");
     }
 
-    let ecg_hash = compute_hash(&[&recorded_date, device.as_deref().unwrap_or("")]);
+    let ecg_hash = compute_hash(&[
+        wall_clock(&raw_recorded_date),
+        device.as_deref().unwrap_or(""),
+    ]);
+    let recorded_date = clean_date(&raw_recorded_date);
 
     // Insert ECG reading using Appender
     {
@@ -125,16 +144,17 @@ pub(crate) fn import_single_ecg(conn: &Connection, path: &Path, import_id: &str)
             symptoms,
             software_version,
             import_id,
+            None::<String>,
+            None::<String>,
         ])?;
         appender.flush()?;
     }
 
-    // Parse voltage samples using Appender
-    let content = fs::read_to_string(path)?;
+    // Parse voltage samples using Appender, continuing from where the header ended
     let mut in_data = false;
     let mut sample_idx = 0i32;
     let mut appender = conn.appender("ecg_samples")?;
-    for line in content.lines() {
+    for line in first_data_line.into_iter().chain(lines) {
         let line = line.trim();
         if line.is_empty() {
             continue;
@@ -205,6 +225,39 @@ Unit,µV
         assert_eq!(classification, "Sinus Rhythm");
     }
 
+    #[test]
+    fn import_single_ecg_no_trailing_newline() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let ecg_path = dir.path().join("ecg_2024.csv");
+        assert!(!MINIMAL_ECG_CSV.ends_with('\n'));
+        std::fs::write(&ecg_path, MINIMAL_ECG_CSV).unwrap();
+
+        import_single_ecg(&conn, &ecg_path, "test_import").unwrap();
+
+        let voltages: Vec<f64> = conn
+            .prepare("SELECT voltage_uv FROM ecg_samples ORDER BY sample_idx")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(voltages, vec![100.0, 200.0, -50.0, 150.0, 75.0]);
+
+        // Name and DOB are never stored
+        let stored: String = conn
+            .query_row(
+                "SELECT CAST(ecg_readings AS VARCHAR) FROM ecg_readings",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(!stored.contains("Test User"));
+        assert!(!stored.contains("1990-01-01"));
+    }
+
     #[test]
     fn import_ecg_missing_date() {
         let conn = open_db_in_memory().unwrap();
@@ -226,7 +279,8 @@ Unit,µV
         ensure_schema(&conn).unwrap();
 
         let missing = std::path::PathBuf::from("/nonexistent/path/ecgs");
-        let count = import_ecg_files(&conn, &missing, "test").unwrap();
+        let mut stats = ImportStats::default();
+        let count = import_ecg_files(&conn, &missing, "test", &mut stats).unwrap();
         assert_eq!(count, 0);
     }
 
@@ -238,7 +292,25 @@ Unit,µV
         let dir = tempfile::tempdir().unwrap();
         std::fs::write(dir.path().join("ecg1.csv"), MINIMAL_ECG_CSV).unwrap();
 
-        let count = import_ecg_files(&conn, dir.path(), "test").unwrap();
+        let mut stats = ImportStats::default();
+        let count = import_ecg_files(&conn, dir.path(), "test", &mut stats).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(stats.ecg_readings, 1);
+        assert_eq!(stats.ecg_files_failed, 0);
+    }
+
+    #[test]
+    fn import_ecg_files_counts_failures() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("ecg1.csv"), MINIMAL_ECG_CSV).unwrap();
+        std::fs::write(dir.path().join("ecg2.csv"), "Name,Test\n\n100\n").unwrap();
+
+        let mut stats = ImportStats::default();
+        let count = import_ecg_files(&conn, dir.path(), "test", &mut stats).unwrap();
         assert_eq!(count, 1);
+        assert_eq!(stats.ecg_files_failed, 1);
     }
 }
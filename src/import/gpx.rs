@@ -1,19 +1,26 @@
 use anyhow::{Context, Result};
-use duckdb::Connection;
+use duckdb::{Connection, OptionalExt};
 use quick_xml::events::Event;
 use quick_xml::reader::Reader;
+use std::collections::HashMap;
 use std::fs;
-use std::io::BufReader;
+use std::io::{BufRead, BufReader};
 use std::path::Path;
-use tracing::info;
+use tracing::{info, warn};
 
-use crate::models::compute_hash;
+use crate::models::{compute_hash, ImportStats};
 
+/// Import every GPX file in `routes_dir`. Files that fail to parse are logged and
+/// counted in `stats.gpx_files_failed` rather than aborting the import. Routes whose
+/// timestamps fall outside their workout are reported by `check_route_window`, which
+/// errors instead with `strict_routes`.
 pub fn import_gpx_files(
     conn: &Connection,
     routes_dir: &Path,
     import_id: &str,
-    workout_route_map: &std::collections::HashMap<String, String>,
+    workout_route_map: &HashMap<String, String>,
+    strict_routes: bool,
+    stats: &mut ImportStats,
 ) -> Result<u64> {
     if !routes_dir.exists() {
         info!("No workout-routes directory found, skipping GPX import");
@@ -43,17 +50,77 @@ pub fn import_gpx_files(
         let workout_hash = workout_route_map.get(&route_key).cloned();
 
         match import_single_gpx(conn, &path, import_id, workout_hash.as_deref()) {
-            Ok(n) => total_points += n,
+            Ok(n) => {
+                total_points += n;
+                stats.gpx_files += 1;
+                if let Some(ref wh) = workout_hash {
+                    check_route_window(conn, &filename, wh, import_id, strict_routes, stats)?;
+                }
+            }
             Err(e) => {
                 tracing::warn!("Failed to import GPX file {:?}: {:?}", path, e);
+                stats.gpx_files_failed += 1;
             }
         }
     }
+    stats.route_points += total_points;
 
-    info!("Imported {} route points from GPX files", total_points);
+    info!(
+        "Imported {} route points from {} GPX files ({} failed)",
+        total_points, stats.gpx_files, stats.gpx_files_failed
+    );
     Ok(total_points)
 }
 
+/// How far a route may stray outside its workout's start/end before it is reported. Generous,
+/// because databases imported before export.xml dates were converted to UTC keep workouts in
+/// the device's local time while GPX times are UTC.
+const ROUTE_WINDOW_SLACK: &str = "INTERVAL 1 DAY";
+
+/// Compare the points `route` (a file name, for messages) just added for `workout_hash` with
+/// the workout's start and end. A route outside that window (plus `ROUTE_WINDOW_SLACK`) was
+/// probably linked to the wrong workout: it is logged and counted in
+/// `stats.routes_outside_workout`, or fails the import when `strict`.
+pub(crate) fn check_route_window(
+    conn: &Connection,
+    route: &str,
+    workout_hash: &str,
+    import_id: &str,
+    strict: bool,
+    stats: &mut ImportStats,
+) -> Result<()> {
+    let mismatch: Option<(String, String, String, String)> = conn
+        .query_row(
+            &format!(
+                "SELECT CAST(r.first AS VARCHAR), CAST(r.last AS VARCHAR),
+                        CAST(w.start_date AS VARCHAR), CAST(w.end_date AS VARCHAR)
+                 FROM (SELECT MIN(timestamp) AS first, MAX(timestamp) AS last FROM route_points
+                       WHERE workout_hash = $1 AND import_id = $2) r,
+                      (SELECT MIN(start_date) AS start_date, MAX(end_date) AS end_date
+                       FROM workouts WHERE workout_hash = $1) w
+                 WHERE r.first < w.start_date - {slack} OR r.last > w.end_date + {slack}",
+                slack = ROUTE_WINDOW_SLACK
+            ),
+            [workout_hash, import_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()?;
+    let Some((first, last, start, end)) = mismatch else {
+        return Ok(());
+    };
+
+    let message = format!(
+        "Route {} runs {} to {}, outside its workout {} ({} to {})",
+        route, first, last, workout_hash, start, end
+    );
+    if strict {
+        anyhow::bail!("{} (--strict-routes)", message);
+    }
+    warn!("{}", message);
+    stats.routes_outside_workout += 1;
+    Ok(())
+}
+
 fn attr_value(e: &quick_xml::events::BytesStart, name: &[u8]) -> Option<String> {
     e.attributes().filter_map(|a| a.ok()).find_map(|a| {
         if a.key.as_ref() == name {
@@ -64,6 +131,47 @@ fn attr_value(e: &quick_xml::events::BytesStart, name: &[u8]) -> Option<String>
     })
 }
 
+struct RoutePointRow {
+    point_hash: String,
+    latitude: f64,
+    longitude: f64,
+    elevation: Option<f64>,
+    timestamp: String,
+    speed: Option<f64>,
+    course: Option<f64>,
+    h_accuracy: Option<f64>,
+    v_accuracy: Option<f64>,
+}
+
+/// A `trkpt` child element whose text we keep.
+#[derive(Clone, Copy)]
+enum PointField {
+    Elevation,
+    Time,
+    Speed,
+    Course,
+    HorizontalAccuracy,
+    VerticalAccuracy,
+}
+
+impl PointField {
+    /// Map an element's local name (namespace prefix already stripped) to a field. The
+    /// Garmin TrackPointExtension schema calls the heading `bearing`.
+    fn from_local_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"ele" => Some(Self::Elevation),
+            b"time" => Some(Self::Time),
+            b"speed" => Some(Self::Speed),
+            b"course" | b"bearing" => Some(Self::Course),
+            b"hAcc" => Some(Self::HorizontalAccuracy),
+            b"vAcc" => Some(Self::VerticalAccuracy),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a single GPX file and append its track points. Points are buffered so a
+/// malformed file is rejected as a whole instead of leaving a partial route behind.
 pub(crate) fn import_single_gpx(
     conn: &Connection,
     path: &Path,
@@ -71,12 +179,21 @@ pub(crate) fn import_single_gpx(
     workout_hash: Option<&str>,
 ) -> Result<u64> {
     let file = fs::File::open(path).context("Failed to open GPX file")?;
-    let reader = BufReader::new(file);
+    import_gpx_reader(conn, BufReader::new(file), import_id, workout_hash)
+}
+
+/// Parse one GPX stream; see `import_single_gpx`.
+pub(crate) fn import_gpx_reader<R: BufRead>(
+    conn: &Connection,
+    reader: R,
+    import_id: &str,
+    workout_hash: Option<&str>,
+) -> Result<u64> {
     let mut xml = Reader::from_reader(reader);
     xml.config_mut().trim_text(true);
 
     let mut buf = Vec::new();
-    let mut count = 0u64;
+    let mut points: Vec<RoutePointRow> = Vec::new();
 
     let mut in_trkpt = false;
     let mut lat: Option<f64> = None;
@@ -87,9 +204,8 @@ pub(crate) fn import_single_gpx(
     let mut course: Option<f64> = None;
     let mut h_accuracy: Option<f64> = None;
     let mut v_accuracy: Option<f64> = None;
-    let mut current_tag: Option<String> = None;
-
-    let mut appender = conn.appender("route_points")?;
+    let mut current_tag: Option<PointField> = None;
+    let mut seen: HashMap<(String, String, String), u32> = HashMap::new();
 
     loop {
         match xml.read_event_into(&mut buf) {
@@ -108,23 +224,22 @@ pub(crate) fn import_single_gpx(
                         h_accuracy = None;
                         v_accuracy = None;
                     }
-                    b"ele" | b"time" | b"speed" | b"course" | b"hAcc" | b"vAcc" if in_trkpt => {
-                        current_tag = Some(String::from_utf8_lossy(local.as_ref()).to_string());
-                    }
+                    // Matched by local name at any depth, so Apple's `<extensions><speed>`
+                    // and Garmin's `<gpxtpx:TrackPointExtension><gpxtpx:speed>` both count.
+                    name if in_trkpt => current_tag = PointField::from_local_name(name),
                     _ => {}
                 }
             }
             Ok(Event::Text(ref t)) if in_trkpt => {
-                if let Some(ref tag) = current_tag {
-                    let text = t.unescape().unwrap_or_default().to_string();
-                    match tag.as_str() {
-                        "ele" => ele = text.parse().ok(),
-                        "time" => timestamp = Some(text),
-                        "speed" => speed = text.parse().ok(),
-                        "course" => course = text.parse().ok(),
-                        "hAcc" => h_accuracy = text.parse().ok(),
-                        "vAcc" => v_accuracy = text.parse().ok(),
-                        _ => {}
+                if let Some(field) = current_tag {
+                    let text = t.unescape().unwrap_or_default().trim().to_string();
+                    match field {
+                        PointField::Elevation => ele = text.parse().ok(),
+                        PointField::Time => timestamp = Some(text),
+                        PointField::Speed => speed = text.parse().ok(),
+                        PointField::Course => course = text.parse().ok(),
+                        PointField::HorizontalAccuracy => h_accuracy = text.parse().ok(),
+                        PointField::VerticalAccuracy => v_accuracy = text.parse().ok(),
                     }
                 }
             }
@@ -133,26 +248,31 @@ pub(crate) fn import_single_gpx(
                 if local.as_ref() == b"trkpt" && in_trkpt {
                     if let (Some(lat_v), Some(lon_v), Some(ref ts)) = (lat, lon, &timestamp) {
                         let wh = workout_hash.unwrap_or("");
-                        let point_hash =
-                            compute_hash(&[wh, ts, &lat_v.to_string(), &lon_v.to_string()]);
-
-                        // Clean timestamp for DuckDB (strip timezone suffix)
-                        let clean_ts = clean_timestamp(ts);
-
-                        appender.append_row(duckdb::params![
+                        let (lat_s, lon_s) = (lat_v.to_string(), lon_v.to_string());
+                        // Stationary GPS glitches repeat a point verbatim; number the repeats
+                        // so dedup keeps them. First occurrences keep the plain hash, so
+                        // re-importing routes stored before this still dedups.
+                        let repeat = seen
+                            .entry((ts.clone(), lat_s.clone(), lon_s.clone()))
+                            .and_modify(|n| *n += 1)
+                            .or_insert(0u32);
+                        let point_hash = match *repeat {
+                            0 => compute_hash(&[wh, ts, &lat_s, &lon_s]),
+                            n => compute_hash(&[wh, ts, &lat_s, &lon_s, &n.to_string()]),
+                        };
+
+                        points.push(RoutePointRow {
                             point_hash,
-                            workout_hash,
-                            lat_v,
-                            lon_v,
-                            ele,
-                            clean_ts,
+                            latitude: lat_v,
+                            longitude: lon_v,
+                            elevation: ele,
+                            // Clean timestamp for DuckDB (strip timezone suffix)
+                            timestamp: clean_timestamp(ts),
                             speed,
                             course,
                             h_accuracy,
                             v_accuracy,
-                            import_id,
-                        ])?;
-                        count += 1;
+                        });
                     }
                     in_trkpt = false;
                 }
@@ -163,14 +283,31 @@ pub(crate) fn import_single_gpx(
             }
             Ok(_) => {}
             Err(e) => {
-                tracing::warn!("GPX parse error: {:?}", e);
+                return Err(e).context("Malformed GPX file");
             }
         }
         buf.clear();
     }
+
+    let mut appender = conn.appender("route_points")?;
+    for p in &points {
+        appender.append_row(duckdb::params![
+            p.point_hash,
+            workout_hash,
+            p.latitude,
+            p.longitude,
+            p.elevation,
+            p.timestamp,
+            p.speed,
+            p.course,
+            p.h_accuracy,
+            p.v_accuracy,
+            import_id,
+        ])?;
+    }
     appender.flush()?;
 
-    Ok(count)
+    Ok(points.len() as u64)
 }
 
 /// Strip timezone info from GPX timestamps for DuckDB TIMESTAMP compatibility.
@@ -197,8 +334,7 @@ pub(crate) fn clean_timestamp(ts: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::{ensure_schema, open_db_in_memory};
-    use std::collections::HashMap;
+    use crate::db::{deduplicate_tables, ensure_schema, open_db_in_memory};
 
     #[test]
     fn clean_timestamp_z_suffix() {
@@ -287,6 +423,88 @@ mod tests {
         assert_eq!(wh, "workout_hash_1");
     }
 
+    #[test]
+    fn import_gpx_speed_and_course_from_extensions() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+
+        // Apple writes speed/course/accuracy inside <extensions>; other tools use the
+        // Garmin TrackPointExtension namespace.
+        let gpx = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="Apple Health Export" xmlns="http://www.topografix.com/GPX/1/1"
+     xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v2">
+  <metadata><time>2024-01-01T09:59:00Z</time></metadata>
+  <trk><name>Route 2024-01-01 10:00am</name><trkseg>
+    <trkpt lon="-122.419400" lat="37.774900"><ele>10.5</ele><time>2024-01-01T10:00:00Z</time><extensions><speed>2.47</speed><course>143.6</course><hAcc>2.3</hAcc><vAcc>1.7</vAcc></extensions></trkpt>
+    <trkpt lon="-122.419500" lat="37.775000"><ele>11.0</ele><time>2024-01-01T10:00:05Z</time><extensions><gpxtpx:TrackPointExtension><gpxtpx:speed>2.9</gpxtpx:speed><gpxtpx:bearing>150.0</gpxtpx:bearing></gpxtpx:TrackPointExtension></extensions></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+
+        let count = import_gpx_reader(&conn, gpx.as_bytes(), "test_import", Some("workout_hash_1"))
+            .unwrap();
+        assert_eq!(count, 2);
+
+        // speed, course, hAcc, vAcc
+        type Extensions = (Option<f64>, Option<f64>, Option<f64>, Option<f64>);
+        let rows: Vec<Extensions> = conn
+            .prepare(
+                "SELECT speed, course, h_accuracy, v_accuracy FROM route_points ORDER BY timestamp",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (Some(2.47), Some(143.6), Some(2.3), Some(1.7)),
+                (Some(2.9), Some(150.0), None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn import_gpx_keeps_repeated_points() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+
+        let gpx = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx xmlns="http://www.topografix.com/GPX/1/1" version="1.1">
+  <trk><trkseg>
+    <trkpt lat="37.0" lon="-122.0"><time>2024-01-01T10:00:00Z</time></trkpt>
+    <trkpt lat="37.0" lon="-122.0"><time>2024-01-01T10:00:00Z</time></trkpt>
+    <trkpt lat="37.1" lon="-122.1"><time>2024-01-01T10:00:05Z</time></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+
+        for import_id in ["imp1", "imp2"] {
+            let count = import_gpx_reader(&conn, gpx.as_bytes(), import_id, Some("workout_hash_1"))
+                .unwrap();
+            assert_eq!(count, 3);
+        }
+        deduplicate_tables(&conn).unwrap();
+
+        // Both copies of the stationary point survive, and a re-import still dedups
+        let db_count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM route_points", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(db_count, 3);
+        let first_hash: String = conn
+            .query_row(
+                "SELECT point_hash FROM route_points WHERE timestamp = '2024-01-01 10:00:05'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(
+            first_hash,
+            compute_hash(&["workout_hash_1", "2024-01-01T10:00:05Z", "37.1", "-122.1"])
+        );
+    }
+
     #[test]
     fn import_gpx_files_missing_dir() {
         let conn = open_db_in_memory().unwrap();
@@ -294,10 +512,96 @@ mod tests {
 
         let missing = std::path::PathBuf::from("/nonexistent/path/routes");
         let map = HashMap::new();
-        let count = import_gpx_files(&conn, &missing, "test", &map).unwrap();
+        let mut stats = ImportStats::default();
+        let count = import_gpx_files(&conn, &missing, "test", &map, false, &mut stats).unwrap();
         assert_eq!(count, 0);
     }
 
+    #[test]
+    fn import_gpx_files_counts_failures() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+
+        let valid = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx xmlns="http://www.topografix.com/GPX/1/1" version="1.1">
+  <trk><trkseg>
+    <trkpt lat="37.0" lon="-122.0"><time>2024-01-01T10:00:00Z</time></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+        let corrupt = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx xmlns="http://www.topografix.com/GPX/1/1" version="1.1">
+  <trk><trkseg>
+    <trkpt lat="38.0" lon="-121.0"><time>2024-01-01T11:00:00Z</time></trkpt>
+    <trkpt lat="38.1" lon="-121.1"><time>2024-01-01T11:00:05Z</trkpt>
+  </trkseg>"#;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a_valid.gpx"), valid).unwrap();
+        std::fs::write(dir.path().join("b_corrupt.gpx"), corrupt).unwrap();
+
+        let mut stats = ImportStats::default();
+        let count = import_gpx_files(
+            &conn,
+            dir.path(),
+            "test",
+            &HashMap::new(),
+            false,
+            &mut stats,
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(stats.gpx_files, 1);
+        assert_eq!(stats.gpx_files_failed, 1);
+        assert_eq!(stats.route_points, 1);
+
+        // Nothing from the corrupt file should have been appended
+        let db_count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM route_points", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(db_count, 1);
+    }
+
+    #[test]
+    fn import_gpx_files_flags_routes_outside_workout() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO workouts (workout_hash, activity_type, start_date, end_date, import_id)
+             VALUES ('wh_ok', 'HKWorkoutActivityTypeRunning', '2024-01-01 10:00:00', '2024-01-01 10:30:00', 'test'),
+                    ('wh_bad', 'HKWorkoutActivityTypeRunning', '2024-01-01 10:00:00', '2024-01-01 10:30:00', 'test');",
+        )
+        .unwrap();
+
+        let route = |time: &str| {
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx xmlns="http://www.topografix.com/GPX/1/1" version="1.1">
+  <trk><trkseg>
+    <trkpt lat="37.0" lon="-122.0"><time>{}</time></trkpt>
+  </trkseg></trk>
+</gpx>"#,
+                time
+            )
+        };
+        let dir = tempfile::tempdir().unwrap();
+        // Seven hours off is just the local-time/UTC difference
+        std::fs::write(dir.path().join("ok.gpx"), route("2024-01-01T17:10:00Z")).unwrap();
+        std::fs::write(dir.path().join("bad.gpx"), route("2024-03-01T10:10:00Z")).unwrap();
+        let map: HashMap<String, String> = [
+            ("/workout-routes/ok.gpx".to_string(), "wh_ok".to_string()),
+            ("/workout-routes/bad.gpx".to_string(), "wh_bad".to_string()),
+        ]
+        .into();
+
+        let mut stats = ImportStats::default();
+        let count = import_gpx_files(&conn, dir.path(), "test", &map, false, &mut stats).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(stats.routes_outside_workout, 1);
+
+        let err = import_gpx_files(&conn, dir.path(), "test", &map, true, &mut stats).unwrap_err();
+        assert!(err.to_string().contains("bad.gpx"), "{}", err);
+    }
+
     #[test]
     fn import_gpx_no_workout_hash() {
         let conn = open_db_in_memory().unwrap();
@@ -1,68 +1,225 @@
+pub mod aliases;
+pub mod archive;
+pub mod cda;
+pub mod downsample;
 pub mod ecg;
 pub mod gpx;
+pub mod watch;
 pub mod xml;
 
-use anyhow::Result;
-use std::collections::HashMap;
-use std::path::Path;
-use tracing::info;
+use anyhow::{Context, Result};
+use duckdb::{Connection, OptionalExt};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
 
-use crate::db::{deduplicate_tables, ensure_schema, open_db, rebuild_daily_stats};
+use crate::db::{
+    compact_db, deduplicate_tables, ensure_schema, migrate, normalize_units, open_db_in_memory,
+    open_db_with_options, rebuild_daily_stats_in, stored_timezone, DbOptions, BASE_TABLES,
+};
+use crate::models::ImportStats;
+use crate::timezone::DayTimezone;
+use downsample::DownsampleRule;
 
-pub fn run_import(export_dir: &Path, db_path: &Path) -> Result<()> {
-    let start = std::time::Instant::now();
-    let import_id = format!("import_{}", chrono::Utc::now().format("%Y%m%d_%H%M%S"));
+/// Options for `import` beyond the source and destination paths.
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    /// Record types to store at reduced resolution (`--downsample TYPE=INTERVAL`).
+    pub downsample: Vec<DownsampleRule>,
+    /// DuckDB thread count and memory limit for the import connection.
+    pub db_options: DbOptions,
+    /// Threads parsing export.xml (`--parse-threads`); 0 or 1 parses on the importing thread.
+    pub parse_threads: usize,
+    /// Rows buffered per Appender flush while parsing export.xml (`--batch-size`); `None`
+    /// uses `xml::DEFAULT_BATCH_SIZE`.
+    pub batch_size: Option<NonZeroUsize>,
+    /// Parse into a throwaway in-memory database and report counts (`--dry-run`); the
+    /// database at `db_path` is never opened or created.
+    pub dry_run: bool,
+    /// Fail the import when a GPX route's timestamps fall outside its workout
+    /// (`--strict-routes`) instead of only warning.
+    pub strict_routes: bool,
+    /// Write the final `ImportStats`, import id, duration, and per-table row counts here as
+    /// JSON (`--summary-json`).
+    pub summary_json: Option<PathBuf>,
+    /// `PATTERN=CANONICAL` file stored in `source_aliases` (`--source-aliases`).
+    pub source_aliases: Option<PathBuf>,
+    /// Timezone whose calendar days `daily_record_stats` uses (`--timezone`); `None` keeps
+    /// the one an earlier import was given, or UTC.
+    pub timezone: Option<DayTimezone>,
+    /// Commit export.xml batch by batch with a resume point in `import_progress`, and continue
+    /// an interrupted checkpointed import of the same directory instead of starting over
+    /// (`--checkpoint`). Single export directory only; parses on one thread.
+    pub checkpoint: bool,
+}
 
-    info!("Starting import {} from {:?}", import_id, export_dir);
+impl ImportOptions {
+    fn batch_size(&self) -> usize {
+        self.batch_size
+            .map_or(xml::DEFAULT_BATCH_SIZE, NonZeroUsize::get)
+    }
+}
 
-    let conn = open_db(db_path)?;
+/// Import an export directory or archive into the database at `db_path`, returning what the
+/// parsers loaded. With `options.dry_run`, stops after deduplication and fills
+/// `ImportStats::record_types` instead of writing anything.
+pub fn run_import(
+    export_dir: &Path,
+    db_path: &Path,
+    options: &ImportOptions,
+) -> Result<ImportStats> {
+    run_import_dirs(&[export_dir.to_path_buf()], db_path, options)
+}
+
+/// Like `run_import`, but merges several export directories or archives (family members,
+/// multiple devices) in one go: each is parsed under its own import id and gets its own
+/// `imports` row, and deduplication and the daily statistics run once at the end. Returns
+/// the summed stats.
+pub fn run_import_dirs(
+    export_dirs: &[PathBuf],
+    db_path: &Path,
+    options: &ImportOptions,
+) -> Result<ImportStats> {
+    if export_dirs.is_empty() {
+        anyhow::bail!("No export directory given");
+    }
+    if options.checkpoint
+        && (export_dirs.len() > 1 || options.dry_run || archive::is_archive(&export_dirs[0]))
+    {
+        anyhow::bail!(
+            "--checkpoint needs a single unpacked export directory and can't be combined with --dry-run"
+        );
+    }
+    let start = std::time::Instant::now();
+
+    let conn = if options.dry_run {
+        info!("Dry run: importing into a throwaway in-memory database");
+        open_db_in_memory()?
+    } else {
+        open_db_with_options(db_path, &options.db_options)?
+    };
     ensure_schema(&conn)?;
+    migrate(&conn)?;
 
-    // Phase 1: Parse export.xml
-    info!("Phase 1: Parsing export.xml...");
-    let xml_path = export_dir.join("export.xml");
-    let stats = xml::import_xml(&conn, &xml_path, &import_id)?;
+    let base_id = format!("import_{}", chrono::Utc::now().format("%Y%m%d_%H%M%S"));
+    let import_ids: Vec<String> = if options.checkpoint {
+        vec![checkpoint_import_id(&conn, &export_dirs[0], &base_id)?]
+    } else if export_dirs.len() == 1 {
+        vec![base_id]
+    } else {
+        (1..=export_dirs.len())
+            .map(|n| format!("{}_{}", base_id, n))
+            .collect()
+    };
 
-    // Build workout route map from the XML data
-    // We need to query the workouts and their associated route files
-    // The XML parser stores route file references — we'll build the map from the DB
-    // For now, we'll build it by re-scanning the XML for WorkoutRoute → FileReference mappings
-    let workout_route_map = build_workout_route_map(&conn, &xml_path)?;
+    // With --checkpoint, export.xml is committed batch by batch ahead of the transaction below,
+    // so an interrupted import keeps what it loaded and the next run skips it
+    let mut checkpointed = None;
+    if options.checkpoint {
+        info!("Phase 1: Parsing export.xml (checkpointed)...");
+        checkpointed = Some(xml::import_xml_checkpointed(
+            &conn,
+            &export_dirs[0].join("export.xml"),
+            &import_ids[0],
+            options.batch_size(),
+        )?);
+    }
 
-    // Phase 2: Parse ECG files
-    info!("Phase 2: Parsing ECG files...");
-    let ecg_count =
-        ecg::import_ecg_files(&conn, &export_dir.join("electrocardiograms"), &import_id)?;
+    // Phases 1-6 run in one transaction: a failed or killed import leaves the database as it
+    // was, and the imports rows only become visible once every phase has succeeded.
+    // Returning early drops `tx`, which rolls back.
+    let tx = conn.unchecked_transaction()?;
 
-    // Phase 3: Parse GPX routes
-    info!("Phase 3: Parsing GPX route files...");
-    let route_points = gpx::import_gpx_files(
-        &conn,
-        &export_dir.join("workout-routes"),
-        &import_id,
-        &workout_route_map,
-    )?;
+    let mut stats = ImportStats::default();
+    let mut dir_stats = Vec::with_capacity(export_dirs.len());
+    for (export_dir, import_id) in export_dirs.iter().zip(&import_ids) {
+        info!("Starting import {} from {:?}", import_id, export_dir);
+
+        // Phases 1-3: export.xml (and export_cda.xml), ECG files, and GPX routes
+        let dir = if archive::is_archive(export_dir) {
+            archive::import_archive(&tx, export_dir, import_id, options)?
+        } else {
+            import_export_dir(&tx, export_dir, import_id, options, checkpointed.take())?
+        };
+        if dir.records == 0 && dir.workouts == 0 {
+            warn!(
+                "No records or workouts found in export.xml; check that {} is a complete Apple Health export",
+                export_dir.display()
+            );
+        }
+
+        if !options.downsample.is_empty() {
+            info!("Downsampling high-frequency records...");
+            downsample::downsample_records(&tx, import_id, &options.downsample)?;
+        }
+
+        add_stats(&mut stats, &dir);
+        dir_stats.push((dir.records, dir.workouts));
+    }
 
-    // Phase 4: Deduplicate tables
-    info!("Phase 4: Deduplicating tables...");
-    deduplicate_tables(&conn)?;
+    if let Some(ref path) = options.source_aliases {
+        aliases::load_source_aliases(&tx, path)?;
+    }
+
+    // Phase 4: Deduplicate tables and fill canonical-unit columns
+    info!("Phase 4: Deduplicating tables and normalizing units...");
+    deduplicate_tables(&tx)?;
+    normalize_units(&tx)?;
+
+    if options.dry_run {
+        let mut stmt = tx.prepare(
+            "SELECT record_type, COUNT(*) FROM records GROUP BY record_type ORDER BY 2 DESC, 1",
+        )?;
+        stats.record_types = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))?
+            .collect::<std::result::Result<_, _>>()?;
+        if let Some(ref path) = options.summary_json {
+            let duration = start.elapsed().as_secs_f64();
+            write_summary(&tx, path, &import_ids, duration, &stats)?;
+        }
+        return Ok(stats);
+    }
 
     // Phase 5: Rebuild aggregation tables
     info!("Phase 5: Building daily statistics...");
-    rebuild_daily_stats(&conn)?;
+    let timezone = match options.timezone {
+        Some(timezone) => Some(timezone),
+        None => stored_timezone(&tx)?,
+    };
+    if let Some(ref timezone) = timezone {
+        info!("Splitting days in timezone {}", timezone);
+    }
+    rebuild_daily_stats_in(&tx, timezone.as_ref())?;
 
-    // Phase 6: Log import metadata
+    // Phase 6: Log import metadata, one row per export directory
     let duration = start.elapsed();
-    conn.execute(
-        "INSERT INTO imports (import_id, export_dir, record_count, workout_count, duration_secs) VALUES (?, ?, ?, ?, ?)",
-        duckdb::params![
-            import_id,
-            export_dir.to_string_lossy().to_string(),
-            stats.records as i64,
-            stats.workouts as i64,
-            duration.as_secs_f64(),
-        ],
-    )?;
+    for ((export_dir, import_id), (records, workouts)) in
+        export_dirs.iter().zip(&import_ids).zip(dir_stats)
+    {
+        tx.execute(
+            "INSERT INTO imports (import_id, export_dir, record_count, workout_count, duration_secs, timezone) VALUES (?, ?, ?, ?, ?, ?)",
+            duckdb::params![
+                import_id,
+                export_dir.to_string_lossy().to_string(),
+                records as i64,
+                workouts as i64,
+                duration.as_secs_f64(),
+                timezone.map(|tz| tz.to_string()),
+            ],
+        )?;
+        tx.execute(
+            "DELETE FROM import_progress WHERE import_id = ?",
+            [import_id],
+        )?;
+    }
+    tx.commit()?;
+
+    // Phase 7: Reclaim space left behind by deduplication
+    compact_db(&conn)?;
+
+    if let Some(ref path) = options.summary_json {
+        write_summary(&conn, path, &import_ids, duration.as_secs_f64(), &stats)?;
+    }
 
     info!("Import complete in {:.1}s", duration.as_secs_f64());
     info!(
@@ -71,87 +228,164 @@ pub fn run_import(export_dir: &Path, db_path: &Path) -> Result<()> {
     );
     info!(
         "  ECG readings: {}, Route points: {}, Metadata entries: {}",
-        ecg_count, route_points, stats.metadata_entries
+        stats.ecg_readings, stats.route_points, stats.metadata_entries
     );
+    if stats.clinical_records > 0 {
+        info!("  Clinical records: {}", stats.clinical_records);
+    }
+    if stats.ecg_files_failed > 0 || stats.gpx_files_failed > 0 {
+        warn!(
+            "  Failed files: {} ECG, {} GPX (see warnings above)",
+            stats.ecg_files_failed, stats.gpx_files_failed
+        );
+    }
+    if stats.routes_outside_workout > 0 {
+        warn!(
+            "  Routes outside their workout: {} (see warnings above)",
+            stats.routes_outside_workout
+        );
+    }
 
-    Ok(())
+    Ok(stats)
+}
+
+/// Parse an unpacked `apple_health_export` directory. `xml_stats` is given when export.xml
+/// was already loaded (`--checkpoint`), and phase 1 is skipped.
+fn import_export_dir(
+    conn: &Connection,
+    export_dir: &Path,
+    import_id: &str,
+    options: &ImportOptions,
+    xml_stats: Option<ImportStats>,
+) -> Result<ImportStats> {
+    // Phase 1: Parse export.xml
+    let mut stats = match xml_stats {
+        Some(stats) => stats,
+        None => {
+            info!("Phase 1: Parsing export.xml...");
+            xml::import_xml_parallel(
+                conn,
+                &export_dir.join("export.xml"),
+                import_id,
+                options.parse_threads,
+                options.batch_size(),
+            )?
+        }
+    };
+    let workout_route_map = std::mem::take(&mut stats.workout_routes);
+    stats.clinical_records = cda::import_cda(conn, &export_dir.join("export_cda.xml"), import_id)?;
+
+    // Phase 2: Parse ECG files
+    info!("Phase 2: Parsing ECG files...");
+    ecg::import_ecg_files(
+        conn,
+        &export_dir.join("electrocardiograms"),
+        import_id,
+        &mut stats,
+    )?;
+
+    // Phase 3: Parse GPX routes
+    info!("Phase 3: Parsing GPX route files...");
+    gpx::import_gpx_files(
+        conn,
+        &export_dir.join("workout-routes"),
+        import_id,
+        &workout_route_map,
+        options.strict_routes,
+        &mut stats,
+    )?;
+
+    Ok(stats)
+}
+
+/// Import id for a `--checkpoint` import of `export_dir`: that of an earlier one that never
+/// finished (it has a resume point but no `imports` row), or else `new_id`, registered in
+/// `import_progress`.
+fn checkpoint_import_id(conn: &Connection, export_dir: &Path, new_id: &str) -> Result<String> {
+    let export_dir = export_dir.to_string_lossy().to_string();
+    let unfinished: Option<String> = conn
+        .query_row(
+            "SELECT import_id FROM import_progress p \
+             WHERE export_dir = ? \
+               AND NOT EXISTS (SELECT 1 FROM imports i WHERE i.import_id = p.import_id) \
+             ORDER BY updated_at DESC LIMIT 1",
+            [&export_dir],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match unfinished {
+        Some(import_id) => {
+            info!("Resuming interrupted import {}", import_id);
+            Ok(import_id)
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO import_progress (import_id, export_dir) VALUES (?, ?)",
+                [new_id, export_dir.as_str()],
+            )?;
+            Ok(new_id.to_string())
+        }
+    }
+}
+
+/// Add one export directory's counts to the running total of a multi-directory import.
+fn add_stats(total: &mut ImportStats, dir: &ImportStats) {
+    total.records += dir.records;
+    total.workouts += dir.workouts;
+    total.activity_summaries += dir.activity_summaries;
+    total.correlations += dir.correlations;
+    total.ecg_readings += dir.ecg_readings;
+    total.ecg_files_failed += dir.ecg_files_failed;
+    total.route_points += dir.route_points;
+    total.gpx_files += dir.gpx_files;
+    total.gpx_files_failed += dir.gpx_files_failed;
+    total.routes_outside_workout += dir.routes_outside_workout;
+    total.metadata_entries += dir.metadata_entries;
+    total.audiogram_points += dir.audiogram_points;
+    total.workout_metadata_entries += dir.workout_metadata_entries;
+    total.workout_events += dir.workout_events;
+    total.workout_statistics += dir.workout_statistics;
+    total.clinical_records += dir.clinical_records;
 }
 
-/// Build a map from route file path to workout hash by re-scanning the XML
-/// for Workout elements that contain WorkoutRoute > FileReference children.
-fn build_workout_route_map(
-    _conn: &duckdb::Connection,
-    xml_path: &Path,
-) -> Result<HashMap<String, String>> {
-    use quick_xml::events::Event;
-    use quick_xml::reader::Reader;
-    use std::io::BufReader;
-
-    let mut map = HashMap::new();
-    let file = std::fs::File::open(xml_path)?;
-    let reader = BufReader::with_capacity(4 * 1024 * 1024, file);
-    let mut xml = Reader::from_reader(reader);
-    xml.config_mut().trim_text(true);
-    let mut buf = Vec::new();
-
-    let mut in_workout = false;
-    let mut current_workout_hash: Option<String> = None;
-
-    fn attr_val(e: &quick_xml::events::BytesStart, name: &[u8]) -> Option<String> {
-        e.attributes().filter_map(|a| a.ok()).find_map(|a| {
-            if a.key.as_ref() == name {
-                String::from_utf8(a.value.to_vec()).ok()
-            } else {
-                None
-            }
-        })
-    }
-
-    loop {
-        match xml.read_event_into(&mut buf) {
-            Ok(Event::Eof) => break,
-            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e)) => {
-                let local = e.name().as_ref().to_vec();
-                match local.as_slice() {
-                    b"Workout" => {
-                        in_workout = true;
-                        let activity_type = attr_val(e, b"workoutActivityType").unwrap_or_default();
-                        let source_name = attr_val(e, b"sourceName").unwrap_or_default();
-                        let start_date = attr_val(e, b"startDate").unwrap_or_default();
-                        let end_date = attr_val(e, b"endDate").unwrap_or_default();
-                        let duration_str = attr_val(e, b"duration");
-
-                        let hash = crate::models::compute_hash(&[
-                            &activity_type,
-                            &source_name,
-                            &start_date,
-                            &end_date,
-                            duration_str.as_deref().unwrap_or(""),
-                        ]);
-                        current_workout_hash = Some(hash);
-                    }
-                    b"FileReference" if in_workout => {
-                        if let (Some(ref wh), Some(path)) =
-                            (&current_workout_hash, attr_val(e, b"path"))
-                        {
-                            map.insert(path, wh.clone());
-                        }
-                    }
-                    _ => {}
-                }
-            }
-            Ok(Event::End(ref e)) => {
-                if e.name().as_ref() == b"Workout" {
-                    in_workout = false;
-                    current_workout_hash = None;
-                }
-            }
-            Ok(_) => {}
-            Err(_) => {}
+/// `--summary-json`: `stats` plus `import_id`, `duration_secs`, and `table_rows` (row count of
+/// every table in `BASE_TABLES` that exists), for scripts wrapping the CLI. A multi-directory
+/// import lists its ids as `import_ids` instead of `import_id`.
+fn write_summary(
+    conn: &Connection,
+    path: &Path,
+    import_ids: &[String],
+    duration_secs: f64,
+    stats: &ImportStats,
+) -> Result<()> {
+    let mut table_rows = serde_json::Map::new();
+    for table in BASE_TABLES {
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM information_schema.tables \
+             WHERE table_schema = 'main' AND table_name = ?",
+            [table],
+            |row| row.get(0),
+        )?;
+        if exists {
+            let count: i64 =
+                conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                    row.get(0)
+                })?;
+            table_rows.insert(table.to_string(), count.into());
         }
-        buf.clear();
     }
 
-    info!("Built workout route map: {} entries", map.len());
-    Ok(map)
+    let mut summary = serde_json::to_value(stats)?;
+    match import_ids {
+        [import_id] => summary["import_id"] = import_id.as_str().into(),
+        _ => summary["import_ids"] = import_ids.into(),
+    }
+    summary["duration_secs"] = duration_secs.into();
+    summary["table_rows"] = table_rows.into();
+
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to create summary file {}", path.display()))?;
+    serde_json::to_writer_pretty(file, &summary)?;
+    info!("Wrote import summary to {}", path.display());
+    Ok(())
 }
@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use notify::event::{EventKind, ModifyKind};
+use notify::{RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use super::{run_import, ImportOptions};
+use crate::models::ImportStats;
+
+/// How long the directory must be quiet after a change before reimporting (`--debounce-secs`).
+/// Copying a multi-gigabyte export.xml produces a stream of write events; this waits them out.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// Tracks whether `export.xml` changed since the last reimport, and runs it once the
+/// filesystem has settled.
+pub struct ReimportHandler {
+    export_dir: PathBuf,
+    db_path: PathBuf,
+    options: ImportOptions,
+    pending: bool,
+}
+
+impl ReimportHandler {
+    pub fn new(export_dir: &Path, db_path: &Path, options: &ImportOptions) -> Self {
+        Self {
+            export_dir: export_dir.to_path_buf(),
+            db_path: db_path.to_path_buf(),
+            options: options.clone(),
+            pending: false,
+        }
+    }
+
+    /// Note a filesystem event. Returns whether it created or changed `export.xml`, in which
+    /// case the next `on_quiet` reimports.
+    pub fn on_event(&mut self, event: &notify::Event) -> bool {
+        let changed = is_export_change(event);
+        self.pending |= changed;
+        changed
+    }
+
+    /// Called once no events have arrived for the debounce period: reimport if `export.xml`
+    /// changed since the last call. Returns the import's stats, or `None` if nothing changed.
+    pub fn on_quiet(&mut self) -> Result<Option<ImportStats>> {
+        if !std::mem::take(&mut self.pending) {
+            return Ok(None);
+        }
+        info!("export.xml changed, reimporting {:?}", self.export_dir);
+        let stats = run_import(&self.export_dir, &self.db_path, &self.options)?;
+        info!(
+            "Reimported into {}: {} records, {} workouts, {} activity summaries",
+            self.db_path.display(),
+            stats.records,
+            stats.workouts,
+            stats.activity_summaries
+        );
+        Ok(Some(stats))
+    }
+}
+
+/// Whether `event` creates, writes, or renames a file named `export.xml`. Reads and metadata
+/// changes are ignored, so the import reading the file doesn't trigger another one.
+fn is_export_change(event: &notify::Event) -> bool {
+    let kind_matches = match event.kind {
+        EventKind::Create(_) => true,
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        EventKind::Modify(_) => true,
+        _ => false,
+    };
+    kind_matches
+        && event
+            .paths
+            .iter()
+            .any(|p| p.file_name().is_some_and(|name| name == "export.xml"))
+}
+
+/// Watch `export_dir` and reimport into `db_path` each time `export.xml` is created or changed
+/// and then left alone for `debounce`. Runs until the process is stopped; failed reimports are
+/// logged and the watch continues.
+///
+/// Each reimport is an ordinary `run_import` with its own read-write connection, closed when
+/// it finishes, so nothing is shared with a `serve` process. While `serve` holds the file,
+/// DuckDB's lock makes the reimport fail instead of writing under it, and as the import runs
+/// in one transaction, stopping the watcher mid-import leaves the database as it was.
+pub fn watch_export_dir(
+    export_dir: &Path,
+    db_path: &Path,
+    options: &ImportOptions,
+    debounce: Duration,
+) -> Result<()> {
+    if !export_dir.is_dir() {
+        anyhow::bail!(
+            "{} is not a directory; watch needs an unpacked Apple Health export directory",
+            export_dir.display()
+        );
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).context("Failed to start file watcher")?;
+    watcher
+        .watch(export_dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("Failed to watch {}", export_dir.display()))?;
+    info!(
+        "Watching {:?} for export.xml changes (debounce {}s)",
+        export_dir,
+        debounce.as_secs_f64()
+    );
+
+    let mut handler = ReimportHandler::new(export_dir, db_path, options);
+    loop {
+        match rx.recv_timeout(debounce) {
+            Ok(Ok(event)) => {
+                handler.on_event(&event);
+            }
+            Ok(Err(e)) => warn!("File watcher error: {}", e),
+            Err(RecvTimeoutError::Timeout) => {
+                if let Err(e) = handler.on_quiet() {
+                    warn!("Reimport failed, will retry on the next change: {:#}", e);
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
@@ -1,14 +1,22 @@
 use anyhow::{Context, Result};
-use duckdb::Connection;
+use duckdb::{Connection, OptionalExt};
 use quick_xml::events::Event;
 use quick_xml::reader::Reader;
-use std::io::BufReader;
+use std::io::{BufRead, BufReader};
 use std::path::Path;
+use std::sync::{mpsc, Mutex};
 use tracing::info;
 
 use crate::models::{compute_hash, ImportStats};
+use crate::timezone::DayTimezone;
 
-const BATCH_SIZE: usize = 100_000;
+/// Rows buffered before each Appender flush unless `--batch-size` says otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 100_000;
+
+/// Approximate size of the pieces of export.xml handed to parse workers.
+const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+const PROGRESS_INTERVAL: u64 = 500_000;
 
 fn attr_value(e: &quick_xml::events::BytesStart, name: &[u8]) -> Option<String> {
     e.attributes().filter_map(|a| a.ok()).find_map(|a| {
@@ -24,82 +32,726 @@ fn parse_opt_f64(s: &Option<String>) -> Option<f64> {
     s.as_ref().and_then(|v| v.parse::<f64>().ok())
 }
 
-/// Strip timezone suffix like " +0000" from Apple Health date strings
-/// so DuckDB can parse them as plain TIMESTAMP (UTC assumed).
-fn clean_date(s: &str) -> String {
-    // "2020-06-20 16:56:44 +0000" -> "2020-06-20 16:56:44"
-    if let Some(pos) = s.rfind(" +") {
-        s[..pos].to_string()
-    } else if let Some(pos) = s.rfind(" -") {
-        s[..pos].to_string()
-    } else {
-        s.to_string()
+/// Split an Apple Health date string into its wall-clock part and its UTC offset suffix.
+fn split_offset(s: &str) -> (&str, Option<&str>) {
+    // "2020-06-20 16:56:44 -0500" -> ("2020-06-20 16:56:44", Some("-0500"))
+    match s.rfind(" +").or_else(|| s.rfind(" -")) {
+        Some(pos) => (&s[..pos], Some(&s[pos + 1..])),
+        None => (s, None),
     }
 }
 
+/// The device-local wall-clock time of an Apple Health date string, without its offset.
+/// Record, workout, and ECG hashes are built from it, so they stay the same as in databases
+/// imported before dates were converted to UTC.
+pub(crate) fn wall_clock(s: &str) -> &str {
+    split_offset(s).0
+}
+
+/// Convert an Apple Health date string to UTC using its offset suffix, so DuckDB can parse it
+/// as a plain TIMESTAMP and every stored time is UTC. A missing or unparseable offset leaves
+/// the wall-clock time as is.
+pub(crate) fn clean_date(s: &str) -> String {
+    // "2020-06-20 16:56:44 -0500" -> "2020-06-20 21:56:44"
+    let (local, offset) = split_offset(s);
+    let offset = match offset.map(DayTimezone::parse) {
+        Some(Ok(DayTimezone::Fixed(secs))) if secs != 0 => secs,
+        _ => return local.to_string(),
+    };
+    chrono::NaiveDateTime::parse_from_str(local, "%Y-%m-%d %H:%M:%S")
+        .map(|local| {
+            (local - chrono::Duration::seconds(offset.into()))
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_else(|_| local.to_string())
+}
+
 fn clean_date_opt(s: &Option<String>) -> Option<String> {
     s.as_ref().map(|v| clean_date(v))
 }
 
-pub fn import_xml(conn: &Connection, xml_path: &Path, import_id: &str) -> Result<ImportStats> {
+fn open_export_xml(xml_path: &Path) -> Result<BufReader<std::fs::File>> {
+    if !xml_path.exists() {
+        anyhow::bail!(
+            "export.xml not found at {}; point --export-dir at the unzipped apple_health_export directory or export.zip",
+            xml_path.display()
+        );
+    }
     let file = std::fs::File::open(xml_path).context("Failed to open export.xml")?;
-    let reader = BufReader::with_capacity(8 * 1024 * 1024, file);
+    Ok(BufReader::with_capacity(8 * 1024 * 1024, file))
+}
+
+pub fn import_xml(conn: &Connection, xml_path: &Path, import_id: &str) -> Result<ImportStats> {
+    import_xml_reader(conn, open_export_xml(xml_path)?, import_id)
+}
+
+/// Like `import_xml`, parsing on `threads` worker threads (see `import_xml_reader_parallel`)
+/// and flushing every `batch_size` rows.
+pub fn import_xml_parallel(
+    conn: &Connection,
+    xml_path: &Path,
+    import_id: &str,
+    threads: usize,
+    batch_size: usize,
+) -> Result<ImportStats> {
+    import_xml_reader_parallel(
+        conn,
+        open_export_xml(xml_path)?,
+        import_id,
+        threads,
+        batch_size,
+    )
+}
+
+/// Parse an `export.xml` stream, e.g. an entry read straight out of `export.zip`.
+pub fn import_xml_reader<R: BufRead>(
+    conn: &Connection,
+    reader: R,
+    import_id: &str,
+) -> Result<ImportStats> {
+    parse_serial(conn, reader, import_id, DEFAULT_BATCH_SIZE)
+}
+
+/// Parse on the calling thread, appending buffered rows whenever `batch_size` have piled up.
+fn parse_serial<R: BufRead>(
+    conn: &Connection,
+    reader: R,
+    import_id: &str,
+    batch_size: usize,
+) -> Result<ImportStats> {
     let mut xml = Reader::from_reader(reader);
     xml.config_mut().trim_text(true);
 
-    let mut buf = Vec::with_capacity(4096);
+    let mut parser = Parser::new(import_id);
+    let mut next_progress = PROGRESS_INTERVAL;
+    parse_events(&mut xml, &mut parser, |parser| {
+        if parser.rows.len() >= batch_size {
+            parser.rows.flush(conn)?;
+        }
+        log_progress(parser.stats.records, &mut next_progress);
+        Ok(())
+    })?;
+    check_root(parser.root.as_deref())?;
+    parser.rows.flush(conn)?;
+
+    log_complete(&parser.stats);
+    Ok(parser.stats)
+}
+
+/// Like `import_xml` with `batch_size`, but for `import --checkpoint`: each batch is committed
+/// on its own together with the number of top-level elements it completes, in
+/// `import_progress` under `import_id`. Run again with the same `import_id` after an
+/// interruption, it still parses the elements already committed (for the counters and route
+/// map) but drops their rows instead of appending them twice. Must not run inside a
+/// transaction, and always parses on one thread.
+pub fn import_xml_checkpointed(
+    conn: &Connection,
+    xml_path: &Path,
+    import_id: &str,
+    batch_size: usize,
+) -> Result<ImportStats> {
+    import_xml_reader_checkpointed(conn, open_export_xml(xml_path)?, import_id, batch_size)
+}
+
+/// `import_xml_checkpointed` over any `export.xml` stream.
+pub fn import_xml_reader_checkpointed<R: BufRead>(
+    conn: &Connection,
+    reader: R,
+    import_id: &str,
+    batch_size: usize,
+) -> Result<ImportStats> {
+    let committed: u64 = conn
+        .query_row(
+            "SELECT elements_committed FROM import_progress WHERE import_id = ?",
+            [import_id],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()?
+        .map_or(0, |n| n as u64);
+    if committed > 0 {
+        info!(
+            "Resuming {}: skipping {} export.xml elements already committed",
+            import_id, committed
+        );
+    } else {
+        conn.execute(
+            "INSERT INTO import_progress (import_id) SELECT ? \
+             WHERE NOT EXISTS (SELECT 1 FROM import_progress WHERE import_id = ?)",
+            [import_id, import_id],
+        )?;
+    }
+
+    let mut xml = Reader::from_reader(reader);
+    xml.config_mut().trim_text(true);
+
+    let mut parser = Parser::new(import_id);
+    let mut next_progress = PROGRESS_INTERVAL;
+    parse_events(&mut xml, &mut parser, |parser| {
+        // Only cut batches between top-level elements, so a resume point never splits one
+        if parser.depth <= 1 {
+            if parser.elements <= committed {
+                parser.rows = Rows::default();
+            } else if parser.rows.len() >= batch_size {
+                commit_batch(conn, &mut parser.rows, import_id, parser.elements)?;
+            }
+        }
+        log_progress(parser.stats.records, &mut next_progress);
+        Ok(())
+    })?;
+    check_root(parser.root.as_deref())?;
+    if parser.elements > committed {
+        commit_batch(conn, &mut parser.rows, import_id, parser.elements)?;
+    }
+
+    log_complete(&parser.stats);
+    Ok(parser.stats)
+}
+
+/// Append `rows` and record the first `elements` top-level elements as committed, atomically.
+fn commit_batch(conn: &Connection, rows: &mut Rows, import_id: &str, elements: u64) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    rows.flush(&tx)?;
+    tx.execute(
+        "UPDATE import_progress SET elements_committed = ?, updated_at = CURRENT_TIMESTAMP \
+         WHERE import_id = ?",
+        duckdb::params![elements as i64, import_id],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Parse an `export.xml` stream on `threads` worker threads. The calling thread cuts the
+/// stream into chunks of whole top-level elements (`ChunkSplitter`), the workers turn
+/// chunks into rows, and the calling thread appends those rows, so DuckDB still sees a
+/// single writer. Rows land in a different order than with `import_xml_reader`, which
+/// deduplication doesn't care about. One thread or fewer parses serially.
+pub fn import_xml_reader_parallel<R: BufRead>(
+    conn: &Connection,
+    reader: R,
+    import_id: &str,
+    threads: usize,
+    batch_size: usize,
+) -> Result<ImportStats> {
+    parse_parallel(conn, reader, import_id, threads, CHUNK_SIZE, batch_size)
+}
+
+fn parse_parallel<R: BufRead>(
+    conn: &Connection,
+    reader: R,
+    import_id: &str,
+    threads: usize,
+    chunk_size: usize,
+    batch_size: usize,
+) -> Result<ImportStats> {
+    if threads <= 1 {
+        return parse_serial(conn, reader, import_id, batch_size);
+    }
+
+    let (chunk_tx, chunk_rx) = mpsc::channel::<Vec<u8>>();
+    let chunk_rx = Mutex::new(chunk_rx);
+    let (parsed_tx, parsed_rx) = mpsc::channel::<Parser>();
+
+    let stats = std::thread::scope(|scope| {
+        for _ in 0..threads {
+            let chunk_rx = &chunk_rx;
+            let parsed_tx = parsed_tx.clone();
+            scope.spawn(move || loop {
+                let chunk = match chunk_rx.lock() {
+                    Ok(rx) => match rx.recv() {
+                        Ok(chunk) => chunk,
+                        Err(_) => break,
+                    },
+                    Err(_) => break,
+                };
+                if parsed_tx.send(parse_chunk(&chunk, import_id)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(parsed_tx);
+
+        // Dropping chunk_tx (on return, including errors) lets the workers finish
+        write_parsed_chunks(
+            conn,
+            ChunkSplitter::new(reader, chunk_size),
+            chunk_tx,
+            &parsed_rx,
+            threads,
+            batch_size,
+        )
+    })?;
+
+    log_complete(&stats);
+    Ok(stats)
+}
+
+/// Feed chunks to the workers and append what they parse. At most two chunks per worker
+/// are in flight, which bounds memory when parsing outpaces appending.
+fn write_parsed_chunks<R: BufRead>(
+    conn: &Connection,
+    mut splitter: ChunkSplitter<R>,
+    chunk_tx: mpsc::Sender<Vec<u8>>,
+    parsed_rx: &mpsc::Receiver<Parser>,
+    threads: usize,
+    batch_size: usize,
+) -> Result<ImportStats> {
     let mut stats = ImportStats::default();
+    let mut pending = Rows::default();
+    let mut next_progress = PROGRESS_INTERVAL;
+    let mut absorb = |parsed: Parser| -> Result<()> {
+        let Parser {
+            mut rows,
+            stats: chunk_stats,
+            ..
+        } = parsed;
+        pending.append(&mut rows);
+        if pending.len() >= batch_size {
+            pending.flush(conn)?;
+        }
+        add_xml_stats(&mut stats, chunk_stats);
+        log_progress(stats.records, &mut next_progress);
+        Ok(())
+    };
 
-    // Batch buffers
-    let mut record_batch: Vec<RecordRow> = Vec::with_capacity(BATCH_SIZE);
-    let mut metadata_batch: Vec<MetadataRow> = Vec::with_capacity(BATCH_SIZE);
-    let mut workout_batch: Vec<WorkoutRow> = Vec::with_capacity(BATCH_SIZE);
-    let mut workout_event_batch: Vec<WorkoutEventRow> = Vec::with_capacity(BATCH_SIZE);
-    let mut workout_stat_batch: Vec<WorkoutStatRow> = Vec::with_capacity(BATCH_SIZE);
-    let mut activity_batch: Vec<ActivityRow> = Vec::with_capacity(BATCH_SIZE);
+    let mut in_flight = 0;
+    let mut first_chunk = true;
+    while let Some(chunk) = splitter.next_chunk()? {
+        if first_chunk {
+            check_root(first_element(&chunk).as_deref())?;
+            first_chunk = false;
+        }
+        chunk_tx
+            .send(chunk)
+            .context("XML parse workers exited early")?;
+        in_flight += 1;
+        while in_flight >= 2 * threads {
+            absorb(parsed_rx.recv().context("XML parse workers exited early")?)?;
+            in_flight -= 1;
+        }
+    }
+    if first_chunk {
+        check_root(None)?;
+    }
+    drop(chunk_tx);
+    for parsed in parsed_rx.iter() {
+        absorb(parsed)?;
+        in_flight -= 1;
+    }
+    if in_flight > 0 {
+        anyhow::bail!("XML parse workers exited early");
+    }
 
-    // State for nested parsing
-    let mut in_workout = false;
-    let mut current_workout: Option<WorkoutRow> = None;
-    let mut current_workout_events: Vec<WorkoutEventRow> = Vec::new();
-    let mut current_workout_stats: Vec<WorkoutStatRow> = Vec::new();
-    let mut _current_workout_route_file: Option<String> = None;
+    pending.flush(conn)?;
+    Ok(stats)
+}
 
-    let mut in_record = false;
-    let mut current_record_hash: Option<String> = None;
+/// Parse one chunk from `ChunkSplitter`. Only the first chunk holds the `HealthData` start
+/// tag and only the last its end tag, so unmatched end tags are expected here.
+fn parse_chunk<'a>(chunk: &[u8], import_id: &'a str) -> Parser<'a> {
+    let mut xml = Reader::from_reader(chunk);
+    xml.config_mut().trim_text(true);
+    xml.config_mut().allow_unmatched_ends = true;
 
-    // We skip Correlation children since the DTD says correlation member records
-    // also appear as top-level records
-    let mut in_correlation = false;
+    let mut parser = Parser::new(import_id);
+    // Nothing can fail without a connection to flush to
+    let _ = parse_events(&mut xml, &mut parser, |_| Ok(()));
+    parser
+}
+
+/// Fail unless the document's root element (`None` if it has none) is `HealthData`, so an
+/// empty, truncated, or unrelated file isn't imported as zero records.
+fn check_root(root: Option<&[u8]>) -> Result<()> {
+    match root {
+        Some(b"HealthData") => Ok(()),
+        Some(other) => anyhow::bail!(
+            "export.xml is not an Apple Health export: root element is <{}>, expected <HealthData>",
+            String::from_utf8_lossy(other)
+        ),
+        None => anyhow::bail!("export.xml is empty or contains no XML elements"),
+    }
+}
+
+/// Name of the first element in `chunk`, i.e. the root element for the first chunk.
+fn first_element(chunk: &[u8]) -> Option<Vec<u8>> {
+    let mut xml = Reader::from_reader(chunk);
+    let mut buf = Vec::new();
+    loop {
+        match xml.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => return Some(e.name().as_ref().to_vec()),
+            Ok(Event::Eof) | Err(_) => return None,
+            Ok(_) => buf.clear(),
+        }
+    }
+}
 
+/// Run `parser` over every event of `xml`, calling `after_event` after each one.
+fn parse_events<R: BufRead>(
+    xml: &mut Reader<R>,
+    parser: &mut Parser,
+    mut after_event: impl FnMut(&mut Parser) -> Result<()>,
+) -> Result<()> {
+    let mut buf = Vec::with_capacity(4096);
     loop {
         match xml.read_event_into(&mut buf) {
             Ok(Event::Eof) => break,
-            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e)) => {
+            Ok(event) => parser.handle(&event),
+            Err(e) => {
+                tracing::warn!("XML parse error: {:?}, continuing...", e);
+            }
+        }
+        buf.clear();
+        after_event(parser)?;
+    }
+    Ok(())
+}
+
+fn log_progress(records: u64, next_progress: &mut u64) {
+    while records >= *next_progress {
+        info!("Processed {} records...", *next_progress);
+        *next_progress += PROGRESS_INTERVAL;
+    }
+}
+
+fn log_complete(stats: &ImportStats) {
+    info!(
+        "XML import complete: {} records, {} workouts, {} activity summaries, {} correlations",
+        stats.records, stats.workouts, stats.activity_summaries, stats.correlations
+    );
+}
+
+/// Add the export.xml counters (and route map) of one parsed chunk to `total`.
+fn add_xml_stats(total: &mut ImportStats, chunk: ImportStats) {
+    total.records += chunk.records;
+    total.workouts += chunk.workouts;
+    total.activity_summaries += chunk.activity_summaries;
+    total.correlations += chunk.correlations;
+    total.metadata_entries += chunk.metadata_entries;
+    total.audiogram_points += chunk.audiogram_points;
+    total.workout_metadata_entries += chunk.workout_metadata_entries;
+    total.workout_events += chunk.workout_events;
+    total.workout_statistics += chunk.workout_statistics;
+    total.workout_routes.extend(chunk.workout_routes);
+}
+
+/// Cuts an export.xml stream into chunks of roughly `chunk_size` bytes that end right after
+/// a top-level element (a child of `HealthData`), so a Workout or Correlation and its nested
+/// elements always land in one chunk. Tracks element depth with a byte-level scan of the
+/// markup, which is much cheaper than the attribute parsing and hashing done per chunk.
+struct ChunkSplitter<R> {
+    reader: R,
+    chunk_size: usize,
+    markup: MarkupScan,
+}
+
+/// Element depth and position within the markup, advanced one byte at a time.
+#[derive(Default)]
+struct MarkupScan {
+    depth: usize,
+    scan: Scan,
+}
+
+#[derive(Clone, Copy, Default)]
+enum Scan {
+    #[default]
+    Text,
+    /// Just after `<`
+    Lt,
+    /// Just after `<!`, having seen this many `-` of a comment opener
+    Bang(u8),
+    /// Inside a start tag until its closing `>`; `prev` is the last byte seen (for `/>`)
+    StartTag { quote: Option<u8>, prev: u8 },
+    /// Inside an end tag until `>`
+    EndTag,
+    /// Inside a declaration or processing instruction until `>`
+    Other,
+    /// Inside `<!-- -->`, counting trailing `-`
+    Comment(u8),
+    /// Inside `<![CDATA[ ]]>`, counting trailing `]`
+    CData(u8),
+}
+
+impl<R: BufRead> ChunkSplitter<R> {
+    fn new(reader: R, chunk_size: usize) -> Self {
+        Self {
+            reader,
+            chunk_size,
+            markup: MarkupScan::default(),
+        }
+    }
+
+    fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut chunk = Vec::with_capacity(self.chunk_size + 64 * 1024);
+        loop {
+            let buf = self
+                .reader
+                .fill_buf()
+                .context("Failed to read export.xml")?;
+            if buf.is_empty() {
+                return Ok((!chunk.is_empty()).then_some(chunk));
+            }
+
+            let mut cut = None;
+            let mut i = 0;
+            while i < buf.len() {
+                if matches!(self.markup.scan, Scan::Text) {
+                    match buf[i..].iter().position(|&b| b == b'<') {
+                        Some(offset) => i += offset,
+                        None => break,
+                    }
+                }
+                let element_closed = self.markup.step(buf[i]);
+                i += 1;
+                if element_closed && self.markup.depth == 1 && chunk.len() + i >= self.chunk_size {
+                    cut = Some(i);
+                    break;
+                }
+            }
+
+            let consumed = cut.unwrap_or(buf.len());
+            chunk.extend_from_slice(&buf[..consumed]);
+            self.reader.consume(consumed);
+            if cut.is_some() {
+                return Ok(Some(chunk));
+            }
+        }
+    }
+}
+
+impl MarkupScan {
+    /// Advance the scan by one byte; true if it closed an element (end tag or `/>`).
+    fn step(&mut self, b: u8) -> bool {
+        let mut closed = false;
+        self.scan = match self.scan {
+            Scan::Text => {
+                if b == b'<' {
+                    Scan::Lt
+                } else {
+                    Scan::Text
+                }
+            }
+            Scan::Lt => match b {
+                b'/' => Scan::EndTag,
+                b'!' => Scan::Bang(0),
+                b'?' => Scan::Other,
+                _ => Scan::StartTag {
+                    quote: None,
+                    prev: b,
+                },
+            },
+            Scan::Bang(0) if b == b'-' => Scan::Bang(1),
+            Scan::Bang(1) if b == b'-' => Scan::Comment(0),
+            Scan::Bang(0) if b == b'[' => Scan::CData(0),
+            Scan::Bang(_) => {
+                if b == b'>' {
+                    Scan::Text
+                } else {
+                    Scan::Other
+                }
+            }
+            Scan::StartTag { quote: Some(q), .. } => Scan::StartTag {
+                quote: (b != q).then_some(q),
+                prev: b,
+            },
+            Scan::StartTag { quote: None, prev } => match b {
+                b'"' | b'\'' => Scan::StartTag {
+                    quote: Some(b),
+                    prev: b,
+                },
+                b'>' => {
+                    if prev == b'/' {
+                        closed = true;
+                    } else {
+                        self.depth += 1;
+                    }
+                    Scan::Text
+                }
+                _ => Scan::StartTag {
+                    quote: None,
+                    prev: b,
+                },
+            },
+            Scan::EndTag => {
+                if b == b'>' {
+                    self.depth = self.depth.saturating_sub(1);
+                    closed = true;
+                    Scan::Text
+                } else {
+                    Scan::EndTag
+                }
+            }
+            Scan::Other => {
+                if b == b'>' {
+                    Scan::Text
+                } else {
+                    Scan::Other
+                }
+            }
+            Scan::Comment(n) => match b {
+                b'-' => Scan::Comment((n + 1).min(2)),
+                b'>' if n == 2 => Scan::Text,
+                _ => Scan::Comment(0),
+            },
+            Scan::CData(n) => match b {
+                b']' => Scan::CData((n + 1).min(2)),
+                b'>' if n == 2 => Scan::Text,
+                _ => Scan::CData(0),
+            },
+        };
+        closed
+    }
+}
+
+/// Rows parsed from export.xml, waiting to be appended.
+#[derive(Default)]
+struct Rows {
+    records: Vec<RecordRow>,
+    metadata: Vec<MetadataRow>,
+    audiogram_points: Vec<AudiogramPointRow>,
+    workouts: Vec<WorkoutRow>,
+    workout_events: Vec<WorkoutEventRow>,
+    workout_stats: Vec<WorkoutStatRow>,
+    workout_metadata: Vec<WorkoutMetadataRow>,
+    activities: Vec<ActivityRow>,
+}
+
+impl Rows {
+    fn len(&self) -> usize {
+        self.records.len()
+            + self.metadata.len()
+            + self.audiogram_points.len()
+            + self.workouts.len()
+            + self.workout_events.len()
+            + self.workout_stats.len()
+            + self.workout_metadata.len()
+            + self.activities.len()
+    }
+
+    fn append(&mut self, other: &mut Rows) {
+        self.records.append(&mut other.records);
+        self.metadata.append(&mut other.metadata);
+        self.audiogram_points.append(&mut other.audiogram_points);
+        self.workouts.append(&mut other.workouts);
+        self.workout_events.append(&mut other.workout_events);
+        self.workout_stats.append(&mut other.workout_stats);
+        self.workout_metadata.append(&mut other.workout_metadata);
+        self.activities.append(&mut other.activities);
+    }
+
+    fn flush(&mut self, conn: &Connection) -> Result<()> {
+        flush_records(conn, &mut self.records)?;
+        flush_metadata(conn, &mut self.metadata)?;
+        flush_audiogram_points(conn, &mut self.audiogram_points)?;
+        flush_workouts(conn, &mut self.workouts)?;
+        flush_workout_events(conn, &mut self.workout_events)?;
+        flush_workout_stats(conn, &mut self.workout_stats)?;
+        flush_workout_metadata(conn, &mut self.workout_metadata)?;
+        flush_activities(conn, &mut self.activities)?;
+        Ok(())
+    }
+}
+
+/// Turns export.xml events into rows. Fed either the whole file or chunks of whole
+/// top-level elements, so its nesting state never spans two chunks.
+struct Parser<'a> {
+    import_id: &'a str,
+    rows: Rows,
+    stats: ImportStats,
+    /// Name of the first element seen: the root element when fed the whole file
+    root: Option<Vec<u8>>,
+    /// Element nesting depth, and top-level elements (children of the root) completed so
+    /// far. Only meaningful when fed the whole file; used by `--checkpoint` resume points.
+    depth: usize,
+    elements: u64,
+
+    // State for nested parsing
+    in_workout: bool,
+    current_workout: Option<WorkoutRow>,
+    current_workout_events: Vec<WorkoutEventRow>,
+    current_workout_stats: Vec<WorkoutStatRow>,
+    current_workout_metadata: Vec<WorkoutMetadataRow>,
+    // Set while inside a non-empty child of Workout (WorkoutEvent, WorkoutRoute, ...)
+    // so their own MetadataEntry elements aren't attributed to the workout itself.
+    in_workout_child: bool,
+
+    in_record: bool,
+    current_record_hash: Option<String>,
+
+    // We skip Correlation children since the DTD says correlation member records
+    // also appear as top-level records
+    in_correlation: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn new(import_id: &'a str) -> Self {
+        Self {
+            import_id,
+            rows: Rows::default(),
+            stats: ImportStats::default(),
+            root: None,
+            depth: 0,
+            elements: 0,
+            in_workout: false,
+            current_workout: None,
+            current_workout_events: Vec::new(),
+            current_workout_stats: Vec::new(),
+            current_workout_metadata: Vec::new(),
+            in_workout_child: false,
+            in_record: false,
+            current_record_hash: None,
+            in_correlation: false,
+        }
+    }
+
+    fn handle(&mut self, event: &Event) {
+        match event {
+            Event::Start(_) => self.depth += 1,
+            Event::Empty(_) if self.depth == 1 => self.elements += 1,
+            Event::End(_) => {
+                self.depth = self.depth.saturating_sub(1);
+                if self.depth == 1 {
+                    self.elements += 1;
+                }
+            }
+            _ => {}
+        }
+        let is_start = matches!(event, Event::Start(_));
+        match event {
+            Event::Start(e) | Event::Empty(e) => {
                 let name = e.name();
                 let local = name.as_ref();
+                if self.root.is_none() {
+                    self.root = Some(local.to_vec());
+                }
 
                 match local {
-                    b"Record" if !in_correlation => {
+                    // An Audiogram is stored as a value-less record of its type
+                    // (HKDataTypeIdentifierAudiogram); its SensitivityPoints go to audiogram_points.
+                    b"Record" | b"Audiogram" if !self.in_correlation => {
                         let record_type = attr_value(e, b"type").unwrap_or_default();
                         let source_name = attr_value(e, b"sourceName").unwrap_or_default();
-                        let start_date =
-                            clean_date(&attr_value(e, b"startDate").unwrap_or_default());
-                        let end_date = clean_date(&attr_value(e, b"endDate").unwrap_or_default());
+                        let raw_start = attr_value(e, b"startDate").unwrap_or_default();
+                        let raw_end = attr_value(e, b"endDate").unwrap_or_default();
                         let value_str = attr_value(e, b"value");
                         let unit = attr_value(e, b"unit");
                         let value = parse_opt_f64(&value_str);
+                        // Category records (sleep analysis, stand hours, ...) carry an enum string
+                        let value_text = if value.is_none() {
+                            value_str.clone()
+                        } else {
+                            None
+                        };
 
                         let hash = compute_hash(&[
                             &record_type,
                             &source_name,
-                            &start_date,
-                            &end_date,
+                            wall_clock(&raw_start),
+                            wall_clock(&raw_end),
                             value_str.as_deref().unwrap_or(""),
                             unit.as_deref().unwrap_or(""),
                         ]);
 
-                        record_batch.push(RecordRow {
+                        self.rows.records.push(RecordRow {
                             record_hash: hash.clone(),
                             record_type,
                             value,
@@ -108,64 +760,82 @@ pub fn import_xml(conn: &Connection, xml_path: &Path, import_id: &str) -> Result
                             source_version: attr_value(e, b"sourceVersion"),
                             device: attr_value(e, b"device"),
                             creation_date: clean_date_opt(&attr_value(e, b"creationDate")),
-                            start_date,
-                            end_date,
-                            import_id: import_id.to_string(),
+                            start_date: clean_date(&raw_start),
+                            end_date: clean_date(&raw_end),
+                            import_id: self.import_id.to_string(),
+                            value_text,
                         });
-                        stats.records += 1;
-
-                        // Check if this is a self-closing element or has children
-                        in_record = true;
-                        current_record_hash = Some(hash);
+                        self.stats.records += 1;
 
-                        if record_batch.len() >= BATCH_SIZE {
-                            flush_records(conn, &mut record_batch)?;
-                        }
-
-                        if stats.records % 500_000 == 0 {
-                            info!("Processed {} records...", stats.records);
+                        // Only a non-empty Record can carry MetadataEntry (or SensitivityPoint) children
+                        if is_start {
+                            self.in_record = true;
+                            self.current_record_hash = Some(hash);
                         }
                     }
                     b"MetadataEntry" => {
                         let key = attr_value(e, b"key").unwrap_or_default();
                         let value = attr_value(e, b"value").unwrap_or_default();
 
-                        if in_workout {
-                            // Skip workout metadata for now (could store if needed)
-                        } else if in_record {
-                            if let Some(ref hash) = current_record_hash {
-                                metadata_batch.push(MetadataRow {
+                        if self.in_workout {
+                            if !self.in_workout_child {
+                                if let Some(ref w) = self.current_workout {
+                                    self.current_workout_metadata.push(WorkoutMetadataRow {
+                                        workout_hash: w.workout_hash.clone(),
+                                        key,
+                                        value,
+                                    });
+                                }
+                            }
+                        } else if self.in_record {
+                            if let Some(ref hash) = self.current_record_hash {
+                                self.rows.metadata.push(MetadataRow {
                                     record_hash: hash.clone(),
                                     key,
                                     value,
                                 });
-                                stats.metadata_entries += 1;
-                                if metadata_batch.len() >= BATCH_SIZE {
-                                    flush_metadata(conn, &mut metadata_batch)?;
-                                }
+                                self.stats.metadata_entries += 1;
+                            }
+                        }
+                    }
+                    b"SensitivityPoint" if self.in_record => {
+                        if let Some(ref hash) = self.current_record_hash {
+                            let masked =
+                                |name: &[u8]| attr_value(e, name).map(|v| v == "true" || v == "1");
+                            if let Some(frequency_hz) =
+                                parse_opt_f64(&attr_value(e, b"frequencyValue"))
+                            {
+                                self.rows.audiogram_points.push(AudiogramPointRow {
+                                    record_hash: hash.clone(),
+                                    frequency_hz,
+                                    left_ear_db: parse_opt_f64(&attr_value(e, b"leftEarValue")),
+                                    right_ear_db: parse_opt_f64(&attr_value(e, b"rightEarValue")),
+                                    left_ear_masked: masked(b"leftEarMasked"),
+                                    right_ear_masked: masked(b"rightEarMasked"),
+                                });
+                                self.stats.audiogram_points += 1;
                             }
                         }
                     }
                     b"Workout" => {
-                        in_workout = true;
+                        self.in_workout = true;
                         let activity_type =
                             attr_value(e, b"workoutActivityType").unwrap_or_default();
                         let source_name = attr_value(e, b"sourceName").unwrap_or_default();
-                        let start_date =
-                            clean_date(&attr_value(e, b"startDate").unwrap_or_default());
-                        let end_date = clean_date(&attr_value(e, b"endDate").unwrap_or_default());
+                        let raw_start = attr_value(e, b"startDate").unwrap_or_default();
+                        let raw_end = attr_value(e, b"endDate").unwrap_or_default();
                         let duration_str = attr_value(e, b"duration");
                         let duration = parse_opt_f64(&duration_str);
 
                         let hash = compute_hash(&[
                             &activity_type,
                             &source_name,
-                            &start_date,
-                            &end_date,
+                            wall_clock(&raw_start),
+                            wall_clock(&raw_end),
                             duration_str.as_deref().unwrap_or(""),
                         ]);
 
-                        current_workout = Some(WorkoutRow {
+                        self.current_workout = Some(WorkoutRow {
                             workout_hash: hash,
                             activity_type,
                             duration,
@@ -181,17 +851,23 @@ pub fn import_xml(conn: &Connection, xml_path: &Path, import_id: &str) -> Result
                             source_version: attr_value(e, b"sourceVersion"),
                             device: attr_value(e, b"device"),
                             creation_date: clean_date_opt(&attr_value(e, b"creationDate")),
-                            start_date,
-                            end_date,
-                            import_id: import_id.to_string(),
+                            start_date: clean_date(&raw_start),
+                            end_date: clean_date(&raw_end),
+                            import_id: self.import_id.to_string(),
+                            route_file: None,
                         });
-                        current_workout_events.clear();
-                        current_workout_stats.clear();
-                        _current_workout_route_file = None;
+                        self.current_workout_events.clear();
+                        self.current_workout_stats.clear();
+                        self.current_workout_metadata.clear();
+                        self.in_workout_child = false;
+                    }
+                    b"WorkoutRoute" if self.in_workout && is_start => {
+                        self.in_workout_child = true;
                     }
-                    b"WorkoutEvent" if in_workout => {
-                        if let Some(ref w) = current_workout {
-                            current_workout_events.push(WorkoutEventRow {
+                    b"WorkoutEvent" if self.in_workout => {
+                        self.in_workout_child = is_start;
+                        if let Some(ref w) = self.current_workout {
+                            self.current_workout_events.push(WorkoutEventRow {
                                 workout_hash: w.workout_hash.clone(),
                                 event_type: attr_value(e, b"type").unwrap_or_default(),
                                 date: clean_date_opt(&attr_value(e, b"date")),
@@ -200,9 +876,10 @@ pub fn import_xml(conn: &Connection, xml_path: &Path, import_id: &str) -> Result
                             });
                         }
                     }
-                    b"WorkoutStatistics" if in_workout => {
-                        if let Some(ref w) = current_workout {
-                            current_workout_stats.push(WorkoutStatRow {
+                    b"WorkoutStatistics" if self.in_workout => {
+                        self.in_workout_child = is_start;
+                        if let Some(ref w) = self.current_workout {
+                            self.current_workout_stats.push(WorkoutStatRow {
                                 workout_hash: w.workout_hash.clone(),
                                 stat_type: attr_value(e, b"type").unwrap_or_default(),
                                 start_date: clean_date_opt(&attr_value(e, b"startDate")),
@@ -215,12 +892,19 @@ pub fn import_xml(conn: &Connection, xml_path: &Path, import_id: &str) -> Result
                             });
                         }
                     }
-                    b"FileReference" if in_workout => {
-                        _current_workout_route_file = attr_value(e, b"path");
+                    b"FileReference" if self.in_workout => {
+                        if let (Some(ref mut w), Some(path)) =
+                            (&mut self.current_workout, attr_value(e, b"path"))
+                        {
+                            self.stats
+                                .workout_routes
+                                .insert(path.clone(), w.workout_hash.clone());
+                            w.route_file = Some(path);
+                        }
                     }
                     b"ActivitySummary" => {
                         let date_comp = attr_value(e, b"dateComponents").unwrap_or_default();
-                        activity_batch.push(ActivityRow {
+                        self.rows.activities.push(ActivityRow {
                             date_components: date_comp,
                             active_energy_burned: parse_opt_f64(&attr_value(
                                 e,
@@ -248,82 +932,58 @@ pub fn import_xml(conn: &Connection, xml_path: &Path, import_id: &str) -> Result
                                 e,
                                 b"appleStandHoursGoal",
                             )),
-                            import_id: import_id.to_string(),
+                            import_id: self.import_id.to_string(),
                         });
-                        stats.activity_summaries += 1;
-                        if activity_batch.len() >= BATCH_SIZE {
-                            flush_activities(conn, &mut activity_batch)?;
-                        }
+                        self.stats.activity_summaries += 1;
                     }
                     b"Correlation" => {
-                        in_correlation = true;
-                        stats.correlations += 1;
+                        self.in_correlation = true;
+                        self.stats.correlations += 1;
                     }
                     _ => {}
                 }
             }
-            Ok(Event::End(ref e)) => {
+            Event::End(e) => {
                 let name = e.name();
                 let local = name.as_ref();
                 match local {
-                    b"Record" => {
-                        in_record = false;
-                        current_record_hash = None;
+                    b"Record" | b"Audiogram" => {
+                        self.in_record = false;
+                        self.current_record_hash = None;
                     }
                     b"Workout" => {
-                        if let Some(w) = current_workout.take() {
-                            workout_batch.push(w);
-                            stats.workouts += 1;
+                        if let Some(w) = self.current_workout.take() {
+                            self.rows.workouts.push(w);
+                            self.stats.workouts += 1;
 
-                            for ev in current_workout_events.drain(..) {
-                                workout_event_batch.push(ev);
-                                stats.workout_events += 1;
+                            for ev in self.current_workout_events.drain(..) {
+                                self.rows.workout_events.push(ev);
+                                self.stats.workout_events += 1;
                             }
-                            for st in current_workout_stats.drain(..) {
-                                workout_stat_batch.push(st);
-                                stats.workout_statistics += 1;
+                            for st in self.current_workout_stats.drain(..) {
+                                self.rows.workout_stats.push(st);
+                                self.stats.workout_statistics += 1;
                             }
-
-                            if workout_batch.len() >= BATCH_SIZE {
-                                flush_workouts(conn, &mut workout_batch)?;
-                            }
-                            if workout_event_batch.len() >= BATCH_SIZE {
-                                flush_workout_events(conn, &mut workout_event_batch)?;
-                            }
-                            if workout_stat_batch.len() >= BATCH_SIZE {
-                                flush_workout_stats(conn, &mut workout_stat_batch)?;
+                            for m in self.current_workout_metadata.drain(..) {
+                                self.rows.workout_metadata.push(m);
+                                self.stats.workout_metadata_entries += 1;
                             }
                         }
-                        in_workout = false;
+                        self.in_workout = false;
+                        self.in_workout_child = false;
+                    }
+                    b"WorkoutEvent" | b"WorkoutStatistics" | b"WorkoutRoute" => {
+                        self.in_workout_child = false;
                     }
                     b"Correlation" => {
-                        in_correlation = false;
+                        self.in_correlation = false;
                     }
                     _ => {}
                 }
             }
-            Ok(_) => {}
-            Err(e) => {
-                tracing::warn!("XML parse error: {:?}, continuing...", e);
-            }
+            _ => {}
         }
-        buf.clear();
     }
-
-    // Flush remaining batches
-    flush_records(conn, &mut record_batch)?;
-    flush_metadata(conn, &mut metadata_batch)?;
-    flush_workouts(conn, &mut workout_batch)?;
-    flush_workout_events(conn, &mut workout_event_batch)?;
-    flush_workout_stats(conn, &mut workout_stat_batch)?;
-    flush_activities(conn, &mut activity_batch)?;
-
-    info!(
-        "XML import complete: {} records, {} workouts, {} activity summaries, {} correlations",
-        stats.records, stats.workouts, stats.activity_summaries, stats.correlations
-    );
-
-    Ok(stats)
 }
 
 // -- Row types for batching --
@@ -340,6 +1000,7 @@ struct RecordRow {
     start_date: String,
     end_date: String,
     import_id: String,
+    value_text: Option<String>,
 }
 
 struct MetadataRow {
@@ -348,6 +1009,21 @@ struct MetadataRow {
     value: String,
 }
 
+struct AudiogramPointRow {
+    record_hash: String,
+    frequency_hz: f64,
+    left_ear_db: Option<f64>,
+    right_ear_db: Option<f64>,
+    left_ear_masked: Option<bool>,
+    right_ear_masked: Option<bool>,
+}
+
+struct WorkoutMetadataRow {
+    workout_hash: String,
+    key: String,
+    value: String,
+}
+
 struct WorkoutRow {
     workout_hash: String,
     activity_type: String,
@@ -364,6 +1040,7 @@ struct WorkoutRow {
     start_date: String,
     end_date: String,
     import_id: String,
+    route_file: Option<String>,
 }
 
 struct WorkoutEventRow {
@@ -419,6 +1096,10 @@ fn flush_records(conn: &Connection, batch: &mut Vec<RecordRow>) -> Result<()> {
             r.start_date,
             r.end_date,
             r.import_id,
+            None::<String>,
+            None::<String>,
+            None::<f64>,
+            r.value_text,
         ])?;
     }
     appender.flush()?;
@@ -439,6 +1120,39 @@ fn flush_metadata(conn: &Connection, batch: &mut Vec<MetadataRow>) -> Result<()>
     Ok(())
 }
 
+fn flush_audiogram_points(conn: &Connection, batch: &mut Vec<AudiogramPointRow>) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let mut appender = conn.appender("audiogram_points")?;
+    for p in batch.iter() {
+        appender.append_row(duckdb::params![
+            p.record_hash,
+            p.frequency_hz,
+            p.left_ear_db,
+            p.right_ear_db,
+            p.left_ear_masked,
+            p.right_ear_masked,
+        ])?;
+    }
+    appender.flush()?;
+    batch.clear();
+    Ok(())
+}
+
+fn flush_workout_metadata(conn: &Connection, batch: &mut Vec<WorkoutMetadataRow>) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let mut appender = conn.appender("workout_metadata")?;
+    for m in batch.iter() {
+        appender.append_row(duckdb::params![m.workout_hash, m.key, m.value])?;
+    }
+    appender.flush()?;
+    batch.clear();
+    Ok(())
+}
+
 fn flush_workouts(conn: &Connection, batch: &mut Vec<WorkoutRow>) -> Result<()> {
     if batch.is_empty() {
         return Ok(());
@@ -461,6 +1175,11 @@ fn flush_workouts(conn: &Connection, batch: &mut Vec<WorkoutRow>) -> Result<()>
             w.start_date,
             w.end_date,
             w.import_id,
+            None::<String>,
+            None::<String>,
+            None::<f64>,
+            None::<f64>,
+            w.route_file,
         ])?;
     }
     appender.flush()?;
@@ -527,6 +1246,8 @@ fn flush_activities(conn: &Connection, batch: &mut Vec<ActivityRow>) -> Result<(
             a.apple_stand_hours,
             a.apple_stand_hours_goal,
             a.import_id,
+            None::<String>,
+            None::<String>,
         ])?;
     }
     appender.flush()?;
@@ -548,9 +1269,17 @@ mod tests {
     }
 
     #[test]
-    fn clean_date_strips_negative_offset() {
+    fn clean_date_converts_offsets_to_utc() {
         assert_eq!(
             clean_date("2020-06-20 16:56:44 -0500"),
+            "2020-06-20 21:56:44"
+        );
+        assert_eq!(
+            clean_date("2020-06-21 01:56:44 +0200"),
+            "2020-06-20 23:56:44"
+        );
+        assert_eq!(
+            wall_clock("2020-06-20 16:56:44 -0500"),
             "2020-06-20 16:56:44"
         );
     }
@@ -601,11 +1330,14 @@ mod tests {
  </Record>
  <Record type="HKQuantityTypeIdentifierStepCount" sourceName="Phone" unit="count" value="100" startDate="2024-01-01 09:00:00 +0000" endDate="2024-01-01 09:30:00 +0000"/>
  <Workout workoutActivityType="HKWorkoutActivityTypeRunning" duration="30.5" durationUnit="min" totalDistance="5.0" totalDistanceUnit="km" totalEnergyBurned="300" totalEnergyBurnedUnit="kcal" sourceName="Watch" startDate="2024-01-01 10:00:00 +0000" endDate="2024-01-01 10:30:00 +0000">
+  <MetadataEntry key="HKWeatherTemperature" value="68 degF"/>
   <WorkoutEvent type="HKWorkoutEventTypeLap" date="2024-01-01 10:15:00 +0000"/>
   <WorkoutStatistics type="HKQuantityTypeIdentifierHeartRate" startDate="2024-01-01 10:00:00 +0000" endDate="2024-01-01 10:30:00 +0000" average="150" minimum="120" maximum="180" unit="count/min"/>
   <WorkoutRoute sourceName="Watch">
+   <MetadataEntry key="HKMetadataKeySyncVersion" value="2"/>
    <FileReference path="/workout-routes/route_2024-01-01.gpx"/>
   </WorkoutRoute>
+  <MetadataEntry key="HKElevationAscended" value="4500 cm"/>
  </Workout>
  <Correlation type="HKCorrelationTypeIdentifierBloodPressure" sourceName="BP" startDate="2024-01-01 12:00:00 +0000" endDate="2024-01-01 12:00:00 +0000">
   <Record type="HKQuantityTypeIdentifierBloodPressureSystolic" sourceName="BP" unit="mmHg" value="120" startDate="2024-01-01 12:00:00 +0000" endDate="2024-01-01 12:00:00 +0000"/>
@@ -626,6 +1358,17 @@ mod tests {
         assert_eq!(stats.metadata_entries, 1);
         assert_eq!(stats.workout_events, 1);
         assert_eq!(stats.workout_statistics, 1);
+        // Route-level metadata is not attributed to the workout
+        assert_eq!(stats.workout_metadata_entries, 2);
+
+        let workout_hash: String = conn
+            .query_row("SELECT workout_hash FROM workouts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stats.workout_routes.len(), 1);
+        assert_eq!(
+            stats.workout_routes["/workout-routes/route_2024-01-01.gpx"],
+            workout_hash
+        );
 
         // Verify data in DB
         let rec_count: i64 = conn
@@ -642,5 +1385,305 @@ mod tests {
             .query_row("SELECT COUNT(*) FROM record_metadata", [], |row| row.get(0))
             .unwrap();
         assert_eq!(meta_count, 1);
+
+        let workout_meta_keys: Vec<String> = conn
+            .prepare("SELECT key FROM workout_metadata ORDER BY key")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(
+            workout_meta_keys,
+            vec!["HKElevationAscended", "HKWeatherTemperature"]
+        );
+    }
+
+    #[test]
+    fn import_xml_route_map_matches_workouts() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+
+        // Two routed workouts and one without a route; the map built during the single
+        // pass must agree with what the workouts themselves reference.
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<HealthData locale="en_US">
+ <Workout workoutActivityType="HKWorkoutActivityTypeRunning" duration="30" durationUnit="min" sourceName="Watch" startDate="2024-01-01 10:00:00 +0000" endDate="2024-01-01 10:30:00 +0000">
+  <WorkoutRoute sourceName="Watch">
+   <FileReference path="/workout-routes/route_2024-01-01_10.00am.gpx"/>
+  </WorkoutRoute>
+ </Workout>
+ <Workout workoutActivityType="HKWorkoutActivityTypeYoga" duration="45" durationUnit="min" sourceName="Watch" startDate="2024-01-02 07:00:00 +0000" endDate="2024-01-02 07:45:00 +0000">
+  <MetadataEntry key="HKIndoorWorkout" value="1"/>
+ </Workout>
+ <Workout workoutActivityType="HKWorkoutActivityTypeCycling" duration="60" durationUnit="min" sourceName="Watch" startDate="2024-01-03 18:00:00 +0000" endDate="2024-01-03 19:00:00 +0000">
+  <WorkoutRoute sourceName="Watch">
+   <FileReference path="/workout-routes/route_2024-01-03_6.00pm.gpx"/>
+  </WorkoutRoute>
+ </Workout>
+</HealthData>"#;
+
+        let dir = tempfile::tempdir().unwrap();
+        let xml_path = dir.path().join("export.xml");
+        std::fs::write(&xml_path, xml).unwrap();
+
+        let stats = import_xml(&conn, &xml_path, "test_import").unwrap();
+        assert_eq!(stats.workouts, 3);
+
+        let expected: std::collections::HashMap<String, String> = conn
+            .prepare("SELECT route_file, workout_hash FROM workouts WHERE route_file IS NOT NULL")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(expected.len(), 2);
+        assert_eq!(stats.workout_routes, expected);
+    }
+
+    #[test]
+    fn import_xml_category_value_text() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<HealthData locale="en_US">
+ <Record type="HKCategoryTypeIdentifierSleepAnalysis" sourceName="Watch" value="HKCategoryValueSleepAnalysisAsleepCore" startDate="2024-01-01 23:00:00 +0000" endDate="2024-01-02 01:00:00 +0000"/>
+ <Record type="HKQuantityTypeIdentifierHeartRate" sourceName="Watch" unit="count/min" value="58" startDate="2024-01-02 00:00:00 +0000" endDate="2024-01-02 00:00:00 +0000"/>
+</HealthData>"#;
+
+        let dir = tempfile::tempdir().unwrap();
+        let xml_path = dir.path().join("export.xml");
+        std::fs::write(&xml_path, xml).unwrap();
+        import_xml(&conn, &xml_path, "test_import").unwrap();
+
+        let rows: Vec<(String, Option<f64>, Option<String>)> = conn
+            .prepare("SELECT record_type, value, value_text FROM records ORDER BY record_type")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    "HKCategoryTypeIdentifierSleepAnalysis".to_string(),
+                    None,
+                    Some("HKCategoryValueSleepAnalysisAsleepCore".to_string())
+                ),
+                (
+                    "HKQuantityTypeIdentifierHeartRate".to_string(),
+                    Some(58.0),
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn import_xml_audiogram() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<HealthData locale="en_US">
+ <Audiogram type="HKDataTypeIdentifierAudiogram" sourceName="Mimi" startDate="2024-02-01 09:00:00 +0000" endDate="2024-02-01 09:10:00 +0000">
+  <MetadataEntry key="HKMetadataKeyDeviceName" value="AirPods"/>
+  <SensitivityPoint frequencyValue="500" frequencyUnit="Hz" leftEarValue="10" leftEarUnit="dBHL" leftEarMasked="false" rightEarValue="20" rightEarUnit="dBHL" rightEarMasked="true"/>
+  <SensitivityPoint frequencyValue="4000" frequencyUnit="Hz" leftEarValue="35" leftEarUnit="dBHL"/>
+ </Audiogram>
+</HealthData>"#;
+
+        let stats = import_xml_reader(&conn, xml.as_bytes(), "test_import").unwrap();
+        assert_eq!(stats.records, 1);
+        assert_eq!(stats.audiogram_points, 2);
+        assert_eq!(stats.metadata_entries, 1);
+
+        let (record_hash, record_type): (String, String) = conn
+            .query_row("SELECT record_hash, record_type FROM records", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(record_type, "HKDataTypeIdentifierAudiogram");
+
+        // frequency, left ear, right ear, right ear masked
+        type Point = (f64, Option<f64>, Option<f64>, Option<bool>);
+        let points: Vec<Point> = conn
+            .prepare(
+                "SELECT frequency_hz, left_ear_db, right_ear_db, right_ear_masked \
+                 FROM audiogram_points WHERE record_hash = ? ORDER BY frequency_hz",
+            )
+            .unwrap()
+            .query_map([&record_hash], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(
+            points,
+            vec![
+                (500.0, Some(10.0), Some(20.0), Some(true)),
+                (4000.0, Some(35.0), None, None),
+            ]
+        );
+    }
+
+    const MINIMAL_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE HealthData [
+<!-- HealthKit Export Version: 13 -->
+<!ELEMENT HealthData (ExportDate,Me,(Record|Correlation|Workout|ActivitySummary)*)>
+<!ATTLIST HealthData
+  locale CDATA #REQUIRED
+>
+]>
+<HealthData locale="en_US">
+ <ExportDate value="2024-01-02 00:00:00 +0000"/>
+ <Record type="HKQuantityTypeIdentifierHeartRate" sourceName="Watch" unit="count/min" value="72" startDate="2024-01-01 08:00:00 +0000" endDate="2024-01-01 08:01:00 +0000">
+  <MetadataEntry key="HKMetadataKeyHeartRateMotionContext" value="1"/>
+ </Record>
+ <Record type="HKQuantityTypeIdentifierStepCount" sourceName="Phone &gt; Watch" unit="count" value="100" startDate="2024-01-01 09:00:00 +0000" endDate="2024-01-01 09:30:00 +0000"/>
+ <Workout workoutActivityType="HKWorkoutActivityTypeRunning" duration="30.5" durationUnit="min" sourceName="Watch" startDate="2024-01-01 10:00:00 +0000" endDate="2024-01-01 10:30:00 +0000">
+  <MetadataEntry key="HKWeatherTemperature" value="68 degF"/>
+  <WorkoutEvent type="HKWorkoutEventTypeLap" date="2024-01-01 10:15:00 +0000"/>
+  <WorkoutStatistics type="HKQuantityTypeIdentifierHeartRate" startDate="2024-01-01 10:00:00 +0000" endDate="2024-01-01 10:30:00 +0000" average="150" minimum="120" maximum="180" unit="count/min"/>
+  <WorkoutRoute sourceName="Watch">
+   <MetadataEntry key="HKMetadataKeySyncVersion" value="2"/>
+   <FileReference path="/workout-routes/route_2024-01-01.gpx"/>
+  </WorkoutRoute>
+ </Workout>
+ <Correlation type="HKCorrelationTypeIdentifierBloodPressure" sourceName="BP" startDate="2024-01-01 12:00:00 +0000" endDate="2024-01-01 12:00:00 +0000">
+  <Record type="HKQuantityTypeIdentifierBloodPressureSystolic" sourceName="BP" unit="mmHg" value="120" startDate="2024-01-01 12:00:00 +0000" endDate="2024-01-01 12:00:00 +0000"/>
+ </Correlation>
+ <Record type="HKQuantityTypeIdentifierBloodPressureSystolic" sourceName="BP" unit="mmHg" value="120" startDate="2024-01-01 12:00:00 +0000" endDate="2024-01-01 12:00:00 +0000"/>
+ <ActivitySummary dateComponents="2024-01-01" activeEnergyBurned="500" activeEnergyBurnedGoal="600"/>
+</HealthData>
+"#;
+
+    #[test]
+    fn chunk_splitter_cuts_between_top_level_elements() {
+        // A chunk size of one byte cuts after every top-level element
+        let mut splitter = ChunkSplitter::new(MINIMAL_XML.as_bytes(), 1);
+        let mut chunks = Vec::new();
+        while let Some(chunk) = splitter.next_chunk().unwrap() {
+            chunks.push(String::from_utf8(chunk).unwrap());
+        }
+
+        assert_eq!(chunks.concat(), MINIMAL_XML);
+        // ExportDate, 2 Records, Workout, Correlation, Record, ActivitySummary, closing tag
+        assert_eq!(chunks.len(), 8, "{:#?}", chunks);
+        assert!(chunks[0]
+            .trim_end()
+            .ends_with("<ExportDate value=\"2024-01-02 00:00:00 +0000\"/>"));
+        assert!(chunks[3].trim_start().starts_with("<Workout "));
+        assert!(chunks[3].trim_end().ends_with("</Workout>"));
+        assert!(chunks[4].trim_start().starts_with("<Correlation "));
+        assert!(chunks[4].trim_end().ends_with("</Correlation>"));
+        assert_eq!(chunks[7].trim(), "</HealthData>");
+    }
+
+    #[test]
+    fn import_xml_rejects_missing_empty_and_foreign_files() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let xml_path = dir.path().join("export.xml");
+
+        let err = import_xml(&conn, &xml_path, "test_import").unwrap_err();
+        assert!(
+            err.to_string().starts_with("export.xml not found"),
+            "{}",
+            err
+        );
+
+        std::fs::write(&xml_path, "").unwrap();
+        let err = import_xml(&conn, &xml_path, "test_import").unwrap_err();
+        assert!(err.to_string().contains("is empty"), "{}", err);
+
+        let foreign = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1"><trk><name>Route</name></trk></gpx>"#;
+        std::fs::write(&xml_path, foreign).unwrap();
+        let err = import_xml(&conn, &xml_path, "test_import").unwrap_err();
+        assert!(err.to_string().contains("root element is <gpx>"), "{}", err);
+        // The parallel path checks the first chunk instead
+        let err = parse_parallel(&conn, foreign.as_bytes(), "test_import", 2, 1, 2).unwrap_err();
+        assert!(err.to_string().contains("root element is <gpx>"), "{}", err);
+        let err = parse_parallel(&conn, "".as_bytes(), "test_import", 2, 1, 2).unwrap_err();
+        assert!(err.to_string().contains("is empty"), "{}", err);
+    }
+
+    #[test]
+    fn import_xml_without_records_succeeds() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<HealthData locale="en_US">
+ <ExportDate value="2024-01-02 00:00:00 +0000"/>
+ <Me HKCharacteristicTypeIdentifierDateOfBirth=""/>
+</HealthData>"#;
+
+        let stats = import_xml_reader(&conn, xml.as_bytes(), "test_import").unwrap();
+        assert_eq!(stats.records, 0);
+        assert_eq!(stats.workouts, 0);
+    }
+
+    #[test]
+    fn import_xml_parallel_matches_serial() {
+        let serial_conn = open_db_in_memory().unwrap();
+        ensure_schema(&serial_conn).unwrap();
+        let serial =
+            import_xml_reader(&serial_conn, MINIMAL_XML.as_bytes(), "test_import").unwrap();
+
+        let parallel_conn = open_db_in_memory().unwrap();
+        ensure_schema(&parallel_conn).unwrap();
+        let parallel = parse_parallel(
+            &parallel_conn,
+            MINIMAL_XML.as_bytes(),
+            "test_import",
+            3,
+            1,
+            2,
+        )
+        .unwrap();
+
+        assert_eq!(serial.records, 3);
+        assert_eq!(parallel.records, serial.records);
+        assert_eq!(parallel.workouts, serial.workouts);
+        assert_eq!(parallel.activity_summaries, serial.activity_summaries);
+        assert_eq!(parallel.correlations, serial.correlations);
+        assert_eq!(parallel.metadata_entries, serial.metadata_entries);
+        assert_eq!(
+            parallel.workout_metadata_entries,
+            serial.workout_metadata_entries
+        );
+        assert_eq!(parallel.workout_events, serial.workout_events);
+        assert_eq!(parallel.workout_statistics, serial.workout_statistics);
+        assert_eq!(parallel.workout_routes, serial.workout_routes);
+
+        let table = |conn: &Connection, table: &str| -> Vec<String> {
+            conn.prepare(&format!(
+                "SELECT CAST(t AS VARCHAR) FROM {} t ORDER BY ALL",
+                table
+            ))
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect()
+        };
+        for name in [
+            "records",
+            "record_metadata",
+            "workouts",
+            "workout_metadata",
+            "workout_events",
+            "workout_statistics",
+            "activity_summaries",
+        ] {
+            let rows = table(&serial_conn, name);
+            assert!(!rows.is_empty(), "{}", name);
+            assert_eq!(table(&parallel_conn, name), rows, "{}", name);
+        }
     }
 }
@@ -1,4 +1,6 @@
 pub mod db;
+pub mod export;
 pub mod import;
 pub mod models;
 pub mod server;
+pub mod timezone;
@@ -1,4 +1,4 @@
-use apple_health_mcp::{import, server};
+use apple_health_mcp::{db, export, import, server, timezone};
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 use tracing_subscriber::EnvFilter;
@@ -14,20 +14,194 @@ struct Cli {
 enum Commands {
     /// Import Apple Health export data into DuckDB
     Import {
-        /// Path to the Apple Health export directory
+        /// Path to the Apple Health export directory, or the export.zip archive itself.
+        /// Repeat to merge several exports (e.g. from family members) into one database
+        #[arg(long, default_value = ".")]
+        export_dir: Vec<PathBuf>,
+
+        /// Path to the DuckDB database file
+        #[arg(long, default_value = "./health.duckdb")]
+        db: PathBuf,
+
+        /// Keep one record per interval for a type, e.g. HKQuantityTypeIdentifierHeartRate=1m
+        /// (averaged; summed for cumulative types like steps). Repeatable.
+        #[arg(long, value_name = "TYPE=INTERVAL", value_parser = parse_downsample)]
+        downsample: Vec<import::downsample::DownsampleRule>,
+
+        /// DuckDB worker threads (default 4)
+        #[arg(long)]
+        threads: Option<usize>,
+
+        /// DuckDB memory limit, e.g. 2GB or 75%
+        #[arg(long)]
+        memory_limit: Option<String>,
+
+        /// Threads parsing export.xml (default 1)
+        #[arg(long, default_value_t = 1)]
+        parse_threads: usize,
+
+        /// Rows buffered before each write while parsing export.xml (default 100000); lower
+        /// it to cap memory in small containers
+        #[arg(long)]
+        batch_size: Option<std::num::NonZeroUsize>,
+
+        /// Parse the export and print what would be imported, without touching the database
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Fail the import when a GPX route's timestamps fall outside the workout it is
+        /// linked to, instead of only warning
+        #[arg(long)]
+        strict_routes: bool,
+
+        /// Also write the import's stats, id, duration, and per-table row counts to this JSON file
+        #[arg(long, value_name = "PATH")]
+        summary_json: Option<PathBuf>,
+
+        /// File of PATTERN=CANONICAL lines (SQL LIKE patterns, e.g. `%Apple Watch=Apple Watch`)
+        /// that list_data_sources uses to merge source names
+        #[arg(long, value_name = "PATH")]
+        source_aliases: Option<PathBuf>,
+
+        /// Timezone whose calendar days daily statistics use, as an IANA name
+        /// (e.g. America/New_York) or a UTC offset (e.g. -05:00); kept for later imports.
+        /// Default UTC
+        #[arg(long, value_name = "TZ", value_parser = parse_timezone)]
+        timezone: Option<timezone::DayTimezone>,
+
+        /// Commit export.xml in batches so an interrupted import of the same directory resumes
+        /// where it stopped instead of starting over (one directory; parses on one thread)
+        #[arg(long, conflicts_with = "dry_run")]
+        checkpoint: bool,
+    },
+    /// Watch an export directory and reimport whenever export.xml is added or changes
+    Watch {
+        /// Path to the Apple Health export directory to watch
         #[arg(long, default_value = ".")]
         export_dir: PathBuf,
 
+        /// Path to the DuckDB database file; each reimport opens it read-write on its own,
+        /// so it can't run while `serve` has the same file open
+        #[arg(long, default_value = "./health.duckdb")]
+        db: PathBuf,
+
+        /// Seconds export.xml must stay unchanged before reimporting (default 5)
+        #[arg(long)]
+        debounce_secs: Option<u64>,
+
+        /// DuckDB worker threads (default 4)
+        #[arg(long)]
+        threads: Option<usize>,
+
+        /// DuckDB memory limit, e.g. 2GB or 75%
+        #[arg(long)]
+        memory_limit: Option<String>,
+    },
+    /// Export records of one type to a CSV file
+    Export {
         /// Path to the DuckDB database file
         #[arg(long, default_value = "./health.duckdb")]
         db: PathBuf,
+
+        /// Record type to export, e.g. HKQuantityTypeIdentifierHeartRate
+        #[arg(long)]
+        record_type: String,
+
+        /// Only export records starting on or after this date (YYYY-MM-DD)
+        #[arg(long)]
+        start_date: Option<String>,
+
+        /// Only export records ending on or before this date (YYYY-MM-DD)
+        #[arg(long)]
+        end_date: Option<String>,
+
+        /// Path of the CSV file to write
+        #[arg(long)]
+        out: PathBuf,
     },
-    /// Run the MCP server
-    Serve {
+    /// Export a workout's GPS route as a GPX 1.1 file
+    ExportRoute {
+        /// Path to the DuckDB database file
+        #[arg(long, default_value = "./health.duckdb")]
+        db: PathBuf,
+
+        /// Workout hash identifier (see the list_workouts tool)
+        #[arg(long)]
+        workout_hash: String,
+
+        /// Path of the GPX file to write
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Export a workout as a TCX file (route with per-point heart rate)
+    ExportTcx {
+        /// Path to the DuckDB database file
+        #[arg(long, default_value = "./health.duckdb")]
+        db: PathBuf,
+
+        /// Workout hash identifier (see the list_workouts tool)
+        #[arg(long)]
+        workout_hash: String,
+
+        /// Path of the TCX file to write
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Export ECG waveforms as CSV (sample_idx,voltage_uv with a metadata header)
+    ExportEcg {
+        /// Path to the DuckDB database file
+        #[arg(long, default_value = "./health.duckdb")]
+        db: PathBuf,
+
+        /// ECG hash identifier (see the list_ecg_readings tool)
+        #[arg(long, required_unless_present = "all", conflicts_with = "all")]
+        ecg_hash: Option<String>,
+
+        /// Export every reading, one <ecg_hash>.csv per reading, into the --out directory
+        #[arg(long)]
+        all: bool,
+
+        /// Path of the CSV file to write, or the directory to write into with --all
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Dump the full schema and data to a directory (restore with IMPORT DATABASE)
+    Dump {
+        /// Path to the DuckDB database file
+        #[arg(long, default_value = "./health.duckdb")]
+        db: PathBuf,
+
+        /// Directory to write the dump into (must be empty or not exist)
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Copy all tables into a new SQLite file (for Datasette, mobile apps, ...)
+    ExportSqlite {
+        /// Path to the DuckDB database file
+        #[arg(long, default_value = "./health.duckdb")]
+        db: PathBuf,
+
+        /// Path of the SQLite file to create (must not exist)
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Remove the rows one import added and rebuild daily statistics
+    Purge {
         /// Path to the DuckDB database file
         #[arg(long, default_value = "./health.duckdb")]
         db: PathBuf,
 
+        /// Import to roll back, as listed in the imports table (e.g. import_20240101_120000)
+        #[arg(long)]
+        import_id: String,
+    },
+    /// Run the MCP server
+    Serve {
+        /// Path to the DuckDB database file, or NAME=PATH to serve it at /mcp/NAME.
+        /// Repeat with NAME=PATH to serve several databases (HTTP transport only)
+        #[arg(long, value_name = "[NAME=]PATH", default_value = "./health.duckdb", value_parser = parse_serve_db)]
+        db: Vec<(Option<String>, PathBuf)>,
+
         /// Port to listen on (HTTP transport only)
         #[arg(long, default_value_t = 8080)]
         port: u16,
@@ -39,9 +213,159 @@ enum Commands {
         /// Transport type: "http" for Streamable HTTP, "stdio" for stdin/stdout
         #[arg(long, default_value = "http")]
         transport: String,
+
+        /// Require `Authorization: Bearer <token>` on /mcp requests (HTTP transport only)
+        #[arg(long)]
+        auth_token: Option<String>,
+
+        /// Requests per minute each client IP may send to /mcp; more get 429 (HTTP transport only)
+        #[arg(long, value_name = "PER_MINUTE", value_parser = clap::value_parser!(u32).range(1..))]
+        rate_limit: Option<u32>,
+
+        /// Origin allowed to call the server from a browser, e.g. https://dashboard.example.com,
+        /// or * for any. Repeatable or comma-separated; without it no CORS headers are sent
+        /// (HTTP transport only)
+        #[arg(long, value_name = "ORIGIN", value_delimiter = ',', value_parser = parse_cors_origin)]
+        cors_origin: Vec<String>,
+
+        /// Serve Prometheus metrics (tool call counts, query errors, latency) at /metrics
+        /// (HTTP transport only)
+        #[arg(long)]
+        metrics: bool,
+
+        /// Limit date-taking tools to the last N days when the caller gives no start_date
+        #[arg(long)]
+        default_range_days: Option<u32>,
+
+        /// Let run_custom_query read local files and URLs over HTTP (trusted local use only)
+        #[arg(long)]
+        allow_external_access: bool,
+
+        /// Maximum rows returned by run_custom_query (default 10000)
+        #[arg(long)]
+        max_query_rows: Option<usize>,
+
+        /// Maximum rows returned by list tools such as list_workouts and query_records, lowering
+        /// their built-in caps (500 for list_workouts, 365 for daily tools, 10000 points for
+        /// get_workout_route, 1000 otherwise)
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        max_rows: Option<u32>,
+
+        /// Row cap for one list tool, e.g. list_workouts=2000, replacing its built-in cap and
+        /// --max-rows. Repeatable
+        #[arg(long, value_name = "TOOL=N", value_parser = parse_tool_max_rows)]
+        tool_max_rows: Vec<(String, u32)>,
+
+        /// Wrap each tool result as {sql, result} to show the SQL that ran
+        #[arg(long)]
+        include_query: bool,
+
+        /// Hide sensitive tables and record types (reproductive health, ECG) from sessions
+        #[arg(long)]
+        restrict_sensitive: bool,
+
+        /// Table or record type to hide, replacing the default list; implies
+        /// --restrict-sensitive. Repeatable.
+        #[arg(long, value_name = "TABLE_OR_TYPE")]
+        sensitive: Vec<String>,
+
+        /// Open the database read-write so write tools like add_annotation work
+        #[arg(long)]
+        maintenance: bool,
+
+        /// DuckDB worker threads per session (default: one per core)
+        #[arg(long)]
+        threads: Option<usize>,
+
+        /// DuckDB memory limit per session, e.g. 2GB or 75%
+        #[arg(long)]
+        memory_limit: Option<String>,
+
+        /// Write dates in tool results the way this locale does, e.g. de-DE or en-GB
+        #[arg(long, value_name = "LOCALE", value_parser = parse_output_locale)]
+        output_locale: Option<server::locale::OutputLocale>,
+
+        /// With --output-locale, also return numbers as locale-formatted strings
+        #[arg(long, requires = "output_locale")]
+        locale_numbers: bool,
+
+        /// Check the database and tool registry, then exit without binding a port
+        #[arg(long)]
+        validate_only: bool,
     },
 }
 
+fn parse_cors_origin(origin: &str) -> Result<String, String> {
+    let origin = origin.trim();
+    let valid = origin == "*"
+        || ((origin.starts_with("http://") || origin.starts_with("https://"))
+            && !origin.ends_with('/')
+            && axum::http::HeaderValue::from_str(origin).is_ok());
+    if valid {
+        Ok(origin.to_string())
+    } else {
+        Err(format!(
+            "expected * or an origin like https://example.com (scheme and host, no path), got {:?}",
+            origin
+        ))
+    }
+}
+
+/// `--db` for `serve`: `name=path` when the part before the first `=` is a plain name
+/// (letters, digits, `-`, `_`), otherwise the whole value is a path.
+fn parse_serve_db(spec: &str) -> Result<(Option<String>, PathBuf), String> {
+    if let Some((name, path)) = spec.split_once('=') {
+        let plain_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if plain_name {
+            if path.is_empty() {
+                return Err(format!("expected NAME=PATH, got {:?}", spec));
+            }
+            return Ok((Some(name.to_string()), PathBuf::from(path)));
+        }
+    }
+    Ok((None, PathBuf::from(spec)))
+}
+
+/// `--tool-max-rows`: `TOOL=N` for one of the list tools in `DEFAULT_TOOL_MAX_ROWS`.
+fn parse_tool_max_rows(spec: &str) -> Result<(String, u32), String> {
+    let (tool, rows) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("expected TOOL=N, got {:?}", spec))?;
+    if !server::DEFAULT_TOOL_MAX_ROWS
+        .iter()
+        .any(|(name, _)| *name == tool)
+    {
+        let tools: Vec<&str> = server::DEFAULT_TOOL_MAX_ROWS
+            .iter()
+            .map(|(name, _)| *name)
+            .collect();
+        return Err(format!(
+            "unknown list tool {:?}; expected one of {}",
+            tool,
+            tools.join(", ")
+        ));
+    }
+    match rows.parse::<u32>() {
+        Ok(rows) if rows > 0 => Ok((tool.to_string(), rows)),
+        _ => Err(format!("expected a positive row count, got {:?}", rows)),
+    }
+}
+
+fn parse_output_locale(tag: &str) -> Result<server::locale::OutputLocale, String> {
+    server::locale::OutputLocale::parse(tag).map_err(|e| e.to_string())
+}
+
+fn parse_timezone(spec: &str) -> Result<timezone::DayTimezone, String> {
+    timezone::DayTimezone::parse(spec).map_err(|e| e.to_string())
+}
+
+fn parse_downsample(spec: &str) -> Result<import::downsample::DownsampleRule, String> {
+    import::downsample::parse_downsample_rule(spec).map_err(|e| e.to_string())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt()
@@ -54,16 +378,245 @@ async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Import { export_dir, db } => {
-            import::run_import(&export_dir, &db)?;
+        Commands::Import {
+            export_dir,
+            db,
+            downsample,
+            threads,
+            memory_limit,
+            parse_threads,
+            batch_size,
+            dry_run,
+            strict_routes,
+            summary_json,
+            source_aliases,
+            timezone,
+            checkpoint,
+        } => {
+            let options = import::ImportOptions {
+                downsample,
+                db_options: db::DbOptions {
+                    threads,
+                    memory_limit,
+                },
+                parse_threads,
+                batch_size,
+                dry_run,
+                strict_routes,
+                summary_json,
+                source_aliases,
+                timezone,
+                checkpoint,
+            };
+            let stats = import::run_import_dirs(&export_dir, &db, &options)?;
+            if dry_run {
+                println!("Dry run, nothing written to {}", db.display());
+                println!(
+                    "Records: {}, Workouts: {}, Activity summaries: {}, ECG readings: {}, \
+                     Route points: {}, Metadata entries: {}, Clinical records: {}",
+                    stats.records,
+                    stats.workouts,
+                    stats.activity_summaries,
+                    stats.ecg_readings,
+                    stats.route_points,
+                    stats.metadata_entries,
+                    stats.clinical_records
+                );
+                println!(
+                    "Failed files: {} ECG, {} GPX",
+                    stats.ecg_files_failed, stats.gpx_files_failed
+                );
+                println!("Records by type (after deduplication):");
+                for (record_type, count) in &stats.record_types {
+                    println!("  {:>10}  {}", count, record_type);
+                }
+            }
+        }
+        Commands::Watch {
+            export_dir,
+            db,
+            debounce_secs,
+            threads,
+            memory_limit,
+        } => {
+            let options = import::ImportOptions {
+                db_options: db::DbOptions {
+                    threads,
+                    memory_limit,
+                },
+                ..Default::default()
+            };
+            let debounce = debounce_secs.map_or(
+                import::watch::DEFAULT_DEBOUNCE,
+                std::time::Duration::from_secs,
+            );
+            import::watch::watch_export_dir(&export_dir, &db, &options, debounce)?;
+        }
+        Commands::Export {
+            db,
+            record_type,
+            start_date,
+            end_date,
+            out,
+        } => {
+            let conn = db::open_db_readonly(&db)?;
+            let rows = export::export_records_csv(
+                &conn,
+                &record_type,
+                start_date.as_deref(),
+                end_date.as_deref(),
+                &out,
+            )?;
+            println!("Wrote {} rows to {}", rows, out.display());
+        }
+        Commands::ExportRoute {
+            db,
+            workout_hash,
+            out,
+        } => {
+            let conn = db::open_db_readonly(&db)?;
+            let points = export::export_route_gpx(&conn, &workout_hash, &out)?;
+            println!("Wrote {} route points to {}", points, out.display());
+        }
+        Commands::ExportTcx {
+            db,
+            workout_hash,
+            out,
+        } => {
+            let conn = db::open_db_readonly(&db)?;
+            let points = export::export_workout_tcx(&conn, &workout_hash, &out)?;
+            println!("Wrote {} trackpoints to {}", points, out.display());
+        }
+        Commands::ExportEcg {
+            db, ecg_hash, out, ..
+        } => {
+            let conn = db::open_db_readonly(&db)?;
+            match ecg_hash {
+                Some(hash) => {
+                    let samples = export::export_ecg_csv(&conn, &hash, &out)?;
+                    println!("Wrote {} ECG samples to {}", samples, out.display());
+                }
+                None => {
+                    let (readings, samples) = export::export_all_ecg_csv(&conn, &out)?;
+                    println!(
+                        "Wrote {} ECG readings ({} samples) to {}",
+                        readings,
+                        samples,
+                        out.display()
+                    );
+                }
+            }
+        }
+        Commands::Dump { db, out } => {
+            let conn = db::open_db_readonly(&db)?;
+            export::dump_database(&conn, &out)?;
+            println!("Dumped database to {}", out.display());
+        }
+        Commands::ExportSqlite { db, out } => {
+            let conn = db::open_db_readonly(&db)?;
+            let tables = export::export_sqlite(&conn, &out)?;
+            println!("Exported {} tables to {}", tables, out.display());
+        }
+        Commands::Purge { db, import_id } => {
+            let conn = db::open_db(&db)?;
+            db::ensure_schema(&conn)?;
+            db::migrate(&conn)?;
+            let rows = db::purge_import(&conn, &import_id)?;
+            db::compact_db(&conn)?;
+            println!("Purged import {}: {} rows removed", import_id, rows);
         }
         Commands::Serve {
             db,
             port,
             host,
             transport,
+            auth_token,
+            rate_limit,
+            cors_origin,
+            metrics,
+            default_range_days,
+            allow_external_access,
+            max_query_rows,
+            max_rows,
+            tool_max_rows,
+            include_query,
+            restrict_sensitive,
+            sensitive,
+            maintenance,
+            threads,
+            memory_limit,
+            output_locale,
+            locale_numbers,
+            validate_only,
         } => {
-            server::run_server(&db, &host, port, &transport).await?;
+            let (named, unnamed): (Vec<_>, Vec<_>) =
+                db.into_iter().partition(|(name, _)| name.is_some());
+            if !named.is_empty() && !unnamed.is_empty() {
+                anyhow::bail!("Give every --db a name (NAME=PATH) when serving more than one");
+            }
+            if unnamed.len() > 1 {
+                anyhow::bail!("Serving several databases needs a name for each: --db NAME=PATH");
+            }
+            let config = server::ServerConfig {
+                auth_token,
+                default_range_days,
+                allow_external_access,
+                max_query_rows,
+                max_rows,
+                tool_max_rows,
+                include_query,
+                db_options: db::DbOptions {
+                    threads,
+                    memory_limit,
+                },
+                sensitive_denylist: if !sensitive.is_empty() {
+                    sensitive
+                } else if restrict_sensitive {
+                    server::sensitive::DEFAULT_SENSITIVE
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect()
+                } else {
+                    Vec::new()
+                },
+                maintenance,
+                output_locale,
+                locale_numbers,
+                rate_limit,
+                cors_origins: cors_origin,
+                metrics,
+            };
+            if named.is_empty() {
+                let db = &unnamed[0].1;
+                if validate_only {
+                    let tool_count = server::validate_server(db)?;
+                    println!(
+                        "OK: {} is readable, {} tools registered",
+                        db.display(),
+                        tool_count
+                    );
+                    return Ok(());
+                }
+                server::run_server(db, &host, port, &transport, config).await?;
+            } else {
+                let dbs: Vec<(String, PathBuf)> = named
+                    .into_iter()
+                    .filter_map(|(name, path)| Some((name?, path)))
+                    .collect();
+                if validate_only {
+                    for (name, db) in &dbs {
+                        let tool_count = server::validate_server(db)?;
+                        println!(
+                            "OK: {} ({}) is readable, {} tools registered",
+                            name,
+                            db.display(),
+                            tool_count
+                        );
+                    }
+                    return Ok(());
+                }
+                server::run_multi_server(&dbs, &host, port, &transport, config).await?;
+            }
         }
     }
 
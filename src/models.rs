@@ -1,18 +1,151 @@
 #![allow(dead_code)]
 
+use serde::Serialize;
 use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize)]
 pub struct ImportStats {
     pub records: u64,
     pub workouts: u64,
     pub activity_summaries: u64,
     pub correlations: u64,
     pub ecg_readings: u64,
+    pub ecg_files_failed: u64,
     pub route_points: u64,
+    pub gpx_files: u64,
+    pub gpx_files_failed: u64,
+    /// GPX routes whose timestamps fall well outside the workout they were linked to.
+    pub routes_outside_workout: u64,
     pub metadata_entries: u64,
+    pub audiogram_points: u64,
+    pub workout_metadata_entries: u64,
     pub workout_events: u64,
     pub workout_statistics: u64,
+    pub clinical_records: u64,
+    /// Route file path (from `WorkoutRoute > FileReference`) → owning workout hash,
+    /// collected during the XML pass for linking GPX files.
+    #[serde(skip)]
+    pub workout_routes: HashMap<String, String>,
+    /// Records per type after deduplication, most common first; only filled by dry runs.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub record_types: Vec<(String, u64)>,
+}
+
+/// How values of a record type combine over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    /// Amounts accumulated over an interval (steps, distance, energy) — sum them.
+    Cumulative,
+    /// Point-in-time measurements (heart rate, body mass) — average them.
+    Discrete,
+    /// Categorical samples (sleep analysis, stand hours) whose value is an enum code.
+    Category,
+}
+
+impl RecordKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RecordKind::Cumulative => "cumulative",
+            RecordKind::Discrete => "discrete",
+            RecordKind::Category => "category",
+        }
+    }
+}
+
+/// Quantity types HealthKit aggregates by summing (`HKQuantityAggregationStyleCumulative`).
+const CUMULATIVE_QUANTITY_TYPES: &[&str] = &[
+    "StepCount",
+    "DistanceWalkingRunning",
+    "DistanceCycling",
+    "DistanceSwimming",
+    "DistanceWheelchair",
+    "DistanceDownhillSnowSports",
+    "ActiveEnergyBurned",
+    "BasalEnergyBurned",
+    "FlightsClimbed",
+    "AppleExerciseTime",
+    "AppleMoveTime",
+    "AppleStandTime",
+    "PushCount",
+    "SwimmingStrokeCount",
+    "NumberOfTimesFallen",
+    "TimeInDaylight",
+    "NikeFuel",
+];
+
+/// Identifiers whose generated name reads poorly.
+const FRIENDLY_NAME_OVERRIDES: &[(&str, &str)] = &[
+    ("HeartRateVariabilitySDNN", "Heart Rate Variability (SDNN)"),
+    ("VO2Max", "VO2 Max"),
+    ("OxygenSaturation", "Blood Oxygen"),
+    ("BodyMassIndex", "Body Mass Index (BMI)"),
+    ("AppleStandHour", "Stand Hours"),
+    ("SleepAnalysis", "Sleep"),
+];
+
+/// Strip the HealthKit prefix from a record type, e.g.
+/// `HKQuantityTypeIdentifierHeartRate` → `HeartRate`.
+fn short_type_name(record_type: &str) -> &str {
+    [
+        "HKQuantityTypeIdentifier",
+        "HKCategoryTypeIdentifier",
+        "HKCorrelationTypeIdentifier",
+        "HKDataType",
+    ]
+    .iter()
+    .find_map(|prefix| record_type.strip_prefix(prefix))
+    .unwrap_or(record_type)
+}
+
+/// Human-readable name for a record type, e.g. `HKQuantityTypeIdentifierStepCount` → `Step Count`.
+pub fn friendly_record_name(record_type: &str) -> String {
+    let short = short_type_name(record_type);
+    if let Some((_, name)) = FRIENDLY_NAME_OVERRIDES.iter().find(|(id, _)| *id == short) {
+        return name.to_string();
+    }
+
+    // Split CamelCase into words
+    let mut name = String::with_capacity(short.len() + 4);
+    for (i, c) in short.char_indices() {
+        if i > 0 && c.is_uppercase() && !short[..i].ends_with(|p: char| p.is_uppercase()) {
+            name.push(' ');
+        }
+        name.push(c);
+    }
+    name
+}
+
+/// Classify a record type as cumulative, discrete, or categorical.
+pub fn record_kind(record_type: &str) -> RecordKind {
+    if record_type.starts_with("HKCategoryTypeIdentifier") {
+        return RecordKind::Category;
+    }
+    let short = short_type_name(record_type);
+    if CUMULATIVE_QUANTITY_TYPES.contains(&short) || short.starts_with("Dietary") {
+        RecordKind::Cumulative
+    } else {
+        RecordKind::Discrete
+    }
+}
+
+/// Stage of an `HKCategoryTypeIdentifierSleepAnalysis` sample, collapsed to what
+/// sleep-quality metrics need. Core, deep, REM, and legacy "Asleep" all count as asleep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepStage {
+    InBed,
+    Asleep,
+    Awake,
+}
+
+/// Map a sleep-analysis `value_text` (e.g. `HKCategoryValueSleepAnalysisAsleepCore`) to its stage.
+pub fn sleep_stage(value_text: &str) -> Option<SleepStage> {
+    match value_text.strip_prefix("HKCategoryValueSleepAnalysis")? {
+        "InBed" => Some(SleepStage::InBed),
+        "Awake" => Some(SleepStage::Awake),
+        s if s.starts_with("Asleep") => Some(SleepStage::Asleep),
+        _ => None,
+    }
 }
 
 pub fn compute_hash(parts: &[&str]) -> String {
@@ -28,6 +161,64 @@ pub fn compute_hash(parts: &[&str]) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn friendly_record_names() {
+        assert_eq!(
+            friendly_record_name("HKQuantityTypeIdentifierHeartRate"),
+            "Heart Rate"
+        );
+        assert_eq!(
+            friendly_record_name("HKQuantityTypeIdentifierDistanceWalkingRunning"),
+            "Distance Walking Running"
+        );
+        assert_eq!(
+            friendly_record_name("HKQuantityTypeIdentifierHeartRateVariabilitySDNN"),
+            "Heart Rate Variability (SDNN)"
+        );
+        assert_eq!(friendly_record_name("CustomType"), "Custom Type");
+    }
+
+    #[test]
+    fn record_kinds() {
+        assert_eq!(
+            record_kind("HKQuantityTypeIdentifierStepCount"),
+            RecordKind::Cumulative
+        );
+        assert_eq!(
+            record_kind("HKQuantityTypeIdentifierDietaryProtein"),
+            RecordKind::Cumulative
+        );
+        assert_eq!(
+            record_kind("HKQuantityTypeIdentifierHeartRate"),
+            RecordKind::Discrete
+        );
+        assert_eq!(
+            record_kind("HKCategoryTypeIdentifierSleepAnalysis"),
+            RecordKind::Category
+        );
+    }
+
+    #[test]
+    fn sleep_stages() {
+        assert_eq!(
+            sleep_stage("HKCategoryValueSleepAnalysisInBed"),
+            Some(SleepStage::InBed)
+        );
+        assert_eq!(
+            sleep_stage("HKCategoryValueSleepAnalysisAsleepREM"),
+            Some(SleepStage::Asleep)
+        );
+        assert_eq!(
+            sleep_stage("HKCategoryValueSleepAnalysisAsleep"),
+            Some(SleepStage::Asleep)
+        );
+        assert_eq!(
+            sleep_stage("HKCategoryValueSleepAnalysisAwake"),
+            Some(SleepStage::Awake)
+        );
+        assert_eq!(sleep_stage("Something"), None);
+    }
+
     #[test]
     fn compute_hash_deterministic() {
         let h1 = compute_hash(&["a", "b", "c"]);
@@ -70,9 +261,14 @@ mod tests {
         assert_eq!(stats.activity_summaries, 0);
         assert_eq!(stats.correlations, 0);
         assert_eq!(stats.ecg_readings, 0);
+        assert_eq!(stats.ecg_files_failed, 0);
         assert_eq!(stats.route_points, 0);
+        assert_eq!(stats.gpx_files, 0);
+        assert_eq!(stats.gpx_files_failed, 0);
         assert_eq!(stats.metadata_entries, 0);
+        assert_eq!(stats.workout_metadata_entries, 0);
         assert_eq!(stats.workout_events, 0);
         assert_eq!(stats.workout_statistics, 0);
+        assert!(stats.workout_routes.is_empty());
     }
 }
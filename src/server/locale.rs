@@ -0,0 +1,194 @@
+//! `--output-locale`: rewrite the dates in tool results (and, with `--locale-numbers`, the
+//! numbers) the way a locale writes them, for clients that show results to people as-is.
+//! Covers a fixed table of common locales rather than pulling in full CLDR data.
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+/// Order of day, month, and year in a formatted date.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DateOrder {
+    DayMonthYear,
+    MonthDayYear,
+    YearMonthDay,
+}
+
+/// Date and number conventions of one locale.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputLocale {
+    tag: String,
+    date_order: DateOrder,
+    date_separator: char,
+    decimal_separator: char,
+    thousands_separator: char,
+}
+
+/// Language (or language-region) tags `OutputLocale::parse` accepts, with their conventions.
+const LOCALES: &[(&str, DateOrder, char, char, char)] = &[
+    ("en-US", DateOrder::MonthDayYear, '/', '.', ','),
+    ("en-GB", DateOrder::DayMonthYear, '/', '.', ','),
+    ("de", DateOrder::DayMonthYear, '.', ',', '.'),
+    ("fr", DateOrder::DayMonthYear, '/', ',', '\u{202f}'),
+    ("es", DateOrder::DayMonthYear, '/', ',', '.'),
+    ("it", DateOrder::DayMonthYear, '/', ',', '.'),
+    ("nl", DateOrder::DayMonthYear, '-', ',', '.'),
+    ("sv", DateOrder::YearMonthDay, '-', ',', '\u{a0}'),
+    ("ja", DateOrder::YearMonthDay, '/', '.', ','),
+];
+
+impl OutputLocale {
+    /// Look up a tag such as `de-DE`, `de`, `en_GB`, or `en`. A region without its own
+    /// entry falls back to the language's; English ones other than `en-GB` use US conventions.
+    pub fn parse(tag: &str) -> Result<Self> {
+        let normalized = tag.trim().replace('_', "-");
+        let language = normalized.split('-').next().unwrap_or_default();
+        let entry = LOCALES
+            .iter()
+            .find(|(t, ..)| t.eq_ignore_ascii_case(&normalized))
+            .or_else(|| {
+                LOCALES
+                    .iter()
+                    .find(|(t, ..)| t.eq_ignore_ascii_case(language))
+            })
+            .or_else(|| language.eq_ignore_ascii_case("en").then_some(&LOCALES[0]));
+        let Some(&(_, date_order, date_separator, decimal_separator, thousands_separator)) = entry
+        else {
+            bail!(
+                "Unsupported output locale {:?}; supported: {}",
+                tag,
+                LOCALES
+                    .iter()
+                    .map(|(t, ..)| *t)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        };
+        Ok(Self {
+            tag: normalized,
+            date_order,
+            date_separator,
+            decimal_separator,
+            thousands_separator,
+        })
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// `YYYY-MM-DD` in this locale's order and separator.
+    pub fn format_date(&self, date: chrono::NaiveDate) -> String {
+        let pattern = match self.date_order {
+            DateOrder::DayMonthYear => ["%d", "%m", "%Y"],
+            DateOrder::MonthDayYear => ["%m", "%d", "%Y"],
+            DateOrder::YearMonthDay => ["%Y", "%m", "%d"],
+        }
+        .join(&self.date_separator.to_string());
+        date.format(&pattern).to_string()
+    }
+
+    /// A number with this locale's decimal and thousands separators, keeping every digit
+    /// of its JSON form.
+    pub fn format_number(&self, number: &serde_json::Number) -> String {
+        let plain = match number.as_f64() {
+            Some(f) if !number.is_i64() && !number.is_u64() => f.to_string(),
+            _ => number.to_string(),
+        };
+        let (sign, digits) = match plain.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", plain.as_str()),
+        };
+        let (int_part, frac_part) = match digits.split_once('.') {
+            Some((i, f)) => (i, Some(f)),
+            None => (digits, None),
+        };
+
+        let mut grouped = String::new();
+        for (i, c) in int_part.chars().enumerate() {
+            if i > 0 && (int_part.len() - i) % 3 == 0 {
+                grouped.push(self.thousands_separator);
+            }
+            grouped.push(c);
+        }
+        match frac_part {
+            Some(frac) => format!("{}{}{}{}", sign, grouped, self.decimal_separator, frac),
+            None => format!("{}{}", sign, grouped),
+        }
+    }
+
+    /// Rewrite date and timestamp strings (`YYYY-MM-DD`, optionally followed by a time,
+    /// which is kept as is) throughout `value`; with `numbers`, also turn every number into
+    /// a formatted string. Object keys and other strings are left alone.
+    pub fn localize(&self, value: &mut Value, numbers: bool) {
+        match value {
+            Value::String(s) => {
+                let (Some(date), Some(rest)) = (s.get(..10), s.get(10..)) else {
+                    return;
+                };
+                let is_date = date.as_bytes()[4] == b'-'
+                    && date.as_bytes()[7] == b'-'
+                    && (rest.is_empty() || rest.starts_with(' ') || rest.starts_with('T'));
+                if let Some(d) = is_date
+                    .then(|| chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+                    .flatten()
+                {
+                    *s = format!("{}{}", self.format_date(d), rest.replacen('T', " ", 1));
+                }
+            }
+            Value::Number(n) if numbers => *value = Value::String(self.format_number(n)),
+            Value::Array(items) => items.iter_mut().for_each(|v| self.localize(v, numbers)),
+            Value::Object(map) => map.values_mut().for_each(|v| self.localize(v, numbers)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn german_dates_and_numbers() {
+        let de = OutputLocale::parse("de-DE").unwrap();
+        assert_eq!(de.tag(), "de-DE");
+
+        let mut value = json!({
+            "date": "2024-03-05",
+            "start_date": "2024-03-05 07:00:00",
+            "record_hash": "2024-03-05x",
+            "count": 12345,
+            "avg_value": 1234.5,
+        });
+        de.localize(&mut value, false);
+        assert_eq!(value["date"], "05.03.2024");
+        assert_eq!(value["start_date"], "05.03.2024 07:00:00");
+        assert_eq!(value["record_hash"], "2024-03-05x");
+        assert_eq!(value["avg_value"], 1234.5);
+
+        de.localize(&mut value, true);
+        assert_eq!(value["count"], "12.345");
+        assert_eq!(value["avg_value"], "1.234,5");
+        assert_eq!(de.format_number(&(-1234567).into()), "-1.234.567");
+    }
+
+    #[test]
+    fn parse_falls_back_to_language() {
+        let us = OutputLocale::parse("en").unwrap();
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 3, 5).unwrap();
+        assert_eq!(us.format_date(date), "03/05/2024");
+        assert_eq!(
+            OutputLocale::parse("en_GB").unwrap().format_date(date),
+            "05/03/2024"
+        );
+        assert_eq!(
+            OutputLocale::parse("de-AT").unwrap().format_date(date),
+            "05.03.2024"
+        );
+        assert_eq!(
+            OutputLocale::parse("sv-SE").unwrap().format_date(date),
+            "2024-03-05"
+        );
+        assert!(OutputLocale::parse("xx-YY").is_err());
+    }
+}
@@ -0,0 +1,52 @@
+//! Prometheus metrics for the HTTP transport (`--metrics`): tool call counts, query errors,
+//! and tool latency, rendered at `GET /metrics`.
+
+use axum::extract::State;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Histogram buckets for `mcp_tool_duration_seconds`, from a cached lookup to a full scan.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the process-wide Prometheus recorder (once) and return a handle for rendering.
+/// Until this is called, `record_tool_call` is a no-op, so stdio sessions pay nothing.
+pub fn install() -> PrometheusHandle {
+    HANDLE
+        .get_or_init(|| {
+            PrometheusBuilder::new()
+                .set_buckets(LATENCY_BUCKETS)
+                .expect("latency buckets are not empty")
+                .install_recorder()
+                .expect("no other metrics recorder is installed")
+        })
+        .clone()
+}
+
+/// Count one finished tool call. `query_failed` is set when the tool returned a
+/// `query_failed` error, i.e. DuckDB rejected or failed one of its statements.
+pub fn record_tool_call(tool: &str, elapsed: Duration, query_failed: bool) {
+    let tool = tool.to_string();
+    metrics::counter!("mcp_tool_calls_total").increment(1);
+    metrics::counter!("mcp_tool_calls_by_tool_total", "tool" => tool.clone()).increment(1);
+    metrics::histogram!("mcp_tool_duration_seconds", "tool" => tool).record(elapsed.as_secs_f64());
+    if query_failed {
+        metrics::counter!("mcp_query_errors_total").increment(1);
+    }
+}
+
+/// `GET /metrics`: everything recorded so far in the Prometheus text format.
+pub async fn render(State(handle): State<PrometheusHandle>) -> Response {
+    handle.run_upkeep();
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        handle.render(),
+    )
+        .into_response()
+}
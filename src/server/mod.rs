@@ -1,23 +1,119 @@
+pub mod locale;
+pub mod metrics;
+pub mod pool;
+pub mod rate_limit;
+pub mod sensitive;
 pub mod tools;
 
 use anyhow::Result;
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
 use duckdb::types::ValueRef;
 use duckdb::Connection;
 use rmcp::handler::server::router::tool::ToolRouter;
+use rmcp::handler::server::tool::ToolCallContext;
 use rmcp::handler::server::wrapper::Parameters;
+use rmcp::model::{
+    CallToolRequestParams, CallToolResult, ListToolsResult, PaginatedRequestParams, RawContent,
+};
+use rmcp::service::RequestContext;
 use rmcp::transport::streamable_http_server::session::local::LocalSessionManager;
 use rmcp::transport::streamable_http_server::StreamableHttpService;
-use rmcp::{tool, tool_handler, tool_router, ServerHandler, ServiceExt};
+use rmcp::{tool, tool_router, RoleServer, ServerHandler, ServiceExt};
 use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::future::Future;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+use tracing::Instrument;
 
+use crate::models::{friendly_record_name, record_kind, sleep_stage, SleepStage};
 use tools::*;
 
+/// Queries slower than this are logged at warn level; every query is logged at debug.
+const SLOW_QUERY_MS: u128 = 1000;
+
+tokio::task_local! {
+    /// SQL executed by `query_to_json` during the current tool call (see `capture_queries`).
+    static QUERY_LOG: RefCell<Vec<String>>;
+}
+
+/// A sleep-analysis sample as (stage, start epoch seconds, end epoch seconds).
+type SleepSegment = (SleepStage, f64, f64);
+
+/// A route point as (cumulative meters from the workout start, epoch seconds).
+type TrackPoint = (f64, f64);
+
+/// Options for `serve`, shared by every session.
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+    /// Require `Authorization: Bearer <token>` on HTTP requests.
+    pub auth_token: Option<String>,
+    /// When a date-taking tool is called without `start_date`, limit it to the last N days.
+    pub default_range_days: Option<u32>,
+    /// Let HTTP sessions use DuckDB's file and network functions (e.g. `read_csv`) from
+    /// `run_custom_query`. Off by default; stdio sessions always allow them.
+    pub allow_external_access: bool,
+    /// Row cap for `run_custom_query` results (default `DEFAULT_MAX_QUERY_ROWS`).
+    pub max_query_rows: Option<usize>,
+    /// Wrap every tool result as `{sql, result}` so callers can see the SQL that ran.
+    pub include_query: bool,
+    /// DuckDB thread count and memory limit for session connections.
+    pub db_options: crate::db::DbOptions,
+    /// Tables and record types hidden from sessions (`--restrict-sensitive`); empty for none.
+    pub sensitive_denylist: Vec<String>,
+    /// Open the database read-write so write tools like `add_annotation` work. HTTP sessions
+    /// then share one connection instead of opening their own.
+    pub maintenance: bool,
+    /// Write dates in tool results the way this locale does (`--output-locale`).
+    pub output_locale: Option<locale::OutputLocale>,
+    /// With `output_locale`, also turn numbers into locale-formatted strings.
+    pub locale_numbers: bool,
+    /// Requests per minute each client IP may send to `/mcp` (HTTP transport only).
+    pub rate_limit: Option<u32>,
+    /// Origins browsers may call the HTTP server from (`--cors-origin`); `*` allows any.
+    /// Empty adds no CORS headers.
+    pub cors_origins: Vec<String>,
+    /// Serve Prometheus tool-call metrics at `GET /metrics` (`--metrics`, HTTP transport only).
+    pub metrics: bool,
+    /// Row cap for every list tool (`--max-rows`), lowering the built-in caps in
+    /// `DEFAULT_TOOL_MAX_ROWS`.
+    pub max_rows: Option<u32>,
+    /// Per-tool row caps (`--tool-max-rows TOOL=N`), replacing both the built-in cap and
+    /// `max_rows` for that tool.
+    pub tool_max_rows: Vec<(String, u32)>,
+}
+
+/// Default row cap for `run_custom_query`, so an unbounded SELECT can't produce a huge response.
+pub const DEFAULT_MAX_QUERY_ROWS: usize = 10_000;
+
+/// Built-in row caps of the list tools taking a `limit`. A larger requested `limit` is lowered
+/// to the cap; these tools return `{"rows": [...], "truncated": bool, "limit": N}`, with
+/// `truncated` set when the cap cut the rows short.
+pub const DEFAULT_TOOL_MAX_ROWS: &[(&str, u32)] = &[
+    ("query_records", 1000),
+    ("query_window", 1000),
+    ("find_outliers", 1000),
+    ("get_daily_peaks", 365),
+    ("list_workouts", 500),
+    ("get_activity_summaries", 365),
+    ("get_daily_activity_trends", 365),
+    ("list_clinical_records", 1000),
+    ("get_workout_route", 10_000),
+];
+
+/// `run_custom_query` results with more rows than this are written as compact JSON, row by
+/// row, instead of pretty-printed.
+const PRETTY_MAX_ROWS: usize = 1_000;
+
 #[derive(Clone)]
 pub struct HealthServer {
     db_path: PathBuf,
-    conn: Arc<Mutex<Connection>>,
+    conn: Arc<pool::ConnectionPool>,
+    config: ServerConfig,
     tool_router: ToolRouter<Self>,
 }
 
@@ -31,112 +127,630 @@ impl std::fmt::Debug for HealthServer {
 
 impl HealthServer {
     pub fn new(db_path: &Path) -> Result<Self> {
-        let conn = crate::db::open_db_readonly(db_path)?;
+        Self::open(db_path, &ServerConfig::default(), false)
+    }
+
+    /// Like `new`, but with DuckDB external access disabled so `run_custom_query`
+    /// cannot read local files or URLs.
+    pub fn new_restricted(db_path: &Path) -> Result<Self> {
+        Self::open(db_path, &ServerConfig::default(), true)
+    }
+
+    /// Open a session with `config`, using its DuckDB resource settings. `restricted`
+    /// disables external access as in `new_restricted`. With `config.maintenance` the
+    /// connection is read-write and the schema is brought up to date first.
+    pub fn open(db_path: &Path, config: &ServerConfig, restricted: bool) -> Result<Self> {
+        let options = &config.db_options;
+        let conn = match (config.maintenance, restricted) {
+            (true, true) => crate::db::open_db_restricted(db_path, options)?,
+            (true, false) => crate::db::open_db_with_options(db_path, options)?,
+            (false, true) => crate::db::open_db_readonly_restricted(db_path, options)?,
+            (false, false) => crate::db::open_db_readonly_with_options(db_path, options)?,
+        };
+        if config.maintenance {
+            crate::db::ensure_schema(&conn)?;
+            crate::db::migrate(&conn)?;
+        }
+        let mut server = Self::from_connection(db_path, conn)?.with_config(config.clone());
+        server.restrict_sensitive()?;
+        Ok(server)
+    }
+
+    fn from_connection(db_path: &Path, conn: Connection) -> Result<Self> {
+        crate::db::verify_schema(&conn)?;
         Ok(Self {
             db_path: db_path.to_path_buf(),
-            conn: Arc::new(Mutex::new(conn)),
+            conn: Arc::new(pool::ConnectionPool::new(conn, pool::DEFAULT_POOL_SIZE)?),
+            config: ServerConfig::default(),
             tool_router: Self::tool_router(),
         })
     }
 
+    /// A session over `conn` alone (no pool), for in-memory databases in tests.
     pub fn new_in_memory(conn: Connection) -> Self {
         Self {
             db_path: PathBuf::from(":memory:"),
-            conn: Arc::new(Mutex::new(conn)),
+            conn: Arc::new(pool::ConnectionPool::single(conn)),
+            config: ServerConfig::default(),
             tool_router: Self::tool_router(),
         }
     }
 
+    pub fn with_config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Hide `config.sensitive_denylist` from this session: filter the data through temp views
+    /// and drop the tools that read denied tables (see `sensitive`).
+    fn restrict_sensitive(&mut self) -> Result<()> {
+        let denylist = sensitive::Denylist::new(&self.config.sensitive_denylist);
+        if denylist.is_empty() {
+            return Ok(());
+        }
+        // Temp views are per connection
+        self.conn
+            .for_each(|conn| sensitive::install_views(conn, &denylist))?;
+        for tool in denylist.denied_tools() {
+            self.tool_router.remove_route(tool);
+        }
+        Ok(())
+    }
+
+    /// Attach an `annotations` array to each stats row: annotations overlapping the `period`
+    /// starting at the row's `period` date.
+    fn overlay_annotations(&self, rows: &mut Value, period: &str) -> Result<(), String> {
+        let annotations = self.query_to_json(
+            "SELECT tag, note, CAST(start_date AS VARCHAR) AS start_date, \
+             CAST(end_date AS VARCHAR) AS end_date FROM annotations ORDER BY start_date, tag",
+            &[],
+        )?;
+        let annotations = annotations.as_array().cloned().unwrap_or_default();
+        let date = |v: &Value| {
+            v.as_str()
+                .and_then(|s| s.get(..10))
+                .and_then(|s| chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").ok())
+        };
+
+        for row in rows.as_array_mut().into_iter().flatten() {
+            let Some(start) = date(&row["period"]) else {
+                continue;
+            };
+            let end = period_end(start, period);
+            let overlapping: Vec<Value> = annotations
+                .iter()
+                .filter(|a| match (date(&a["start_date"]), date(&a["end_date"])) {
+                    (Some(a_start), Some(a_end)) => a_start < end && a_end >= start,
+                    _ => false,
+                })
+                .cloned()
+                .collect();
+            row["annotations"] = Value::Array(overlapping);
+        }
+        Ok(())
+    }
+
+    /// The row limit for a call to list tool `tool`: the caller's `limit` (or `default`),
+    /// lowered to the tool's cap. Returns the limit and whether the cap lowered it.
+    fn row_limit(&self, tool: &str, requested: Option<u32>, default: u32) -> (u32, bool) {
+        let cap = match self
+            .config
+            .tool_max_rows
+            .iter()
+            .find(|(name, _)| name == tool)
+        {
+            Some(&(_, cap)) => cap,
+            None => {
+                let built_in = DEFAULT_TOOL_MAX_ROWS
+                    .iter()
+                    .find(|(name, _)| *name == tool)
+                    .map_or(u32::MAX, |&(_, cap)| cap);
+                built_in.min(self.config.max_rows.unwrap_or(u32::MAX))
+            }
+        };
+        let wanted = requested.unwrap_or(default);
+        (wanted.min(cap), wanted > cap)
+    }
+
+    /// The day `daily_record_stats` puts the UTC timestamp `column` on, as SQL (see
+    /// `db::local_date_expr`). Take it before a connection of your own: it borrows one.
+    fn local_date_expr(&self, column: &str) -> Result<String, String> {
+        let conn = self.conn.get()?;
+        crate::db::local_date_expr(&conn, column).map_err(|e| e.to_string())
+    }
+
+    /// `column` as wall-clock time in the import timezone, as SQL (see `db::local_time_expr`).
+    fn local_time_expr(&self, column: &str) -> Result<String, String> {
+        let conn = self.conn.get()?;
+        crate::db::local_time_expr(&conn, column).map_err(|e| e.to_string())
+    }
+
+    /// The caller's `start_date` if given, otherwise today minus `default_range_days` (if configured).
+    fn effective_start_date(&self, start_date: Option<String>) -> Option<String> {
+        start_date.or_else(|| {
+            self.config.default_range_days.map(|days| {
+                (chrono::Utc::now() - chrono::Duration::days(days as i64))
+                    .format("%Y-%m-%d")
+                    .to_string()
+            })
+        })
+    }
+
     pub fn query_to_json(&self, sql: &str, params: &[&dyn duckdb::ToSql]) -> Result<Value, String> {
-        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let mut results = Vec::new();
+        self.for_each_row(sql, params, |row| {
+            results.push(row);
+            true
+        })?;
+        Ok(Value::Array(results))
+    }
+
+    /// Run `sql` and pass each row, mapped as by `query_to_json`, to `f` as it is read instead
+    /// of collecting them. Stops early once `f` returns false.
+    fn for_each_row(
+        &self,
+        sql: &str,
+        params: &[&dyn duckdb::ToSql],
+        mut f: impl FnMut(Value) -> bool,
+    ) -> Result<(), String> {
+        let _ = QUERY_LOG.try_with(|log| log.borrow_mut().push(sql.to_string()));
+        let started = std::time::Instant::now();
+        let conn = self.conn.get()?;
         let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
 
         let rows = stmt
-            .query_map(params, |row| {
-                let column_count = row.as_ref().column_count();
-                let mut map = serde_json::Map::new();
-                for i in 0..column_count {
-                    let name = row
-                        .as_ref()
-                        .column_name(i)
-                        .map(|s| s.to_string())
-                        .unwrap_or_else(|_| format!("col{}", i));
-                    let val = match row.get_ref(i) {
-                        Ok(ValueRef::Null) => continue,
-                        Ok(ValueRef::Boolean(b)) => Value::Bool(b),
-                        Ok(ValueRef::TinyInt(n)) => Value::Number(n.into()),
-                        Ok(ValueRef::SmallInt(n)) => Value::Number(n.into()),
-                        Ok(ValueRef::Int(n)) => Value::Number(n.into()),
-                        Ok(ValueRef::BigInt(n)) => Value::Number(n.into()),
-                        Ok(ValueRef::HugeInt(n)) => {
-                            // HugeInt may exceed JSON number range, use string
-                            if let Ok(n64) = i64::try_from(n) {
-                                Value::Number(n64.into())
-                            } else {
-                                Value::String(n.to_string())
-                            }
-                        }
-                        Ok(ValueRef::UTinyInt(n)) => Value::Number(n.into()),
-                        Ok(ValueRef::USmallInt(n)) => Value::Number(n.into()),
-                        Ok(ValueRef::UInt(n)) => Value::Number(n.into()),
-                        Ok(ValueRef::UBigInt(n)) => {
-                            if let Ok(n64) = i64::try_from(n) {
-                                Value::Number(n64.into())
-                            } else {
-                                Value::String(n.to_string())
-                            }
-                        }
-                        Ok(ValueRef::Float(f)) => serde_json::Number::from_f64(f as f64)
-                            .map(Value::Number)
-                            .unwrap_or(Value::String(f.to_string())),
-                        Ok(ValueRef::Double(f)) => serde_json::Number::from_f64(f)
-                            .map(Value::Number)
-                            .unwrap_or(Value::String(f.to_string())),
-                        Ok(ValueRef::Text(bytes)) => {
-                            Value::String(String::from_utf8_lossy(bytes).into_owned())
-                        }
-                        Ok(_) => {
-                            // Timestamp, Date32, Time64, Decimal, Interval, etc.
-                            // Fall back to string via DuckDB's own formatting
-                            match row.get::<_, String>(i) {
-                                Ok(s) => Value::String(s),
-                                Err(_) => continue,
-                            }
-                        }
-                        Err(_) => continue,
-                    };
-                    map.insert(name, val);
-                }
-                Ok(Value::Object(map))
-            })
+            .query_map(params, |row| Ok(row_to_json(row)))
             .map_err(|e| e.to_string())?;
+        let mut row_count = 0u64;
+        for row in rows.flatten() {
+            row_count += 1;
+            if !f(row) {
+                break;
+            }
+        }
 
-        let results: Vec<Value> = rows.filter_map(|r| r.ok()).collect();
-        Ok(Value::Array(results))
+        let elapsed_ms = started.elapsed().as_millis();
+        if elapsed_ms >= SLOW_QUERY_MS {
+            tracing::warn!(rows = row_count, elapsed_ms, sql, "slow query");
+        } else {
+            tracing::debug!(rows = row_count, elapsed_ms, sql, "query");
+        }
+        Ok(())
+    }
+
+    /// Average `record_type` into fixed-width time buckets (e.g. "1 minute") over
+    /// records overlapping the `[start, end]` window. Returns bucket_start, avg_value,
+    /// min_value, max_value and sample_count per bucket, ordered by time.
+    fn resample_records(
+        &self,
+        record_type: &str,
+        start: &str,
+        end: &str,
+        bucket: &str,
+    ) -> Result<Value, String> {
+        let sql = format!(
+            "SELECT CAST(time_bucket(INTERVAL '{bucket}', start_date) AS VARCHAR) AS bucket_start, \
+             AVG(value) AS avg_value, MIN(value) AS min_value, MAX(value) AS max_value, \
+             COUNT(*) AS sample_count \
+             FROM records \
+             WHERE record_type = ? AND value IS NOT NULL \
+             AND start_date <= CAST(? AS TIMESTAMP) AND end_date >= CAST(? AS TIMESTAMP) \
+             GROUP BY bucket_start ORDER BY bucket_start"
+        );
+        self.query_to_json(
+            &sql,
+            &[
+                &record_type as &dyn duckdb::ToSql,
+                &end as &dyn duckdb::ToSql,
+                &start as &dyn duckdb::ToSql,
+            ],
+        )
+    }
+
+    /// Look up a workout's `(start_date, end_date)` as strings.
+    fn workout_window(&self, workout_hash: &str) -> Result<Option<(String, String)>, String> {
+        let rows = self.query_to_json(
+            "SELECT CAST(start_date AS VARCHAR) AS start_date, CAST(end_date AS VARCHAR) AS end_date \
+             FROM workouts WHERE workout_hash = ?",
+            &[&workout_hash as &dyn duckdb::ToSql],
+        )?;
+        Ok(rows.as_array().and_then(|a| a.first()).and_then(|w| {
+            Some((
+                w.get("start_date")?.as_str()?.to_string(),
+                w.get("end_date")?.as_str()?.to_string(),
+            ))
+        }))
+    }
+}
+
+/// Map a DuckDB row to a JSON object (see `HealthServer::query_to_json`).
+fn row_to_json(row: &duckdb::Row<'_>) -> Value {
+    let column_count = row.as_ref().column_count();
+    let mut map = serde_json::Map::new();
+    for i in 0..column_count {
+        let name = row
+            .as_ref()
+            .column_name(i)
+            .map(|s| s.to_string())
+            .unwrap_or_else(|_| format!("col{}", i));
+        let val = match row.get_ref(i) {
+            Ok(ValueRef::Null) => continue,
+            Ok(ValueRef::Boolean(b)) => Value::Bool(b),
+            Ok(ValueRef::TinyInt(n)) => Value::Number(n.into()),
+            Ok(ValueRef::SmallInt(n)) => Value::Number(n.into()),
+            Ok(ValueRef::Int(n)) => Value::Number(n.into()),
+            Ok(ValueRef::BigInt(n)) => Value::Number(n.into()),
+            Ok(ValueRef::HugeInt(n)) => {
+                // HugeInt may exceed JSON number range, use string
+                if let Ok(n64) = i64::try_from(n) {
+                    Value::Number(n64.into())
+                } else {
+                    Value::String(n.to_string())
+                }
+            }
+            Ok(ValueRef::UTinyInt(n)) => Value::Number(n.into()),
+            Ok(ValueRef::USmallInt(n)) => Value::Number(n.into()),
+            Ok(ValueRef::UInt(n)) => Value::Number(n.into()),
+            Ok(ValueRef::UBigInt(n)) => {
+                if let Ok(n64) = i64::try_from(n) {
+                    Value::Number(n64.into())
+                } else {
+                    Value::String(n.to_string())
+                }
+            }
+            Ok(ValueRef::Float(f)) => serde_json::Number::from_f64(f as f64)
+                .map(Value::Number)
+                .unwrap_or(Value::String(f.to_string())),
+            Ok(ValueRef::Double(f)) => serde_json::Number::from_f64(f)
+                .map(Value::Number)
+                .unwrap_or(Value::String(f.to_string())),
+            Ok(ValueRef::Text(bytes)) => Value::String(String::from_utf8_lossy(bytes).into_owned()),
+            Ok(_) => {
+                // Timestamp, Date32, Time64, Decimal, Interval, etc.
+                // Fall back to string via DuckDB's own formatting
+                match row.get::<_, String>(i) {
+                    Ok(s) => Value::String(s),
+                    Err(_) => continue,
+                }
+            }
+            Err(_) => continue,
+        };
+        map.insert(name, val);
+    }
+    Value::Object(map)
+}
+
+/// `'a', 'b', ...` for a SQL `IN (...)` list.
+fn sql_string_list(values: &[String]) -> String {
+    values
+        .iter()
+        .map(|v| format!("'{}'", v.replace('\'', "''")))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Error result for a tool: `{"error": {"kind": ..., "message": ...}}`. Kinds in use are
+/// `query_failed`, `invalid_argument`, `not_found`, and `restricted`.
+fn err_json(kind: &str, message: impl std::fmt::Display) -> String {
+    serde_json::to_string_pretty(&json!({
+        "error": {
+            "kind": kind,
+            "message": message.to_string(),
+        }
+    }))
+    .unwrap_or_default()
+}
+
+/// True if a tool's output is an `err_json` object.
+fn is_err_json(output: &str) -> bool {
+    err_kind(output).is_some()
+}
+
+/// The `kind` of an `err_json` object, or `None` for any other output.
+fn err_kind(output: &str) -> Option<String> {
+    // err_json output is always pretty-printed; checking the prefix first keeps large
+    // results (e.g. a truncated `run_custom_query`) from being parsed just to rule them out
+    if !output.starts_with("{\n  \"error\"") {
+        return None;
+    }
+    let value = serde_json::from_str::<Value>(output).ok()?;
+    value["error"]["kind"].as_str().map(str::to_string)
+}
+
+/// Wrap a row-capped tool's `rows` as `{"rows": [...], "truncated": bool, "limit": N}`.
+/// `truncated` is set when a row cap lowered the caller's limit (see `HealthServer::row_limit`)
+/// and the result filled it.
+fn capped_rows(rows: Value, limit: u32, capped: bool) -> Value {
+    let filled = rows.as_array().is_some_and(|r| r.len() >= limit as usize);
+    json!({ "rows": rows, "truncated": capped && filled, "limit": limit })
+}
+
+/// Great-circle distance in meters between two WGS84 points.
+fn haversine_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    const EARTH_RADIUS_M: f64 = 6_371_000.0;
+    let (dlat, dlon) = ((lat2 - lat1).to_radians(), (lon2 - lon1).to_radians());
+    let a = (dlat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// Seconds per kilometer as `m:ss`.
+fn format_pace(seconds_per_km: f64) -> String {
+    format!(
+        "{}:{:02}",
+        (seconds_per_km / 60.0) as u64,
+        (seconds_per_km % 60.0).round() as u64
+    )
+}
+
+/// Fastest plausible speed in m/s for a workout activity type, used by `validate_routes`
+/// to spot GPS jumps. Generous on purpose: sprints, descents, and ski runs must pass.
+fn max_plausible_speed_mps(activity_type: &str) -> f64 {
+    let activity = activity_type.trim_start_matches("HKWorkoutActivityType");
+    match activity {
+        "Walking" | "Hiking" | "Swimming" | "Rowing" | "Paddling" => 8.0,
+        "Running" | "CrossCountrySkiing" | "Skating" => 12.5,
+        "Cycling" | "HandCycling" | "WheelchairRunPace" => 30.0,
+        "DownhillSkiing" | "Snowboarding" | "SnowSports" | "Sailing" => 45.0,
+        _ => 50.0,
+    }
+}
+
+/// Fastest continuous stretch of `target_m` meters in one track, given points in time order.
+/// The segment start is interpolated between points so the result covers exactly `target_m`.
+/// Returns `(elapsed_seconds, start_epoch, end_epoch)`.
+fn fastest_segment(points: &[TrackPoint], target_m: f64) -> Option<(f64, f64, f64)> {
+    let mut best: Option<(f64, f64, f64)> = None;
+    let mut i = 0;
+    for j in 0..points.len() {
+        let (end_d, end_t) = points[j];
+        // Latest start point that still leaves at least target_m to the end
+        while i + 1 < j && end_d - points[i + 1].0 >= target_m {
+            i += 1;
+        }
+        if i >= j || end_d - points[i].0 < target_m {
+            continue;
+        }
+        let ((d0, t0), (d1, t1)) = (points[i], points[i + 1]);
+        let frac = if d1 > d0 {
+            (end_d - target_m - d0) / (d1 - d0)
+        } else {
+            0.0
+        };
+        let start_t = t0 + frac * (t1 - t0);
+        let elapsed = end_t - start_t;
+        if best.is_none_or(|(b, _, _)| elapsed < b) {
+            best = Some((elapsed, start_t, end_t));
+        }
+    }
+    best
+}
+
+/// Durations, in seconds, reported by `get_power_curve`.
+const POWER_CURVE_DURATIONS: [usize; 4] = [5, 60, 300, 1200];
+
+/// Best average power over each of `durations` seconds of a `seconds`-long workout. Power
+/// samples are `(offset_seconds, watts)` in time order; each holds until the next sample (or the
+/// workout end), and seconds before the first sample count as zero. Durations longer than the
+/// workout are skipped.
+fn power_curve(samples: &[(f64, f64)], seconds: f64, durations: &[usize]) -> Vec<(usize, f64)> {
+    let len = seconds.max(0.0).floor() as usize;
+    let mut watts = vec![0.0; len];
+    for (i, &(t, w)) in samples.iter().enumerate() {
+        let from = t.max(0.0).floor() as usize;
+        let to = samples
+            .get(i + 1)
+            .map_or(len, |&(next, _)| next.max(0.0).floor() as usize)
+            .min(len);
+        if from < to {
+            watts[from..to].fill(w);
+        }
+    }
+
+    let mut prefix = vec![0.0; len + 1];
+    for (i, w) in watts.iter().enumerate() {
+        prefix[i + 1] = prefix[i] + w;
+    }
+    durations
+        .iter()
+        .filter(|&&d| d > 0 && d <= len)
+        .map(|&d| {
+            let best = (d..=len)
+                .map(|end| prefix[end] - prefix[end - d])
+                .fold(f64::MIN, f64::max);
+            (d, best / d as f64)
+        })
+        .collect()
+}
+
+/// Hearing-loss grade for a pure-tone threshold in dB HL.
+fn hearing_grade(level_db: f64) -> &'static str {
+    match level_db {
+        l if l <= 25.0 => "normal",
+        l if l <= 40.0 => "mild",
+        l if l <= 60.0 => "moderate",
+        l if l <= 80.0 => "severe",
+        _ => "profound",
+    }
+}
+
+/// First day after the `period` (as accepted by `period_expr`) starting on `start`.
+fn period_end(start: chrono::NaiveDate, period: &str) -> chrono::NaiveDate {
+    let next = match period {
+        "week" => start.checked_add_days(chrono::Days::new(7)),
+        "month" => start.checked_add_months(chrono::Months::new(1)),
+        "year" => start.checked_add_months(chrono::Months::new(12)),
+        _ => start.checked_add_days(chrono::Days::new(1)),
+    };
+    next.unwrap_or(chrono::NaiveDate::MAX)
+}
+
+/// SQL expression bucketing `daily_record_stats.date` into the requested period.
+fn period_expr(period: &str) -> &'static str {
+    match period {
+        "week" => "DATE_TRUNC('week', date)",
+        "month" => "DATE_TRUNC('month', date)",
+        "year" => "DATE_TRUNC('year', date)",
+        _ => "date",
+    }
+}
+
+/// Whether `[start, end]` (YYYY-MM-DD, either open) covers only whole weeks (ISO, from Monday)
+/// or months, so the `weekly_record_stats` / `monthly_record_stats` rollups answer it exactly.
+fn period_aligned(period: &str, start: Option<&str>, end: Option<&str>) -> bool {
+    use chrono::{Datelike, Weekday};
+    let parse = |s: &str| chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").ok();
+    let is_first = |d: chrono::NaiveDate| match period {
+        "week" => d.weekday() == Weekday::Mon,
+        "month" => d.day() == 1,
+        _ => false,
+    };
+    let start_ok = start.is_none_or(|s| parse(s).is_some_and(is_first));
+    let end_ok = end.is_none_or(|s| parse(s).and_then(|d| d.succ_opt()).is_some_and(is_first));
+    start_ok && end_ok
+}
+
+/// Pearson correlation coefficient of two equally long series.
+/// Returns None for fewer than two points or when either series has zero variance.
+fn pearson(xs: &[f64], ys: &[f64]) -> Option<f64> {
+    let n = xs.len();
+    if n < 2 || n != ys.len() {
+        return None;
+    }
+    let mean_x = xs.iter().sum::<f64>() / n as f64;
+    let mean_y = ys.iter().sum::<f64>() / n as f64;
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (x, y) in xs.iter().zip(ys) {
+        cov += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x).powi(2);
+        var_y += (y - mean_y).powi(2);
     }
+    if var_x == 0.0 || var_y == 0.0 {
+        return None;
+    }
+    Some(cov / (var_x.sqrt() * var_y.sqrt()))
 }
 
 #[tool_router]
 impl HealthServer {
     #[tool(
-        description = "List all available health record types with counts and date ranges. Use this first to discover what data is available. Returns: type (e.g. HKQuantityTypeIdentifierHeartRate, HKQuantityTypeIdentifierStepCount), count, unit, earliest_date, latest_date."
+        description = "List all available health record types with counts and date ranges. Use this first to discover what data is available. Optionally hide types with fewer than min_count records or keep only those whose name contains name_contains. Returns: type (e.g. HKQuantityTypeIdentifierHeartRate, HKQuantityTypeIdentifierStepCount), count, unit, earliest_date, latest_date."
     )]
-    async fn list_record_types(&self) -> String {
-        let sql = "SELECT record_type as type, COUNT(*) as count, unit, MIN(start_date) as earliest_date, MAX(start_date) as latest_date FROM records GROUP BY record_type, unit ORDER BY count DESC";
-        match self.query_to_json(sql, &[]) {
+    async fn list_record_types(&self, params: Parameters<ListRecordTypesParams>) -> String {
+        let Parameters(params) = params;
+        let mut sql = String::from("SELECT record_type as type, COUNT(*) as count, unit, MIN(start_date) as earliest_date, MAX(start_date) as latest_date FROM records");
+        let mut bind: Vec<&dyn duckdb::ToSql> = Vec::new();
+        if let Some(ref text) = params.name_contains {
+            sql.push_str(" WHERE contains(lower(record_type), lower(?))");
+            bind.push(text);
+        }
+        sql.push_str(" GROUP BY record_type, unit");
+        let min_count = params.min_count.map(|n| n as i64);
+        if let Some(ref n) = min_count {
+            sql.push_str(" HAVING COUNT(*) >= ?");
+            bind.push(n);
+        }
+        sql.push_str(" ORDER BY count DESC");
+        match self.query_to_json(&sql, &bind) {
             Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
-            Err(e) => format!("Error: {}", e),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+
+    #[tool(
+        description = "Catalog of the health data available, meant as context before writing queries. Returns per record type: record_type, friendly_name (e.g. Heart Rate), units, count, earliest_date, latest_date, and kind: cumulative (sum over a period, e.g. steps, energy), discrete (average, e.g. heart rate, body mass), or category (enum-coded samples, e.g. sleep analysis)."
+    )]
+    async fn describe_data(&self) -> String {
+        let sql = "SELECT record_type, string_agg(DISTINCT unit, ',' ORDER BY unit) AS units, COUNT(*) AS count, \
+                   CAST(MIN(start_date) AS VARCHAR) AS earliest_date, CAST(MAX(start_date) AS VARCHAR) AS latest_date \
+                   FROM records GROUP BY record_type ORDER BY count DESC";
+        let rows = match self.query_to_json(sql, &[]) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let catalog: Vec<Value> = rows
+            .as_array()
+            .map(|rows| {
+                rows.iter()
+                    .map(|r| {
+                        let record_type = r["record_type"].as_str().unwrap_or_default();
+                        let units: Vec<&str> = r
+                            .get("units")
+                            .and_then(|u| u.as_str())
+                            .map(|u| u.split(',').collect())
+                            .unwrap_or_default();
+                        json!({
+                            "record_type": record_type,
+                            "friendly_name": friendly_record_name(record_type),
+                            "kind": record_kind(record_type).as_str(),
+                            "units": units,
+                            "count": r["count"],
+                            "earliest_date": r["earliest_date"],
+                            "latest_date": r["latest_date"],
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        serde_json::to_string_pretty(&catalog).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Data-quality check: record types stored in more than one unit (e.g. body mass in both kg and lb), whose raw values can't be averaged or summed together without conversion. Returns array of: record_type, friendly_name, total (records with a unit), and units (most common first: unit, count, earliest_date, latest_date). An empty array means every type uses a single unit."
+    )]
+    async fn check_unit_consistency(&self) -> String {
+        let sql = "SELECT record_type, unit, COUNT(*) AS count, \
+                   CAST(MIN(start_date) AS VARCHAR) AS earliest_date, CAST(MAX(start_date) AS VARCHAR) AS latest_date \
+                   FROM records WHERE unit IS NOT NULL AND record_type IN ( \
+                       SELECT record_type FROM records WHERE unit IS NOT NULL \
+                       GROUP BY record_type HAVING COUNT(DISTINCT unit) > 1) \
+                   GROUP BY record_type, unit ORDER BY record_type, count DESC, unit";
+        let rows = match self.query_to_json(sql, &[]) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let mut mixed: Vec<Value> = Vec::new();
+        for row in rows.as_array().into_iter().flatten() {
+            let record_type = row["record_type"].as_str().unwrap_or_default();
+            let count = row["count"].as_i64().unwrap_or(0);
+            let unit = json!({
+                "unit": row["unit"],
+                "count": count,
+                "earliest_date": row["earliest_date"],
+                "latest_date": row["latest_date"],
+            });
+            match mixed.last_mut() {
+                Some(last) if last["record_type"] == record_type => {
+                    last["total"] = (last["total"].as_i64().unwrap_or(0) + count).into();
+                    if let Some(units) = last["units"].as_array_mut() {
+                        units.push(unit);
+                    }
+                }
+                _ => mixed.push(json!({
+                    "record_type": record_type,
+                    "friendly_name": friendly_record_name(record_type),
+                    "total": count,
+                    "units": [unit],
+                })),
+            }
         }
+
+        serde_json::to_string_pretty(&mixed).unwrap_or_default()
     }
 
     #[tool(
-        description = "Query individual health records. Returns: record_hash, record_type, value (numeric measurement), unit, source_name, start_date, end_date. Record types use Apple's HK identifiers (e.g. HKQuantityTypeIdentifierHeartRate). Use list_record_types first to discover available types."
+        description = "Query individual health records. Returns {rows, truncated, limit}; truncated is true when the row cap cut the result short. Rows have: record_hash, record_type, value (numeric measurement), value_text (raw value of category records such as sleep stages, whose value is empty), unit, source_name, start_date, end_date. Record types use Apple's HK identifiers (e.g. HKQuantityTypeIdentifierHeartRate). Results are ordered newest first (start_date DESC, then record_hash), so limit + offset page stably through long series. Use list_record_types first to discover available types."
     )]
     async fn query_records(&self, params: Parameters<QueryRecordsParams>) -> String {
-        let Parameters(params) = params;
-        let limit = params.limit.unwrap_or(100).min(1000);
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let (limit, capped) = self.row_limit("query_records", params.limit, 100);
+        let offset = params.offset.unwrap_or(0);
         let mut sql = String::from(
-            "SELECT record_hash, record_type, value, unit, source_name, start_date, end_date FROM records WHERE record_type = ?",
+            "SELECT record_hash, record_type, value, value_text, unit, source_name, start_date, end_date FROM records WHERE record_type = ?",
         );
         let record_type = params.record_type;
 
@@ -149,564 +763,5765 @@ impl HealthServer {
         if let Some(ref sn) = params.source_name {
             sql.push_str(&format!(" AND source_name = '{}'", sn.replace('\'', "''")));
         }
-        sql.push_str(&format!(" ORDER BY start_date DESC LIMIT {}", limit));
+        sql.push_str(" ORDER BY start_date DESC, record_hash LIMIT ? OFFSET ?");
 
-        match self.query_to_json(&sql, &[&record_type as &dyn duckdb::ToSql]) {
-            Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
-            Err(e) => format!("Error: {}", e),
+        match self.query_to_json(
+            &sql,
+            &[
+                &record_type as &dyn duckdb::ToSql,
+                &limit as &dyn duckdb::ToSql,
+                &offset as &dyn duckdb::ToSql,
+            ],
+        ) {
+            Ok(result) => serde_json::to_string_pretty(&capped_rows(result, limit, capped))
+                .unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
         }
     }
 
     #[tool(
-        description = "Get aggregated statistics for a record type over time periods. Returns: period, count, avg_value, min_value, max_value, sum_value. Uses pre-computed daily_record_stats table for fast aggregation. Prefer this over query_records for trends and summaries."
+        description = "Fetch records of any type (or only the given record_types) overlapping a time window, e.g. everything around 15:00, for correlating events across metrics. Returns {rows, truncated, limit} (truncated: the row cap cut the result short); rows have: record_hash, record_type, value, value_text, unit, source_name, start_date, end_date; ordered by start_date, then record_type."
     )]
-    async fn get_record_statistics(&self, params: Parameters<GetRecordStatisticsParams>) -> String {
+    async fn query_window(&self, params: Parameters<QueryWindowParams>) -> String {
         let Parameters(params) = params;
-        let period = params.period.as_deref().unwrap_or("day");
-        let date_trunc = match period {
-            "week" => "DATE_TRUNC('week', date)",
-            "month" => "DATE_TRUNC('month', date)",
-            "year" => "DATE_TRUNC('year', date)",
-            _ => "date",
-        };
-
-        let mut sql = format!(
-            "SELECT {} as period, SUM(count) as count, \
-             SUM(sum_value)/SUM(count) as avg_value, \
-             MIN(min_value) as min_value, MAX(max_value) as max_value, \
-             SUM(sum_value) as sum_value \
-             FROM daily_record_stats WHERE record_type = ?",
-            date_trunc
+        let (limit, capped) = self.row_limit("query_window", params.limit, 100);
+        let mut sql = String::from(
+            "SELECT record_hash, record_type, value, value_text, unit, source_name, \
+                    CAST(start_date AS VARCHAR) AS start_date, CAST(end_date AS VARCHAR) AS end_date \
+             FROM records WHERE start_date <= CAST(? AS TIMESTAMP) AND end_date >= CAST(? AS TIMESTAMP)",
         );
-
-        let record_type = params.record_type;
-
-        if let Some(ref sd) = params.start_date {
-            sql.push_str(&format!(" AND date >= '{}'", sd.replace('\'', "''")));
+        if let Some(ref types) = params.record_types {
+            if types.is_empty() {
+                return err_json("invalid_argument", "record_types must not be empty");
+            }
+            sql.push_str(&format!(" AND record_type IN ({})", sql_string_list(types)));
         }
-        if let Some(ref ed) = params.end_date {
-            sql.push_str(&format!(" AND date <= '{}'", ed.replace('\'', "''")));
+        sql.push_str(" ORDER BY start_date, record_type, record_hash LIMIT ?");
+
+        match self.query_to_json(
+            &sql,
+            &[
+                &params.end_date as &dyn duckdb::ToSql,
+                &params.start_date,
+                &limit,
+            ],
+        ) {
+            Ok(result) => serde_json::to_string_pretty(&capped_rows(result, limit, capped))
+                .unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
         }
-        sql.push_str(&format!(" GROUP BY {} ORDER BY period", date_trunc));
+    }
+
+    #[tool(
+        description = "Get metadata entries for a single record by record_hash (from query_records). Returns array of: key, value (e.g. HKMetadataKeyHeartRateMotionContext, HKMetadataKeyWasUserEntered). Optionally filter to one key. Unknown hashes return an empty array."
+    )]
+    async fn get_record_metadata(&self, params: Parameters<GetRecordMetadataParams>) -> String {
+        let Parameters(params) = params;
+        let result = match params.key {
+            Some(ref key) => self.query_to_json(
+                "SELECT key, value FROM record_metadata WHERE record_hash = ? AND key = ? ORDER BY key",
+                &[
+                    &params.record_hash as &dyn duckdb::ToSql,
+                    key as &dyn duckdb::ToSql,
+                ],
+            ),
+            None => self.query_to_json(
+                "SELECT key, value FROM record_metadata WHERE record_hash = ? ORDER BY key",
+                &[&params.record_hash as &dyn duckdb::ToSql],
+            ),
+        };
 
-        match self.query_to_json(&sql, &[&record_type as &dyn duckdb::ToSql]) {
+        match result {
             Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
-            Err(e) => format!("Error: {}", e),
+            Err(e) => err_json("query_failed", e),
         }
     }
 
     #[tool(
-        description = "List workouts with optional filtering. Returns: workout_hash, activity_type (e.g. HKWorkoutActivityTypeRunning), duration, duration_unit, total_distance, total_distance_unit, total_energy_burned, total_energy_unit, source_name, start_date, end_date. Use workout_hash with get_workout_details or get_workout_route."
+        description = "Snapshot of the most recent reading of every record type (or only the given record_types) in one call, e.g. for a current-status overview. Returns array of: record_type, value, value_text (category records such as sleep stages), unit, source_name, start_date, end_date; ordered by record_type."
     )]
-    async fn list_workouts(&self, params: Parameters<ListWorkoutsParams>) -> String {
+    async fn get_latest_readings(&self, params: Parameters<GetLatestReadingsParams>) -> String {
         let Parameters(params) = params;
-        let limit = params.limit.unwrap_or(50).min(500);
         let mut sql = String::from(
-            "SELECT workout_hash, activity_type, duration, duration_unit, \
-             total_distance, total_distance_unit, total_energy_burned, total_energy_unit, \
-             source_name, start_date, end_date FROM workouts WHERE 1=1",
+            "SELECT DISTINCT ON (record_type) record_type, value, value_text, unit, source_name, \
+                    CAST(start_date AS VARCHAR) AS start_date, CAST(end_date AS VARCHAR) AS end_date \
+             FROM records",
         );
-
-        if let Some(ref at) = params.activity_type {
+        if let Some(ref types) = params.record_types {
+            if types.is_empty() {
+                return err_json("invalid_argument", "record_types must not be empty");
+            }
             sql.push_str(&format!(
-                " AND activity_type = '{}'",
-                at.replace('\'', "''")
+                " WHERE record_type IN ({})",
+                sql_string_list(types)
             ));
         }
-        if let Some(ref sd) = params.start_date {
-            sql.push_str(&format!(" AND start_date >= '{}'", sd.replace('\'', "''")));
+        sql.push_str(" ORDER BY record_type, start_date DESC, record_hash");
+
+        match self.query_to_json(&sql, &[]) {
+            Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
         }
-        if let Some(ref ed) = params.end_date {
-            sql.push_str(&format!(" AND end_date <= '{}'", ed.replace('\'', "''")));
+    }
+
+    #[tool(
+        description = "List the metadata keys present per record type, to discover what get_record_metadata and run_custom_query (record_metadata table) can filter on. Returns array of: record_type, key (e.g. HKMetadataKeyHeartRateMotionContext), count (records carrying the key). Optionally filter to one record type."
+    )]
+    async fn list_metadata_keys(&self, params: Parameters<ListMetadataKeysParams>) -> String {
+        let Parameters(params) = params;
+        let mut sql = String::from(
+            "SELECT r.record_type, m.key, COUNT(*) AS count \
+             FROM records r JOIN record_metadata m ON m.record_hash = r.record_hash",
+        );
+        if let Some(ref rt) = params.record_type {
+            sql.push_str(&format!(
+                " WHERE r.record_type = '{}'",
+                rt.replace('\'', "''")
+            ));
         }
-        sql.push_str(&format!(" ORDER BY start_date DESC LIMIT {}", limit));
+        sql.push_str(" GROUP BY r.record_type, m.key ORDER BY r.record_type, count DESC, m.key");
 
         match self.query_to_json(&sql, &[]) {
             Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
-            Err(e) => format!("Error: {}", e),
+            Err(e) => err_json("query_failed", e),
         }
     }
 
     #[tool(
-        description = "Get full workout details by workout_hash. Returns: workout object (all fields), events (lap/pause markers), statistics (per-metric breakdowns like heart rate zones), and has_route boolean. Get the workout_hash from list_workouts."
+        description = "Hearing test (audiogram) as a threshold curve per ear, for plotting frequency (Hz, x axis) against hearing level (dB HL, y axis; higher is worse). Find audiograms with query_records record_type HKDataTypeIdentifierAudiogram. Returns: record_hash, date, source_name, threshold_db, left_ear and right_ear (arrays in frequency order of frequency_hz, hearing_level_db, masked, grade, exceeds_threshold), and flagged_frequencies {left, right} listing frequencies above threshold_db. grade follows the common scale: normal (<= 25 dB), mild (26-40), moderate (41-60), severe (61-80), profound (> 80)."
     )]
-    async fn get_workout_details(&self, params: Parameters<GetWorkoutDetailsParams>) -> String {
+    async fn get_audiogram(&self, params: Parameters<GetAudiogramParams>) -> String {
         let Parameters(params) = params;
-        let hash = params.workout_hash;
+        let threshold_db = params.threshold_db.unwrap_or(25.0);
 
-        let workout = match self.query_to_json(
-            "SELECT * FROM workouts WHERE workout_hash = ?",
-            &[&hash as &dyn duckdb::ToSql],
-        ) {
-            Ok(r) => r,
-            Err(e) => return format!("Error: {}", e),
+        let date = match self.local_date_expr("start_date") {
+            Ok(expr) => expr,
+            Err(e) => return err_json("query_failed", e),
         };
-
-        let events = match self.query_to_json(
-            "SELECT event_type, date, duration, duration_unit FROM workout_events WHERE workout_hash = ?",
-            &[&hash as &dyn duckdb::ToSql],
+        let audiogram = match self.query_to_json(
+            &format!(
+                "SELECT record_hash, CAST({} AS VARCHAR) AS date, source_name FROM records WHERE record_hash = ?",
+                date
+            ),
+            &[&params.record_hash as &dyn duckdb::ToSql],
         ) {
-            Ok(r) => r,
-            Err(e) => return format!("Error: {}", e),
+            Ok(r) => match r.as_array().and_then(|rows| rows.first()) {
+                Some(row) => row.clone(),
+                None => {
+                    return err_json(
+                        "not_found",
+                        format!("Audiogram {} not found", params.record_hash),
+                    )
+                }
+            },
+            Err(e) => return err_json("query_failed", e),
         };
-
-        let statistics = match self.query_to_json(
-            "SELECT stat_type, start_date, end_date, average, minimum, maximum, sum, unit FROM workout_statistics WHERE workout_hash = ?",
-            &[&hash as &dyn duckdb::ToSql],
+        let points = match self.query_to_json(
+            "SELECT frequency_hz, left_ear_db, right_ear_db, left_ear_masked, right_ear_masked \
+             FROM audiogram_points WHERE record_hash = ? ORDER BY frequency_hz",
+            &[&params.record_hash as &dyn duckdb::ToSql],
         ) {
-            Ok(r) => r,
-            Err(e) => return format!("Error: {}", e),
+            Ok(r) => r.as_array().cloned().unwrap_or_default(),
+            Err(e) => return err_json("query_failed", e),
         };
 
-        let has_route = match self.query_to_json(
-            "SELECT COUNT(*) as count FROM route_points WHERE workout_hash = ?",
-            &[&hash as &dyn duckdb::ToSql],
-        ) {
-            Ok(r) => r,
-            Err(e) => return format!("Error: {}", e),
+        let ear = |side: &str| -> (Vec<Value>, Vec<Value>) {
+            let mut curve = Vec::new();
+            let mut flagged = Vec::new();
+            for p in &points {
+                let Some(level) = p[format!("{}_ear_db", side).as_str()].as_f64() else {
+                    continue;
+                };
+                let exceeds = level > threshold_db;
+                if exceeds {
+                    flagged.push(p["frequency_hz"].clone());
+                }
+                curve.push(json!({
+                    "frequency_hz": p["frequency_hz"],
+                    "hearing_level_db": level,
+                    "masked": p[format!("{}_ear_masked", side).as_str()],
+                    "grade": hearing_grade(level),
+                    "exceeds_threshold": exceeds,
+                }));
+            }
+            (curve, flagged)
         };
+        let (left_ear, left_flagged) = ear("left");
+        let (right_ear, right_flagged) = ear("right");
 
         let result = json!({
-            "workout": workout.as_array().and_then(|a| a.first()).cloned().unwrap_or(Value::Null),
-            "events": events,
-            "statistics": statistics,
-            "has_route": has_route.as_array().and_then(|a| a.first()).and_then(|r| r.get("count")).and_then(|c| c.as_i64()).unwrap_or(0) > 0,
+            "record_hash": audiogram["record_hash"],
+            "date": audiogram["date"],
+            "source_name": audiogram["source_name"],
+            "threshold_db": threshold_db,
+            "left_ear": left_ear,
+            "right_ear": right_ear,
+            "flagged_frequencies": {"left": left_flagged, "right": right_flagged},
         });
-
         serde_json::to_string_pretty(&result).unwrap_or_default()
     }
 
     #[tool(
-        description = "Get Apple Watch activity ring data. Returns: date_components, active_energy_burned, active_energy_burned_goal, apple_exercise_time, apple_exercise_time_goal, apple_stand_hours, apple_stand_hours_goal. Values are in kcal, minutes, and hours respectively."
+        description = "Get aggregated statistics for a record type over time periods. Returns: period, count (number of underlying records), avg_value, min_value, max_value, sum_value, days_with_data (distinct days contributing to the period), and moving_avg when moving_average_window is set (trailing mean of avg_value over the last N periods with data, including the current one; early rows use the periods available). With include_annotations, each period also gets annotations: the list_annotations entries (tag, note, start_date, end_date) overlapping it. With value_mode=duration_seconds, the aggregates are over each record's duration (end_date - start_date, in seconds) instead of its value, for category records like HKCategoryTypeIdentifierMindfulSession whose meaning is their length; sum_value is then the total time. With include_extremes, each period also gets max_value_date and min_value_date: the start_date of the record holding max_value / min_value (the earliest on ties). Uses the pre-computed daily_record_stats table for fast aggregation, or the weekly/monthly rollups when the range covers whole periods (records directly in duration mode). Prefer this over query_records for trends and summaries."
     )]
-    async fn get_activity_summaries(
-        &self,
-        params: Parameters<GetActivitySummariesParams>,
-    ) -> String {
-        let Parameters(params) = params;
-        let limit = params.limit.unwrap_or(30).min(365);
-        let mut sql = String::from("SELECT * FROM activity_summaries WHERE 1=1");
+    async fn get_record_statistics(&self, params: Parameters<GetRecordStatisticsParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let period = params.period.clone().unwrap_or_else(|| "day".to_string());
+        let date_trunc = period_expr(&period);
+        let value_mode = params.value_mode.as_deref().unwrap_or("value");
+        let rollup = match period.as_str() {
+            "week" => Some("weekly_record_stats"),
+            "month" => Some("monthly_record_stats"),
+            _ => None,
+        }
+        .filter(|_| value_mode == "value")
+        .filter(|_| {
+            period_aligned(
+                &period,
+                params.start_date.as_deref(),
+                params.end_date.as_deref(),
+            )
+        });
 
-        if let Some(ref sd) = params.start_date {
-            sql.push_str(&format!(
-                " AND date_components >= '{}'",
-                sd.replace('\'', "''")
-            ));
+        // Queries on records must put each record on the same day daily_record_stats does
+        let local_date =
+            if value_mode == "duration_seconds" || params.include_extremes.unwrap_or(false) {
+                match self.local_date_expr("start_date") {
+                    Ok(expr) => expr,
+                    Err(e) => return err_json("query_failed", e),
+                }
+            } else {
+                String::new()
+            };
+
+        let mut sql = match value_mode {
+            "value" if rollup.is_some() => String::new(),
+            "value" => format!(
+                "SELECT {} as period, SUM(count) as count, \
+                 SUM(sum_value)/SUM(count) as avg_value, \
+                 MIN(min_value) as min_value, MAX(max_value) as max_value, \
+                 SUM(sum_value) as sum_value, \
+                 COUNT(DISTINCT date) as days_with_data \
+                 FROM daily_record_stats WHERE record_type = ?",
+                date_trunc
+            ),
+            // daily_record_stats only covers records with a numeric value, which category
+            // records lack, so durations come from records
+            "duration_seconds" => format!(
+                "SELECT {} as period, COUNT(*) as count, \
+                 AVG(duration) as avg_value, \
+                 MIN(duration) as min_value, MAX(duration) as max_value, \
+                 SUM(duration) as sum_value, \
+                 COUNT(DISTINCT date) as days_with_data \
+                 FROM (SELECT record_type, {} AS date, \
+                       EXTRACT(EPOCH FROM end_date - start_date) AS duration FROM records) \
+                 WHERE record_type = ?",
+                date_trunc, local_date
+            ),
+            other => {
+                return err_json(
+                    "invalid_argument",
+                    format!(
+                        "value_mode must be value or duration_seconds, got {}",
+                        other
+                    ),
+                )
+            }
+        };
+
+        let record_type = params.record_type;
+
+        if let Some(table) = rollup {
+            // The range covers whole periods, so the precomputed rows are the answer
+            sql = format!(
+                "SELECT period, count, avg_value, min_value, max_value, sum_value, days_with_data \
+                 FROM {} WHERE record_type = ?",
+                table
+            );
+            if let Some(ref sd) = params.start_date {
+                sql.push_str(&format!(" AND period >= '{}'", sd.replace('\'', "''")));
+            }
+            if let Some(ref ed) = params.end_date {
+                sql.push_str(&format!(" AND period <= '{}'", ed.replace('\'', "''")));
+            }
+            sql.push_str(" ORDER BY period");
+        } else {
+            if let Some(ref sd) = params.start_date {
+                sql.push_str(&format!(" AND date >= '{}'", sd.replace('\'', "''")));
+            }
+            if let Some(ref ed) = params.end_date {
+                sql.push_str(&format!(" AND date <= '{}'", ed.replace('\'', "''")));
+            }
+            sql.push_str(&format!(" GROUP BY {} ORDER BY period", date_trunc));
         }
-        if let Some(ref ed) = params.end_date {
-            sql.push_str(&format!(
-                " AND date_components <= '{}'",
-                ed.replace('\'', "''")
-            ));
+
+        if params.include_extremes.unwrap_or(false) {
+            // The aggregates lost which record held the extreme, so look it up in records
+            let value_expr = match value_mode {
+                "duration_seconds" => "EXTRACT(EPOCH FROM end_date - start_date)",
+                _ => "value",
+            };
+            let mut candidates = format!(
+                "SELECT {} AS period, value, start_date \
+                 FROM (SELECT {} AS date, {} AS value, start_date \
+                       FROM records WHERE record_type = ?) WHERE value IS NOT NULL",
+                date_trunc, local_date, value_expr
+            );
+            if let Some(ref sd) = params.start_date {
+                candidates.push_str(&format!(" AND date >= '{}'", sd.replace('\'', "''")));
+            }
+            if let Some(ref ed) = params.end_date {
+                candidates.push_str(&format!(" AND date <= '{}'", ed.replace('\'', "''")));
+            }
+            sql = format!(
+                "SELECT s.*, \
+                 MIN(r.start_date) FILTER (WHERE r.value = s.max_value) AS max_value_date, \
+                 MIN(r.start_date) FILTER (WHERE r.value = s.min_value) AS min_value_date \
+                 FROM ({}) s LEFT JOIN ({}) r ON r.period = s.period \
+                 GROUP BY ALL ORDER BY s.period",
+                sql, candidates
+            );
         }
-        sql.push_str(&format!(" ORDER BY date_components DESC LIMIT {}", limit));
 
-        match self.query_to_json(&sql, &[]) {
-            Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
-            Err(e) => format!("Error: {}", e),
+        if let Some(window) = params.moving_average_window {
+            if window == 0 {
+                return err_json(
+                    "invalid_argument",
+                    "moving_average_window must be at least 1",
+                );
+            }
+            // Early rows average over however many periods precede them
+            sql = format!(
+                "SELECT *, AVG(avg_value) OVER (ORDER BY period ROWS BETWEEN {} PRECEDING AND CURRENT ROW) AS moving_avg \
+                 FROM ({}) ORDER BY period",
+                window - 1,
+                sql
+            );
         }
-    }
 
-    #[tool(
-        description = "Get GPS route data for a workout. Returns array of: latitude, longitude, elevation (meters), timestamp, speed (m/s), course (degrees). Use get_workout_details first to check has_route."
+        // Dates come back as text so the rows carry them
+        let mut casts = String::from("CAST(period AS VARCHAR) AS period");
+        if params.include_extremes.unwrap_or(false) {
+            casts.push_str(
+                ", CAST(max_value_date AS VARCHAR) AS max_value_date, \
+                 CAST(min_value_date AS VARCHAR) AS min_value_date",
+            );
+        }
+        sql = format!(
+            "SELECT * REPLACE ({}) FROM ({}) ORDER BY period",
+            casts, sql
+        );
+
+        let mut bind: Vec<&dyn duckdb::ToSql> = vec![&record_type];
+        if params.include_extremes.unwrap_or(false) {
+            bind.push(&record_type);
+        }
+        let mut rows = match self.query_to_json(&sql, &bind) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+        if params.include_annotations.unwrap_or(false) {
+            if let Err(e) = self.overlay_annotations(&mut rows, &period) {
+                return err_json("query_failed", e);
+            }
+        }
+
+        serde_json::to_string_pretty(&rows).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Compare a metric between two date windows, e.g. this month vs last month. Period A is the baseline. Returns: record_type, period, period_a and period_b (start, end, count, avg_value, min_value, max_value, sum_value, days_with_data, periods_with_data, avg_per_period = sum_value / periods_with_data, e.g. average daily steps), and change with {absolute, percent} of period_b relative to period_a for avg_value, min_value, max_value, sum_value, and avg_per_period. percent is null when the baseline is zero or missing. Uses daily_record_stats."
     )]
-    async fn get_workout_route(&self, params: Parameters<GetWorkoutRouteParams>) -> String {
+    async fn compare_periods(&self, params: Parameters<ComparePeriodsParams>) -> String {
         let Parameters(params) = params;
-        match self.query_to_json(
-            "SELECT latitude, longitude, elevation, timestamp, speed, course FROM route_points WHERE workout_hash = ? ORDER BY timestamp",
-            &[&params.workout_hash as &dyn duckdb::ToSql],
+        let period = params.period.as_deref().unwrap_or("day");
+        let date_trunc = period_expr(period);
+
+        let sql = format!(
+            "SELECT CAST(CAST(? AS DATE) AS VARCHAR) AS start, CAST(CAST(? AS DATE) AS VARCHAR) AS \"end\", \
+             COALESCE(SUM(count), 0) AS count, SUM(sum_value)/SUM(count) AS avg_value, \
+             MIN(min_value) AS min_value, MAX(max_value) AS max_value, SUM(sum_value) AS sum_value, \
+             COUNT(DISTINCT date) AS days_with_data, COUNT(DISTINCT {0}) AS periods_with_data, \
+             SUM(sum_value) / NULLIF(COUNT(DISTINCT {0}), 0) AS avg_per_period \
+             FROM daily_record_stats WHERE record_type = ? AND date >= CAST(? AS DATE) AND date <= CAST(? AS DATE)",
+            date_trunc
+        );
+        let mut windows = Vec::with_capacity(2);
+        for (start, end) in [
+            (&params.period_a_start, &params.period_a_end),
+            (&params.period_b_start, &params.period_b_end),
+        ] {
+            match self.query_to_json(
+                &sql,
+                &[
+                    start as &dyn duckdb::ToSql,
+                    end,
+                    &params.record_type,
+                    start,
+                    end,
+                ],
+            ) {
+                Ok(r) => windows.push(r[0].clone()),
+                Err(e) => return err_json("query_failed", e),
+            }
+        }
+
+        let mut change = serde_json::Map::new();
+        for key in [
+            "avg_value",
+            "min_value",
+            "max_value",
+            "sum_value",
+            "avg_per_period",
+        ] {
+            let (a, b) = (windows[0][key].as_f64(), windows[1][key].as_f64());
+            let absolute = a.zip(b).map(|(a, b)| b - a);
+            // A zero baseline has no meaningful relative change
+            let percent = a
+                .zip(absolute)
+                .filter(|(a, _)| *a != 0.0)
+                .map(|(a, d)| d / a.abs() * 100.0);
+            change.insert(
+                key.to_string(),
+                json!({ "absolute": absolute, "percent": percent }),
+            );
+        }
+
+        let result = json!({
+            "record_type": params.record_type,
+            "period": period,
+            "period_a": windows[0],
+            "period_b": windows[1],
+            "change": change,
+        });
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Find the days without any record of a type, e.g. when the watch wasn't worn. Returns: record_type, start_date, end_date, days_in_range, days_missing, and gaps (runs of consecutive missing days: start, end, days), oldest first. Uses daily_record_stats."
+    )]
+    async fn find_data_gaps(&self, params: Parameters<FindDataGapsParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+
+        let bounds = match self.query_to_json(
+            "SELECT CAST(COALESCE(CAST(? AS DATE), MIN(date)) AS VARCHAR) AS start_date, \
+             CAST(COALESCE(CAST(? AS DATE), MAX(date)) AS VARCHAR) AS end_date \
+             FROM daily_record_stats WHERE record_type = ?",
+            &[
+                &params.start_date as &dyn duckdb::ToSql,
+                &params.end_date,
+                &params.record_type,
+            ],
         ) {
-            Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
-            Err(e) => format!("Error: {}", e),
+            Ok(r) => r[0].clone(),
+            Err(e) => return err_json("query_failed", e),
+        };
+        let day = |key: &str| {
+            bounds[key]
+                .as_str()
+                .and_then(|s| chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").ok())
+        };
+        let (Some(start), Some(end)) = (day("start_date"), day("end_date")) else {
+            let result = json!({
+                "record_type": params.record_type,
+                "gaps": [],
+                "note": "No data for this record type; pass start_date and end_date to check a range",
+            });
+            return serde_json::to_string_pretty(&result).unwrap_or_default();
+        };
+        if start > end {
+            return err_json("invalid_argument", "start_date is after end_date");
         }
+
+        // Missing days minus their row number is constant within a run of consecutive days
+        let sql = "WITH missing AS ( \
+                SELECT CAST(d AS DATE) AS day \
+                FROM generate_series(CAST(? AS DATE), CAST(? AS DATE), INTERVAL 1 DAY) t(d) \
+                WHERE CAST(d AS DATE) NOT IN (SELECT date FROM daily_record_stats WHERE record_type = ?) \
+            ) \
+            SELECT CAST(MIN(day) AS VARCHAR) AS start, CAST(MAX(day) AS VARCHAR) AS \"end\", COUNT(*) AS days \
+            FROM (SELECT day, day - CAST(ROW_NUMBER() OVER (ORDER BY day) AS INTEGER) AS run FROM missing) \
+            GROUP BY run ORDER BY start";
+        let (start_str, end_str) = (start.to_string(), end.to_string());
+        let gaps = match self.query_to_json(
+            sql,
+            &[
+                &start_str as &dyn duckdb::ToSql,
+                &end_str,
+                &params.record_type,
+            ],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+        let days_missing: i64 = gaps
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|g| g["days"].as_i64())
+            .sum();
+
+        let result = json!({
+            "record_type": params.record_type,
+            "start_date": start_str,
+            "end_date": end_str,
+            "days_in_range": (end - start).num_days() + 1,
+            "days_missing": days_missing,
+            "gaps": gaps,
+        });
+        serde_json::to_string_pretty(&result).unwrap_or_default()
     }
 
     #[tool(
-        description = "List ECG recordings. Returns: ecg_hash, recorded_date, classification (e.g. SinusRhythm, AtrialFibrillation), device, sample_rate_hz. Use ecg_hash with get_ecg_data."
+        description = "Date x source matrix of daily sums for a record type, for spotting double counting (e.g. iPhone and Apple Watch both logging the same steps). Returns: sources (all sources in the range, largest first) and days: date, total (sum across sources), by_source ({source_name: daily sum, or null if the source logged nothing that day})."
     )]
-    async fn list_ecg_readings(&self, params: Parameters<ListEcgReadingsParams>) -> String {
-        let Parameters(params) = params;
-        let mut sql = String::from(
-            "SELECT ecg_hash, recorded_date, classification, device, sample_rate_hz FROM ecg_readings WHERE 1=1",
+    async fn get_records_aggregated_by_source_and_day(
+        &self,
+        params: Parameters<GetRecordsBySourceAndDayParams>,
+    ) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+
+        let date = match self.local_date_expr("start_date") {
+            Ok(expr) => expr,
+            Err(e) => return err_json("query_failed", e),
+        };
+        let mut sql = format!(
+            "SELECT CAST({} AS VARCHAR) AS date, source_name, SUM(value) AS sum_value \
+             FROM records WHERE record_type = ? AND value IS NOT NULL",
+            date
         );
         if let Some(ref sd) = params.start_date {
-            sql.push_str(&format!(
-                " AND recorded_date >= '{}'",
-                sd.replace('\'', "''")
-            ));
+            sql.push_str(&format!(" AND start_date >= '{}'", sd.replace('\'', "''")));
         }
         if let Some(ref ed) = params.end_date {
-            sql.push_str(&format!(
-                " AND recorded_date <= '{}'",
-                ed.replace('\'', "''")
-            ));
+            sql.push_str(&format!(" AND end_date <= '{}'", ed.replace('\'', "''")));
         }
-        sql.push_str(" ORDER BY recorded_date DESC");
+        sql.push_str(" GROUP BY 1, 2 ORDER BY 1, 2");
 
-        match self.query_to_json(&sql, &[]) {
-            Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
-            Err(e) => format!("Error: {}", e),
+        let cells = match self.query_to_json(&sql, &[&params.record_type as &dyn duckdb::ToSql]) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+        let cells = cells.as_array().map(Vec::as_slice).unwrap_or_default();
+
+        // Column order: sources by overall contribution
+        let mut source_totals: Vec<(String, f64)> = Vec::new();
+        for cell in cells {
+            let (Some(source), Some(sum)) =
+                (cell["source_name"].as_str(), cell["sum_value"].as_f64())
+            else {
+                continue;
+            };
+            match source_totals.iter_mut().find(|(s, _)| s == source) {
+                Some((_, total)) => *total += sum,
+                None => source_totals.push((source.to_string(), sum)),
+            }
+        }
+        source_totals.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let sources: Vec<String> = source_totals.into_iter().map(|(s, _)| s).collect();
+
+        let mut days: Vec<Value> = Vec::new();
+        for date_cells in cells.chunk_by(|a, b| a["date"] == b["date"]) {
+            let mut by_source = serde_json::Map::new();
+            for source in &sources {
+                let sum = date_cells
+                    .iter()
+                    .find(|c| c["source_name"].as_str() == Some(source.as_str()))
+                    .map(|c| c["sum_value"].clone())
+                    .unwrap_or(Value::Null);
+                by_source.insert(source.clone(), sum);
+            }
+            let total: f64 = date_cells
+                .iter()
+                .filter_map(|c| c["sum_value"].as_f64())
+                .sum();
+            days.push(json!({
+                "date": date_cells[0]["date"],
+                "total": total,
+                "by_source": by_source,
+            }));
         }
+
+        let result = json!({
+            "record_type": params.record_type,
+            "sources": sources,
+            "days": days,
+        });
+        serde_json::to_string_pretty(&result).unwrap_or_default()
     }
 
     #[tool(
-        description = "Get full ECG waveform by ecg_hash. Returns: reading (metadata), sample_count, voltages_uv (array of voltage values in microvolts). Get ecg_hash from list_ecg_readings."
+        description = "Find abnormal values of a record type (e.g. a resting heart rate of 180). Computes the mean and sample standard deviation over the date range and flags records whose value is more than z_threshold (default 3) standard deviations away. Returns: record_type, count, mean, stddev, z_threshold, rows (the outliers: record_hash, value, unit, source_name, start_date, z_score), most extreme first, and truncated and limit (truncated: the row cap cut the outliers short). No outliers are returned when all values are identical."
     )]
-    async fn get_ecg_data(&self, params: Parameters<GetEcgDataParams>) -> String {
-        let Parameters(params) = params;
-        let hash = params.ecg_hash;
-        let metadata = match self.query_to_json(
-            "SELECT * FROM ecg_readings WHERE ecg_hash = ?",
-            &[&hash as &dyn duckdb::ToSql],
+    async fn find_outliers(&self, params: Parameters<FindOutliersParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let z_threshold = params.z_threshold.unwrap_or(3.0);
+        if !z_threshold.is_finite() || z_threshold < 0.0 {
+            return err_json(
+                "invalid_argument",
+                "z_threshold must be a non-negative number",
+            );
+        }
+        let (limit, capped) = self.row_limit("find_outliers", params.limit, 100);
+
+        let mut filter = String::from("record_type = ? AND value IS NOT NULL");
+        if let Some(ref sd) = params.start_date {
+            filter.push_str(&format!(" AND start_date >= '{}'", sd.replace('\'', "''")));
+        }
+        if let Some(ref ed) = params.end_date {
+            filter.push_str(&format!(" AND end_date <= '{}'", ed.replace('\'', "''")));
+        }
+
+        let summary = match self.query_to_json(
+            &format!(
+                "SELECT COUNT(*) AS count, AVG(value) AS mean, STDDEV_SAMP(value) AS stddev FROM records WHERE {}",
+                filter
+            ),
+            &[&params.record_type as &dyn duckdb::ToSql],
         ) {
-            Ok(r) => r,
-            Err(e) => return format!("Error: {}", e),
+            Ok(r) => r[0].clone(),
+            Err(e) => return err_json("query_failed", e),
         };
 
-        let samples = match self.query_to_json(
-            "SELECT voltage_uv FROM ecg_samples WHERE ecg_hash = ? ORDER BY sample_idx",
-            &[&hash as &dyn duckdb::ToSql],
+        // Zero (or undefined, for a single record) variance means nothing stands out
+        let outliers = match (summary["mean"].as_f64(), summary["stddev"].as_f64()) {
+            (Some(mean), Some(stddev)) if stddev > 0.0 => {
+                let sql = format!(
+                    "SELECT record_hash, value, unit, source_name, CAST(start_date AS VARCHAR) AS start_date, \
+                     (value - ?) / ? AS z_score \
+                     FROM records WHERE {} AND ABS(value - ?) > ? * ? \
+                     ORDER BY ABS(value - ?) DESC, start_date LIMIT {}",
+                    filter, limit
+                );
+                match self.query_to_json(
+                    &sql,
+                    &[
+                        &mean as &dyn duckdb::ToSql,
+                        &stddev,
+                        &params.record_type,
+                        &mean,
+                        &z_threshold,
+                        &stddev,
+                        &mean,
+                    ],
+                ) {
+                    Ok(r) => r,
+                    Err(e) => return err_json("query_failed", e),
+                }
+            }
+            _ => json!([]),
+        };
+
+        let truncated = capped
+            && outliers
+                .as_array()
+                .is_some_and(|o| o.len() >= limit as usize);
+        let result = json!({
+            "record_type": params.record_type,
+            "count": summary["count"],
+            "mean": summary["mean"],
+            "stddev": summary["stddev"],
+            "z_threshold": z_threshold,
+            "rows": outliers,
+            "truncated": truncated,
+            "limit": limit,
+        });
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Distribution of a record type's values as equal-width buckets between the smallest and largest value (e.g. the spread of heart rate readings). Returns array of: bucket_min, bucket_max, count, in value order; the last bucket includes bucket_max. Empty buckets are included, and all-identical values give a single bucket."
+    )]
+    async fn get_record_histogram(&self, params: Parameters<GetRecordHistogramParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let bucket_count = params.bucket_count.unwrap_or(20);
+        if !(1..=200).contains(&bucket_count) {
+            return err_json("invalid_argument", "bucket_count must be between 1 and 200");
+        }
+
+        let mut filter = String::from("record_type = ? AND value IS NOT NULL");
+        if let Some(ref sd) = params.start_date {
+            filter.push_str(&format!(" AND start_date >= '{}'", sd.replace('\'', "''")));
+        }
+        if let Some(ref ed) = params.end_date {
+            filter.push_str(&format!(" AND end_date <= '{}'", ed.replace('\'', "''")));
+        }
+
+        let bounds = match self.query_to_json(
+            &format!(
+                "SELECT COUNT(*) AS count, MIN(value) AS lo, MAX(value) AS hi FROM records WHERE {}",
+                filter
+            ),
+            &[&params.record_type as &dyn duckdb::ToSql],
+        ) {
+            Ok(r) => r[0].clone(),
+            Err(e) => return err_json("query_failed", e),
+        };
+        let (Some(lo), Some(hi)) = (bounds["lo"].as_f64(), bounds["hi"].as_f64()) else {
+            return "[]".to_string();
+        };
+        if lo == hi {
+            return serde_json::to_string_pretty(
+                &json!([{"bucket_min": lo, "bucket_max": hi, "count": bounds["count"]}]),
+            )
+            .unwrap_or_default();
+        }
+
+        // Same as width_bucket, with the maximum folded into the last bucket
+        let width = (hi - lo) / bucket_count as f64;
+        let counts = match self.query_to_json(
+            &format!(
+                "SELECT LEAST(CAST(FLOOR((value - ?) / ?) AS BIGINT), ?) AS bucket, COUNT(*) AS count \
+                 FROM records WHERE {} GROUP BY bucket",
+                filter
+            ),
+            &[
+                &lo as &dyn duckdb::ToSql,
+                &width,
+                &(bucket_count as i64 - 1),
+                &params.record_type,
+            ],
         ) {
             Ok(r) => r,
-            Err(e) => return format!("Error: {}", e),
+            Err(e) => return err_json("query_failed", e),
         };
+        let mut per_bucket = vec![0i64; bucket_count as usize];
+        for row in counts.as_array().into_iter().flatten() {
+            if let (Some(bucket), Some(count)) = (row["bucket"].as_u64(), row["count"].as_i64()) {
+                if let Some(slot) = per_bucket.get_mut(bucket as usize) {
+                    *slot += count;
+                }
+            }
+        }
 
-        let voltages: Vec<Value> = samples
-            .as_array()
-            .map(|a| {
-                a.iter()
-                    .filter_map(|r| r.get("voltage_uv").cloned())
-                    .collect()
+        let result: Vec<Value> = per_bucket
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let bucket_max = if i + 1 == per_bucket.len() {
+                    hi
+                } else {
+                    lo + (i + 1) as f64 * width
+                };
+                json!({
+                    "bucket_min": lo + i as f64 * width,
+                    "bucket_max": bucket_max,
+                    "count": count,
+                })
             })
-            .unwrap_or_default();
+            .collect();
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
 
-        let result = json!({
-            "reading": metadata.as_array().and_then(|a| a.first()).cloned().unwrap_or(Value::Null),
-            "sample_count": voltages.len(),
-            "voltages_uv": voltages,
-        });
+    #[tool(
+        description = "Percentiles of a record type's values (e.g. 90th-percentile heart rate), interpolated between readings like DuckDB's quantile_cont. Returns array of: percentile, value, in the order requested. An empty array when there are no readings in range."
+    )]
+    async fn get_record_percentiles(
+        &self,
+        params: Parameters<GetRecordPercentilesParams>,
+    ) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let percentiles = params
+            .percentiles
+            .unwrap_or_else(|| vec![50.0, 90.0, 95.0, 99.0]);
+        if percentiles.is_empty() || percentiles.len() > 20 {
+            return err_json("invalid_argument", "percentiles must have 1 to 20 entries");
+        }
+        if !percentiles.iter().all(|p| (0.0..=100.0).contains(p)) {
+            return err_json("invalid_argument", "percentiles must be between 0 and 100");
+        }
+
+        let columns = percentiles
+            .iter()
+            .enumerate()
+            .map(|(i, p)| format!("quantile_cont(value, {:?}) AS p{}", p / 100.0, i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut sql = format!(
+            "SELECT COUNT(*) AS count, {} FROM records WHERE record_type = ? AND value IS NOT NULL",
+            columns
+        );
+        if let Some(ref sd) = params.start_date {
+            sql.push_str(&format!(" AND start_date >= '{}'", sd.replace('\'', "''")));
+        }
+        if let Some(ref ed) = params.end_date {
+            sql.push_str(&format!(" AND end_date <= '{}'", ed.replace('\'', "''")));
+        }
+
+        let row = match self.query_to_json(&sql, &[&params.record_type as &dyn duckdb::ToSql]) {
+            Ok(r) => r[0].clone(),
+            Err(e) => return err_json("query_failed", e),
+        };
+        if row["count"].as_i64().unwrap_or(0) == 0 {
+            return "[]".to_string();
+        }
 
+        let result: Vec<Value> = percentiles
+            .iter()
+            .enumerate()
+            .map(|(i, p)| json!({"percentile": p, "value": row[format!("p{}", i)]}))
+            .collect();
         serde_json::to_string_pretty(&result).unwrap_or_default()
     }
 
     #[tool(
-        description = "Run a read-only SQL query (DuckDB dialect). Must start with SELECT or WITH. Tables: records (record_hash, record_type, value, unit, source_name, device, start_date, end_date), workouts (workout_hash, activity_type, duration, total_distance, total_energy_burned, start_date, end_date), workout_events, workout_statistics, activity_summaries, ecg_readings, ecg_samples, route_points (latitude, longitude, elevation, timestamp, speed), daily_record_stats (record_type, date, unit, count, avg_value, min_value, max_value, sum_value), record_metadata (record_hash, key, value), imports."
+        description = "When a metric peaked each day (e.g. the time of the day's highest heart rate). Returns {rows, truncated, limit}, truncated meaning the row cap cut the days short, with rows of: date, max_value, unit, peak_time (start of the record holding the maximum; the earliest one on ties), source_name, record_count. Newest first."
     )]
-    async fn run_custom_query(&self, params: Parameters<RunCustomQueryParams>) -> String {
-        let Parameters(params) = params;
-        let trimmed = params.query.trim().to_string();
-        let upper = trimmed.to_uppercase();
-        if !upper.starts_with("SELECT") && !upper.starts_with("WITH") {
-            return "Error: Query must start with SELECT or WITH".to_string();
+    async fn get_daily_peaks(&self, params: Parameters<GetDailyPeaksParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let (limit, capped) = self.row_limit("get_daily_peaks", params.limit, 30);
+        let date = match self.local_date_expr("start_date") {
+            Ok(expr) => expr,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let mut filter = String::from("record_type = ? AND value IS NOT NULL");
+        if let Some(ref sd) = params.start_date {
+            filter.push_str(&format!(" AND start_date >= '{}'", sd.replace('\'', "''")));
+        }
+        if let Some(ref ed) = params.end_date {
+            filter.push_str(&format!(" AND end_date <= '{}'", ed.replace('\'', "''")));
         }
 
-        match self.query_to_json(&trimmed, &[]) {
-            Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
-            Err(e) => format!("Error: {}", e),
+        // Rank each day's records by value (earliest first on ties) and keep the top one
+        let sql = format!(
+            "SELECT CAST(date AS VARCHAR) AS date, value AS max_value, unit, \
+                    CAST(start_date AS VARCHAR) AS peak_time, source_name, record_count \
+             FROM ( \
+                 SELECT {date} AS date, value, unit, start_date, source_name, \
+                        ROW_NUMBER() OVER (PARTITION BY {date} ORDER BY value DESC, start_date, record_hash) AS rank, \
+                        COUNT(*) OVER (PARTITION BY {date}) AS record_count \
+                 FROM records WHERE {filter} \
+             ) WHERE rank = 1 ORDER BY date DESC LIMIT {limit}"
+        );
+        match self.query_to_json(&sql, &[&params.record_type as &dyn duckdb::ToSql]) {
+            Ok(result) => serde_json::to_string_pretty(&capped_rows(result, limit, capped))
+                .unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
         }
     }
 
     #[tool(
-        description = "List all devices and apps that contributed health data. Returns: source_name, record_count, earliest_date, latest_date."
+        description = "Split a metric by data source (e.g. how many steps came from iPhone vs Apple Watch). Returns per source: source_name, unit, record_count, sum_value, percentage (share of the total sum, 0-100), ordered by largest contribution."
     )]
-    async fn list_data_sources(&self) -> String {
-        let sql = "SELECT source_name, COUNT(*) as record_count, MIN(start_date) as earliest_date, MAX(start_date) as latest_date FROM records GROUP BY source_name ORDER BY record_count DESC";
-        match self.query_to_json(sql, &[]) {
+    async fn get_source_contribution(
+        &self,
+        params: Parameters<GetSourceContributionParams>,
+    ) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+
+        let mut sql = String::from(
+            "SELECT source_name, unit, COUNT(*) AS record_count, SUM(value) AS sum_value \
+             FROM records WHERE record_type = ? AND value IS NOT NULL",
+        );
+        if let Some(ref sd) = params.start_date {
+            sql.push_str(&format!(" AND start_date >= '{}'", sd.replace('\'', "''")));
+        }
+        if let Some(ref ed) = params.end_date {
+            sql.push_str(&format!(" AND end_date <= '{}'", ed.replace('\'', "''")));
+        }
+        sql.push_str(" GROUP BY source_name, unit");
+
+        let sql = format!(
+            "SELECT *, 100.0 * sum_value / NULLIF(SUM(sum_value) OVER (PARTITION BY unit), 0) AS percentage \
+             FROM ({}) ORDER BY sum_value DESC",
+            sql
+        );
+
+        match self.query_to_json(&sql, &[&params.record_type as &dyn duckdb::ToSql]) {
             Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
-            Err(e) => format!("Error: {}", e),
+            Err(e) => err_json("query_failed", e),
         }
     }
 
     #[tool(
-        description = "List all data imports. Returns: import_id, export_dir, imported_at, record_count, workout_count, duration_secs."
+        description = "Correlate two record types over time (e.g. resting heart rate vs body mass). Averages each type per period (day/week/month) from daily_record_stats, keeps only periods where both have data, and returns: periods (period, avg_a, avg_b) and correlation (Pearson coefficient, null when fewer than two overlapping periods)."
     )]
-    async fn get_import_history(&self) -> String {
-        let sql = "SELECT * FROM imports ORDER BY imported_at DESC";
-        match self.query_to_json(sql, &[]) {
-            Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
-            Err(e) => format!("Error: {}", e),
+    async fn correlate_metrics(&self, params: Parameters<CorrelateMetricsParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let date_trunc = period_expr(params.period.as_deref().unwrap_or("day"));
+
+        let mut filter = String::new();
+        if let Some(ref sd) = params.start_date {
+            filter.push_str(&format!(" AND date >= '{}'", sd.replace('\'', "''")));
+        }
+        if let Some(ref ed) = params.end_date {
+            filter.push_str(&format!(" AND date <= '{}'", ed.replace('\'', "''")));
         }
-    }
-}
 
-#[tool_handler]
-impl ServerHandler for HealthServer {}
+        let sql = format!(
+            "WITH a AS ( \
+                SELECT {trunc} AS period, SUM(sum_value)/SUM(count) AS avg_value \
+                FROM daily_record_stats WHERE record_type = ?{filter} GROUP BY 1 \
+             ), b AS ( \
+                SELECT {trunc} AS period, SUM(sum_value)/SUM(count) AS avg_value \
+                FROM daily_record_stats WHERE record_type = ?{filter} GROUP BY 1 \
+             ) \
+             SELECT CAST(a.period AS VARCHAR) AS period, a.avg_value AS avg_a, b.avg_value AS avg_b \
+             FROM a JOIN b ON a.period = b.period ORDER BY a.period",
+            trunc = date_trunc,
+            filter = filter,
+        );
 
-pub async fn run_server(db_path: &Path, host: &str, port: u16, transport: &str) -> Result<()> {
-    match transport {
-        "stdio" => run_stdio_server(db_path).await,
-        "http" => run_http_server(db_path, host, port).await,
-        other => anyhow::bail!("Unknown transport: {other}. Expected \"http\" or \"stdio\"."),
+        let periods = match self.query_to_json(
+            &sql,
+            &[
+                &params.record_type_a as &dyn duckdb::ToSql,
+                &params.record_type_b as &dyn duckdb::ToSql,
+            ],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let (xs, ys): (Vec<f64>, Vec<f64>) = periods
+            .as_array()
+            .map(|rows| {
+                rows.iter()
+                    .filter_map(|r| Some((r.get("avg_a")?.as_f64()?, r.get("avg_b")?.as_f64()?)))
+                    .unzip()
+            })
+            .unwrap_or_default();
+
+        let result = json!({
+            "record_type_a": params.record_type_a,
+            "record_type_b": params.record_type_b,
+            "periods": periods,
+            "correlation": pearson(&xs, &ys),
+        });
+
+        serde_json::to_string_pretty(&result).unwrap_or_default()
     }
-}
 
-async fn run_stdio_server(db_path: &Path) -> Result<()> {
-    let server = HealthServer::new(db_path)?;
-    tracing::info!("MCP server running on stdio");
-    let service = server
-        .serve(rmcp::transport::stdio())
-        .await
-        .map_err(|e| anyhow::anyhow!("{e}"))?;
-    service
-        .waiting()
-        .await
-        .map_err(|e| anyhow::anyhow!("{e}"))?;
-    Ok(())
-}
+    #[tool(
+        description = "Linear trend of a record type (am I trending up or down?). Averages the type per period (day/week/month/year) from daily_record_stats and fits a least-squares line with x = periods since first_period. Returns: points (periods with data), first_period, last_period, slope (change in avg_value per period), intercept (fitted value at first_period), r_squared, and forecast (period, value) for the next forecast_periods periods. slope, intercept, and r_squared are null with fewer than two points, and forecast is then empty."
+    )]
+    async fn get_trend(&self, params: Parameters<GetTrendParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let period = params.period.clone().unwrap_or_else(|| "day".to_string());
+        let forecast_periods = params.forecast_periods.unwrap_or(3);
+        if forecast_periods > 100 {
+            return err_json("invalid_argument", "forecast_periods must be at most 100");
+        }
+        let unit = match period.as_str() {
+            "week" | "month" | "year" => period.as_str(),
+            _ => "day",
+        };
 
-async fn run_http_server(db_path: &Path, host: &str, port: u16) -> Result<()> {
-    let db_path = db_path.to_path_buf();
+        let mut filter = String::new();
+        if let Some(ref sd) = params.start_date {
+            filter.push_str(&format!(" AND date >= '{}'", sd.replace('\'', "''")));
+        }
+        if let Some(ref ed) = params.end_date {
+            filter.push_str(&format!(" AND date <= '{}'", ed.replace('\'', "''")));
+        }
 
-    let service = StreamableHttpService::new(
-        move || HealthServer::new(&db_path).map_err(|e| std::io::Error::other(e.to_string())),
-        LocalSessionManager::default().into(),
-        Default::default(),
-    );
+        let sql = format!(
+            "WITH series AS ( \
+                SELECT CAST({trunc} AS DATE) AS period, SUM(sum_value)/SUM(count) AS avg_value \
+                FROM daily_record_stats WHERE record_type = ?{filter} GROUP BY 1 \
+             ), points AS ( \
+                SELECT period, avg_value, \
+                       date_diff('{unit}', MIN(period) OVER (), period) AS x \
+                FROM series \
+             ) \
+             SELECT COUNT(*) AS points, \
+                    CAST(MIN(period) AS VARCHAR) AS first_period, \
+                    CAST(MAX(period) AS VARCHAR) AS last_period, \
+                    MAX(x) AS last_x, \
+                    regr_slope(avg_value, x) AS slope, \
+                    regr_intercept(avg_value, x) AS intercept, \
+                    regr_r2(avg_value, x) AS r_squared \
+             FROM points",
+            trunc = period_expr(&period),
+            filter = filter,
+            unit = unit,
+        );
 
-    let router = axum::Router::new().nest_service("/mcp", service);
+        let fit = match self.query_to_json(&sql, &[&params.record_type as &dyn duckdb::ToSql]) {
+            Ok(rows) => rows[0].clone(),
+            Err(e) => return err_json("query_failed", e),
+        };
 
-    let addr = format!("{}:{}", host, port);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    tracing::info!("MCP server listening at http://{}/mcp", addr);
+        let slope = fit["slope"].as_f64();
+        let intercept = fit["intercept"].as_f64();
+        let last = fit["last_period"]
+            .as_str()
+            .and_then(|s| chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").ok());
+        let forecast: Vec<Value> = match (slope, intercept, last, fit["last_x"].as_f64()) {
+            (Some(slope), Some(intercept), Some(last), Some(last_x)) => (1..=forecast_periods)
+                .filter_map(|k| {
+                    let period = match unit {
+                        "week" => last.checked_add_days(chrono::Days::new(7 * k as u64)),
+                        "month" => last.checked_add_months(chrono::Months::new(k)),
+                        "year" => last.checked_add_months(chrono::Months::new(12 * k)),
+                        _ => last.checked_add_days(chrono::Days::new(k as u64)),
+                    }?;
+                    Some(json!({
+                        "period": period.to_string(),
+                        "value": intercept + slope * (last_x + k as f64),
+                    }))
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
 
-    axum::serve(listener, router)
-        .with_graceful_shutdown(async {
-            tokio::signal::ctrl_c().await.unwrap();
-        })
-        .await?;
+        let result = json!({
+            "record_type": params.record_type,
+            "period": unit,
+            "points": fit["points"],
+            "first_period": fit["first_period"],
+            "last_period": fit["last_period"],
+            "slope": slope,
+            "intercept": intercept,
+            "r_squared": fit["r_squared"].as_f64(),
+            "forecast": forecast,
+        });
 
-    Ok(())
-}
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::db::{ensure_schema, open_db_in_memory, rebuild_daily_stats};
-    use rmcp::handler::server::wrapper::Parameters;
+    #[tool(
+        description = "Blood glucose statistics grouped by meal context (HKMetadataKeyBloodGlucoseMealTime). Returns per context: meal_context (preprandial, postprandial, or unspecified when the reading has no meal-time metadata), unit, count, avg_value, min_value, max_value."
+    )]
+    async fn get_glucose_by_meal_context(
+        &self,
+        params: Parameters<GetGlucoseByMealContextParams>,
+    ) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
 
-    fn setup_server() -> HealthServer {
-        let conn = open_db_in_memory().unwrap();
-        ensure_schema(&conn).unwrap();
-        // Seed data
-        conn.execute_batch(
-            "
-            INSERT INTO records VALUES ('rh1', 'HKQuantityTypeIdentifierHeartRate', 72.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-01 08:00:00', '2024-01-01 08:00:00', '2024-01-01 08:01:00', 'imp1');
-            INSERT INTO records VALUES ('rh2', 'HKQuantityTypeIdentifierHeartRate', 80.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-01 09:00:00', '2024-01-01 09:00:00', '2024-01-01 09:01:00', 'imp1');
-            INSERT INTO records VALUES ('rh3', 'HKQuantityTypeIdentifierStepCount', 1500.0, 'count', 'iPhone', '17.0', NULL, '2024-01-01 00:00:00', '2024-01-01 00:00:00', '2024-01-01 23:59:59', 'imp1');
-            INSERT INTO record_metadata VALUES ('rh1', 'HKMetadataKeyHeartRateMotionContext', '1');
-            INSERT INTO workouts VALUES ('wh1', 'HKWorkoutActivityTypeRunning', 1800.0, 'sec', 5000.0, 'm', 300.0, 'kcal', 'Apple Watch', '10.0', NULL, '2024-01-01 10:00:00', '2024-01-01 10:00:00', '2024-01-01 10:30:00', 'imp1');
-            INSERT INTO workout_events VALUES ('wh1', 'HKWorkoutEventTypeLap', '2024-01-01 10:15:00', NULL, NULL);
-            INSERT INTO workout_statistics VALUES ('wh1', 'HKQuantityTypeIdentifierHeartRate', '2024-01-01 10:00:00', '2024-01-01 10:30:00', 150.0, 120.0, 180.0, NULL, 'count/min');
-            INSERT INTO activity_summaries VALUES ('2024-01-01', 500.0, 600.0, 45.0, 30.0, 30.0, 30.0, 10.0, 12.0, 'imp1');
-            INSERT INTO ecg_readings VALUES ('ecg1', '2024-01-01 12:00:00', 'Sinus Rhythm', 'Apple Watch', 512.0, NULL, '2.0', 'imp1');
+        let mut sql = String::from(
+            "SELECT CASE m.value \
+                WHEN '1' THEN 'preprandial' \
+                WHEN '2' THEN 'postprandial' \
+                ELSE COALESCE(m.value, 'unspecified') END AS meal_context, \
+             r.unit, COUNT(*) AS count, AVG(r.value) AS avg_value, \
+             MIN(r.value) AS min_value, MAX(r.value) AS max_value \
+             FROM records r \
+             LEFT JOIN record_metadata m \
+                ON m.record_hash = r.record_hash AND m.key = 'HKMetadataKeyBloodGlucoseMealTime' \
+             WHERE r.record_type = 'HKQuantityTypeIdentifierBloodGlucose' AND r.value IS NOT NULL",
+        );
+        if let Some(ref sd) = params.start_date {
+            sql.push_str(&format!(
+                " AND r.start_date >= '{}'",
+                sd.replace('\'', "''")
+            ));
+        }
+        if let Some(ref ed) = params.end_date {
+            sql.push_str(&format!(" AND r.end_date <= '{}'", ed.replace('\'', "''")));
+        }
+        sql.push_str(" GROUP BY meal_context, r.unit ORDER BY meal_context");
+
+        match self.query_to_json(&sql, &[]) {
+            Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+
+    #[tool(
+        description = "Heart rate statistics grouped by motion context (HKMetadataKeyHeartRateMotionContext), e.g. to compare resting-like sedentary readings with readings taken while active. Returns per context: motion_context (sedentary, active, not_set when the watch recorded no context, or unknown when the reading has no motion-context metadata), unit, count, avg_bpm, min_bpm, max_bpm."
+    )]
+    async fn get_heart_rate_by_context(
+        &self,
+        params: Parameters<GetHeartRateByContextParams>,
+    ) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+
+        let mut sql = String::from(
+            "SELECT CASE m.value \
+                WHEN '0' THEN 'not_set' \
+                WHEN '1' THEN 'sedentary' \
+                WHEN '2' THEN 'active' \
+                ELSE COALESCE(m.value, 'unknown') END AS motion_context, \
+             r.unit, COUNT(*) AS count, AVG(r.value) AS avg_bpm, \
+             MIN(r.value) AS min_bpm, MAX(r.value) AS max_bpm \
+             FROM records r \
+             LEFT JOIN record_metadata m \
+                ON m.record_hash = r.record_hash AND m.key = 'HKMetadataKeyHeartRateMotionContext' \
+             WHERE r.record_type = 'HKQuantityTypeIdentifierHeartRate' AND r.value IS NOT NULL",
+        );
+        if let Some(ref sd) = params.start_date {
+            sql.push_str(&format!(
+                " AND r.start_date >= '{}'",
+                sd.replace('\'', "''")
+            ));
+        }
+        if let Some(ref ed) = params.end_date {
+            sql.push_str(&format!(" AND r.end_date <= '{}'", ed.replace('\'', "''")));
+        }
+        sql.push_str(" GROUP BY motion_context, r.unit ORDER BY motion_context");
+
+        match self.query_to_json(&sql, &[]) {
+            Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+
+    #[tool(
+        description = "Sleep quality per night from sleep-analysis stages. A night runs noon to noon in the import timezone (UTC unless imported with --timezone) and is labeled by the evening's date. Returns per night: night, in_bed_seconds (InBed samples, or first-to-last sample span when there are none), asleep_seconds, awake_seconds, awakenings (Awake segments between asleep segments), efficiency (asleep / in bed, 0-1), has_stages. Nights with only in-bed data have has_stages=false and null asleep_seconds/efficiency."
+    )]
+    async fn get_sleep_quality(&self, params: Parameters<GetSleepQualityParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+
+        // Nights run from local noon to local noon
+        let local_time = match self.local_time_expr("start_date") {
+            Ok(expr) => expr,
+            Err(e) => return err_json("query_failed", e),
+        };
+        let mut sql = format!(
+            "SELECT CAST(CAST({} - INTERVAL 12 HOUR AS DATE) AS VARCHAR) AS night, value_text AS stage, \
+             epoch(start_date) AS start_s, epoch(end_date) AS end_s \
+             FROM records WHERE record_type = 'HKCategoryTypeIdentifierSleepAnalysis' AND value_text IS NOT NULL",
+            local_time
+        );
+        if let Some(ref sd) = params.start_date {
+            sql.push_str(&format!(" AND start_date >= '{}'", sd.replace('\'', "''")));
+        }
+        if let Some(ref ed) = params.end_date {
+            sql.push_str(&format!(" AND end_date <= '{}'", ed.replace('\'', "''")));
+        }
+        if let Some(ref sn) = params.source_name {
+            sql.push_str(&format!(" AND source_name = '{}'", sn.replace('\'', "''")));
+        }
+        sql.push_str(" ORDER BY night, start_date");
+
+        let segments = match self.query_to_json(&sql, &[]) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        // Group the (already ordered) segments by night
+        let mut nights: Vec<(String, Vec<SleepSegment>)> = Vec::new();
+        for seg in segments.as_array().into_iter().flatten() {
+            let (Some(night), Some(stage), Some(start), Some(end)) = (
+                seg["night"].as_str(),
+                seg["stage"].as_str().and_then(sleep_stage),
+                seg["start_s"].as_f64(),
+                seg["end_s"].as_f64(),
+            ) else {
+                continue;
+            };
+            match nights.last_mut() {
+                Some((n, segs)) if n == night => segs.push((stage, start, end)),
+                _ => nights.push((night.to_string(), vec![(stage, start, end)])),
+            }
+        }
+
+        let result: Vec<Value> = nights
+            .iter()
+            .map(|(night, segs)| {
+                let total = |stage: SleepStage| -> f64 {
+                    segs.iter()
+                        .filter(|(s, _, _)| *s == stage)
+                        .map(|(_, start, end)| end - start)
+                        .sum()
+                };
+                let has_stages = segs.iter().any(|(s, _, _)| *s != SleepStage::InBed);
+                let in_bed = if segs.iter().any(|(s, _, _)| *s == SleepStage::InBed) {
+                    total(SleepStage::InBed)
+                } else {
+                    let first = segs.iter().map(|(_, s, _)| *s).fold(f64::MAX, f64::min);
+                    let last = segs.iter().map(|(_, _, e)| *e).fold(f64::MIN, f64::max);
+                    last - first
+                };
+                let asleep = total(SleepStage::Asleep);
+
+                // An awakening is an Awake segment with sleep both before and after it
+                let staged: Vec<SleepStage> = segs
+                    .iter()
+                    .map(|(s, _, _)| *s)
+                    .filter(|s| *s != SleepStage::InBed)
+                    .collect();
+                let awakenings = staged
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, s)| {
+                        **s == SleepStage::Awake
+                            && staged[..*i].contains(&SleepStage::Asleep)
+                            && staged[i + 1..].contains(&SleepStage::Asleep)
+                    })
+                    .count();
+
+                json!({
+                    "night": night,
+                    "in_bed_seconds": in_bed,
+                    "asleep_seconds": if has_stages { Some(asleep) } else { None },
+                    "awake_seconds": total(SleepStage::Awake),
+                    "awakenings": awakenings,
+                    "efficiency": if has_stages && in_bed > 0.0 { Some(asleep / in_bed) } else { None },
+                    "has_stages": has_stages,
+                })
+            })
+            .collect();
+
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Estimated one-rep max trend for a strength exercise, from sets logged by third-party apps as records with exercise/weight/reps metadata (key names configurable). Uses the Epley formula (weight * (1 + reps / 30); a single rep counts as-is) and keeps each session's (day's) best set. Returns: exercise, and sessions: date, sets, estimated_1rm, best_weight, best_reps. Weight is in whatever unit was logged; sets with missing or non-numeric weight/reps are skipped."
+    )]
+    async fn get_one_rep_max(&self, params: Parameters<GetOneRepMaxParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let exercise_key = params.exercise_key.as_deref().unwrap_or("exercise");
+        let weight_key = params.weight_key.as_deref().unwrap_or("weight");
+        let reps_key = params.reps_key.as_deref().unwrap_or("reps");
+
+        let mut date_filter = String::new();
+        if let Some(ref sd) = params.start_date {
+            date_filter.push_str(&format!(
+                " AND r.start_date >= '{}'",
+                sd.replace('\'', "''")
+            ));
+        }
+        if let Some(ref ed) = params.end_date {
+            date_filter.push_str(&format!(" AND r.end_date <= '{}'", ed.replace('\'', "''")));
+        }
+
+        let date = match self.local_date_expr("r.start_date") {
+            Ok(expr) => expr,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        // Values may carry units ("100 kg"), so take the leading number
+        let sql = format!(
+            "WITH sets AS ( \
+               SELECT CAST({} AS VARCHAR) AS date, \
+                 MAX(m.value) FILTER (WHERE m.key = ?) AS exercise, \
+                 TRY_CAST(regexp_extract(MAX(m.value) FILTER (WHERE m.key = ?), '[0-9]+(\\.[0-9]+)?') AS DOUBLE) AS weight, \
+                 TRY_CAST(regexp_extract(MAX(m.value) FILTER (WHERE m.key = ?), '[0-9]+') AS INTEGER) AS reps \
+               FROM records r JOIN record_metadata m ON m.record_hash = r.record_hash \
+               WHERE 1=1{} \
+               GROUP BY r.record_hash, r.start_date \
+             ), scored AS ( \
+               SELECT *, CASE WHEN reps = 1 THEN weight ELSE weight * (1 + reps / 30.0) END AS estimate \
+               FROM sets WHERE lower(exercise) = lower(?) AND weight > 0 AND reps > 0 \
+             ) \
+             SELECT date, COUNT(*) AS sets, MAX(estimate) AS estimated_1rm, \
+               arg_max(weight, estimate) AS best_weight, arg_max(reps, estimate) AS best_reps \
+             FROM scored GROUP BY date ORDER BY date",
+            date, date_filter
+        );
+
+        match self.query_to_json(
+            &sql,
+            &[
+                &exercise_key as &dyn duckdb::ToSql,
+                &weight_key,
+                &reps_key,
+                &params.exercise,
+            ],
+        ) {
+            Ok(sessions) => serde_json::to_string_pretty(&json!({
+                "exercise": params.exercise,
+                "sessions": sessions,
+            }))
+            .unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+
+    #[tool(
+        description = "List all workout activity types with counts and date ranges. Use this to discover valid activity_type values before calling list_workouts. Returns: activity_type (e.g. HKWorkoutActivityTypeRunning), count, earliest_date, latest_date, total_distance (meters), total_duration (seconds)."
+    )]
+    async fn list_workout_activity_types(&self) -> String {
+        let sql = "SELECT activity_type, COUNT(*) AS count, \
+                   CAST(MIN(start_date) AS VARCHAR) AS earliest_date, CAST(MAX(start_date) AS VARCHAR) AS latest_date, \
+                   SUM(total_distance_m) AS total_distance, SUM(epoch(end_date) - epoch(start_date)) AS total_duration \
+                   FROM workouts GROUP BY activity_type ORDER BY count DESC";
+        match self.query_to_json(sql, &[]) {
+            Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+
+    #[tool(
+        description = "List workouts with optional filtering. Returns {rows, truncated, limit}; truncated is true when the row cap cut the list short. Rows have: workout_hash, activity_type (e.g. HKWorkoutActivityTypeRunning), duration, duration_unit, total_distance, total_distance_unit, total_energy_burned, total_energy_unit, source_name, start_date, end_date. Use workout_hash with get_workout_details or get_workout_route."
+    )]
+    async fn list_workouts(&self, params: Parameters<ListWorkoutsParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let (limit, capped) = self.row_limit("list_workouts", params.limit, 50);
+        let mut sql = String::from(
+            "SELECT workout_hash, activity_type, duration, duration_unit, \
+             total_distance, total_distance_unit, total_energy_burned, total_energy_unit, \
+             source_name, start_date, end_date FROM workouts WHERE 1=1",
+        );
+
+        if let Some(ref at) = params.activity_type {
+            sql.push_str(&format!(
+                " AND activity_type = '{}'",
+                at.replace('\'', "''")
+            ));
+        }
+        if let Some(ref sd) = params.start_date {
+            sql.push_str(&format!(" AND start_date >= '{}'", sd.replace('\'', "''")));
+        }
+        if let Some(ref ed) = params.end_date {
+            sql.push_str(&format!(" AND end_date <= '{}'", ed.replace('\'', "''")));
+        }
+        sql.push_str(&format!(" ORDER BY start_date DESC LIMIT {}", limit));
+
+        match self.query_to_json(&sql, &[]) {
+            Ok(result) => serde_json::to_string_pretty(&capped_rows(result, limit, capped))
+                .unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+
+    #[tool(
+        description = "Get full workout details by workout_hash. Returns: workout object (all fields), events (lap/pause markers), statistics (per-metric breakdowns like heart rate zones), metadata (key/value pairs such as HKWeatherTemperature, HKElevationAscended), has_route boolean (route points were imported), and route_file (the GPX path the export referenced, or null; set with has_route false means the file was missing from workout-routes/). Get the workout_hash from list_workouts."
+    )]
+    async fn get_workout_details(&self, params: Parameters<GetWorkoutDetailsParams>) -> String {
+        let Parameters(params) = params;
+        let hash = params.workout_hash;
+
+        let workout = match self.query_to_json(
+            "SELECT * FROM workouts WHERE workout_hash = ?",
+            &[&hash as &dyn duckdb::ToSql],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let events = match self.query_to_json(
+            "SELECT event_type, date, duration, duration_unit FROM workout_events WHERE workout_hash = ?",
+            &[&hash as &dyn duckdb::ToSql],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let statistics = match self.query_to_json(
+            "SELECT stat_type, start_date, end_date, average, minimum, maximum, sum, unit FROM workout_statistics WHERE workout_hash = ?",
+            &[&hash as &dyn duckdb::ToSql],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let metadata = match self.query_to_json(
+            "SELECT key, value FROM workout_metadata WHERE workout_hash = ? ORDER BY key",
+            &[&hash as &dyn duckdb::ToSql],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let has_route = match self.query_to_json(
+            "SELECT COUNT(*) as count FROM route_points WHERE workout_hash = ?",
+            &[&hash as &dyn duckdb::ToSql],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let workout = workout
+            .as_array()
+            .and_then(|a| a.first())
+            .cloned()
+            .unwrap_or(Value::Null);
+        let result = json!({
+            "route_file": workout.get("route_file"),
+            "workout": workout,
+            "events": events,
+            "statistics": statistics,
+            "metadata": metadata,
+            "has_route": has_route.as_array().and_then(|a| a.first()).and_then(|r| r.get("count")).and_then(|c| c.as_i64()).unwrap_or(0) > 0,
+        });
+
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "The most recent workout (optionally of one activity_type) with everything needed to answer \"how was my last run?\" in one call. Returns: workout_hash, the get_workout_details fields (workout, events, statistics, metadata, has_route, route_file), route (points, distance_m, start, end, min_elevation, max_elevation; null without route data), and heart_rate_zones (as get_heart_rate_zones with default zones)."
+    )]
+    async fn get_last_workout(&self, params: Parameters<GetLastWorkoutParams>) -> String {
+        let Parameters(params) = params;
+        let mut sql = String::from("SELECT workout_hash FROM workouts");
+        if let Some(ref at) = params.activity_type {
+            sql.push_str(&format!(
+                " WHERE activity_type = '{}'",
+                at.replace('\'', "''")
+            ));
+        }
+        sql.push_str(" ORDER BY start_date DESC LIMIT 1");
+
+        let workout_hash = match self.query_to_json(&sql, &[]) {
+            Ok(r) => match r[0]["workout_hash"].as_str() {
+                Some(h) => h.to_string(),
+                None => {
+                    return match params.activity_type {
+                        Some(at) => err_json("not_found", format!("No {} workouts found", at)),
+                        None => err_json("not_found", "No workouts found"),
+                    }
+                }
+            },
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let details = self
+            .get_workout_details(Parameters(GetWorkoutDetailsParams {
+                workout_hash: workout_hash.clone(),
+            }))
+            .await;
+        let zones = self
+            .get_heart_rate_zones(Parameters(GetHeartRateZonesParams {
+                workout_hash: workout_hash.clone(),
+                boundaries: None,
+                max_heart_rate: None,
+            }))
+            .await;
+        for output in [&details, &zones] {
+            if is_err_json(output) {
+                return output.clone();
+            }
+        }
+        let (Ok(Value::Object(mut result)), Ok(zones)) = (
+            serde_json::from_str::<Value>(&details),
+            serde_json::from_str::<Value>(&zones),
+        ) else {
+            return err_json("query_failed", "Unexpected workout details output");
+        };
+
+        let points = match self.query_to_json(
+            "SELECT latitude, longitude, elevation, CAST(timestamp AS VARCHAR) AS timestamp \
+             FROM route_points WHERE workout_hash = ? ORDER BY timestamp",
+            &[&workout_hash as &dyn duckdb::ToSql],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+        let points = points.as_array().cloned().unwrap_or_default();
+        let route = if points.is_empty() {
+            Value::Null
+        } else {
+            let coords: Vec<(f64, f64)> = points
+                .iter()
+                .filter_map(|p| Some((p["latitude"].as_f64()?, p["longitude"].as_f64()?)))
+                .collect();
+            let distance: f64 = coords
+                .windows(2)
+                .map(|w| haversine_m(w[0].0, w[0].1, w[1].0, w[1].1))
+                .sum();
+            let elevations = points.iter().filter_map(|p| p["elevation"].as_f64());
+            json!({
+                "points": points.len(),
+                "distance_m": distance,
+                "start": points.first().map(|p| &p["timestamp"]),
+                "end": points.last().map(|p| &p["timestamp"]),
+                "min_elevation": elevations.clone().reduce(f64::min),
+                "max_elevation": elevations.reduce(f64::max),
+            })
+        };
+
+        result.insert("workout_hash".to_string(), json!(workout_hash));
+        result.insert("route".to_string(), route);
+        result.insert("heart_rate_zones".to_string(), zones);
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Get the heart rate curve during a workout, resampled to 1-minute buckets. Returns array of: minute, avg_bpm, min_bpm, max_bpm, sample_count. Empty when no heart rate data overlaps the workout. Get the workout_hash from list_workouts."
+    )]
+    async fn get_workout_heart_rate(
+        &self,
+        params: Parameters<GetWorkoutHeartRateParams>,
+    ) -> String {
+        let Parameters(params) = params;
+        let (start, end) = match self.workout_window(&params.workout_hash) {
+            Ok(Some(window)) => window,
+            Ok(None) => {
+                return err_json(
+                    "not_found",
+                    format!("Workout {} not found", params.workout_hash),
+                )
+            }
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let buckets = match self.resample_records(
+            "HKQuantityTypeIdentifierHeartRate",
+            &start,
+            &end,
+            "1 minute",
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let series: Vec<Value> = buckets
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .map(|b| {
+                        json!({
+                            "minute": b.get("bucket_start"),
+                            "avg_bpm": b.get("avg_value"),
+                            "min_bpm": b.get("min_value"),
+                            "max_bpm": b.get("max_value"),
+                            "sample_count": b.get("sample_count"),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        serde_json::to_string_pretty(&series).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Power curve of a cycling workout: the best average power over 5 s, 1 min, 5 min, and 20 min rolling windows, from HKQuantityTypeIdentifierCyclingPower records within the workout. Each sample counts until the next one (or the workout end). Returns array of: duration_secs, best_avg_watts. Durations longer than the workout are skipped; empty when no power data overlaps the workout. Get the workout_hash from list_workouts."
+    )]
+    async fn get_power_curve(&self, params: Parameters<GetPowerCurveParams>) -> String {
+        let Parameters(params) = params;
+        let (start, end) = match self.workout_window(&params.workout_hash) {
+            Ok(Some(window)) => window,
+            Ok(None) => {
+                return err_json(
+                    "not_found",
+                    format!("Workout {} not found", params.workout_hash),
+                )
+            }
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let samples = match self.query_to_json(
+            "SELECT epoch(start_date) - epoch(CAST(? AS TIMESTAMP)) AS t, value AS watts, \
+             epoch(CAST(? AS TIMESTAMP)) - epoch(CAST(? AS TIMESTAMP)) AS workout_seconds \
+             FROM records WHERE record_type = 'HKQuantityTypeIdentifierCyclingPower' AND value IS NOT NULL \
+             AND start_date >= CAST(? AS TIMESTAMP) AND start_date < CAST(? AS TIMESTAMP) \
+             ORDER BY start_date",
+            &[
+                &start as &dyn duckdb::ToSql,
+                &end as &dyn duckdb::ToSql,
+                &start as &dyn duckdb::ToSql,
+                &start as &dyn duckdb::ToSql,
+                &end as &dyn duckdb::ToSql,
+            ],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+        let samples = samples.as_array().cloned().unwrap_or_default();
+        let workout_seconds = samples
+            .first()
+            .and_then(|s| s["workout_seconds"].as_f64())
+            .unwrap_or(0.0);
+        let points: Vec<(f64, f64)> = samples
+            .iter()
+            .filter_map(|s| Some((s["t"].as_f64()?, s["watts"].as_f64()?)))
+            .collect();
+
+        let curve: Vec<Value> = power_curve(&points, workout_seconds, &POWER_CURVE_DURATIONS)
+            .into_iter()
+            .map(|(duration, watts)| json!({"duration_secs": duration, "best_avg_watts": watts}))
+            .collect();
+
+        serde_json::to_string_pretty(&curve).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Time in heart-rate zones during a workout. Each heart rate sample counts until the next sample (or the workout end). Returns: workout_hash, boundaries (bpm), total_seconds, and zones: zone (1 = lowest), min_bpm, max_bpm, seconds, percentage. Zones are all zero when no heart rate data overlaps the workout."
+    )]
+    async fn get_heart_rate_zones(&self, params: Parameters<GetHeartRateZonesParams>) -> String {
+        let Parameters(params) = params;
+        let (start, end) = match self.workout_window(&params.workout_hash) {
+            Ok(Some(window)) => window,
+            Ok(None) => {
+                return err_json(
+                    "not_found",
+                    format!("Workout {} not found", params.workout_hash),
+                )
+            }
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let boundaries = match params.boundaries {
+            Some(b) => {
+                if b.windows(2).any(|w| w[0] >= w[1]) {
+                    return err_json("invalid_argument", "boundaries must be strictly ascending");
+                }
+                b
+            }
+            None => {
+                let max_hr = match params.max_heart_rate {
+                    Some(m) => m,
+                    None => match self.query_to_json(
+                        "SELECT MAX(value) AS max_hr FROM records WHERE record_type = 'HKQuantityTypeIdentifierHeartRate'",
+                        &[],
+                    ) {
+                        Ok(r) => r[0]["max_hr"].as_f64().unwrap_or(0.0),
+                        Err(e) => return err_json("query_failed", e),
+                    },
+                };
+                [0.6, 0.7, 0.8, 0.9].iter().map(|p| p * max_hr).collect()
+            }
+        };
+
+        // Each sample lasts until the next one, capped at the workout end
+        let samples = match self.query_to_json(
+            "SELECT value AS bpm, \
+             epoch(LEAST(COALESCE(LEAD(start_date) OVER (ORDER BY start_date), CAST(? AS TIMESTAMP)), CAST(? AS TIMESTAMP))) \
+               - epoch(start_date) AS seconds \
+             FROM records WHERE record_type = 'HKQuantityTypeIdentifierHeartRate' AND value IS NOT NULL \
+             AND start_date >= CAST(? AS TIMESTAMP) AND start_date < CAST(? AS TIMESTAMP) \
+             ORDER BY start_date",
+            &[
+                &end as &dyn duckdb::ToSql,
+                &end as &dyn duckdb::ToSql,
+                &start as &dyn duckdb::ToSql,
+                &end as &dyn duckdb::ToSql,
+            ],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let mut seconds = vec![0.0; boundaries.len() + 1];
+        for s in samples.as_array().into_iter().flatten() {
+            let (Some(bpm), Some(secs)) = (s["bpm"].as_f64(), s["seconds"].as_f64()) else {
+                continue;
+            };
+            let zone = boundaries.iter().take_while(|b| bpm >= **b).count();
+            seconds[zone] += secs;
+        }
+        let total: f64 = seconds.iter().sum();
+
+        let zones: Vec<Value> = seconds
+            .iter()
+            .enumerate()
+            .map(|(i, secs)| {
+                json!({
+                    "zone": i + 1,
+                    "min_bpm": if i == 0 { None } else { Some(boundaries[i - 1]) },
+                    "max_bpm": boundaries.get(i),
+                    "seconds": secs,
+                    "percentage": if total > 0.0 { 100.0 * secs / total } else { 0.0 },
+                })
+            })
+            .collect();
+
+        let result = json!({
+            "workout_hash": params.workout_hash,
+            "boundaries": boundaries,
+            "total_seconds": total,
+            "zones": zones,
+        });
+
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Approximate daily exercise minutes from heart rate, for devices that don't log exercise time. Heart rate samples are grouped into clock minutes; a minute is active when its average is at or above the threshold (default 64% of max heart rate, i.e. moderate intensity). Minutes without samples are not counted. Returns: threshold_bpm, and days: date, active_minutes, minutes_with_data."
+    )]
+    async fn get_active_minutes(&self, params: Parameters<GetActiveMinutesParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+
+        let threshold = match params.threshold_bpm {
+            Some(t) => t,
+            None => {
+                let intensity = params.intensity.unwrap_or(0.64);
+                if !(intensity > 0.0 && intensity <= 1.0) {
+                    return err_json("invalid_argument", "intensity must be between 0 and 1");
+                }
+                let max_hr = match params.max_heart_rate {
+                    Some(m) => m,
+                    None => match self.query_to_json(
+                        "SELECT MAX(value) AS max_hr FROM records WHERE record_type = 'HKQuantityTypeIdentifierHeartRate'",
+                        &[],
+                    ) {
+                        Ok(r) => match r[0]["max_hr"].as_f64() {
+                            Some(m) => m,
+                            None => return err_json("not_found", "No heart rate data"),
+                        },
+                        Err(e) => return err_json("query_failed", e),
+                    },
+                };
+                intensity * max_hr
+            }
+        };
+
+        let mut filters = String::new();
+        if let Some(ref sd) = params.start_date {
+            filters.push_str(&format!(" AND start_date >= '{}'", sd.replace('\'', "''")));
+        }
+        if let Some(ref ed) = params.end_date {
+            filters.push_str(&format!(" AND end_date <= '{}'", ed.replace('\'', "''")));
+        }
+        let date = match self.local_date_expr("minute") {
+            Ok(expr) => expr,
+            Err(e) => return err_json("query_failed", e),
+        };
+        let sql = format!(
+            "SELECT CAST({} AS VARCHAR) AS date, \
+             COUNT(*) FILTER (WHERE avg_bpm >= ?) AS active_minutes, COUNT(*) AS minutes_with_data \
+             FROM ( \
+                 SELECT date_trunc('minute', start_date) AS minute, AVG(value) AS avg_bpm FROM records \
+                 WHERE record_type = 'HKQuantityTypeIdentifierHeartRate' AND value IS NOT NULL{} \
+                 GROUP BY 1 \
+             ) GROUP BY 1 ORDER BY 1",
+            date, filters
+        );
+
+        let days = match self.query_to_json(&sql, &[&threshold as &dyn duckdb::ToSql]) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let result = json!({
+            "threshold_bpm": threshold,
+            "days": days,
+        });
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Fastest continuous effort over a target distance (e.g. 5000 m for a 5K) across all workouts of an activity type (default running), computed from GPS route data with a sliding window over cumulative distance. Returns: workout_hash, workout_start_date, distance_m, elapsed_seconds, pace_seconds_per_km, pace (m:ss per km), segment_start, segment_end (UTC), and workouts_considered."
+    )]
+    async fn get_best_effort(&self, params: Parameters<GetBestEffortParams>) -> String {
+        let Parameters(params) = params;
+        if !params.distance_m.is_finite() || params.distance_m <= 0.0 {
+            return err_json("invalid_argument", "distance_m must be positive");
+        }
+        let activity_type = params
+            .activity_type
+            .unwrap_or_else(|| "HKWorkoutActivityTypeRunning".to_string());
+
+        let points = match self.query_to_json(
+            "SELECT rp.workout_hash, CAST(w.start_date AS VARCHAR) AS workout_start_date, rp.latitude, rp.longitude, \
+             epoch(rp.timestamp) AS t \
+             FROM route_points rp JOIN workouts w ON rp.workout_hash = w.workout_hash \
+             WHERE w.activity_type = ? \
+             ORDER BY rp.workout_hash, rp.timestamp",
+            &[&activity_type as &dyn duckdb::ToSql],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        // Build (cumulative meters, epoch seconds) tracks per workout
+        let mut tracks: Vec<(String, String, Vec<TrackPoint>)> = Vec::new();
+        let mut prev: Option<(f64, f64)> = None;
+        for p in points.as_array().into_iter().flatten() {
+            let (Some(hash), Some(lat), Some(lon), Some(t)) = (
+                p["workout_hash"].as_str(),
+                p["latitude"].as_f64(),
+                p["longitude"].as_f64(),
+                p["t"].as_f64(),
+            ) else {
+                continue;
+            };
+            if tracks.last().is_none_or(|(h, _, _)| h != hash) {
+                let start = p["workout_start_date"].as_str().unwrap_or_default();
+                tracks.push((hash.to_string(), start.to_string(), Vec::new()));
+                prev = None;
+            }
+            let track = &mut tracks.last_mut().unwrap().2;
+            let cumulative = match (prev, track.last()) {
+                (Some((plat, plon)), Some((d, _))) => d + haversine_m(plat, plon, lat, lon),
+                _ => 0.0,
+            };
+            track.push((cumulative, t));
+            prev = Some((lat, lon));
+        }
+
+        let best = tracks
+            .iter()
+            .filter_map(|(hash, start, track)| {
+                fastest_segment(track, params.distance_m).map(|seg| (hash, start, seg))
+            })
+            .min_by(|a, b| a.2 .0.total_cmp(&b.2 .0));
+
+        let Some((hash, start, (elapsed, seg_start, seg_end))) = best else {
+            return err_json(
+                "not_found",
+                format!(
+                    "No {} workout with route data covers {} m",
+                    activity_type, params.distance_m
+                ),
+            );
+        };
+
+        let pace = elapsed / (params.distance_m / 1000.0);
+        let to_utc = |epoch: f64| {
+            chrono::DateTime::from_timestamp_millis((epoch * 1000.0).round() as i64)
+                .map(|dt| dt.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+        };
+        let result = json!({
+            "workout_hash": hash,
+            "workout_start_date": start,
+            "distance_m": params.distance_m,
+            "elapsed_seconds": elapsed,
+            "pace_seconds_per_km": pace,
+            "pace": format_pace(pace),
+            "segment_start": to_utc(seg_start),
+            "segment_end": to_utc(seg_end),
+            "workouts_considered": tracks.len(),
+        });
+
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Splits of a workout's GPS route: elapsed time for each consecutive split_distance_m (default 1000 m; 1609.344 for miles) of cumulative great-circle distance, with split boundaries interpolated between points. Returns: workout_hash, split_distance_m, total_distance_m, total_seconds, splits (split, distance_m, seconds, pace_seconds_per_km, pace as m:ss per km, partial: true for the final short split). Workouts without at least two route points return an empty splits array and a note."
+    )]
+    async fn get_workout_splits(&self, params: Parameters<GetWorkoutSplitsParams>) -> String {
+        let Parameters(params) = params;
+        let split_m = params.split_distance_m.unwrap_or(1000.0);
+        if !split_m.is_finite() || split_m <= 0.0 {
+            return err_json("invalid_argument", "split_distance_m must be positive");
+        }
+        match self.workout_window(&params.workout_hash) {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return err_json(
+                    "not_found",
+                    format!("Workout {} not found", params.workout_hash),
+                )
+            }
+            Err(e) => return err_json("query_failed", e),
+        }
+
+        let points = match self.query_to_json(
+            "SELECT latitude, longitude, epoch(timestamp) AS t FROM route_points \
+             WHERE workout_hash = ? ORDER BY timestamp, point_hash",
+            &[&params.workout_hash as &dyn duckdb::ToSql],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+        let track: Vec<(f64, f64, f64)> = points
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|p| {
+                Some((
+                    p["latitude"].as_f64()?,
+                    p["longitude"].as_f64()?,
+                    p["t"].as_f64()?,
+                ))
+            })
+            .collect();
+
+        let split = |n: usize, distance: f64, seconds: f64, partial: bool| {
+            let pace = seconds / (distance / 1000.0);
+            let mut s = json!({
+                "split": n,
+                "distance_m": distance,
+                "seconds": seconds,
+                "pace_seconds_per_km": pace,
+                "pace": format_pace(pace),
+            });
+            if partial {
+                s["partial"] = json!(true);
+            }
+            s
+        };
+
+        // Walk the cumulative distance, cutting a split (at the time interpolated within the
+        // segment) every time it passes the next multiple of split_m
+        let mut splits = Vec::new();
+        let mut distance = 0.0;
+        let mut split_start_t = track.first().map_or(0.0, |p| p.2);
+        for pair in track.windows(2) {
+            let ((lat1, lon1, t1), (lat2, lon2, t2)) = (pair[0], pair[1]);
+            let step = haversine_m(lat1, lon1, lat2, lon2);
+            while step > 0.0 && distance + step >= (splits.len() + 1) as f64 * split_m {
+                let boundary = (splits.len() + 1) as f64 * split_m;
+                let t = t1 + (t2 - t1) * (boundary - distance) / step;
+                splits.push(split(splits.len() + 1, split_m, t - split_start_t, false));
+                split_start_t = t;
+            }
+            distance += step;
+        }
+        let end_t = track.last().map_or(0.0, |p| p.2);
+        let remainder = distance - splits.len() as f64 * split_m;
+        if remainder > 0.0 && end_t > split_start_t {
+            splits.push(split(
+                splits.len() + 1,
+                remainder,
+                end_t - split_start_t,
+                true,
+            ));
+        }
+
+        let mut result = json!({
+            "workout_hash": params.workout_hash,
+            "split_distance_m": split_m,
+            "total_distance_m": distance,
+            "total_seconds": track.first().map_or(0.0, |p| end_t - p.2),
+            "splits": splits,
+        });
+        if track.len() < 2 {
+            result["note"] = json!("Workout has no route data to split");
+        }
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Find workouts whose GPS route jumps implausibly fast between consecutive points (GPS teleports), which inflate distance and pace. Each segment's speed is its great-circle distance over elapsed time (at least 1 s), compared with a per-activity maximum (e.g. 12.5 m/s running, 30 m/s cycling, 50 m/s otherwise) or max_speed_mps. Returns: workouts_checked, and flagged: workout_hash, activity_type, start_date, max_speed_mps, segment_count, segments (up to 20, fastest first: from_time, to_time, from_latitude, from_longitude, to_latitude, to_longitude, distance_m, seconds, speed_mps)."
+    )]
+    async fn validate_routes(&self, params: Parameters<ValidateRoutesParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        if let Some(max) = params.max_speed_mps {
+            if !max.is_finite() || max <= 0.0 {
+                return err_json("invalid_argument", "max_speed_mps must be positive");
+            }
+        }
+
+        let mut sql = String::from(
+            "SELECT rp.workout_hash, w.activity_type, CAST(w.start_date AS VARCHAR) AS workout_start_date, \
+             rp.latitude, rp.longitude, CAST(rp.timestamp AS VARCHAR) AS timestamp, epoch(rp.timestamp) AS t \
+             FROM route_points rp JOIN workouts w ON rp.workout_hash = w.workout_hash WHERE 1=1",
+        );
+        if let Some(ref at) = params.activity_type {
+            sql.push_str(&format!(
+                " AND w.activity_type = '{}'",
+                at.replace('\'', "''")
+            ));
+        }
+        if let Some(ref sd) = params.start_date {
+            sql.push_str(&format!(
+                " AND w.start_date >= '{}'",
+                sd.replace('\'', "''")
+            ));
+        }
+        if let Some(ref ed) = params.end_date {
+            sql.push_str(&format!(
+                " AND w.start_date < CAST('{}' AS DATE) + INTERVAL 1 DAY",
+                ed.replace('\'', "''")
+            ));
+        }
+        sql.push_str(" ORDER BY rp.workout_hash, rp.timestamp, rp.point_hash");
+
+        let points = match self.query_to_json(&sql, &[]) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let mut workouts_checked = 0;
+        let mut flagged: Vec<Value> = Vec::new();
+        let points = points.as_array().cloned().unwrap_or_default();
+        for route in points.chunk_by(|a, b| a["workout_hash"] == b["workout_hash"]) {
+            workouts_checked += 1;
+            let activity_type = route[0]["activity_type"].as_str().unwrap_or_default();
+            let max_speed = params
+                .max_speed_mps
+                .unwrap_or_else(|| max_plausible_speed_mps(activity_type));
+
+            let mut segments: Vec<(f64, Value)> = route
+                .windows(2)
+                .filter_map(|pair| {
+                    let (from, to) = (&pair[0], &pair[1]);
+                    let (lat1, lon1, t1) = (
+                        from["latitude"].as_f64()?,
+                        from["longitude"].as_f64()?,
+                        from["t"].as_f64()?,
+                    );
+                    let (lat2, lon2, t2) = (
+                        to["latitude"].as_f64()?,
+                        to["longitude"].as_f64()?,
+                        to["t"].as_f64()?,
+                    );
+                    let distance = haversine_m(lat1, lon1, lat2, lon2);
+                    let seconds = t2 - t1;
+                    let speed = distance / seconds.max(1.0);
+                    (speed > max_speed).then(|| {
+                        (
+                            speed,
+                            json!({
+                                "from_time": from["timestamp"],
+                                "to_time": to["timestamp"],
+                                "from_latitude": lat1,
+                                "from_longitude": lon1,
+                                "to_latitude": lat2,
+                                "to_longitude": lon2,
+                                "distance_m": distance,
+                                "seconds": seconds,
+                                "speed_mps": speed,
+                            }),
+                        )
+                    })
+                })
+                .collect();
+            if segments.is_empty() {
+                continue;
+            }
+            segments.sort_by(|a, b| b.0.total_cmp(&a.0));
+            flagged.push(json!({
+                "workout_hash": route[0]["workout_hash"],
+                "activity_type": activity_type,
+                "start_date": route[0]["workout_start_date"],
+                "max_speed_mps": max_speed,
+                "segment_count": segments.len(),
+                "segments": segments.into_iter().take(20).map(|(_, s)| s).collect::<Vec<_>>(),
+            }));
+        }
+
+        let result = json!({
+            "workouts_checked": workouts_checked,
+            "flagged": flagged,
+        });
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Get Apple Watch activity ring data. Returns {rows, truncated, limit} (truncated: the row cap cut the days short); rows have: date_components, active_energy_burned, active_energy_burned_goal, apple_exercise_time, apple_exercise_time_goal, apple_stand_hours, apple_stand_hours_goal. Values are in kcal, minutes, and hours respectively."
+    )]
+    async fn get_activity_summaries(
+        &self,
+        params: Parameters<GetActivitySummariesParams>,
+    ) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let (limit, capped) = self.row_limit("get_activity_summaries", params.limit, 30);
+        let mut sql = String::from("SELECT * FROM activity_summaries WHERE 1=1");
+
+        if let Some(ref sd) = params.start_date {
+            sql.push_str(&format!(
+                " AND date_components >= '{}'",
+                sd.replace('\'', "''")
+            ));
+        }
+        if let Some(ref ed) = params.end_date {
+            sql.push_str(&format!(
+                " AND date_components <= '{}'",
+                ed.replace('\'', "''")
+            ));
+        }
+        sql.push_str(&format!(" ORDER BY date_components DESC LIMIT {}", limit));
+
+        match self.query_to_json(&sql, &[]) {
+            Ok(result) => serde_json::to_string_pretty(&capped_rows(result, limit, capped))
+                .unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+
+    #[tool(
+        description = "Daily activity in one view: activity rings joined with the day's step count. Returns {rows, truncated, limit}, truncated meaning the row cap cut the days short, with rows of: date, active_energy_burned (kcal), exercise_minutes, stand_hours, steps (summed across sources). Days with only ring data or only steps are included with the other fields null. Newest first."
+    )]
+    async fn get_daily_activity_trends(
+        &self,
+        params: Parameters<GetDailyActivityTrendsParams>,
+    ) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let (limit, capped) = self.row_limit("get_daily_activity_trends", params.limit, 30);
+        let mut sql = String::from(
+            "WITH rings AS ( \
+                 SELECT TRY_CAST(date_components AS DATE) AS date, active_energy_burned, \
+                        apple_exercise_time AS exercise_minutes, apple_stand_hours AS stand_hours \
+                 FROM activity_summaries \
+             ), steps AS ( \
+                 SELECT date, SUM(sum_value) AS steps FROM daily_record_stats \
+                 WHERE record_type = 'HKQuantityTypeIdentifierStepCount' GROUP BY date \
+             ) \
+             SELECT CAST(COALESCE(r.date, s.date) AS VARCHAR) AS date, r.active_energy_burned, \
+                    r.exercise_minutes, r.stand_hours, s.steps \
+             FROM rings r FULL OUTER JOIN steps s ON r.date = s.date \
+             WHERE COALESCE(r.date, s.date) IS NOT NULL",
+        );
+
+        if let Some(ref sd) = params.start_date {
+            sql.push_str(&format!(
+                " AND COALESCE(r.date, s.date) >= CAST('{}' AS DATE)",
+                sd.replace('\'', "''")
+            ));
+        }
+        if let Some(ref ed) = params.end_date {
+            sql.push_str(&format!(
+                " AND COALESCE(r.date, s.date) <= CAST('{}' AS DATE)",
+                ed.replace('\'', "''")
+            ));
+        }
+        sql.push_str(&format!(" ORDER BY 1 DESC LIMIT {}", limit));
+
+        match self.query_to_json(&sql, &[]) {
+            Ok(result) => serde_json::to_string_pretty(&capped_rows(result, limit, capped))
+                .unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+
+    #[tool(
+        description = "Weekly review for the 7 days ending on week_ending, in one object ready to narrate. Returns: week_start, week_end, workouts (count, total_duration_minutes, total_distance_m, total_energy_kcal, by_activity_type: activity_type, count, total_distance_m, total_duration_minutes), resting_heart_rate (avg_bpm, days, previous_week_avg_bpm), sleep (nights, avg_asleep_hours, avg_efficiency; as get_sleep_quality), rings (days, move_closed, exercise_closed, stand_closed, all_closed), and personal_records: workouts this week that beat every earlier workout of their activity type on distance_m or duration_seconds (activity_type, metric, value, previous_best, workout_hash, date). Fields without data are omitted."
+    )]
+    async fn generate_weekly_report(
+        &self,
+        params: Parameters<GenerateWeeklyReportParams>,
+    ) -> String {
+        let Parameters(params) = params;
+        let Ok(end) = chrono::NaiveDate::parse_from_str(params.week_ending.trim(), "%Y-%m-%d")
+        else {
+            return err_json(
+                "invalid_argument",
+                format!(
+                    "Invalid week_ending {:?}, expected YYYY-MM-DD",
+                    params.week_ending
+                ),
+            );
+        };
+        let start = end - chrono::Duration::days(6);
+        let (week_start, week_end) = (start.to_string(), end.to_string());
+        let previous_start = (start - chrono::Duration::days(7)).to_string();
+
+        // Nights are labeled by the local evening they start, so pad the UTC window past any
+        // offset and keep the nights labeled within the week
+        let sleep = self
+            .get_sleep_quality(Parameters(GetSleepQualityParams {
+                start_date: Some((start - chrono::Duration::days(1)).to_string()),
+                end_date: Some((end + chrono::Duration::days(2)).to_string()),
+                source_name: None,
+            }))
+            .await;
+        if is_err_json(&sleep) {
+            return sleep;
+        }
+        let nights: Vec<Value> = serde_json::from_str::<Vec<Value>>(&sleep)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|n| {
+                n["night"]
+                    .as_str()
+                    .is_some_and(|night| night >= week_start.as_str() && night <= week_end.as_str())
+            })
+            .collect();
+        let average = |field: &str| -> Option<f64> {
+            let values: Vec<f64> = nights.iter().filter_map(|n| n[field].as_f64()).collect();
+            (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+        };
+        let sleep = json!({
+            "nights": nights.len(),
+            "avg_asleep_hours": average("asleep_seconds").map(|s| s / 3600.0),
+            "avg_efficiency": average("efficiency"),
+        });
+
+        let window = [
+            &week_start as &dyn duckdb::ToSql,
+            &week_end as &dyn duckdb::ToSql,
+        ];
+        let first_row = |sql: &str, params: &[&dyn duckdb::ToSql]| {
+            self.query_to_json(sql, params)
+                .map(|rows| rows.get(0).cloned().unwrap_or_else(|| json!({})))
+        };
+
+        let local_start = match self.local_time_expr("start_date") {
+            Ok(expr) => expr,
+            Err(e) => return err_json("query_failed", e),
+        };
+        let workout_filter = format!(
+            "{local_start} >= CAST(? AS DATE) AND {local_start} < CAST(? AS DATE) + INTERVAL 1 DAY"
+        );
+        let totals = first_row(
+            &format!(
+                "SELECT COUNT(*) AS count, \
+                 SUM(epoch(end_date) - epoch(start_date)) / 60 AS total_duration_minutes, \
+                 SUM(total_distance_m) AS total_distance_m, SUM(total_energy_kcal) AS total_energy_kcal \
+                 FROM workouts WHERE {workout_filter}"
+            ),
+            &window,
+        );
+        let by_type = self.query_to_json(
+            &format!(
+                "SELECT activity_type, COUNT(*) AS count, SUM(total_distance_m) AS total_distance_m, \
+                 SUM(epoch(end_date) - epoch(start_date)) / 60 AS total_duration_minutes \
+                 FROM workouts WHERE {workout_filter} \
+                 GROUP BY activity_type ORDER BY count DESC, activity_type"
+            ),
+            &window,
+        );
+        let resting_hr = first_row(
+            "SELECT AVG(avg_value) FILTER (WHERE date >= CAST(? AS DATE)) AS avg_bpm, \
+             COUNT(*) FILTER (WHERE date >= CAST(? AS DATE)) AS days, \
+             AVG(avg_value) FILTER (WHERE date < CAST(? AS DATE)) AS previous_week_avg_bpm \
+             FROM daily_record_stats \
+             WHERE record_type = 'HKQuantityTypeIdentifierRestingHeartRate' \
+             AND date >= CAST(? AS DATE) AND date <= CAST(? AS DATE)",
+            &[
+                &week_start as &dyn duckdb::ToSql,
+                &week_start as &dyn duckdb::ToSql,
+                &week_start as &dyn duckdb::ToSql,
+                &previous_start as &dyn duckdb::ToSql,
+                &week_end as &dyn duckdb::ToSql,
+            ],
+        );
+        let rings = first_row(
+            "WITH days AS ( \
+                 SELECT active_energy_burned >= active_energy_burned_goal AND active_energy_burned_goal > 0 AS move, \
+                        apple_exercise_time >= apple_exercise_time_goal AND apple_exercise_time_goal > 0 AS exercise, \
+                        apple_stand_hours >= apple_stand_hours_goal AND apple_stand_hours_goal > 0 AS stand \
+                 FROM activity_summaries \
+                 WHERE TRY_CAST(date_components AS DATE) BETWEEN CAST(? AS DATE) AND CAST(? AS DATE) \
+             ) \
+             SELECT COUNT(*) AS days, COUNT(*) FILTER (WHERE move) AS move_closed, \
+                    COUNT(*) FILTER (WHERE exercise) AS exercise_closed, \
+                    COUNT(*) FILTER (WHERE stand) AS stand_closed, \
+                    COUNT(*) FILTER (WHERE move AND exercise AND stand) AS all_closed \
+             FROM days",
+            &window,
+        );
+        let records = self.query_to_json(
+            &format!(
+                "WITH week AS ( \
+                     SELECT workout_hash, activity_type, start_date, total_distance_m AS distance_m, \
+                            epoch(end_date) - epoch(start_date) AS duration_seconds \
+                     FROM workouts WHERE {workout_filter} \
+                 ), earlier AS ( \
+                     SELECT activity_type, MAX(total_distance_m) AS distance_m, \
+                            MAX(epoch(end_date) - epoch(start_date)) AS duration_seconds \
+                     FROM workouts WHERE {local_start} < CAST(? AS DATE) GROUP BY activity_type \
+                 ), beaten AS ( \
+                     SELECT w.activity_type, 'distance_m' AS metric, w.distance_m AS value, \
+                            e.distance_m AS previous_best, w.workout_hash, w.start_date \
+                     FROM week w JOIN earlier e USING (activity_type) WHERE w.distance_m > e.distance_m \
+                     UNION ALL \
+                     SELECT w.activity_type, 'duration_seconds', w.duration_seconds, \
+                            e.duration_seconds, w.workout_hash, w.start_date \
+                     FROM week w JOIN earlier e USING (activity_type) \
+                     WHERE w.duration_seconds > e.duration_seconds \
+                 ) \
+                 SELECT DISTINCT ON (activity_type, metric) activity_type, metric, value, previous_best, \
+                        workout_hash, CAST(CAST({local_start} AS DATE) AS VARCHAR) AS date \
+                 FROM beaten ORDER BY activity_type, metric, value DESC"
+            ),
+            &[
+                &week_start as &dyn duckdb::ToSql,
+                &week_end as &dyn duckdb::ToSql,
+                &week_start as &dyn duckdb::ToSql,
+            ],
+        );
+        let (mut totals, by_type, resting_hr, rings, records) =
+            match (totals, by_type, resting_hr, rings, records) {
+                (Ok(a), Ok(b), Ok(c), Ok(d), Ok(e)) => (a, b, c, d, e),
+                (Err(e), ..)
+                | (_, Err(e), ..)
+                | (_, _, Err(e), ..)
+                | (_, _, _, Err(e), _)
+                | (.., Err(e)) => return err_json("query_failed", e),
+            };
+        totals["by_activity_type"] = by_type;
+
+        let result = json!({
+            "week_start": week_start,
+            "week_end": week_end,
+            "workouts": totals,
+            "resting_heart_rate": resting_hr,
+            "sleep": sleep,
+            "rings": rings,
+            "personal_records": records,
+        });
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Get GPS route data for a workout, in time order. Returns {rows, truncated, limit}, truncated meaning the row cap cut the route short, with rows of: latitude, longitude, elevation (meters), timestamp, speed (m/s), course (degrees). Long routes can have thousands of points: use start_time/end_time for a segment, or limit/offset to page through; without them all points up to the row cap (10,000 by default) are returned. Use get_workout_details first to check has_route."
+    )]
+    async fn get_workout_route(&self, params: Parameters<GetWorkoutRouteParams>) -> String {
+        let Parameters(params) = params;
+        let (limit, capped) = self.row_limit("get_workout_route", params.limit, u32::MAX);
+        let mut sql = String::from(
+            "SELECT latitude, longitude, elevation, CAST(timestamp AS VARCHAR) AS timestamp, speed, course \
+             FROM route_points WHERE workout_hash = ?",
+        );
+        if let Some(ref st) = params.start_time {
+            sql.push_str(&format!(" AND timestamp >= '{}'", st.replace('\'', "''")));
+        }
+        if let Some(ref et) = params.end_time {
+            sql.push_str(&format!(" AND timestamp <= '{}'", et.replace('\'', "''")));
+        }
+        sql.push_str(&format!(" ORDER BY timestamp, point_hash LIMIT {}", limit));
+        if let Some(offset) = params.offset {
+            sql.push_str(&format!(" OFFSET {}", offset));
+        }
+
+        match self.query_to_json(&sql, &[&params.workout_hash as &dyn duckdb::ToSql]) {
+            Ok(result) => serde_json::to_string_pretty(&capped_rows(result, limit, capped))
+                .unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+
+    #[tool(
+        description = "List ECG recordings. Returns: ecg_hash, recorded_date, classification (e.g. SinusRhythm, AtrialFibrillation), device, sample_rate_hz. Use ecg_hash with get_ecg_data."
+    )]
+    async fn list_ecg_readings(&self, params: Parameters<ListEcgReadingsParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let mut sql = String::from(
+            "SELECT ecg_hash, recorded_date, classification, device, sample_rate_hz FROM ecg_readings WHERE 1=1",
+        );
+        if let Some(ref sd) = params.start_date {
+            sql.push_str(&format!(
+                " AND recorded_date >= '{}'",
+                sd.replace('\'', "''")
+            ));
+        }
+        if let Some(ref ed) = params.end_date {
+            sql.push_str(&format!(
+                " AND recorded_date <= '{}'",
+                ed.replace('\'', "''")
+            ));
+        }
+        sql.push_str(" ORDER BY recorded_date DESC");
+
+        match self.query_to_json(&sql, &[]) {
+            Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+
+    #[tool(
+        description = "List clinical records (lab results, vitals) imported from export_cda.xml, newest first. Returns {rows, truncated, limit}; truncated is true when the row cap cut the list short. Rows have: record_type (HealthKit identifier, or the observation code such as LOINC 2345-7), display_name (e.g. Glucose [Mass/volume] in Serum or Plasma), value (numeric results), value_text (coded or text results), unit, effective_date, source. Empty when the export had no export_cda.xml."
+    )]
+    async fn list_clinical_records(&self, params: Parameters<ListClinicalRecordsParams>) -> String {
+        let Parameters(mut params) = params;
+        params.start_date = self.effective_start_date(params.start_date.take());
+        let (limit, capped) = self.row_limit("list_clinical_records", params.limit, 100);
+        let mut sql = String::from(
+            "SELECT record_type, display_name, value, value_text, unit, \
+                    CAST(effective_date AS VARCHAR) AS effective_date, source \
+             FROM clinical_records WHERE 1=1",
+        );
+        if let Some(ref rt) = params.record_type {
+            sql.push_str(&format!(" AND record_type = '{}'", rt.replace('\'', "''")));
+        }
+        if let Some(ref sd) = params.start_date {
+            sql.push_str(&format!(
+                " AND effective_date >= '{}'",
+                sd.replace('\'', "''")
+            ));
+        }
+        if let Some(ref ed) = params.end_date {
+            sql.push_str(&format!(
+                " AND effective_date <= '{}'",
+                ed.replace('\'', "''")
+            ));
+        }
+        sql.push_str(" ORDER BY effective_date DESC NULLS LAST, record_type LIMIT ?");
+
+        match self.query_to_json(&sql, &[&limit as &dyn duckdb::ToSql]) {
+            Ok(result) => serde_json::to_string_pretty(&capped_rows(result, limit, capped))
+                .unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+
+    #[tool(
+        description = "Get full ECG waveform by ecg_hash. Returns: reading (metadata), sample_count, voltages_uv (array of voltage values in microvolts). With max_samples, longer recordings are decimated uniformly (every Nth sample) and the result adds downsampled: true, original_sample_count, and sample_rate_hz (the rate after decimation). Get ecg_hash from list_ecg_readings."
+    )]
+    async fn get_ecg_data(&self, params: Parameters<GetEcgDataParams>) -> String {
+        let Parameters(params) = params;
+        if params.max_samples == Some(0) {
+            return err_json("invalid_argument", "max_samples must be at least 1");
+        }
+        let hash = params.ecg_hash;
+        let metadata = match self.query_to_json(
+            "SELECT * FROM ecg_readings WHERE ecg_hash = ?",
+            &[&hash as &dyn duckdb::ToSql],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let samples = match self.query_to_json(
+            "SELECT voltage_uv FROM ecg_samples WHERE ecg_hash = ? ORDER BY sample_idx",
+            &[&hash as &dyn duckdb::ToSql],
+        ) {
+            Ok(r) => r,
+            Err(e) => return err_json("query_failed", e),
+        };
+
+        let mut voltages: Vec<Value> = samples
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|r| r.get("voltage_uv").cloned())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let reading = metadata
+            .as_array()
+            .and_then(|a| a.first())
+            .cloned()
+            .unwrap_or(Value::Null);
+        let original_count = voltages.len();
+        let step = match params.max_samples {
+            Some(max) if original_count > max as usize => original_count.div_ceil(max as usize),
+            _ => 1,
+        };
+        if step > 1 {
+            voltages = voltages.into_iter().step_by(step).collect();
+        }
+
+        let mut result = json!({
+            "reading": reading,
+            "sample_count": voltages.len(),
+            "voltages_uv": voltages,
+        });
+        if step > 1 {
+            result["downsampled"] = json!(true);
+            result["original_sample_count"] = json!(original_count);
+            result["sample_rate_hz"] = json!(reading["sample_rate_hz"]
+                .as_f64()
+                .map(|rate| rate / step as f64));
+        }
+
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Run a read-only SQL query (DuckDB dialect). Must start with SELECT or WITH. Returns a JSON array of rows; if the result exceeds the server's row cap (default 10000), returns {rows, truncated: true, note} with the first rows only. With format=ndjson, returns one JSON object per row per line instead, ending with a {truncated: true, note} line when capped. Tables: records (record_hash, record_type, value, unit, source_name, device, start_date, end_date, first_import_id, last_import_id, value_si, value_text), workouts (workout_hash, activity_type, duration, total_distance, total_energy_burned, total_distance_m, total_energy_kcal, route_file, start_date, end_date), workout_metadata (workout_hash, key, value), workout_events, workout_statistics, activity_summaries, ecg_readings, ecg_samples, route_points (latitude, longitude, elevation, timestamp, speed), daily_record_stats (record_type, date, unit, count, avg_value, min_value, max_value, sum_value), weekly_record_stats and monthly_record_stats (record_type, period, count, avg_value, min_value, max_value, sum_value, days_with_data; daily_record_stats rolled up by ISO week / month), record_metadata (record_hash, key, value), audiogram_points (record_hash, frequency_hz, left_ear_db, right_ear_db, left_ear_masked, right_ear_masked; dB HL), imports, annotations (annotation_id, start_date, end_date, tag, note, created_at), clinical_records (record_hash, record_type, display_name, value, value_text, unit, effective_date, source; from export_cda.xml), source_aliases (pattern, canonical_name; SQL LIKE patterns from import --source-aliases). Prefer the canonical-unit columns (value_si: distance in m, energy in kcal, mass in kg, time in s; workouts.total_distance_m / total_energy_kcal) when summing across sources; they are NULL for unrecognized units. value_text holds the raw value of category records (e.g. sleep stages), whose numeric value is NULL."
+    )]
+    async fn run_custom_query(&self, params: Parameters<RunCustomQueryParams>) -> String {
+        let Parameters(params) = params;
+        let ndjson = match params.format.as_deref() {
+            None | Some("json") => false,
+            Some("ndjson") => true,
+            Some(other) => {
+                return err_json(
+                    "invalid_argument",
+                    format!("Unknown format {:?}, expected json or ndjson", other),
+                )
+            }
+        };
+        let trimmed = params.query.trim().to_string();
+        let upper = trimmed.to_uppercase();
+        if !upper.starts_with("SELECT") && !upper.starts_with("WITH") {
+            return err_json("invalid_argument", "Query must start with SELECT or WITH");
+        }
+        let denylist = sensitive::Denylist::new(&self.config.sensitive_denylist);
+        if !denylist.is_empty() {
+            if let Err(e) = sensitive::check_query(&trimmed, &denylist) {
+                return err_json("restricted", e);
+            }
+        }
+
+        // Fetch one row past the cap to detect truncation. A smaller LIMIT in the query still applies.
+        let max_rows = self.config.max_query_rows.unwrap_or(DEFAULT_MAX_QUERY_ROWS);
+        let sql = format!(
+            "SELECT * FROM (\n{}\n) LIMIT {}",
+            trimmed.trim_end_matches(';'),
+            max_rows + 1
+        );
+        let truncation_note = format!(
+            "Result truncated to the first {} rows; add a LIMIT, filter, or aggregate to narrow it",
+            max_rows
+        );
+
+        if ndjson {
+            // Rows are serialized as they are read, without building the whole array
+            let (mut output, mut written, mut truncated) = (String::new(), 0, false);
+            let result = self.for_each_row(&sql, &[], |row| {
+                if written == max_rows {
+                    truncated = true;
+                    return false;
+                }
+                output.push_str(&serde_json::to_string(&row).unwrap_or_default());
+                output.push('\n');
+                written += 1;
+                true
+            });
+            if let Err(e) = result {
+                return err_json("query_failed", e);
+            }
+            if truncated {
+                output.push_str(&json!({"truncated": true, "note": truncation_note}).to_string());
+                output.push('\n');
+            }
+            return output;
+        }
+
+        // Small results are pretty-printed as before. Past PRETTY_MAX_ROWS, rows are written
+        // compactly into one buffer as they are read, so a big result isn't held both as
+        // `Value`s and as the output text.
+        let (mut rows, mut buf): (Vec<Value>, Option<Vec<u8>>) = (Vec::new(), None);
+        let (mut written, mut truncated) = (0, false);
+        let result = self.for_each_row(&sql, &[], |row| {
+            if written == max_rows {
+                truncated = true;
+                return false;
+            }
+            written += 1;
+            match buf.as_mut() {
+                Some(buf) => {
+                    buf.push(b',');
+                    let _ = serde_json::to_writer(&mut *buf, &row);
+                }
+                None if rows.len() < PRETTY_MAX_ROWS => rows.push(row),
+                None => {
+                    let mut compact = vec![b'['];
+                    for (i, row) in rows.drain(..).chain(std::iter::once(row)).enumerate() {
+                        if i > 0 {
+                            compact.push(b',');
+                        }
+                        let _ = serde_json::to_writer(&mut compact, &row);
+                    }
+                    buf = Some(compact);
+                }
+            }
+            true
+        });
+        if let Err(e) = result {
+            return err_json("query_failed", e);
+        }
+
+        match (buf, truncated) {
+            (None, false) => serde_json::to_string_pretty(&rows).unwrap_or_default(),
+            (None, true) => serde_json::to_string_pretty(&json!({
+                "rows": rows,
+                "truncated": true,
+                "note": truncation_note,
+            }))
+            .unwrap_or_default(),
+            (Some(mut buf), truncated) => {
+                buf.push(b']');
+                if truncated {
+                    buf.splice(0..0, b"{\"rows\":".iter().copied());
+                    buf.extend_from_slice(b",\"truncated\":true,\"note\":");
+                    let _ = serde_json::to_writer(&mut buf, &truncation_note);
+                    buf.push(b'}');
+                }
+                String::from_utf8(buf).unwrap_or_default()
+            }
+        }
+    }
+
+    #[tool(
+        description = "List all devices and apps that contributed health data. Source names matched by an import --source-aliases file are merged under their canonical name, with the raw names in source_names. Returns: source_name, record_count, earliest_date, latest_date[, source_names]."
+    )]
+    async fn list_data_sources(&self) -> String {
+        let has_aliases = match self.query_to_json("SELECT 1 FROM source_aliases LIMIT 1", &[]) {
+            Ok(rows) => rows.as_array().is_some_and(|r| !r.is_empty()),
+            Err(e) => return err_json("query_failed", e),
+        };
+        if !has_aliases {
+            let sql = "SELECT source_name, COUNT(*) as record_count, MIN(start_date) as earliest_date, MAX(start_date) as latest_date FROM records GROUP BY source_name ORDER BY record_count DESC";
+            return match self.query_to_json(sql, &[]) {
+                Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
+                Err(e) => err_json("query_failed", e),
+            };
+        }
+
+        // The longest matching pattern wins; raw names are joined with \x1f and split below
+        let sql = "WITH per_source AS ( \
+                SELECT source_name, COUNT(*) AS record_count, MIN(start_date) AS earliest_date, MAX(start_date) AS latest_date \
+                FROM records GROUP BY source_name \
+            ), mapped AS ( \
+                SELECT p.*, COALESCE(( \
+                    SELECT a.canonical_name FROM source_aliases a WHERE p.source_name LIKE a.pattern \
+                    ORDER BY length(a.pattern) DESC, a.pattern LIMIT 1 \
+                ), p.source_name) AS canonical_name \
+                FROM per_source p \
+            ) \
+            SELECT canonical_name AS source_name, SUM(record_count)::BIGINT AS record_count, \
+                MIN(earliest_date) AS earliest_date, MAX(latest_date) AS latest_date, \
+                string_agg(source_name, chr(31) ORDER BY source_name) AS source_names \
+            FROM mapped GROUP BY canonical_name ORDER BY record_count DESC, source_name";
+        let mut result = match self.query_to_json(sql, &[]) {
+            Ok(result) => result,
+            Err(e) => return err_json("query_failed", e),
+        };
+        for row in result.as_array_mut().into_iter().flatten() {
+            let names: Vec<Value> = row["source_names"]
+                .as_str()
+                .unwrap_or_default()
+                .split('\x1f')
+                .map(|name| Value::String(name.to_string()))
+                .collect();
+            row["source_names"] = Value::Array(names);
+        }
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "List all data imports. Returns: import_id, export_dir, imported_at, record_count, workout_count, duration_secs."
+    )]
+    async fn get_import_history(&self) -> String {
+        let sql = "SELECT * FROM imports ORDER BY imported_at DESC";
+        match self.query_to_json(sql, &[]) {
+            Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+
+    #[tool(
+        description = "Compare two imports: per table (records, workouts, ecg_readings), how many rows (by hash) only import A contained, only import B contained, or both. Deduplication keeps each row's first and last import (first_import_id, last_import_id), so a row counts as contained in every import between those two by imported_at. Returns: import_id_a, import_id_b, tables {records, workouts, ecg_readings: {only_a, only_b, both}}."
+    )]
+    async fn diff_imports(&self, params: Parameters<DiffImportsParams>) -> String {
+        let Parameters(params) = params;
+        for id in [&params.import_id_a, &params.import_id_b] {
+            match self.query_to_json(
+                "SELECT import_id FROM imports WHERE import_id = ?",
+                &[id as &dyn duckdb::ToSql],
+            ) {
+                Ok(rows) if rows.as_array().is_some_and(|r| !r.is_empty()) => {}
+                Ok(_) => return err_json("not_found", format!("Import {} not found", id)),
+                Err(e) => return err_json("query_failed", e),
+            }
+        }
+
+        let mut tables = serde_json::Map::new();
+        for (table, key) in [
+            ("records", "record_hash"),
+            ("workouts", "workout_hash"),
+            ("ecg_readings", "ecg_hash"),
+        ] {
+            let sql = format!(
+                "WITH pick AS ( \
+                    SELECT (SELECT MIN(imported_at) FROM imports WHERE import_id = ?) AS a, \
+                           (SELECT MIN(imported_at) FROM imports WHERE import_id = ?) AS b \
+                 ), spans AS ( \
+                    SELECT DISTINCT t.{key}, f.imported_at AS first_at, l.imported_at AS last_at \
+                    FROM {table} t \
+                    JOIN imports f ON f.import_id = COALESCE(t.first_import_id, t.import_id) \
+                    JOIN imports l ON l.import_id = COALESCE(t.last_import_id, t.import_id) \
+                 ), flags AS ( \
+                    SELECT pick.a BETWEEN first_at AND last_at AS in_a, \
+                           pick.b BETWEEN first_at AND last_at AS in_b \
+                    FROM spans, pick \
+                 ) \
+                 SELECT COUNT(*) FILTER (WHERE in_a AND NOT in_b) AS only_a, \
+                        COUNT(*) FILTER (WHERE in_b AND NOT in_a) AS only_b, \
+                        COUNT(*) FILTER (WHERE in_a AND in_b) AS both \
+                 FROM flags"
+            );
+            match self.query_to_json(
+                &sql,
+                &[
+                    &params.import_id_a as &dyn duckdb::ToSql,
+                    &params.import_id_b as &dyn duckdb::ToSql,
+                ],
+            ) {
+                Ok(rows) => {
+                    let counts = rows.as_array().and_then(|r| r.first()).cloned();
+                    tables.insert(table.to_string(), counts.unwrap_or(Value::Null));
+                }
+                Err(e) => return err_json("query_failed", e),
+            }
+        }
+
+        let result = json!({
+            "import_id_a": params.import_id_a,
+            "import_id_b": params.import_id_b,
+            "tables": tables,
+        });
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    }
+
+    #[tool(
+        description = "Annotate a date range for later analysis, e.g. tag=medication note=\"started 10 mg\" (self-experiments, travel, illness). Only available when the server runs with --maintenance. Adding the same annotation twice is a no-op. Returns: annotation_id, tag, start_date, end_date, note, created (false if it already existed). See list_annotations and get_record_statistics include_annotations."
+    )]
+    async fn add_annotation(&self, params: Parameters<AddAnnotationParams>) -> String {
+        let Parameters(params) = params;
+        if !self.config.maintenance {
+            return err_json(
+                "restricted",
+                "add_annotation needs the server to run with --maintenance",
+            );
+        }
+
+        let tag = params.tag.trim();
+        if tag.is_empty() {
+            return err_json("invalid_argument", "tag must not be empty");
+        }
+        let parse = |d: &str| chrono::NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d");
+        let Ok(start) = parse(&params.start_date) else {
+            return err_json(
+                "invalid_argument",
+                format!(
+                    "Invalid start_date {:?}, expected YYYY-MM-DD",
+                    params.start_date
+                ),
+            );
+        };
+        let end = match params.end_date.as_deref().map(parse) {
+            None => start,
+            Some(Ok(end)) => end,
+            Some(Err(_)) => {
+                return err_json(
+                    "invalid_argument",
+                    format!(
+                        "Invalid end_date {:?}, expected YYYY-MM-DD",
+                        params.end_date
+                    ),
+                )
+            }
+        };
+        if end < start {
+            return err_json("invalid_argument", "end_date is before start_date");
+        }
+
+        let (start, end) = (start.to_string(), end.to_string());
+        let note = params
+            .note
+            .as_deref()
+            .map(str::trim)
+            .filter(|n| !n.is_empty());
+        let annotation_id =
+            crate::models::compute_hash(&[tag, &start, &end, note.unwrap_or_default()]);
+
+        let sql = "INSERT INTO annotations (annotation_id, start_date, end_date, tag, note) \
+                   SELECT ?, CAST(? AS DATE), CAST(? AS DATE), ?, ? \
+                   WHERE NOT EXISTS (SELECT 1 FROM annotations WHERE annotation_id = ?)";
+        let _ = QUERY_LOG.try_with(|log| log.borrow_mut().push(sql.to_string()));
+        let inserted = match self.conn.get() {
+            Ok(conn) => conn
+                .execute(
+                    sql,
+                    duckdb::params![annotation_id, start, end, tag, note, annotation_id],
+                )
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+
+        match inserted {
+            Ok(n) => serde_json::to_string_pretty(&json!({
+                "annotation_id": annotation_id,
+                "tag": tag,
+                "start_date": start,
+                "end_date": end,
+                "note": note,
+                "created": n > 0,
+            }))
+            .unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+
+    #[tool(
+        description = "List annotations added with add_annotation, oldest first, optionally filtered by tag and by overlap with a date range. Returns: annotation_id, tag, start_date, end_date, note, created_at."
+    )]
+    async fn list_annotations(&self, params: Parameters<ListAnnotationsParams>) -> String {
+        let Parameters(params) = params;
+        let mut sql = String::from(
+            "SELECT annotation_id, tag, CAST(start_date AS VARCHAR) AS start_date, CAST(end_date AS VARCHAR) AS end_date, \
+             note, CAST(created_at AS VARCHAR) AS created_at FROM annotations WHERE 1=1",
+        );
+        if let Some(ref tag) = params.tag {
+            sql.push_str(&format!(" AND tag = '{}'", tag.replace('\'', "''")));
+        }
+        if let Some(ref sd) = params.start_date {
+            sql.push_str(&format!(" AND end_date >= '{}'", sd.replace('\'', "''")));
+        }
+        if let Some(ref ed) = params.end_date {
+            sql.push_str(&format!(" AND start_date <= '{}'", ed.replace('\'', "''")));
+        }
+        sql.push_str(" ORDER BY start_date, tag");
+
+        match self.query_to_json(&sql, &[]) {
+            Ok(result) => serde_json::to_string_pretty(&result).unwrap_or_default(),
+            Err(e) => err_json("query_failed", e),
+        }
+    }
+}
+
+// Spelled out instead of `#[tool_handler]` so `call_tool` can attach the SQL when
+// `include_query` is set.
+impl ServerHandler for HealthServer {
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        // Query events logged during the call carry the tool name from this span
+        let span = tracing::debug_span!("tool", name = %request.name);
+        let tool_name = request.name.clone();
+        let started = std::time::Instant::now();
+        let tcc = ToolCallContext::new(self, request, context);
+        let (result, queries) = if self.config.include_query {
+            capture_queries(self.tool_router.call(tcc))
+                .instrument(span.clone())
+                .await
+        } else {
+            (
+                self.tool_router.call(tcc).instrument(span.clone()).await,
+                Vec::new(),
+            )
+        };
+        span.in_scope(|| {
+            tracing::debug!(
+                elapsed_ms = started.elapsed().as_millis(),
+                "tool call finished"
+            )
+        });
+
+        let mut result = result?;
+        let mut failed = false;
+        let mut query_failed = false;
+        for content in &mut result.content {
+            if let RawContent::Text(text) = &mut content.raw {
+                let kind = err_kind(&text.text);
+                query_failed |= kind.as_deref() == Some("query_failed");
+                failed |= kind.is_some();
+                if let (Some(locale), false) = (&self.config.output_locale, failed) {
+                    text.text = localize_output(&text.text, locale, self.config.locale_numbers);
+                }
+                if self.config.include_query {
+                    text.text = wrap_with_query(&text.text, &queries);
+                }
+            }
+        }
+        if failed {
+            result.is_error = Some(true);
+        }
+        // Only tools the router knows get here, so the label set stays bounded
+        metrics::record_tool_call(&tool_name, started.elapsed(), query_failed);
+        Ok(result)
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, rmcp::ErrorData> {
+        Ok(ListToolsResult {
+            tools: self.tool_router.list_all(),
+            meta: None,
+            next_cursor: None,
+        })
+    }
+
+    fn get_tool(&self, name: &str) -> Option<rmcp::model::Tool> {
+        self.tool_router.get(name).cloned()
+    }
+}
+
+/// Run `fut`, collecting every statement `query_to_json` executes along the way.
+async fn capture_queries<T>(fut: impl Future<Output = T>) -> (T, Vec<String>) {
+    QUERY_LOG
+        .scope(RefCell::new(Vec::new()), async move {
+            let output = fut.await;
+            (output, QUERY_LOG.with(|log| log.take()))
+        })
+        .await
+}
+
+/// Apply `--output-locale` to a tool's JSON output; non-JSON output is returned unchanged.
+fn localize_output(output: &str, locale: &locale::OutputLocale, numbers: bool) -> String {
+    match serde_json::from_str::<Value>(output) {
+        Ok(mut value) => {
+            locale.localize(&mut value, numbers);
+            serde_json::to_string_pretty(&value).unwrap_or_default()
+        }
+        Err(_) => output.to_string(),
+    }
+}
+
+/// Wrap a tool's text output as `{"sql": ..., "result": ...}`. Statements are joined with `;`;
+/// bound parameter values stay as `?` placeholders. Non-JSON output (errors) is kept as a string.
+fn wrap_with_query(output: &str, queries: &[String]) -> String {
+    let result =
+        serde_json::from_str::<Value>(output).unwrap_or_else(|_| Value::String(output.to_string()));
+    serde_json::to_string_pretty(&json!({
+        "sql": queries.join(";\n"),
+        "result": result,
+    }))
+    .unwrap_or_default()
+}
+
+pub async fn run_server(
+    db_path: &Path,
+    host: &str,
+    port: u16,
+    transport: &str,
+    config: ServerConfig,
+) -> Result<()> {
+    // Sessions open the DB read-only, so upgrade an older file once, up front
+    crate::db::migrate_file(db_path, &config.db_options)?;
+    match transport {
+        "stdio" => run_stdio_server(db_path, config).await,
+        "http" => run_http_server(db_path, host, port, config).await,
+        other => anyhow::bail!("Unknown transport: {other}. Expected \"http\" or \"stdio\"."),
+    }
+}
+
+/// Preflight for `serve --validate-only`: open the DB the way a session would, run a
+/// trivial query against every base table, and make sure the tool router builds.
+/// Returns the number of registered tools.
+pub fn validate_server(db_path: &Path) -> Result<usize> {
+    let server = HealthServer::new(db_path)?;
+    {
+        let conn = server.conn.get().map_err(|e| anyhow::anyhow!(e))?;
+        for table in crate::db::BASE_TABLES {
+            conn.execute_batch(&format!("SELECT * FROM {} LIMIT 0", table))
+                .map_err(|e| anyhow::anyhow!("Table {} is not queryable: {}", table, e))?;
+        }
+    }
+
+    let tool_count = server.tool_router.list_all().len();
+    if tool_count == 0 {
+        anyhow::bail!("No tools registered");
+    }
+    Ok(tool_count)
+}
+
+async fn run_stdio_server(db_path: &Path, config: ServerConfig) -> Result<()> {
+    let server = HealthServer::open(db_path, &config, false)?;
+    tracing::info!("MCP server running on stdio");
+    serve_until_closed(server, rmcp::transport::stdio()).await
+}
+
+/// Serve one MCP session on `transport` until the client closes it (stdin EOF when the
+/// parent process exits) or ctrl-c arrives. The session task owns the `HealthServer`, so by
+/// the time this returns its DuckDB connection has been dropped.
+async fn serve_until_closed<T, E, A>(server: HealthServer, transport: T) -> Result<()>
+where
+    T: rmcp::transport::IntoTransport<RoleServer, E, A>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let service = server
+        .serve(transport)
+        .await
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+
+    let cancel = service.cancellation_token();
+    let ctrl_c = tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            cancel.cancel();
+        }
+    });
+    let reason = service.waiting().await;
+    ctrl_c.abort();
+
+    let reason = reason.map_err(|e| anyhow::anyhow!("{e}"))?;
+    tracing::info!("MCP session ended ({:?}), shutting down", reason);
+    Ok(())
+}
+
+/// Build the axum router serving the MCP endpoint at `/mcp` and a `/health` probe, plus
+/// `/metrics` when `config.metrics` is set.
+/// When `config.auth_token` is set, every `/mcp` request must carry `Authorization: Bearer <token>`;
+/// `/health` and `/metrics` stay open so load balancers and scrapers can reach them. Sessions
+/// open the DB with external access disabled unless `config.allow_external_access` is set.
+pub fn build_http_router(db_path: &Path, config: &ServerConfig) -> axum::Router {
+    let mcp = axum::Router::new().nest_service("/mcp", mcp_service(db_path, config));
+    let health = axum::Router::new().route(
+        "/health",
+        axum::routing::get(health_check).with_state(Arc::new(db_path.to_path_buf())),
+    );
+    finish_http_router(mcp, health, config)
+}
+
+/// Like `build_http_router`, but for several databases: each `(name, path)` gets its own
+/// endpoint at `/mcp/<name>` whose sessions open only that file, and `/health` checks all of
+/// them. Auth, rate limiting, CORS, and metrics apply to every endpoint alike.
+pub fn build_multi_http_router(dbs: &[(String, PathBuf)], config: &ServerConfig) -> axum::Router {
+    let mcp = dbs
+        .iter()
+        .fold(axum::Router::new(), |router, (name, path)| {
+            router.nest_service(&format!("/mcp/{}", name), mcp_service(path, config))
+        });
+    let health = axum::Router::new().route(
+        "/health",
+        axum::routing::get(health_check_all).with_state(Arc::new(dbs.to_vec())),
+    );
+    finish_http_router(mcp, health, config)
+}
+
+/// The Streamable HTTP service for one database file.
+fn mcp_service(
+    db_path: &Path,
+    config: &ServerConfig,
+) -> StreamableHttpService<HealthServer, LocalSessionManager> {
+    let db_path = db_path.to_path_buf();
+    let config = config.clone();
+    let restricted = !config.allow_external_access;
+    // In maintenance mode the first session opens the read-write connection and later
+    // sessions share it, rather than each opening the file for writing
+    let shared: Mutex<Option<HealthServer>> = Mutex::new(None);
+
+    StreamableHttpService::new(
+        move || {
+            let open = || HealthServer::open(&db_path, &config, restricted);
+            let server = if config.maintenance {
+                let mut shared = shared
+                    .lock()
+                    .map_err(|e| std::io::Error::other(e.to_string()))?;
+                match shared.as_ref() {
+                    Some(server) => Ok(server.clone()),
+                    None => open().inspect(|server| *shared = Some(server.clone())),
+                }
+            } else {
+                open()
+            };
+            server.map_err(|e| std::io::Error::other(e.to_string()))
+        },
+        LocalSessionManager::default().into(),
+        Default::default(),
+    )
+}
+
+/// Put the MCP routes in `mcp` behind auth and rate limiting, then add the open `health`
+/// routes, `/metrics`, and CORS as `config` asks.
+fn finish_http_router(
+    mcp: axum::Router,
+    health: axum::Router,
+    config: &ServerConfig,
+) -> axum::Router {
+    let metrics_handle = config.metrics.then(metrics::install);
+
+    let router = match config.auth_token.clone() {
+        Some(token) => mcp.layer(axum::middleware::from_fn_with_state(
+            Arc::new(token),
+            require_bearer_token,
+        )),
+        None => mcp,
+    };
+
+    // Outside the auth check, so guessing tokens counts against the limit too
+    let router = match config.rate_limit {
+        Some(per_minute) => router.layer(axum::middleware::from_fn_with_state(
+            Arc::new(rate_limit::RateLimiter::new(per_minute)),
+            rate_limit::limit_requests,
+        )),
+        None => router,
+    };
+
+    let router = router.merge(health);
+    let router = match metrics_handle {
+        Some(handle) => router.route(
+            "/metrics",
+            axum::routing::get(metrics::render).with_state(handle),
+        ),
+        None => router,
+    };
+
+    // Outermost, so preflight requests (which carry no token) are answered before auth
+    match cors_layer(&config.cors_origins) {
+        Some(cors) => router.layer(cors),
+        None => router,
+    }
+}
+
+/// CORS policy for `origins`, or `None` when there are none. Allows the headers MCP's
+/// Streamable HTTP transport uses and exposes the session id to scripts.
+fn cors_layer(origins: &[String]) -> Option<tower_http::cors::CorsLayer> {
+    use tower_http::cors::{AllowOrigin, CorsLayer};
+
+    if origins.is_empty() {
+        return None;
+    }
+    let allow_origin = if origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins.iter().filter_map(|o| o.parse().ok()))
+    };
+    let session_id = header::HeaderName::from_static("mcp-session-id");
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([
+                axum::http::Method::GET,
+                axum::http::Method::POST,
+                axum::http::Method::DELETE,
+            ])
+            .allow_headers([
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                header::ACCEPT,
+                session_id.clone(),
+                header::HeaderName::from_static("mcp-protocol-version"),
+                header::HeaderName::from_static("last-event-id"),
+            ])
+            .expose_headers([session_id]),
+    )
+}
+
+/// Open `db_path` read-only and run `SELECT 1`.
+fn check_db(db_path: &Path) -> Result<()> {
+    let conn = crate::db::open_db_readonly(db_path)?;
+    conn.query_row("SELECT 1", [], |row| row.get::<_, i32>(0))?;
+    Ok(())
+}
+
+/// `GET /health`: 200 if the database opens read-only and answers `SELECT 1`, 503 otherwise.
+async fn health_check(State(db_path): State<Arc<PathBuf>>) -> Response {
+    match check_db(&db_path) {
+        Ok(()) => axum::Json(json!({
+            "status": "ok",
+            "db_path": db_path.display().to_string(),
+        }))
+        .into_response(),
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            axum::Json(json!({
+                "status": "error",
+                "db_path": db_path.display().to_string(),
+                "error": e.to_string(),
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// `GET /health` with several databases: the `health_check` body for each, keyed by name,
+/// under `databases`. 200 only if every database answers.
+async fn health_check_all(State(dbs): State<Arc<Vec<(String, PathBuf)>>>) -> Response {
+    let mut healthy = true;
+    let mut databases = serde_json::Map::new();
+    for (name, db_path) in dbs.iter() {
+        let status = match check_db(db_path) {
+            Ok(()) => json!({
+                "status": "ok",
+                "db_path": db_path.display().to_string(),
+            }),
+            Err(e) => {
+                healthy = false;
+                json!({
+                    "status": "error",
+                    "db_path": db_path.display().to_string(),
+                    "error": e.to_string(),
+                })
+            }
+        };
+        databases.insert(name.clone(), status);
+    }
+
+    let body = axum::Json(json!({
+        "status": if healthy { "ok" } else { "error" },
+        "databases": databases,
+    }));
+    if healthy {
+        body.into_response()
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, body).into_response()
+    }
+}
+
+async fn require_bearer_token(
+    State(token): State<Arc<String>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|provided| bool::from(provided.as_bytes().ct_eq(token.as_bytes())));
+
+    if authorized {
+        next.run(request).await
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+async fn run_http_server(
+    db_path: &Path,
+    host: &str,
+    port: u16,
+    config: ServerConfig,
+) -> Result<()> {
+    // Sessions open the DB lazily; fail fast on a missing or outdated DB instead.
+    crate::db::verify_schema(&crate::db::open_db_readonly_with_options(
+        db_path,
+        &config.db_options,
+    )?)?;
+
+    let router = build_http_router(db_path, &config);
+    serve_http(router, &["/mcp".to_string()], host, port, &config).await
+}
+
+/// `serve` with several `--db name=path` mappings: one HTTP endpoint per database at
+/// `/mcp/<name>`. Names must be unique; the stdio transport only serves a single database.
+pub async fn run_multi_server(
+    dbs: &[(String, PathBuf)],
+    host: &str,
+    port: u16,
+    transport: &str,
+    config: ServerConfig,
+) -> Result<()> {
+    if transport != "http" {
+        anyhow::bail!(
+            "The {transport} transport serves a single database; pass one --db without a name"
+        );
+    }
+    for (i, (name, _)) in dbs.iter().enumerate() {
+        if dbs[..i].iter().any(|(other, _)| other == name) {
+            anyhow::bail!("Database name {name:?} is given more than once");
+        }
+    }
+    for (name, db_path) in dbs {
+        crate::db::migrate_file(db_path, &config.db_options)?;
+        crate::db::verify_schema(&crate::db::open_db_readonly_with_options(
+            db_path,
+            &config.db_options,
+        )?)
+        .map_err(|e| anyhow::anyhow!("Database {name} ({}): {e}", db_path.display()))?;
+    }
+
+    let router = build_multi_http_router(dbs, &config);
+    let paths: Vec<String> = dbs
+        .iter()
+        .map(|(name, _)| format!("/mcp/{}", name))
+        .collect();
+    serve_http(router, &paths, host, port, &config).await
+}
+
+/// Bind `host:port`, log the endpoints and the options in effect, and serve `router` until
+/// ctrl-c.
+async fn serve_http(
+    router: axum::Router,
+    mcp_paths: &[String],
+    host: &str,
+    port: u16,
+    config: &ServerConfig,
+) -> Result<()> {
+    let addr = format!("{}:{}", host, port);
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    for path in mcp_paths {
+        tracing::info!("MCP server listening at http://{}{}", addr, path);
+    }
+    tracing::info!("Health check at http://{}/health", addr);
+    if config.metrics {
+        tracing::info!("Prometheus metrics at http://{}/metrics", addr);
+    }
+    if config.auth_token.is_some() {
+        tracing::info!("Bearer token authentication enabled");
+    }
+    if let Some(per_minute) = config.rate_limit {
+        tracing::info!("Rate limit: {} requests per minute per client", per_minute);
+    }
+    if !config.cors_origins.is_empty() {
+        tracing::info!("CORS allowed origins: {}", config.cors_origins.join(", "));
+    }
+    if let Some(days) = config.default_range_days {
+        tracing::info!("Default date range: last {} days", days);
+    }
+    if config.include_query {
+        tracing::info!("Tool results include the SQL that produced them");
+    }
+    if let Some(rows) = config.max_query_rows {
+        tracing::info!("run_custom_query row cap: {}", rows);
+    }
+    if let Some(rows) = config.max_rows {
+        tracing::info!("List tool row cap: {}", rows);
+    }
+    for (tool, rows) in &config.tool_max_rows {
+        tracing::info!("{} row cap: {}", tool, rows);
+    }
+    if let Some(threads) = config.db_options.threads {
+        tracing::info!("DuckDB threads: {}", threads);
+    }
+    if let Some(ref limit) = config.db_options.memory_limit {
+        tracing::info!("DuckDB memory limit: {}", limit);
+    }
+    if !config.sensitive_denylist.is_empty() {
+        tracing::info!(
+            "Sensitive data restricted: {}",
+            config.sensitive_denylist.join(", ")
+        );
+    }
+    if config.maintenance {
+        tracing::warn!("Maintenance mode: database opened read-write for add_annotation");
+    }
+    if config.allow_external_access {
+        tracing::warn!("External access enabled: run_custom_query can read local files and URLs");
+    }
+
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(async {
+        tokio::signal::ctrl_c().await.unwrap();
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ensure_schema, open_db_in_memory, rebuild_daily_stats};
+    use rmcp::handler::server::wrapper::Parameters;
+
+    fn setup_server() -> HealthServer {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        // Seed data
+        conn.execute_batch(
+            "
+            INSERT INTO records VALUES ('rh1', 'HKQuantityTypeIdentifierHeartRate', 72.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-01 08:00:00', '2024-01-01 08:00:00', '2024-01-01 08:01:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('rh2', 'HKQuantityTypeIdentifierHeartRate', 80.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-01 09:00:00', '2024-01-01 09:00:00', '2024-01-01 09:01:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('rh3', 'HKQuantityTypeIdentifierStepCount', 1500.0, 'count', 'iPhone', '17.0', NULL, '2024-01-01 00:00:00', '2024-01-01 00:00:00', '2024-01-01 23:59:59', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO record_metadata VALUES ('rh1', 'HKMetadataKeyHeartRateMotionContext', '1');
+            INSERT INTO workouts VALUES ('wh1', 'HKWorkoutActivityTypeRunning', 1800.0, 'sec', 5000.0, 'm', 300.0, 'kcal', 'Apple Watch', '10.0', NULL, '2024-01-01 10:00:00', '2024-01-01 10:00:00', '2024-01-01 10:30:00', 'imp1', NULL, NULL, NULL, NULL, NULL);
+            INSERT INTO workout_events VALUES ('wh1', 'HKWorkoutEventTypeLap', '2024-01-01 10:15:00', NULL, NULL);
+            INSERT INTO workout_statistics VALUES ('wh1', 'HKQuantityTypeIdentifierHeartRate', '2024-01-01 10:00:00', '2024-01-01 10:30:00', 150.0, 120.0, 180.0, NULL, 'count/min');
+            INSERT INTO activity_summaries VALUES ('2024-01-01', 500.0, 600.0, 45.0, 30.0, 30.0, 30.0, 10.0, 12.0, 'imp1', NULL, NULL);
+            INSERT INTO ecg_readings VALUES ('ecg1', '2024-01-01 12:00:00', 'Sinus Rhythm', 'Apple Watch', 512.0, NULL, '2.0', 'imp1', NULL, NULL);
             INSERT INTO ecg_samples VALUES ('ecg1', 0, 100.0);
             INSERT INTO ecg_samples VALUES ('ecg1', 1, 200.0);
             INSERT INTO ecg_samples VALUES ('ecg1', 2, -50.0);
             INSERT INTO route_points VALUES ('rp1', 'wh1', 37.7749, -122.4194, 10.5, '2024-01-01 10:00:00', 3.5, 180.0, 5.0, 3.0, 'imp1');
             INSERT INTO route_points VALUES ('rp2', 'wh1', 37.7750, -122.4195, 11.0, '2024-01-01 10:00:05', 3.6, 181.0, 4.5, 2.8, 'imp1');
-            INSERT INTO imports VALUES ('imp1', '/tmp/export', '2024-01-01 00:00:00', 3, 1, 5.0);
+            INSERT INTO imports VALUES ('imp1', '/tmp/export', '2024-01-01 00:00:00', 3, 1, 5.0, NULL);
+            ",
+        )
+        .unwrap();
+        rebuild_daily_stats(&conn).unwrap();
+        HealthServer::new_in_memory(conn)
+    }
+
+    fn all_record_types() -> Parameters<ListRecordTypesParams> {
+        Parameters(ListRecordTypesParams {
+            min_count: None,
+            name_contains: None,
+        })
+    }
+
+    /// Assert `result` is an `err_json` object of `kind` whose message starts with `message`.
+    fn assert_error(result: &str, kind: &str, message: &str) {
+        let parsed: Value = serde_json::from_str(result).unwrap();
+        assert_eq!(parsed["error"]["kind"], kind, "{}", result);
+        assert!(
+            parsed["error"]["message"]
+                .as_str()
+                .unwrap()
+                .starts_with(message),
+            "{}",
+            result
+        );
+    }
+
+    #[tokio::test]
+    async fn tool_errors_are_structured() {
+        let server = setup_server();
+        let result = server
+            .run_custom_query(Parameters(RunCustomQueryParams {
+                query: "SELECT no_such_column FROM records".to_string(),
+                format: None,
+            }))
+            .await;
+        assert_error(&result, "query_failed", "");
+        assert!(is_err_json(&result));
+        assert!(result.contains("no_such_column"), "{}", result);
+
+        let ok = server.list_record_types(all_record_types()).await;
+        assert!(!is_err_json(&ok));
+    }
+
+    #[test]
+    fn concurrent_queries_use_separate_connections() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("health.duckdb");
+        {
+            let conn = crate::db::open_db(&db_path).unwrap();
+            ensure_schema(&conn).unwrap();
+            conn.execute_batch(
+                "INSERT INTO records
+                 SELECT 'r' || i, 'HKQuantityTypeIdentifierHeartRate', 60 + i % 40, 'count/min', 'Apple Watch',
+                        NULL, NULL, NULL, TIMESTAMP '2024-01-01' + INTERVAL (i) MINUTE,
+                        TIMESTAMP '2024-01-01' + INTERVAL (i) MINUTE, 'imp1', NULL, NULL, NULL, NULL
+                 FROM range(10000) t(i);",
+            )
+            .unwrap();
+            rebuild_daily_stats(&conn).unwrap();
+        }
+
+        let server = HealthServer::new(&db_path).unwrap();
+        assert_eq!(server.conn.size(), pool::DEFAULT_POOL_SIZE);
+        // Holding one connection doesn't block queries on the others
+        let held = server.conn.get().unwrap();
+        let results: Vec<Result<Value, String>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|i| {
+                    let server = &server;
+                    scope.spawn(move || {
+                        server.query_to_json(
+                            "SELECT COUNT(*) AS n, AVG(value) AS avg FROM records WHERE value >= ?",
+                            &[&(60 + i) as &dyn duckdb::ToSql],
+                        )
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        drop(held);
+
+        for (i, result) in results.iter().enumerate() {
+            let rows = result.as_ref().unwrap();
+            assert_eq!(rows[0]["n"], json!(10000 - 250 * i), "{:?}", rows);
+        }
+    }
+
+    #[test]
+    fn query_to_json_empty_result() {
+        let server = setup_server();
+        let result = server
+            .query_to_json("SELECT * FROM records WHERE 1=0", &[])
+            .unwrap();
+        assert_eq!(result, Value::Array(vec![]));
+    }
+
+    #[test]
+    fn query_to_json_with_data() {
+        let server = setup_server();
+        let result = server
+            .query_to_json(
+                "SELECT record_type, value FROM records ORDER BY record_hash",
+                &[],
+            )
+            .unwrap();
+        let arr = result.as_array().unwrap();
+        assert_eq!(arr.len(), 3);
+    }
+
+    #[test]
+    fn query_to_json_null_skipped() {
+        let server = setup_server();
+        let result = server
+            .query_to_json("SELECT device FROM records WHERE record_hash = 'rh1'", &[])
+            .unwrap();
+        let arr = result.as_array().unwrap();
+        let obj = arr[0].as_object().unwrap();
+        // device is NULL, so the key should not be present
+        assert!(!obj.contains_key("device"));
+    }
+
+    #[test]
+    fn query_to_json_bool() {
+        let server = setup_server();
+        let result = server.query_to_json("SELECT true AS flag", &[]).unwrap();
+        let arr = result.as_array().unwrap();
+        assert_eq!(arr[0].get("flag").unwrap(), &Value::Bool(true));
+    }
+
+    #[test]
+    fn query_to_json_int_types() {
+        let server = setup_server();
+        let result = server
+            .query_to_json(
+                "SELECT 42::TINYINT AS t, 1000::SMALLINT AS s, 100000::INT AS i, 999999999::BIGINT AS b",
+                &[],
+            )
+            .unwrap();
+        let arr = result.as_array().unwrap();
+        let obj = arr[0].as_object().unwrap();
+        assert_eq!(obj["t"], json!(42));
+        assert_eq!(obj["s"], json!(1000));
+        assert_eq!(obj["i"], json!(100000));
+        assert_eq!(obj["b"], json!(999999999));
+    }
+
+    #[test]
+    fn query_to_json_float_and_double() {
+        let server = setup_server();
+        let result = server
+            .query_to_json("SELECT 3.14::FLOAT AS f, 2.718281828::DOUBLE AS d", &[])
+            .unwrap();
+        let arr = result.as_array().unwrap();
+        let obj = arr[0].as_object().unwrap();
+        assert!(obj["f"].as_f64().unwrap() > 3.0);
+        assert!(obj["d"].as_f64().unwrap() > 2.7);
+    }
+
+    #[test]
+    fn query_to_json_text() {
+        let server = setup_server();
+        let result = server.query_to_json("SELECT 'hello' AS msg", &[]).unwrap();
+        let arr = result.as_array().unwrap();
+        assert_eq!(arr[0].get("msg").unwrap(), "hello");
+    }
+
+    #[test]
+    fn query_to_json_timestamp_cast() {
+        let server = setup_server();
+        // Cast timestamp to varchar in SQL so it comes through as Text
+        let result = server
+            .query_to_json(
+                "SELECT CAST(start_date AS VARCHAR) AS sd FROM records WHERE record_hash = 'rh1'",
+                &[],
+            )
+            .unwrap();
+        let arr = result.as_array().unwrap();
+        let sd = arr[0].get("sd").unwrap().as_str().unwrap();
+        assert!(sd.contains("2024-01-01"));
+    }
+
+    #[test]
+    fn query_to_json_timestamp_raw() {
+        // Raw timestamps may be skipped if the DuckDB driver can't convert them to String.
+        // This tests that the query still succeeds even with timestamp columns.
+        let server = setup_server();
+        let result = server
+            .query_to_json(
+                "SELECT start_date FROM records WHERE record_hash = 'rh1'",
+                &[],
+            )
+            .unwrap();
+        let arr = result.as_array().unwrap();
+        assert_eq!(arr.len(), 1);
+    }
+
+    #[test]
+    fn query_to_json_invalid_sql() {
+        let server = setup_server();
+        let result = server.query_to_json("INVALID SQL STATEMENT", &[]);
+        assert!(result.is_err());
+    }
+
+    /// `tracing` writer appending to a shared buffer.
+    struct CaptureWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CaptureWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_log_timing_at_debug() {
+        let server = setup_server();
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || CaptureWriter(writer.clone()))
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let span = tracing::debug_span!("tool", name = %"list_record_types");
+        server
+            .list_record_types(all_record_types())
+            .instrument(span)
+            .await;
+
+        let logs = String::from_utf8(logs.lock().unwrap().clone()).unwrap();
+        let line = logs
+            .lines()
+            .find(|l| l.contains("DEBUG") && l.contains(" query "))
+            .unwrap_or_else(|| panic!("no query event in {:?}", logs));
+        assert!(line.contains("tool{name=list_record_types}"), "{}", line);
+        assert!(line.contains("rows=2"), "{}", line);
+        assert!(line.contains("elapsed_ms="), "{}", line);
+    }
+
+    #[tokio::test]
+    async fn include_query_wraps_list_record_types() {
+        let server = setup_server();
+        let (output, queries) = capture_queries(server.list_record_types(all_record_types())).await;
+        let wrapped: Value = serde_json::from_str(&wrap_with_query(&output, &queries)).unwrap();
+        assert!(wrapped["sql"]
+            .as_str()
+            .unwrap()
+            .starts_with("SELECT record_type as type, COUNT(*) as count"));
+        assert_eq!(wrapped["result"].as_array().unwrap().len(), 2);
+
+        // Bound values stay as placeholders; errors come back as strings
+        let (output, queries) = capture_queries(server.get_record_metadata(Parameters(
+            GetRecordMetadataParams {
+                record_hash: "secret-hash".to_string(),
+                key: None,
+            },
+        )))
+        .await;
+        let wrapped = wrap_with_query(&output, &queries);
+        assert!(wrapped.contains("record_hash = ?"), "{}", wrapped);
+        assert!(!wrapped.contains("secret-hash"));
+    }
+
+    #[tokio::test]
+    async fn output_locale_formats_tool_results() {
+        let server = setup_server();
+        let output = server
+            .get_activity_summaries(Parameters(GetActivitySummariesParams {
+                start_date: None,
+                end_date: None,
+                limit: None,
+            }))
+            .await;
+        let de = locale::OutputLocale::parse("de-DE").unwrap();
+
+        let parsed: Value = serde_json::from_str(&localize_output(&output, &de, false)).unwrap();
+        assert_eq!(parsed["rows"][0]["date_components"], "01.01.2024");
+        assert_eq!(parsed["rows"][0]["active_energy_burned"], 500.0);
+
+        let parsed: Value = serde_json::from_str(&localize_output(&output, &de, true)).unwrap();
+        assert_eq!(parsed["rows"][0]["active_energy_burned"], "500");
+        assert_eq!(parsed["rows"][0]["apple_stand_hours_goal"], "12");
+    }
+
+    #[tokio::test]
+    async fn tool_list_record_types() {
+        let server = setup_server();
+        let result = server.list_record_types(all_record_types()).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        assert_eq!(arr.len(), 2); // HeartRate and StepCount
+    }
+
+    #[tokio::test]
+    async fn tool_list_record_types_filters() {
+        let server = setup_server();
+        let result = server
+            .list_record_types(Parameters(ListRecordTypesParams {
+                min_count: Some(2),
+                name_contains: None,
+            }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        assert_eq!(arr.len(), 1); // the single StepCount record is dropped
+        assert_eq!(arr[0]["type"], "HKQuantityTypeIdentifierHeartRate");
+
+        let result = server
+            .list_record_types(Parameters(ListRecordTypesParams {
+                min_count: None,
+                name_contains: Some("stepcount".to_string()),
+            }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        assert_eq!(arr.len(), 1);
+        assert_eq!(arr[0]["type"], "HKQuantityTypeIdentifierStepCount");
+    }
+
+    #[tokio::test]
+    async fn tool_describe_data() {
+        let server = setup_server();
+        let result = server.describe_data().await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        assert_eq!(arr.len(), 2);
+
+        let hr = arr
+            .iter()
+            .find(|r| r["record_type"] == "HKQuantityTypeIdentifierHeartRate")
+            .unwrap();
+        assert_eq!(hr["friendly_name"], "Heart Rate");
+        assert_eq!(hr["kind"], "discrete");
+        assert_eq!(hr["units"], json!(["count/min"]));
+        assert_eq!(hr["count"], 2);
+        assert_eq!(hr["earliest_date"], "2024-01-01 08:00:00");
+
+        let steps = arr
+            .iter()
+            .find(|r| r["record_type"] == "HKQuantityTypeIdentifierStepCount")
+            .unwrap();
+        assert_eq!(steps["friendly_name"], "Step Count");
+        assert_eq!(steps["kind"], "cumulative");
+    }
+
+    #[tokio::test]
+    async fn tool_check_unit_consistency() {
+        let server = setup_server();
+        assert_eq!(
+            serde_json::from_str::<Value>(&server.check_unit_consistency().await).unwrap(),
+            json!([])
+        );
+
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "
+                INSERT INTO records VALUES ('bm1', 'HKQuantityTypeIdentifierBodyMass', 70.0, 'kg', 'Scale', NULL, NULL, NULL, '2024-01-01 07:00:00', '2024-01-01 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('bm2', 'HKQuantityTypeIdentifierBodyMass', 71.0, 'kg', 'Scale', NULL, NULL, NULL, '2024-01-02 07:00:00', '2024-01-02 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('bm3', 'HKQuantityTypeIdentifierBodyMass', 155.0, 'lb', 'Old Scale', NULL, NULL, NULL, '2023-06-01 07:00:00', '2023-06-01 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                ",
+            )
+            .unwrap();
+        }
+
+        let result = server.check_unit_consistency().await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        // Heart rate and steps each use one unit, so only body mass is flagged
+        assert_eq!(arr.len(), 1, "{}", result);
+        assert_eq!(arr[0]["record_type"], "HKQuantityTypeIdentifierBodyMass");
+        assert_eq!(arr[0]["friendly_name"], "Body Mass");
+        assert_eq!(arr[0]["total"], 3);
+        assert_eq!(arr[0]["units"][0]["unit"], "kg");
+        assert_eq!(arr[0]["units"][0]["count"], 2);
+        assert_eq!(arr[0]["units"][1]["unit"], "lb");
+        assert_eq!(arr[0]["units"][1]["earliest_date"], "2023-06-01 07:00:00");
+    }
+
+    #[tokio::test]
+    async fn tool_query_records() {
+        let server = setup_server();
+        let params = Parameters(QueryRecordsParams {
+            record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+            start_date: None,
+            end_date: None,
+            source_name: None,
+            limit: Some(10),
+            offset: None,
+        });
+        let result = server.query_records(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["rows"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn tool_query_records_default_range() {
+        let server = setup_server().with_config(ServerConfig {
+            default_range_days: Some(30),
+            ..Default::default()
+        });
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "INSERT INTO records VALUES ('rh_recent', 'HKQuantityTypeIdentifierHeartRate', 65.0, 'count/min', 'Apple Watch', '10.0', NULL, \
+                 now()::TIMESTAMP - INTERVAL 1 DAY, now()::TIMESTAMP - INTERVAL 1 DAY, now()::TIMESTAMP - INTERVAL 1 DAY, 'imp1', NULL, NULL, NULL, NULL);",
+            )
+            .unwrap();
+        }
+
+        // No dates: only the record inside the default window
+        let params = Parameters(QueryRecordsParams {
+            record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+            start_date: None,
+            end_date: None,
+            source_name: None,
+            limit: None,
+            offset: None,
+        });
+        let result = server.query_records(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed["rows"].as_array().unwrap();
+        assert_eq!(arr.len(), 1);
+        assert_eq!(arr[0]["record_hash"], "rh_recent");
+
+        // Explicit start_date overrides the default
+        let params = Parameters(QueryRecordsParams {
+            record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+            start_date: Some("2024-01-01".to_string()),
+            end_date: None,
+            source_name: None,
+            limit: None,
+            offset: None,
+        });
+        let result = server.query_records(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["rows"].as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn tool_query_records_pagination() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            for i in 1..=5 {
+                conn.execute(
+                    &format!(
+                        "INSERT INTO records VALUES ('bm{i}', 'HKQuantityTypeIdentifierBodyMass', {i}.0, 'kg', 'Scale', NULL, NULL, \
+                         '2024-02-0{i} 07:00:00', '2024-02-0{i} 07:00:00', '2024-02-0{i} 07:00:00', 'imp1', NULL, NULL, NULL, NULL)"
+                    ),
+                    [],
+                )
+                .unwrap();
+            }
+        }
+
+        let params = Parameters(QueryRecordsParams {
+            record_type: "HKQuantityTypeIdentifierBodyMass".to_string(),
+            start_date: None,
+            end_date: None,
+            source_name: None,
+            limit: Some(2),
+            offset: Some(2),
+        });
+        let result = server.query_records(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let hashes: Vec<&str> = parsed["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["record_hash"].as_str().unwrap())
+            .collect();
+        // Newest first: bm5, bm4 | bm3, bm2 | bm1
+        assert_eq!(hashes, vec!["bm3", "bm2"]);
+    }
+
+    #[tokio::test]
+    async fn tool_query_records_with_filters() {
+        let server = setup_server();
+        let params = Parameters(QueryRecordsParams {
+            record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+            start_date: Some("2024-01-01 08:30:00".to_string()),
+            end_date: None,
+            source_name: Some("Apple Watch".to_string()),
+            limit: None,
+            offset: None,
+        });
+        let result = server.query_records(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["rows"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn tool_get_record_metadata() {
+        let server = setup_server();
+        let params = Parameters(GetRecordMetadataParams {
+            record_hash: "rh1".to_string(),
+            key: None,
+        });
+        let result = server.get_record_metadata(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        assert_eq!(arr.len(), 1);
+        assert_eq!(arr[0]["key"], "HKMetadataKeyHeartRateMotionContext");
+        assert_eq!(arr[0]["value"], "1");
+
+        // Key filter that doesn't match
+        let params = Parameters(GetRecordMetadataParams {
+            record_hash: "rh1".to_string(),
+            key: Some("HKMetadataKeyWasUserEntered".to_string()),
+        });
+        let result = server.get_record_metadata(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed.as_array().unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn tool_list_metadata_keys() {
+        let server = setup_server();
+        let result = server
+            .list_metadata_keys(Parameters(ListMetadataKeysParams { record_type: None }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        assert_eq!(arr.len(), 1);
+        assert_eq!(arr[0]["record_type"], "HKQuantityTypeIdentifierHeartRate");
+        assert_eq!(arr[0]["key"], "HKMetadataKeyHeartRateMotionContext");
+        assert_eq!(arr[0]["count"], 1);
+
+        let result = server
+            .list_metadata_keys(Parameters(ListMetadataKeysParams {
+                record_type: Some("HKQuantityTypeIdentifierStepCount".to_string()),
+            }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed, Value::Array(vec![]));
+    }
+
+    #[tokio::test]
+    async fn tool_query_window() {
+        let server = setup_server();
+        server
+            .conn
+            .get()
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO records VALUES ('w_hr', 'HKQuantityTypeIdentifierHeartRate', 110.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-05 15:00:10', '2024-01-05 15:00:10', 'imp1', NULL, NULL, NULL, NULL);
+                 INSERT INTO records VALUES ('w_steps', 'HKQuantityTypeIdentifierStepCount', 95.0, 'count', 'iPhone', NULL, NULL, NULL, '2024-01-05 14:59:30', '2024-01-05 15:00:30', 'imp1', NULL, NULL, NULL, NULL);",
+            )
+            .unwrap();
+        let window = |record_types: Option<Vec<String>>| {
+            Parameters(QueryWindowParams {
+                start_date: "2024-01-05 15:00:00".to_string(),
+                end_date: "2024-01-05 15:01:00".to_string(),
+                record_types,
+                limit: None,
+            })
+        };
+
+        let result = server.query_window(window(None)).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let hashes: Vec<&str> = parsed["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["record_hash"].as_str().unwrap())
+            .collect();
+        // The step sample started before the window but overlaps it
+        assert_eq!(hashes, ["w_steps", "w_hr"]);
+
+        let result = server
+            .query_window(window(Some(vec![
+                "HKQuantityTypeIdentifierHeartRate".to_string()
+            ])))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["rows"].as_array().unwrap().len(), 1);
+        assert_eq!(parsed["rows"][0]["value"], 110.0);
+    }
+
+    #[tokio::test]
+    async fn tool_get_latest_readings() {
+        let server = setup_server();
+        server
+            .conn
+            .get()
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO records VALUES ('rh_steps2', 'HKQuantityTypeIdentifierStepCount', 900.0, 'count', 'iPhone', '17.0', NULL, '2024-01-02 00:00:00', '2024-01-02 00:00:00', '2024-01-02 23:59:59', 'imp1', NULL, NULL, NULL, NULL);",
+            )
+            .unwrap();
+
+        let result = server
+            .get_latest_readings(Parameters(GetLatestReadingsParams { record_types: None }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        assert_eq!(arr.len(), 2);
+        assert_eq!(arr[0]["record_type"], "HKQuantityTypeIdentifierHeartRate");
+        assert_eq!(arr[0]["value"], 80.0);
+        assert_eq!(arr[1]["record_type"], "HKQuantityTypeIdentifierStepCount");
+        assert_eq!(arr[1]["value"], 900.0);
+
+        let result = server
+            .get_latest_readings(Parameters(GetLatestReadingsParams {
+                record_types: Some(vec!["HKQuantityTypeIdentifierStepCount".to_string()]),
+            }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed.as_array().unwrap().len(), 1);
+        assert_eq!(parsed[0]["value"], 900.0);
+
+        let result = server
+            .get_latest_readings(Parameters(GetLatestReadingsParams {
+                record_types: Some(vec![]),
+            }))
+            .await;
+        assert_error(
+            &result,
+            "invalid_argument",
+            "record_types must not be empty",
+        );
+    }
+
+    #[tokio::test]
+    async fn tool_get_record_metadata_unknown_hash() {
+        let server = setup_server();
+        let params = Parameters(GetRecordMetadataParams {
+            record_hash: "nonexistent".to_string(),
+            key: None,
+        });
+        let result = server.get_record_metadata(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed, Value::Array(vec![]));
+    }
+
+    #[tokio::test]
+    async fn tool_get_record_statistics() {
+        let server = setup_server();
+        let params = Parameters(GetRecordStatisticsParams {
+            record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+            start_date: None,
+            end_date: None,
+            period: Some("day".to_string()),
+            moving_average_window: None,
+            include_annotations: None,
+            value_mode: None,
+            include_extremes: None,
+        });
+        let result = server.get_record_statistics(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert!(!parsed.as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tool_get_record_statistics_duration_mode() {
+        let server = setup_server();
+        server
+            .conn
+            .get()
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO records VALUES ('m1', 'HKCategoryTypeIdentifierMindfulSession', NULL, NULL, 'Breathe', NULL, NULL, NULL, '2024-01-01 07:00:00', '2024-01-01 07:10:00', 'imp1', NULL, NULL, NULL, 'HKCategoryValueNotApplicable');
+                 INSERT INTO records VALUES ('m2', 'HKCategoryTypeIdentifierMindfulSession', NULL, NULL, 'Breathe', NULL, NULL, NULL, '2024-01-01 21:00:00', '2024-01-01 21:05:00', 'imp1', NULL, NULL, NULL, 'HKCategoryValueNotApplicable');
+                 INSERT INTO records VALUES ('m3', 'HKCategoryTypeIdentifierMindfulSession', NULL, NULL, 'Breathe', NULL, NULL, NULL, '2024-01-02 07:00:00', '2024-01-02 07:01:00', 'imp1', NULL, NULL, NULL, 'HKCategoryValueNotApplicable');",
+            )
+            .unwrap();
+        let stats = |value_mode: &str| {
+            Parameters(GetRecordStatisticsParams {
+                record_type: "HKCategoryTypeIdentifierMindfulSession".to_string(),
+                start_date: None,
+                end_date: Some("2024-01-01".to_string()),
+                period: Some("day".to_string()),
+                moving_average_window: None,
+                include_annotations: None,
+                value_mode: Some(value_mode.to_string()),
+                include_extremes: None,
+            })
+        };
+
+        let result = server
+            .get_record_statistics(stats("duration_seconds"))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let rows = parsed.as_array().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["count"], 2);
+        assert_eq!(rows[0]["sum_value"], 900.0);
+        assert_eq!(rows[0]["max_value"], 600.0);
+
+        // Value-less category records have no value statistics
+        let result = server.get_record_statistics(stats("value")).await;
+        assert_eq!(result.trim(), "[]");
+
+        let result = server.get_record_statistics(stats("minutes")).await;
+        assert_error(&result, "invalid_argument", "value_mode must be");
+    }
+
+    #[tokio::test]
+    async fn tool_get_record_statistics_include_extremes() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            // A clear daily max at 14:30, tied later in the day; the 72 min is repeated on day two
+            conn.execute_batch(
+                "INSERT INTO records VALUES ('rh4', 'HKQuantityTypeIdentifierHeartRate', 155.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-01 14:30:00', '2024-01-01 14:31:00', 'imp1', NULL, NULL, NULL, NULL);
+                 INSERT INTO records VALUES ('rh5', 'HKQuantityTypeIdentifierHeartRate', 155.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-01 18:00:00', '2024-01-01 18:01:00', 'imp1', NULL, NULL, NULL, NULL);
+                 INSERT INTO records VALUES ('rh6', 'HKQuantityTypeIdentifierHeartRate', 72.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-02 06:00:00', '2024-01-02 06:01:00', 'imp1', NULL, NULL, NULL, NULL);",
+            )
+            .unwrap();
+            rebuild_daily_stats(&conn).unwrap();
+        }
+        let stats = |period: &str, include_extremes: Option<bool>| {
+            Parameters(GetRecordStatisticsParams {
+                record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+                start_date: None,
+                end_date: None,
+                period: Some(period.to_string()),
+                moving_average_window: None,
+                include_annotations: None,
+                value_mode: None,
+                include_extremes,
+            })
+        };
+
+        let result = server.get_record_statistics(stats("day", Some(true))).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let rows = parsed.as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["max_value"], 155.0);
+        assert_eq!(rows[0]["max_value_date"], "2024-01-01 14:30:00");
+        assert_eq!(rows[0]["min_value_date"], "2024-01-01 08:00:00");
+        assert_eq!(rows[1]["max_value_date"], "2024-01-02 06:00:00");
+
+        // Monthly rows come from the rollup but find the same records
+        let result = server
+            .get_record_statistics(stats("month", Some(true)))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed[0]["max_value_date"], "2024-01-01 14:30:00");
+        assert_eq!(parsed[0]["min_value_date"], "2024-01-01 08:00:00");
+
+        let result = server.get_record_statistics(stats("day", None)).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert!(parsed[0].get("max_value_date").is_none());
+    }
+
+    #[tokio::test]
+    async fn tool_get_record_statistics_extremes_on_local_days() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            // Imported with --timezone -05:00: 04:30 UTC on Jan 2 is 23:30 on Jan 1 locally
+            conn.execute_batch(
+                "UPDATE imports SET timezone = '-05:00';
+                 INSERT INTO records VALUES ('rh4', 'HKQuantityTypeIdentifierHeartRate', 150.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-02 04:30:00', '2024-01-02 04:31:00', 'imp1', NULL, NULL, NULL, NULL);",
+            )
+            .unwrap();
+            rebuild_daily_stats(&conn).unwrap();
+        }
+        let stats = |value_mode: Option<&str>| {
+            Parameters(GetRecordStatisticsParams {
+                record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+                start_date: None,
+                end_date: None,
+                period: Some("day".to_string()),
+                moving_average_window: None,
+                include_annotations: None,
+                value_mode: value_mode.map(str::to_string),
+                include_extremes: Some(true),
+            })
+        };
+
+        let result = server.get_record_statistics(stats(None)).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let rows = parsed.as_array().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["period"], "2024-01-01");
+        assert_eq!(rows[0]["max_value"], 150.0);
+        assert_eq!(rows[0]["max_value_date"], "2024-01-02 04:30:00");
+        assert_eq!(rows[0]["min_value_date"], "2024-01-01 08:00:00");
+
+        // Durations split days the same way
+        let result = server
+            .get_record_statistics(stats(Some("duration_seconds")))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let rows = parsed.as_array().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["count"], 3);
+        assert_eq!(rows[0]["max_value_date"], "2024-01-01 08:00:00");
+    }
+
+    #[tokio::test]
+    async fn tool_get_record_statistics_days_with_data() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "
+                INSERT INTO records VALUES ('rh4', 'HKQuantityTypeIdentifierHeartRate', 60.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-03 08:00:00', '2024-01-03 08:01:00', 'imp1', NULL, NULL, NULL, NULL);
+                ",
+            )
+            .unwrap();
+            rebuild_daily_stats(&conn).unwrap();
+        }
+
+        let params = Parameters(GetRecordStatisticsParams {
+            record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+            start_date: None,
+            end_date: None,
+            period: Some("month".to_string()),
+            moving_average_window: None,
+            include_annotations: None,
+            value_mode: None,
+            include_extremes: None,
+        });
+        let result = server.get_record_statistics(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let rows = parsed.as_array().unwrap();
+        assert_eq!(rows.len(), 1);
+        // Three records (two on Jan 1, one on Jan 3) across two distinct days
+        assert_eq!(rows[0]["count"], json!(3));
+        assert_eq!(rows[0]["days_with_data"], json!(2));
+        assert_eq!(rows[0]["period"], "2024-01-01");
+    }
+
+    #[tokio::test]
+    async fn tool_get_record_statistics_weekly_rollup_matches_daily() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "INSERT INTO records VALUES ('rh4', 'HKQuantityTypeIdentifierHeartRate', 60.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-03 08:00:00', '2024-01-03 08:01:00', 'imp1', NULL, NULL, NULL, NULL);",
+            )
+            .unwrap();
+            rebuild_daily_stats(&conn).unwrap();
+        }
+        let weekly = |start: &str, end: &str| {
+            Parameters(GetRecordStatisticsParams {
+                record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+                start_date: Some(start.to_string()),
+                end_date: Some(end.to_string()),
+                period: Some("week".to_string()),
+                moving_average_window: None,
+                include_annotations: None,
+                value_mode: None,
+                include_extremes: None,
+            })
+        };
+
+        // Monday to Sunday reads weekly_record_stats; the day before aggregates daily rows
+        let aligned = server
+            .get_record_statistics(weekly("2024-01-01", "2024-01-07"))
+            .await;
+        let unaligned = server
+            .get_record_statistics(weekly("2023-12-31", "2024-01-07"))
+            .await;
+        assert_eq!(aligned, unaligned);
+        let parsed: Value = serde_json::from_str(&aligned).unwrap();
+        assert_eq!(parsed[0]["count"], json!(3));
+        assert!((parsed[0]["avg_value"].as_f64().unwrap() - 212.0 / 3.0).abs() < 1e-9);
+
+        // A range starting mid-week only counts its own days
+        let result = server
+            .get_record_statistics(weekly("2024-01-02", "2024-01-07"))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed[0]["count"], json!(1));
+    }
+
+    #[tokio::test]
+    async fn tool_add_annotation_overlays_statistics() {
+        let annotation = || {
+            Parameters(AddAnnotationParams {
+                tag: "medication".to_string(),
+                start_date: "2024-01-01".to_string(),
+                end_date: Some("2024-01-14".to_string()),
+                note: Some("started 10 mg".to_string()),
+            })
+        };
+
+        let server = setup_server();
+        let result = server.add_annotation(annotation()).await;
+        assert_error(&result, "restricted", "add_annotation needs");
+
+        let server = server.with_config(ServerConfig {
+            maintenance: true,
+            ..Default::default()
+        });
+        let added: Value =
+            serde_json::from_str(&server.add_annotation(annotation()).await).unwrap();
+        assert_eq!(added["created"], json!(true));
+        let again: Value =
+            serde_json::from_str(&server.add_annotation(annotation()).await).unwrap();
+        assert_eq!(again["created"], json!(false));
+        assert_eq!(again["annotation_id"], added["annotation_id"]);
+
+        let listed: Value = serde_json::from_str(
+            &server
+                .list_annotations(Parameters(ListAnnotationsParams {
+                    tag: None,
+                    start_date: Some("2024-01-10".to_string()),
+                    end_date: None,
+                }))
+                .await,
+        )
+        .unwrap();
+        assert_eq!(listed.as_array().unwrap().len(), 1);
+        assert_eq!(listed[0]["note"], "started 10 mg");
+
+        let stats = |start_date: &str, include_annotations| {
+            Parameters(GetRecordStatisticsParams {
+                record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+                start_date: Some(start_date.to_string()),
+                end_date: None,
+                period: Some("day".to_string()),
+                moving_average_window: None,
+                include_annotations,
+                value_mode: None,
+                include_extremes: None,
+            })
+        };
+        let parsed: Value = serde_json::from_str(
+            &server
+                .get_record_statistics(stats("2024-01-01", Some(true)))
+                .await,
+        )
+        .unwrap();
+        assert_eq!(parsed[0]["annotations"][0]["tag"], "medication");
+
+        let parsed: Value = serde_json::from_str(
+            &server
+                .get_record_statistics(stats("2024-01-01", None))
+                .await,
+        )
+        .unwrap();
+        assert!(parsed[0].get("annotations").is_none());
+    }
+
+    #[tokio::test]
+    async fn tool_get_audiogram() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "
+                INSERT INTO records VALUES ('aud1', 'HKDataTypeIdentifierAudiogram', NULL, NULL, 'Mimi', NULL, NULL, NULL, '2024-02-01 09:00:00', '2024-02-01 09:10:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO audiogram_points VALUES ('aud1', 4000.0, 35.0, 20.0, false, false);
+                INSERT INTO audiogram_points VALUES ('aud1', 250.0, 10.0, 15.0, false, true);
+                INSERT INTO audiogram_points VALUES ('aud1', 1000.0, 15.0, NULL, NULL, NULL);
+                INSERT INTO audiogram_points VALUES ('aud1', 8000.0, 50.0, 30.0, false, false);
+                ",
+            )
+            .unwrap();
+        }
+
+        let params = Parameters(GetAudiogramParams {
+            record_hash: "aud1".to_string(),
+            threshold_db: None,
+        });
+        let result = server.get_audiogram(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["date"], "2024-02-01");
+
+        let curve = |ear: &str| -> Vec<(f64, f64)> {
+            parsed[ear]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| {
+                    (
+                        p["frequency_hz"].as_f64().unwrap(),
+                        p["hearing_level_db"].as_f64().unwrap(),
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(
+            curve("left_ear"),
+            vec![
+                (250.0, 10.0),
+                (1000.0, 15.0),
+                (4000.0, 35.0),
+                (8000.0, 50.0)
+            ]
+        );
+        // The right ear has no reading at 1000 Hz
+        assert_eq!(
+            curve("right_ear"),
+            vec![(250.0, 15.0), (4000.0, 20.0), (8000.0, 30.0)]
+        );
+        assert_eq!(parsed["left_ear"][2]["grade"], "mild");
+        assert_eq!(parsed["left_ear"][3]["grade"], "moderate");
+        assert_eq!(parsed["right_ear"][0]["masked"], json!(true));
+        assert_eq!(
+            parsed["flagged_frequencies"]["left"],
+            json!([4000.0, 8000.0])
+        );
+        assert_eq!(parsed["flagged_frequencies"]["right"], json!([8000.0]));
+
+        let params = Parameters(GetAudiogramParams {
+            record_hash: "nope".to_string(),
+            threshold_db: None,
+        });
+        assert_error(
+            &server.get_audiogram(params).await,
+            "not_found",
+            "Audiogram nope not found",
+        );
+    }
+
+    #[tokio::test]
+    async fn tool_find_data_gaps() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            // Heart rate on 2024-01-01 (seeded) and 2024-01-03, nothing on 2024-01-02
+            conn.execute_batch(
+                "INSERT INTO records VALUES ('rh_gap', 'HKQuantityTypeIdentifierHeartRate', 65.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-03 08:00:00', '2024-01-03 08:00:00', '2024-01-03 08:01:00', 'imp1', NULL, NULL, NULL, NULL);",
+            )
+            .unwrap();
+            rebuild_daily_stats(&conn).unwrap();
+        }
+        let gaps = |start: Option<&str>, end: Option<&str>| {
+            Parameters(FindDataGapsParams {
+                record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+                start_date: start.map(str::to_string),
+                end_date: end.map(str::to_string),
+            })
+        };
+
+        let result = server
+            .find_data_gaps(gaps(Some("2024-01-01"), Some("2024-01-03")))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["days_in_range"], 3);
+        assert_eq!(parsed["days_missing"], 1);
+        assert_eq!(
+            parsed["gaps"],
+            json!([{"start": "2024-01-02", "end": "2024-01-02", "days": 1}])
+        );
+
+        // The range defaults to the type's first and last day; runs are merged
+        let result = server.find_data_gaps(gaps(None, Some("2024-01-06"))).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["start_date"], "2024-01-01");
+        assert_eq!(parsed["days_missing"], 4);
+        assert_eq!(
+            parsed["gaps"][1],
+            json!({"start": "2024-01-04", "end": "2024-01-06", "days": 3})
+        );
+
+        let result = server
+            .find_data_gaps(gaps(Some("2024-01-03"), Some("2024-01-01")))
+            .await;
+        assert_error(&result, "invalid_argument", "start_date is after end_date");
+
+        let result = server
+            .find_data_gaps(Parameters(FindDataGapsParams {
+                record_type: "HKQuantityTypeIdentifierBodyMass".to_string(),
+                start_date: None,
+                end_date: None,
+            }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["gaps"], json!([]));
+        assert!(parsed["note"].is_string());
+    }
+
+    #[tokio::test]
+    async fn tool_compare_periods() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            // Week A: 1000 steps a day; week B: 1200 steps a day
+            conn.execute_batch(
+                "
+                DELETE FROM records;
+                INSERT INTO records
+                SELECT 'a' || i, 'HKQuantityTypeIdentifierStepCount', 1000.0, 'count', 'iPhone', NULL, NULL, NULL,
+                       TIMESTAMP '2024-03-04 12:00:00' + INTERVAL (i) DAY, TIMESTAMP '2024-03-04 12:00:00' + INTERVAL (i) DAY,
+                       'imp1', NULL, NULL, NULL, NULL
+                FROM range(7) t(i);
+                INSERT INTO records
+                SELECT 'b' || i, 'HKQuantityTypeIdentifierStepCount', 1200.0, 'count', 'iPhone', NULL, NULL, NULL,
+                       TIMESTAMP '2024-03-11 12:00:00' + INTERVAL (i) DAY, TIMESTAMP '2024-03-11 12:00:00' + INTERVAL (i) DAY,
+                       'imp1', NULL, NULL, NULL, NULL
+                FROM range(7) t(i);
+                INSERT INTO records VALUES ('z0', 'HKQuantityTypeIdentifierFlightsClimbed', 0.0, 'count', 'iPhone', NULL, NULL, NULL, '2024-03-05 12:00:00', '2024-03-05 12:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('z1', 'HKQuantityTypeIdentifierFlightsClimbed', 3.0, 'count', 'iPhone', NULL, NULL, NULL, '2024-03-12 12:00:00', '2024-03-12 12:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                ",
+            )
+            .unwrap();
+            rebuild_daily_stats(&conn).unwrap();
+        }
+
+        let compare = |record_type: &str, period: Option<&str>| {
+            Parameters(ComparePeriodsParams {
+                record_type: record_type.to_string(),
+                period: period.map(str::to_string),
+                period_a_start: "2024-03-04".to_string(),
+                period_a_end: "2024-03-10".to_string(),
+                period_b_start: "2024-03-11".to_string(),
+                period_b_end: "2024-03-17".to_string(),
+            })
+        };
+
+        let result = server
+            .compare_periods(compare("HKQuantityTypeIdentifierStepCount", None))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["period_a"]["sum_value"], json!(7000.0));
+        assert_eq!(parsed["period_b"]["days_with_data"], json!(7));
+        assert_eq!(parsed["period_b"]["avg_per_period"], json!(1200.0));
+        assert_eq!(parsed["change"]["sum_value"]["absolute"], json!(1400.0));
+        let percent = parsed["change"]["avg_per_period"]["percent"]
+            .as_f64()
+            .unwrap();
+        assert!((percent - 20.0).abs() < 1e-9, "{}", result);
+
+        let result = server
+            .compare_periods(compare("HKQuantityTypeIdentifierStepCount", Some("week")))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["period_b"]["periods_with_data"], json!(1));
+        assert_eq!(parsed["period_b"]["avg_per_period"], json!(8400.0));
+
+        // Zero baseline: the percent change is null rather than infinite
+        let result = server
+            .compare_periods(compare("HKQuantityTypeIdentifierFlightsClimbed", None))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["period_a"]["sum_value"], json!(0.0));
+        assert_eq!(parsed["change"]["sum_value"]["absolute"], json!(3.0));
+        assert_eq!(parsed["change"]["sum_value"]["percent"], Value::Null);
+
+        let result = server
+            .compare_periods(compare("HKQuantityTypeIdentifierHeartRate", None))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["period_a"]["count"], json!(0));
+        assert_eq!(parsed["change"]["avg_value"]["absolute"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn tool_get_record_histogram() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            // Heart rates 60, 61, ..., 99 on top of the seeded 72 and 80
+            conn.execute_batch(
+                "INSERT INTO records SELECT 'hist' || i, 'HKQuantityTypeIdentifierHeartRate', 60.0 + i, 'count/min', 'Apple Watch', NULL, NULL, NULL, \
+                 TIMESTAMP '2024-02-01 08:00:00' + i * INTERVAL 1 MINUTE, TIMESTAMP '2024-02-01 08:00:00' + i * INTERVAL 1 MINUTE, 'imp1', NULL, NULL, NULL, NULL \
+                 FROM range(40) t(i);",
+            )
+            .unwrap();
+        }
+        let histogram = |record_type: &str, bucket_count| {
+            server.get_record_histogram(Parameters(GetRecordHistogramParams {
+                record_type: record_type.to_string(),
+                start_date: None,
+                end_date: None,
+                bucket_count,
+            }))
+        };
+
+        let buckets: Value =
+            serde_json::from_str(&histogram("HKQuantityTypeIdentifierHeartRate", Some(4)).await)
+                .unwrap();
+        let buckets = buckets.as_array().unwrap();
+        assert_eq!(buckets.len(), 4);
+        assert_eq!(buckets[0]["bucket_min"], 60.0);
+        assert_eq!(buckets[3]["bucket_max"], 99.0);
+        let counts: Vec<i64> = buckets
+            .iter()
+            .map(|b| b["count"].as_i64().unwrap())
+            .collect();
+        // Width 9.75: [60, 69.75) has 10 readings, [69.75, 79.5) 10 + the seeded 72, and so on
+        assert_eq!(counts, vec![10, 11, 11, 10]);
+        assert_eq!(counts.iter().sum::<i64>(), 42);
+
+        // One step-count record: a single bucket
+        let buckets: Value =
+            serde_json::from_str(&histogram("HKQuantityTypeIdentifierStepCount", None).await)
+                .unwrap();
+        assert_eq!(
+            buckets,
+            json!([{"bucket_min": 1500.0, "bucket_max": 1500.0, "count": 1}])
+        );
+
+        assert_eq!(
+            histogram("HKQuantityTypeIdentifierBodyMass", None).await,
+            "[]"
+        );
+        assert_error(
+            &histogram("HKQuantityTypeIdentifierHeartRate", Some(0)).await,
+            "invalid_argument",
+            "bucket_count must be between 1 and 200",
+        );
+    }
+
+    #[tokio::test]
+    async fn tool_get_record_percentiles() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            // Body temperatures 36.0, 36.1, ..., 37.0: median 36.5
+            conn.execute_batch(
+                "INSERT INTO records SELECT 'pct' || i, 'HKQuantityTypeIdentifierBodyTemperature', 36.0 + i / 10, 'degC', 'Apple Watch', NULL, NULL, NULL, \
+                 TIMESTAMP '2024-02-01 08:00:00' + i * INTERVAL 1 HOUR, TIMESTAMP '2024-02-01 08:00:00' + i * INTERVAL 1 HOUR, 'imp1', NULL, NULL, NULL, NULL \
+                 FROM range(11) t(i);",
+            )
+            .unwrap();
+        }
+        let percentiles = |record_type: &str, percentiles| {
+            server.get_record_percentiles(Parameters(GetRecordPercentilesParams {
+                record_type: record_type.to_string(),
+                start_date: None,
+                end_date: None,
+                percentiles,
+            }))
+        };
+
+        let result: Value = serde_json::from_str(
+            &percentiles(
+                "HKQuantityTypeIdentifierBodyTemperature",
+                Some(vec![0.0, 50.0, 100.0]),
+            )
+            .await,
+        )
+        .unwrap();
+        let result = result.as_array().unwrap();
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0]["percentile"], 0.0);
+        assert!((result[0]["value"].as_f64().unwrap() - 36.0).abs() < 1e-9);
+        assert_eq!(result[1]["percentile"], 50.0);
+        assert!((result[1]["value"].as_f64().unwrap() - 36.5).abs() < 1e-9);
+        assert!((result[2]["value"].as_f64().unwrap() - 37.0).abs() < 1e-9);
+
+        // Defaults to the 50th, 90th, 95th and 99th percentiles
+        let result: Value = serde_json::from_str(
+            &percentiles("HKQuantityTypeIdentifierBodyTemperature", None).await,
+        )
+        .unwrap();
+        let requested: Vec<f64> = result
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["percentile"].as_f64().unwrap())
+            .collect();
+        assert_eq!(requested, vec![50.0, 90.0, 95.0, 99.0]);
+
+        assert_eq!(
+            percentiles("HKQuantityTypeIdentifierBodyMass", None).await,
+            "[]"
+        );
+        assert_error(
+            &percentiles("HKQuantityTypeIdentifierBodyTemperature", Some(vec![101.0])).await,
+            "invalid_argument",
+            "percentiles must be between 0 and 100",
+        );
+    }
+
+    #[tokio::test]
+    async fn tool_get_daily_peaks() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "
+                INSERT INTO records VALUES ('rh4', 'HKQuantityTypeIdentifierHeartRate', 155.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-01 17:42:00', '2024-01-01 17:42:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('rh5', 'HKQuantityTypeIdentifierHeartRate', 64.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-01 22:00:00', '2024-01-01 22:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('rh6', 'HKQuantityTypeIdentifierHeartRate', 90.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-02 07:15:00', '2024-01-02 07:15:00', 'imp1', NULL, NULL, NULL, NULL);
+                ",
+            )
+            .unwrap();
+        }
+
+        let params = Parameters(GetDailyPeaksParams {
+            record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+            start_date: None,
+            end_date: None,
+            limit: None,
+        });
+        let result = server.get_daily_peaks(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let rows = parsed["rows"].as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["date"], "2024-01-02");
+        assert_eq!(rows[1]["date"], "2024-01-01");
+        assert_eq!(rows[1]["max_value"], json!(155.0));
+        assert_eq!(rows[1]["peak_time"], "2024-01-01 17:42:00");
+        assert_eq!(rows[1]["record_count"], json!(4));
+    }
+
+    #[tokio::test]
+    async fn tool_get_daily_peaks_matches_daily_stats_days() {
+        let server = setup_server();
+        let stats_days = {
+            let conn = server.conn.get().unwrap();
+            // Imported with --timezone -05:00: 04:30 UTC on Jan 2 is 23:30 on Jan 1 locally
+            conn.execute_batch(
+                "UPDATE imports SET timezone = '-05:00';
+                 INSERT INTO records VALUES ('rh4', 'HKQuantityTypeIdentifierHeartRate', 150.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-02 04:30:00', '2024-01-02 04:31:00', 'imp1', NULL, NULL, NULL, NULL);",
+            )
+            .unwrap();
+            rebuild_daily_stats(&conn).unwrap();
+            let mut stmt = conn
+                .prepare(
+                    "SELECT CAST(date AS VARCHAR) FROM daily_record_stats \
+                     WHERE record_type = 'HKQuantityTypeIdentifierHeartRate' ORDER BY date DESC",
+                )
+                .unwrap();
+            stmt.query_map([], |row| row.get::<_, String>(0))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        assert_eq!(stats_days, ["2024-01-01"]);
+
+        let params = Parameters(GetDailyPeaksParams {
+            record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+            start_date: None,
+            end_date: None,
+            limit: None,
+        });
+        let result = server.get_daily_peaks(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let days: Vec<&str> = parsed["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row["date"].as_str().unwrap())
+            .collect();
+        assert_eq!(days, stats_days);
+        assert_eq!(parsed["rows"][0]["max_value"], json!(150.0));
+    }
+
+    #[tokio::test]
+    async fn tool_get_record_statistics_moving_average() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "
+                DELETE FROM records;
+                INSERT INTO records VALUES ('w1', 'HKQuantityTypeIdentifierBodyMass', 80.0, 'kg', 'Scale', NULL, NULL, NULL, '2024-03-01 07:00:00', '2024-03-01 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('w2', 'HKQuantityTypeIdentifierBodyMass', 82.0, 'kg', 'Scale', NULL, NULL, NULL, '2024-03-02 07:00:00', '2024-03-02 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('w3', 'HKQuantityTypeIdentifierBodyMass', 81.0, 'kg', 'Scale', NULL, NULL, NULL, '2024-03-03 07:00:00', '2024-03-03 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('w4', 'HKQuantityTypeIdentifierBodyMass', 85.0, 'kg', 'Scale', NULL, NULL, NULL, '2024-03-04 07:00:00', '2024-03-04 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('w5', 'HKQuantityTypeIdentifierBodyMass', 80.0, 'kg', 'Scale', NULL, NULL, NULL, '2024-03-05 07:00:00', '2024-03-05 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                ",
+            )
+            .unwrap();
+            rebuild_daily_stats(&conn).unwrap();
+        }
+
+        let stats = |window| {
+            let server = server.clone();
+            async move {
+                let params = Parameters(GetRecordStatisticsParams {
+                    record_type: "HKQuantityTypeIdentifierBodyMass".to_string(),
+                    start_date: None,
+                    end_date: None,
+                    period: Some("day".to_string()),
+                    moving_average_window: window,
+                    include_annotations: None,
+                    value_mode: None,
+                    include_extremes: None,
+                });
+                let result = server.get_record_statistics(params).await;
+                serde_json::from_str::<Value>(&result).unwrap()
+            }
+        };
+
+        let parsed = stats(Some(3)).await;
+        let rows = parsed.as_array().unwrap();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0]["moving_avg"], json!(80.0));
+        assert_eq!(rows[1]["moving_avg"], json!(81.0));
+        assert_eq!(rows[2]["moving_avg"], json!(81.0)); // (80 + 82 + 81) / 3
+        assert_eq!(rows[4]["moving_avg"], json!(82.0)); // (81 + 85 + 80) / 3
+
+        // A window longer than the data averages everything available
+        let parsed = stats(Some(30)).await;
+        assert_eq!(parsed[4]["moving_avg"], json!(81.6));
+
+        let parsed = stats(None).await;
+        assert!(parsed[0].get("moving_avg").is_none());
+    }
+
+    #[tokio::test]
+    async fn tool_get_records_aggregated_by_source_and_day() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "
+                INSERT INTO records VALUES ('st1', 'HKQuantityTypeIdentifierStepCount', 1000.0, 'count', 'Apple Watch', NULL, NULL, NULL, '2024-01-01 09:00:00', '2024-01-01 09:30:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('st2', 'HKQuantityTypeIdentifierStepCount', 200.0, 'count', 'Apple Watch', NULL, NULL, NULL, '2024-01-01 18:00:00', '2024-01-01 18:10:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('st3', 'HKQuantityTypeIdentifierStepCount', 3000.0, 'count', 'Apple Watch', NULL, NULL, NULL, '2024-01-02 09:00:00', '2024-01-02 09:30:00', 'imp1', NULL, NULL, NULL, NULL);
+                ",
+            )
+            .unwrap();
+        }
+
+        let params = Parameters(GetRecordsBySourceAndDayParams {
+            record_type: "HKQuantityTypeIdentifierStepCount".to_string(),
+            start_date: None,
+            end_date: None,
+        });
+        let result = server
+            .get_records_aggregated_by_source_and_day(params)
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+
+        // The fixture's iPhone record (1500 steps on Jan 1) overlaps the watch
+        assert_eq!(parsed["sources"], json!(["Apple Watch", "iPhone"]));
+        let days = parsed["days"].as_array().unwrap();
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0]["date"], "2024-01-01");
+        assert_eq!(days[0]["by_source"]["Apple Watch"], json!(1200.0));
+        assert_eq!(days[0]["by_source"]["iPhone"], json!(1500.0));
+        assert_eq!(days[0]["total"], json!(2700.0));
+        assert_eq!(days[1]["date"], "2024-01-02");
+        assert_eq!(days[1]["by_source"]["Apple Watch"], json!(3000.0));
+        assert!(days[1]["by_source"]["iPhone"].is_null());
+    }
+
+    #[tokio::test]
+    async fn tool_find_outliers() {
+        let server = setup_server();
+        {
+            // 20 resting heart rates between 58 and 62, plus one 180
+            let conn = server.conn.get().unwrap();
+            for i in 0..20 {
+                conn.execute(
+                    "INSERT INTO records VALUES (?, 'HKQuantityTypeIdentifierRestingHeartRate', ?, 'count/min', 'Apple Watch', NULL, NULL, NULL, \
+                     CAST('2024-02-01' AS TIMESTAMP) + to_days(?), CAST('2024-02-01' AS TIMESTAMP) + to_days(?), 'imp1', NULL, NULL, NULL, NULL)",
+                    duckdb::params![format!("rhr{i}"), 58.0 + (i % 5) as f64, i, i],
+                )
+                .unwrap();
+            }
+            conn.execute_batch(
+                "INSERT INTO records VALUES ('rhr_spike', 'HKQuantityTypeIdentifierRestingHeartRate', 180.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, \
+                 '2024-02-25 08:00:00', '2024-02-25 08:00:00', 'imp1', NULL, NULL, NULL, NULL);",
+            )
+            .unwrap();
+        }
+
+        let params = Parameters(FindOutliersParams {
+            record_type: "HKQuantityTypeIdentifierRestingHeartRate".to_string(),
+            start_date: None,
+            end_date: None,
+            z_threshold: None,
+            limit: None,
+        });
+        let result = server.find_outliers(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["count"], 21);
+        assert_eq!(parsed["truncated"], false);
+        let outliers = parsed["rows"].as_array().unwrap();
+        assert_eq!(outliers.len(), 1);
+        assert_eq!(outliers[0]["record_hash"], "rhr_spike");
+        assert!(outliers[0]["z_score"].as_f64().unwrap() > 3.0);
+    }
+
+    #[tokio::test]
+    async fn tool_find_outliers_zero_variance() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "UPDATE records SET value = 70.0 WHERE record_type = 'HKQuantityTypeIdentifierHeartRate';",
+            )
+            .unwrap();
+        }
+
+        let params = Parameters(FindOutliersParams {
+            record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+            start_date: None,
+            end_date: None,
+            z_threshold: Some(0.0),
+            limit: None,
+        });
+        let result = server.find_outliers(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["stddev"], json!(0.0));
+        assert!(parsed["rows"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn pearson_edge_cases() {
+        assert_eq!(pearson(&[], &[]), None);
+        assert_eq!(pearson(&[1.0], &[2.0]), None);
+        assert_eq!(pearson(&[1.0, 1.0], &[2.0, 3.0]), None);
+        let r = pearson(&[1.0, 2.0, 3.0], &[3.0, 2.0, 1.0]).unwrap();
+        assert!((r + 1.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn tool_correlate_metrics() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        // Body mass = 0.5 * resting HR + 40 on four days; an extra HR-only day is ignored
+        conn.execute_batch(
+            "
+            INSERT INTO records VALUES ('a1', 'HKQuantityTypeIdentifierRestingHeartRate', 60.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-01 08:00:00', '2024-01-01 08:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('a2', 'HKQuantityTypeIdentifierRestingHeartRate', 64.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-02 08:00:00', '2024-01-02 08:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('a3', 'HKQuantityTypeIdentifierRestingHeartRate', 70.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-03 08:00:00', '2024-01-03 08:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('a4', 'HKQuantityTypeIdentifierRestingHeartRate', 58.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-04 08:00:00', '2024-01-04 08:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('a5', 'HKQuantityTypeIdentifierRestingHeartRate', 99.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-05 08:00:00', '2024-01-05 08:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('b1', 'HKQuantityTypeIdentifierBodyMass', 70.0, 'kg', 'Scale', NULL, NULL, NULL, '2024-01-01 07:00:00', '2024-01-01 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('b2', 'HKQuantityTypeIdentifierBodyMass', 72.0, 'kg', 'Scale', NULL, NULL, NULL, '2024-01-02 07:00:00', '2024-01-02 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('b3', 'HKQuantityTypeIdentifierBodyMass', 75.0, 'kg', 'Scale', NULL, NULL, NULL, '2024-01-03 07:00:00', '2024-01-03 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('b4', 'HKQuantityTypeIdentifierBodyMass', 69.0, 'kg', 'Scale', NULL, NULL, NULL, '2024-01-04 07:00:00', '2024-01-04 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
             ",
         )
         .unwrap();
         rebuild_daily_stats(&conn).unwrap();
-        HealthServer::new_in_memory(conn)
+        let server = HealthServer::new_in_memory(conn);
+
+        let params = Parameters(CorrelateMetricsParams {
+            record_type_a: "HKQuantityTypeIdentifierRestingHeartRate".to_string(),
+            record_type_b: "HKQuantityTypeIdentifierBodyMass".to_string(),
+            period: Some("day".to_string()),
+            start_date: None,
+            end_date: None,
+        });
+        let result = server.correlate_metrics(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["periods"].as_array().unwrap().len(), 4);
+        let r = parsed["correlation"].as_f64().unwrap();
+        assert!((r - 1.0).abs() < 1e-9, "expected ~1.0, got {}", r);
     }
 
-    #[test]
-    fn query_to_json_empty_result() {
+    #[tokio::test]
+    async fn tool_correlate_metrics_single_period() {
         let server = setup_server();
+        let params = Parameters(CorrelateMetricsParams {
+            record_type_a: "HKQuantityTypeIdentifierHeartRate".to_string(),
+            record_type_b: "HKQuantityTypeIdentifierStepCount".to_string(),
+            period: None,
+            start_date: None,
+            end_date: None,
+        });
+        let result = server.correlate_metrics(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["periods"].as_array().unwrap().len(), 1);
+        assert!(parsed["correlation"].is_null());
+    }
+
+    #[tokio::test]
+    async fn tool_get_trend() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        // Body mass falls 0.5 kg a day; day 3 has two readings averaging 79.0, day 4 has none
+        conn.execute_batch(
+            "
+            INSERT INTO records VALUES ('t1', 'HKQuantityTypeIdentifierBodyMass', 80.0, 'kg', 'Scale', NULL, NULL, NULL, '2024-01-01 07:00:00', '2024-01-01 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('t2', 'HKQuantityTypeIdentifierBodyMass', 79.5, 'kg', 'Scale', NULL, NULL, NULL, '2024-01-02 07:00:00', '2024-01-02 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('t3', 'HKQuantityTypeIdentifierBodyMass', 78.5, 'kg', 'Scale', NULL, NULL, NULL, '2024-01-03 07:00:00', '2024-01-03 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('t4', 'HKQuantityTypeIdentifierBodyMass', 79.5, 'kg', 'Scale', NULL, NULL, NULL, '2024-01-03 19:00:00', '2024-01-03 19:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            INSERT INTO records VALUES ('t5', 'HKQuantityTypeIdentifierBodyMass', 78.0, 'kg', 'Scale', NULL, NULL, NULL, '2024-01-05 07:00:00', '2024-01-05 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+            ",
+        )
+        .unwrap();
+        rebuild_daily_stats(&conn).unwrap();
+        let server = HealthServer::new_in_memory(conn);
+
         let result = server
-            .query_to_json("SELECT * FROM records WHERE 1=0", &[])
+            .get_trend(Parameters(GetTrendParams {
+                record_type: "HKQuantityTypeIdentifierBodyMass".to_string(),
+                period: None,
+                start_date: None,
+                end_date: None,
+                forecast_periods: Some(2),
+            }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["points"], 4);
+        assert_eq!(parsed["first_period"], "2024-01-01");
+        assert_eq!(parsed["last_period"], "2024-01-05");
+        let slope = parsed["slope"].as_f64().unwrap();
+        assert!((slope + 0.5).abs() < 1e-9, "expected -0.5, got {}", slope);
+        let intercept = parsed["intercept"].as_f64().unwrap();
+        assert!(
+            (intercept - 80.0).abs() < 1e-9,
+            "expected 80.0, got {}",
+            intercept
+        );
+        let r2 = parsed["r_squared"].as_f64().unwrap();
+        assert!((r2 - 1.0).abs() < 1e-9, "expected ~1.0, got {}", r2);
+
+        let forecast = parsed["forecast"].as_array().unwrap();
+        assert_eq!(forecast.len(), 2);
+        assert_eq!(forecast[0]["period"], "2024-01-06");
+        assert!((forecast[0]["value"].as_f64().unwrap() - 77.5).abs() < 1e-9);
+        assert_eq!(forecast[1]["period"], "2024-01-07");
+        assert!((forecast[1]["value"].as_f64().unwrap() - 77.0).abs() < 1e-9);
+
+        // A single period has no trend
+        let result = server
+            .get_trend(Parameters(GetTrendParams {
+                record_type: "HKQuantityTypeIdentifierBodyMass".to_string(),
+                period: Some("month".to_string()),
+                start_date: None,
+                end_date: None,
+                forecast_periods: None,
+            }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["points"], 1);
+        assert!(parsed["slope"].is_null());
+        assert!(parsed["r_squared"].is_null());
+        assert!(parsed["forecast"].as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tool_get_source_contribution() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "
+                INSERT INTO records VALUES ('st1', 'HKQuantityTypeIdentifierStepCount', 500.0, 'count', 'Apple Watch', NULL, NULL, '2024-01-02 08:00:00', '2024-01-02 08:00:00', '2024-01-02 09:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('st2', 'HKQuantityTypeIdentifierStepCount', 1000.0, 'count', 'Apple Watch', NULL, NULL, '2024-01-02 10:00:00', '2024-01-02 10:00:00', '2024-01-02 11:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                ",
+            )
             .unwrap();
-        assert_eq!(result, Value::Array(vec![]));
+        }
+
+        // iPhone: 1500 (rh3), Apple Watch: 1500 (st1 + st2)
+        let params = Parameters(GetSourceContributionParams {
+            record_type: "HKQuantityTypeIdentifierStepCount".to_string(),
+            start_date: None,
+            end_date: None,
+        });
+        let result = server.get_source_contribution(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        assert_eq!(arr.len(), 2);
+
+        let total: f64 = arr.iter().map(|r| r["percentage"].as_f64().unwrap()).sum();
+        assert!((total - 100.0).abs() < 1e-9);
+        for row in arr {
+            assert_eq!(row["sum_value"], 1500.0);
+            assert_eq!(row["percentage"], 50.0);
+        }
+        let watch = arr
+            .iter()
+            .find(|r| r["source_name"] == "Apple Watch")
+            .unwrap();
+        assert_eq!(watch["record_count"], 2);
+    }
+
+    #[tokio::test]
+    async fn tool_get_glucose_by_meal_context() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "
+                INSERT INTO records VALUES ('bg1', 'HKQuantityTypeIdentifierBloodGlucose', 90.0, 'mg/dL', 'Meter', NULL, NULL, '2024-01-01 07:00:00', '2024-01-01 07:00:00', '2024-01-01 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('bg2', 'HKQuantityTypeIdentifierBloodGlucose', 100.0, 'mg/dL', 'Meter', NULL, NULL, '2024-01-02 07:00:00', '2024-01-02 07:00:00', '2024-01-02 07:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('bg3', 'HKQuantityTypeIdentifierBloodGlucose', 140.0, 'mg/dL', 'Meter', NULL, NULL, '2024-01-01 13:00:00', '2024-01-01 13:00:00', '2024-01-01 13:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('bg4', 'HKQuantityTypeIdentifierBloodGlucose', 110.0, 'mg/dL', 'Meter', NULL, NULL, '2024-01-01 20:00:00', '2024-01-01 20:00:00', '2024-01-01 20:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO record_metadata VALUES ('bg1', 'HKMetadataKeyBloodGlucoseMealTime', '1');
+                INSERT INTO record_metadata VALUES ('bg2', 'HKMetadataKeyBloodGlucoseMealTime', '1');
+                INSERT INTO record_metadata VALUES ('bg3', 'HKMetadataKeyBloodGlucoseMealTime', '2');
+                INSERT INTO record_metadata VALUES ('bg4', 'HKMetadataKeyDevicePlacementSide', '1');
+                ",
+            )
+            .unwrap();
+        }
+
+        let params = Parameters(GetGlucoseByMealContextParams {
+            start_date: None,
+            end_date: None,
+        });
+        let result = server.get_glucose_by_meal_context(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        assert_eq!(arr.len(), 3);
+
+        assert_eq!(arr[0]["meal_context"], "postprandial");
+        assert_eq!(arr[0]["avg_value"], 140.0);
+        assert_eq!(arr[1]["meal_context"], "preprandial");
+        assert_eq!(arr[1]["count"], 2);
+        assert_eq!(arr[1]["avg_value"], 95.0);
+        assert_eq!(arr[1]["min_value"], 90.0);
+        assert_eq!(arr[1]["max_value"], 100.0);
+        // Other metadata keys don't count as a meal context
+        assert_eq!(arr[2]["meal_context"], "unspecified");
+        assert_eq!(arr[2]["avg_value"], 110.0);
+    }
+
+    #[tokio::test]
+    async fn tool_get_heart_rate_by_context() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            // rh1 (72 bpm) is seeded as sedentary; rh2 (80 bpm) has no motion context
+            conn.execute_batch(
+                "
+                INSERT INTO records VALUES ('rh4', 'HKQuantityTypeIdentifierHeartRate', 140.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-01 10:10:00', '2024-01-01 10:10:00', '2024-01-01 10:10:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('rh5', 'HKQuantityTypeIdentifierHeartRate', 160.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-01 10:20:00', '2024-01-01 10:20:00', '2024-01-01 10:20:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('rh6', 'HKQuantityTypeIdentifierHeartRate', 60.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-01 23:00:00', '2024-01-01 23:00:00', '2024-01-01 23:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO record_metadata VALUES ('rh4', 'HKMetadataKeyHeartRateMotionContext', '2');
+                INSERT INTO record_metadata VALUES ('rh5', 'HKMetadataKeyHeartRateMotionContext', '2');
+                INSERT INTO record_metadata VALUES ('rh6', 'HKMetadataKeyHeartRateMotionContext', '0');
+                ",
+            )
+            .unwrap();
+        }
+
+        let params = Parameters(GetHeartRateByContextParams {
+            start_date: None,
+            end_date: None,
+        });
+        let result = server.get_heart_rate_by_context(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        let contexts: Vec<&str> = arr
+            .iter()
+            .map(|r| r["motion_context"].as_str().unwrap())
+            .collect();
+        assert_eq!(contexts, ["active", "not_set", "sedentary", "unknown"]);
+
+        assert_eq!(arr[0]["count"], 2);
+        assert_eq!(arr[0]["avg_bpm"], 150.0);
+        assert_eq!(arr[0]["min_bpm"], 140.0);
+        assert_eq!(arr[0]["max_bpm"], 160.0);
+        assert_eq!(arr[1]["avg_bpm"], 60.0);
+        assert_eq!(arr[2]["avg_bpm"], 72.0);
+        assert_eq!(arr[3]["avg_bpm"], 80.0);
+    }
+
+    #[tokio::test]
+    async fn tool_get_sleep_quality() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            let segments = [
+                // Night of Jan 1: 8h in bed, asleep 23:00-02:00, awake 30 min, asleep 02:30-06:30
+                ("s1", "InBed", "2024-01-01 22:30:00", "2024-01-02 06:30:00"),
+                (
+                    "s2",
+                    "AsleepCore",
+                    "2024-01-01 23:00:00",
+                    "2024-01-02 02:00:00",
+                ),
+                ("s3", "Awake", "2024-01-02 02:00:00", "2024-01-02 02:30:00"),
+                (
+                    "s4",
+                    "AsleepDeep",
+                    "2024-01-02 02:30:00",
+                    "2024-01-02 06:30:00",
+                ),
+                // Night of Jan 2: in-bed only
+                ("s5", "InBed", "2024-01-02 23:00:00", "2024-01-03 07:00:00"),
+            ];
+            for (hash, stage, start, end) in segments {
+                conn.execute(
+                    "INSERT INTO records VALUES (?, 'HKCategoryTypeIdentifierSleepAnalysis', NULL, NULL, 'Apple Watch', NULL, NULL, NULL, \
+                     ?, ?, 'imp1', NULL, NULL, NULL, ?)",
+                    duckdb::params![hash, start, end, format!("HKCategoryValueSleepAnalysis{stage}")],
+                )
+                .unwrap();
+            }
+        }
+
+        let params = Parameters(GetSleepQualityParams {
+            start_date: None,
+            end_date: None,
+            source_name: None,
+        });
+        let result = server.get_sleep_quality(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        assert_eq!(arr.len(), 2);
+
+        let night = &arr[0];
+        assert_eq!(night["night"], "2024-01-01");
+        assert_eq!(night["in_bed_seconds"], 8.0 * 3600.0);
+        assert_eq!(night["asleep_seconds"], 7.0 * 3600.0);
+        assert_eq!(night["awake_seconds"], 1800.0);
+        assert_eq!(night["awakenings"], 1);
+        assert_eq!(night["efficiency"], 0.875);
+        assert_eq!(night["has_stages"], true);
+
+        let in_bed_only = &arr[1];
+        assert_eq!(in_bed_only["night"], "2024-01-02");
+        assert_eq!(in_bed_only["in_bed_seconds"], 8.0 * 3600.0);
+        assert_eq!(in_bed_only["has_stages"], false);
+        assert_eq!(in_bed_only["awakenings"], 0);
+        assert!(in_bed_only["efficiency"].is_null());
+        assert!(in_bed_only["asleep_seconds"].is_null());
+    }
+
+    #[tokio::test]
+    async fn tool_get_best_effort() {
+        let server = setup_server();
+        {
+            // Two runs heading north in 0.001 degree (~111 m) steps: A every 30 s, B every 25 s
+            let conn = server.conn.get().unwrap();
+            for (hash, day, step_secs) in [("wh_a", "2024-02-01", 30), ("wh_b", "2024-02-02", 25)] {
+                conn.execute(
+                    "INSERT INTO workouts VALUES (?, 'HKWorkoutActivityTypeRunning', 10.0, 'min', 1.0, 'km', NULL, NULL, 'Apple Watch', NULL, NULL, \
+                     CAST(? AS TIMESTAMP), CAST(? AS TIMESTAMP), CAST(? AS TIMESTAMP) + INTERVAL 10 MINUTE, 'imp1', NULL, NULL, NULL, NULL, NULL)",
+                    duckdb::params![hash, day, day, day],
+                )
+                .unwrap();
+                for i in 0..10 {
+                    conn.execute(
+                        "INSERT INTO route_points VALUES (?, ?, ?, -122.0, NULL, CAST(? AS TIMESTAMP) + to_seconds(?), NULL, NULL, NULL, NULL, 'imp1')",
+                        duckdb::params![
+                            format!("{hash}_{i}"),
+                            hash,
+                            37.0 + 0.001 * i as f64,
+                            day,
+                            (i * step_secs) as i64
+                        ],
+                    )
+                    .unwrap();
+                }
+            }
+        }
+
+        let params = Parameters(GetBestEffortParams {
+            distance_m: 500.0,
+            activity_type: None,
+        });
+        let result = server.get_best_effort(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["workout_hash"], "wh_b");
+        assert_eq!(parsed["workout_start_date"], "2024-02-02 00:00:00");
+        // 500 m at 25 s per ~111.2 m
+        let step_m = haversine_m(37.0, -122.0, 37.001, -122.0);
+        let elapsed = parsed["elapsed_seconds"].as_f64().unwrap();
+        assert!(
+            (elapsed - 500.0 / step_m * 25.0).abs() < 0.01,
+            "{}",
+            elapsed
+        );
+        assert_eq!(parsed["workouts_considered"], 3);
+    }
+
+    #[tokio::test]
+    async fn tool_get_workout_splits() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            // 10 points ~111.2 m apart, 30 s between points: ~1000.8 m in 270 s
+            for i in 0..10 {
+                conn.execute(
+                    "INSERT INTO route_points VALUES (?, 'wh1', ?, -122.0, NULL, \
+                     TIMESTAMP '2024-01-01 10:00:00' + to_seconds(?), NULL, NULL, NULL, NULL, 'imp1')",
+                    duckdb::params![format!("sp{}", i), 37.0 + i as f64 * 0.001, i * 30],
+                )
+                .unwrap();
+            }
+            conn.execute_batch("DELETE FROM route_points WHERE point_hash IN ('rp1', 'rp2')")
+                .unwrap();
+        }
+
+        let splits = |workout_hash: &str, split_distance_m| {
+            server.get_workout_splits(Parameters(GetWorkoutSplitsParams {
+                workout_hash: workout_hash.to_string(),
+                split_distance_m,
+            }))
+        };
+        let parsed: Value = serde_json::from_str(&splits("wh1", Some(250.0)).await).unwrap();
+        let arr = parsed["splits"].as_array().unwrap();
+        // Four full splits plus the last ~0.8 m
+        assert_eq!(arr.len(), 5);
+        assert_eq!(arr[4]["partial"], true);
+        for s in &arr[..4] {
+            assert_eq!(s["distance_m"], 250.0);
+            let pace = s["pace_seconds_per_km"].as_f64().unwrap();
+            // ~30 s per 111.2 m
+            assert!((pace - 269.8).abs() < 1.0, "{}", pace);
+        }
+        let total: f64 = arr.iter().map(|s| s["seconds"].as_f64().unwrap()).sum();
+        assert!((total - 270.0).abs() < 1e-6);
+
+        // No route: empty splits with a note
+        server
+            .conn
+            .get()
+            .unwrap()
+            .execute_batch("DELETE FROM route_points")
+            .unwrap();
+        let parsed: Value = serde_json::from_str(&splits("wh1", None).await).unwrap();
+        assert_eq!(parsed["splits"], json!([]));
+        assert!(parsed["note"].is_string());
+
+        assert_error(
+            &splits("wh1", Some(0.0)).await,
+            "invalid_argument",
+            "split_distance_m",
+        );
+        assert_error(&splits("nope", None).await, "not_found", "Workout nope");
     }
 
-    #[test]
-    fn query_to_json_with_data() {
+    #[tokio::test]
+    async fn tool_validate_routes() {
         let server = setup_server();
-        let result = server
-            .query_to_json(
-                "SELECT record_type, value FROM records ORDER BY record_hash",
-                &[],
+        {
+            let conn = server.conn.get().unwrap();
+            // A second run whose third point jumps ~11 km in 5 seconds
+            conn.execute_batch(
+                "
+                INSERT INTO workouts VALUES ('wh2', 'HKWorkoutActivityTypeRunning', 1800.0, 'sec', 5000.0, 'm', 300.0, 'kcal', 'Apple Watch', NULL, NULL, NULL, '2024-01-02 10:00:00', '2024-01-02 10:30:00', 'imp1', NULL, NULL, 5000.0, 300.0, NULL);
+                INSERT INTO route_points VALUES ('tp1', 'wh2', 37.7749, -122.4194, NULL, '2024-01-02 10:00:00', NULL, NULL, NULL, NULL, 'imp1');
+                INSERT INTO route_points VALUES ('tp2', 'wh2', 37.7750, -122.4195, NULL, '2024-01-02 10:00:05', NULL, NULL, NULL, NULL, 'imp1');
+                INSERT INTO route_points VALUES ('tp3', 'wh2', 37.8750, -122.4195, NULL, '2024-01-02 10:00:10', NULL, NULL, NULL, NULL, 'imp1');
+                INSERT INTO route_points VALUES ('tp4', 'wh2', 37.8751, -122.4196, NULL, '2024-01-02 10:00:15', NULL, NULL, NULL, NULL, 'imp1');
+                ",
             )
             .unwrap();
-        let arr = result.as_array().unwrap();
-        assert_eq!(arr.len(), 3);
+        }
+        let validate = |max_speed_mps| {
+            server.validate_routes(Parameters(ValidateRoutesParams {
+                activity_type: None,
+                start_date: None,
+                end_date: None,
+                max_speed_mps,
+            }))
+        };
+
+        let parsed: Value = serde_json::from_str(&validate(None).await).unwrap();
+        assert_eq!(parsed["workouts_checked"], 2);
+        let flagged = parsed["flagged"].as_array().unwrap();
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0]["workout_hash"], "wh2");
+        assert_eq!(flagged[0]["max_speed_mps"], 12.5);
+        assert_eq!(flagged[0]["segment_count"], 1);
+        let segment = &flagged[0]["segments"][0];
+        assert_eq!(segment["from_time"], "2024-01-02 10:00:05");
+        assert_eq!(segment["to_time"], "2024-01-02 10:00:10");
+        assert!(segment["speed_mps"].as_f64().unwrap() > 2000.0);
+
+        // wh1's normal points (~3 m/s) only trip a very strict limit
+        let parsed: Value = serde_json::from_str(&validate(Some(1.0)).await).unwrap();
+        assert_eq!(parsed["flagged"].as_array().unwrap().len(), 2);
+
+        assert_error(
+            &validate(Some(0.0)).await,
+            "invalid_argument",
+            "max_speed_mps must be positive",
+        );
     }
 
-    #[test]
-    fn query_to_json_null_skipped() {
+    #[tokio::test]
+    async fn tool_get_best_effort_too_long() {
         let server = setup_server();
-        let result = server
-            .query_to_json("SELECT device FROM records WHERE record_hash = 'rh1'", &[])
-            .unwrap();
-        let arr = result.as_array().unwrap();
-        let obj = arr[0].as_object().unwrap();
-        // device is NULL, so the key should not be present
-        assert!(!obj.contains_key("device"));
+        let params = Parameters(GetBestEffortParams {
+            distance_m: 5000.0,
+            activity_type: None,
+        });
+        let result = server.get_best_effort(params).await;
+        assert_error(
+            &result,
+            "not_found",
+            "No HKWorkoutActivityTypeRunning workout",
+        );
     }
 
     #[test]
-    fn query_to_json_bool() {
+    fn fastest_segment_interpolates_start() {
+        // 100 m per point; slow first stretch, fast second
+        let points = [(0.0, 0.0), (100.0, 60.0), (200.0, 80.0), (300.0, 100.0)];
+        assert_eq!(fastest_segment(&points, 200.0), Some((40.0, 60.0, 100.0)));
+        // 150 m: best ends at 300 m, starting halfway through the 100-200 stretch
+        assert_eq!(fastest_segment(&points, 150.0), Some((30.0, 70.0, 100.0)));
+        assert_eq!(fastest_segment(&points, 400.0), None);
+    }
+
+    #[tokio::test]
+    async fn tool_list_workout_activity_types() {
         let server = setup_server();
-        let result = server.query_to_json("SELECT true AS flag", &[]).unwrap();
-        let arr = result.as_array().unwrap();
-        assert_eq!(arr[0].get("flag").unwrap(), &Value::Bool(true));
+        let result = server.list_workout_activity_types().await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        assert_eq!(arr.len(), 1);
+        assert_eq!(arr[0]["activity_type"], "HKWorkoutActivityTypeRunning");
+        assert_eq!(arr[0]["count"], 1);
+        assert_eq!(arr[0]["earliest_date"], "2024-01-01 10:00:00");
+        assert_eq!(arr[0]["total_duration"], 1800.0);
     }
 
-    #[test]
-    fn query_to_json_int_types() {
+    #[tokio::test]
+    async fn tool_get_one_rep_max() {
         let server = setup_server();
-        let result = server
-            .query_to_json(
-                "SELECT 42::TINYINT AS t, 1000::SMALLINT AS s, 100000::INT AS i, 999999999::BIGINT AS b",
-                &[],
-            )
-            .unwrap();
-        let arr = result.as_array().unwrap();
-        let obj = arr[0].as_object().unwrap();
-        assert_eq!(obj["t"], json!(42));
-        assert_eq!(obj["s"], json!(1000));
-        assert_eq!(obj["i"], json!(100000));
-        assert_eq!(obj["b"], json!(999999999));
+        {
+            let conn = server.conn.get().unwrap();
+            // (hash, start, exercise, weight, reps); an empty value is left out of the metadata
+            let sets = [
+                ("set1", "2024-03-01 18:00:00", "Bench Press", "100 kg", "5"),
+                ("set2", "2024-03-01 18:05:00", "Bench Press", "90", "10"),
+                ("set3", "2024-03-08 18:00:00", "bench press", "105", "3"),
+                ("set4", "2024-03-08 18:10:00", "Squat", "200", "5"),
+                // Missing reps: skipped
+                ("set5", "2024-03-08 18:20:00", "Bench Press", "150", ""),
+            ];
+            for (hash, at, exercise, weight, reps) in sets {
+                conn.execute(
+                    "INSERT INTO records VALUES (?, 'HKQuantityTypeIdentifierActiveEnergyBurned', 5.0, 'kcal', 'Lift Log', NULL, NULL, NULL, ?, ?, 'imp1', NULL, NULL, NULL, NULL)",
+                    duckdb::params![hash, at, at],
+                )
+                .unwrap();
+                for (key, value) in [("exercise", exercise), ("weight", weight), ("reps", reps)] {
+                    if !value.is_empty() {
+                        conn.execute(
+                            "INSERT INTO record_metadata VALUES (?, ?, ?)",
+                            duckdb::params![hash, key, value],
+                        )
+                        .unwrap();
+                    }
+                }
+            }
+        }
+
+        let params = Parameters(GetOneRepMaxParams {
+            exercise: "Bench Press".to_string(),
+            start_date: None,
+            end_date: None,
+            exercise_key: None,
+            weight_key: None,
+            reps_key: None,
+        });
+        let result = server.get_one_rep_max(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let sessions = parsed["sessions"].as_array().unwrap();
+        assert_eq!(sessions.len(), 2);
+
+        // 90 x 10 (120) beats 100 x 5 (116.7)
+        assert_eq!(sessions[0]["date"], "2024-03-01");
+        assert_eq!(sessions[0]["sets"], 2);
+        assert!((sessions[0]["estimated_1rm"].as_f64().unwrap() - 120.0).abs() < 1e-9);
+        assert_eq!(sessions[0]["best_weight"], json!(90.0));
+        assert_eq!(sessions[0]["best_reps"], 10);
+
+        assert_eq!(sessions[1]["date"], "2024-03-08");
+        assert_eq!(sessions[1]["sets"], 1);
+        assert!((sessions[1]["estimated_1rm"].as_f64().unwrap() - 115.5).abs() < 1e-9);
     }
 
-    #[test]
-    fn query_to_json_float_and_double() {
+    #[tokio::test]
+    async fn tool_get_one_rep_max_no_data() {
         let server = setup_server();
-        let result = server
-            .query_to_json("SELECT 3.14::FLOAT AS f, 2.718281828::DOUBLE AS d", &[])
-            .unwrap();
-        let arr = result.as_array().unwrap();
-        let obj = arr[0].as_object().unwrap();
-        assert!(obj["f"].as_f64().unwrap() > 3.0);
-        assert!(obj["d"].as_f64().unwrap() > 2.7);
+        let params = Parameters(GetOneRepMaxParams {
+            exercise: "Deadlift".to_string(),
+            start_date: None,
+            end_date: None,
+            exercise_key: None,
+            weight_key: None,
+            reps_key: None,
+        });
+        let result = server.get_one_rep_max(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert!(parsed["sessions"].as_array().unwrap().is_empty());
     }
 
-    #[test]
-    fn query_to_json_text() {
+    #[tokio::test]
+    async fn tool_list_workouts() {
         let server = setup_server();
-        let result = server.query_to_json("SELECT 'hello' AS msg", &[]).unwrap();
-        let arr = result.as_array().unwrap();
-        assert_eq!(arr[0].get("msg").unwrap(), "hello");
+        let params = Parameters(ListWorkoutsParams {
+            activity_type: None,
+            start_date: None,
+            end_date: None,
+            limit: None,
+        });
+        let result = server.list_workouts(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["rows"].as_array().unwrap().len(), 1);
     }
 
-    #[test]
-    fn query_to_json_timestamp_cast() {
+    #[tokio::test]
+    async fn tool_get_workout_details() {
         let server = setup_server();
-        // Cast timestamp to varchar in SQL so it comes through as Text
-        let result = server
-            .query_to_json(
-                "SELECT CAST(start_date AS VARCHAR) AS sd FROM records WHERE record_hash = 'rh1'",
-                &[],
-            )
+        let params = Parameters(GetWorkoutDetailsParams {
+            workout_hash: "wh1".to_string(),
+        });
+        let result = server.get_workout_details(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert!(parsed.get("workout").unwrap().is_object());
+        assert!(parsed.get("events").unwrap().is_array());
+        assert!(parsed.get("statistics").unwrap().is_array());
+        assert!(parsed.get("metadata").unwrap().is_array());
+        assert_eq!(parsed.get("has_route").unwrap(), &Value::Bool(true));
+    }
+
+    #[tokio::test]
+    async fn tool_get_workout_details_metadata() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<HealthData locale="en_US">
+ <Workout workoutActivityType="HKWorkoutActivityTypeRunning" duration="30" durationUnit="min" sourceName="Watch" startDate="2024-01-01 10:00:00 +0000" endDate="2024-01-01 10:30:00 +0000">
+  <MetadataEntry key="HKWeatherTemperature" value="68 degF"/>
+  <MetadataEntry key="HKWeatherHumidity" value="5500 %"/>
+  <WorkoutEvent type="HKWorkoutEventTypeLap" date="2024-01-01 10:15:00 +0000"/>
+ </Workout>
+</HealthData>"#;
+        let dir = tempfile::tempdir().unwrap();
+        let xml_path = dir.path().join("export.xml");
+        std::fs::write(&xml_path, xml).unwrap();
+        crate::import::xml::import_xml(&conn, &xml_path, "imp1").unwrap();
+
+        let workout_hash: String = conn
+            .query_row("SELECT workout_hash FROM workouts", [], |row| row.get(0))
             .unwrap();
-        let arr = result.as_array().unwrap();
-        let sd = arr[0].get("sd").unwrap().as_str().unwrap();
-        assert!(sd.contains("2024-01-01"));
+        let server = HealthServer::new_in_memory(conn);
+
+        let params = Parameters(GetWorkoutDetailsParams { workout_hash });
+        let result = server.get_workout_details(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let metadata = parsed.get("metadata").unwrap().as_array().unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata[0]["key"], "HKWeatherHumidity");
+        assert_eq!(metadata[0]["value"], "5500 %");
+        assert_eq!(metadata[1]["key"], "HKWeatherTemperature");
+        assert_eq!(metadata[1]["value"], "68 degF");
     }
 
-    #[test]
-    fn query_to_json_timestamp_raw() {
-        // Raw timestamps may be skipped if the DuckDB driver can't convert them to String.
-        // This tests that the query still succeeds even with timestamp columns.
-        let server = setup_server();
-        let result = server
-            .query_to_json(
-                "SELECT start_date FROM records WHERE record_hash = 'rh1'",
-                &[],
-            )
+    #[tokio::test]
+    async fn tool_get_workout_details_missing_route_file() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+
+        // The export references a GPX file that was never imported
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<HealthData locale="en_US">
+ <Workout workoutActivityType="HKWorkoutActivityTypeRunning" duration="30" durationUnit="min" sourceName="Watch" startDate="2024-01-01 10:00:00 +0000" endDate="2024-01-01 10:30:00 +0000">
+  <WorkoutRoute sourceName="Watch">
+   <FileReference path="/workout-routes/route_2024-01-01_10.00am.gpx"/>
+  </WorkoutRoute>
+ </Workout>
+</HealthData>"#;
+        let dir = tempfile::tempdir().unwrap();
+        let xml_path = dir.path().join("export.xml");
+        std::fs::write(&xml_path, xml).unwrap();
+        crate::import::xml::import_xml(&conn, &xml_path, "imp1").unwrap();
+
+        let workout_hash: String = conn
+            .query_row("SELECT workout_hash FROM workouts", [], |row| row.get(0))
             .unwrap();
-        let arr = result.as_array().unwrap();
-        assert_eq!(arr.len(), 1);
+        let server = HealthServer::new_in_memory(conn);
+
+        let params = Parameters(GetWorkoutDetailsParams { workout_hash });
+        let result = server.get_workout_details(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(
+            parsed["route_file"],
+            "/workout-routes/route_2024-01-01_10.00am.gpx"
+        );
+        assert_eq!(parsed["has_route"], false);
     }
 
-    #[test]
-    fn query_to_json_invalid_sql() {
+    #[tokio::test]
+    async fn tool_get_last_workout() {
         let server = setup_server();
-        let result = server.query_to_json("INVALID SQL STATEMENT", &[]);
-        assert!(result.is_err());
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "INSERT INTO workouts VALUES ('wh0', 'HKWorkoutActivityTypeRunning', 1200.0, 'sec', 3000.0, 'm', 200.0, 'kcal', 'Apple Watch', '10.0', NULL, '2023-12-30 10:00:00', '2023-12-30 10:00:00', '2023-12-30 10:20:00', 'imp1', NULL, NULL, NULL, NULL, NULL);
+                 INSERT INTO workouts VALUES ('wy1', 'HKWorkoutActivityTypeYoga', 1800.0, 'sec', NULL, NULL, 100.0, 'kcal', 'Apple Watch', '10.0', NULL, '2024-01-02 07:00:00', '2024-01-02 07:00:00', '2024-01-02 07:30:00', 'imp1', NULL, NULL, NULL, NULL, NULL);
+                 INSERT INTO records VALUES ('rh4', 'HKQuantityTypeIdentifierHeartRate', 150.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-01 10:00:00', '2024-01-01 10:00:00', '2024-01-01 10:00:00', 'imp1', NULL, NULL, NULL, NULL);",
+            )
+            .unwrap();
+        }
+
+        let result = server
+            .get_last_workout(Parameters(GetLastWorkoutParams {
+                activity_type: Some("HKWorkoutActivityTypeRunning".to_string()),
+            }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["workout_hash"], "wh1");
+        assert_eq!(
+            parsed["workout"]["activity_type"],
+            "HKWorkoutActivityTypeRunning"
+        );
+        assert_eq!(parsed["events"].as_array().unwrap().len(), 1);
+        assert_eq!(parsed["has_route"], true);
+        assert_eq!(parsed["route"]["points"], 2);
+        assert!(parsed["route"]["distance_m"].as_f64().unwrap() > 0.0);
+        assert_eq!(parsed["route"]["max_elevation"], 11.0);
+        assert_eq!(parsed["heart_rate_zones"]["total_seconds"], 1800.0);
+
+        // Without a filter the newer yoga session wins
+        let result = server
+            .get_last_workout(Parameters(GetLastWorkoutParams {
+                activity_type: None,
+            }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["workout_hash"], "wy1");
+        assert!(parsed["route"].is_null());
+
+        let result = server
+            .get_last_workout(Parameters(GetLastWorkoutParams {
+                activity_type: Some("HKWorkoutActivityTypeSwimming".to_string()),
+            }))
+            .await;
+        assert_error(&result, "not_found", "");
     }
 
     #[tokio::test]
-    async fn tool_list_record_types() {
+    async fn tool_get_workout_heart_rate() {
         let server = setup_server();
-        let result = server.list_record_types().await;
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "
+                INSERT INTO records VALUES ('hr1', 'HKQuantityTypeIdentifierHeartRate', 140.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-01 10:01:10', '2024-01-01 10:01:10', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('hr2', 'HKQuantityTypeIdentifierHeartRate', 150.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-01 10:01:40', '2024-01-01 10:01:40', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('hr3', 'HKQuantityTypeIdentifierHeartRate', 160.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-01 10:02:05', '2024-01-01 10:02:05', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('hr4', 'HKQuantityTypeIdentifierHeartRate', 90.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-01 11:00:00', '2024-01-01 11:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                ",
+            )
+            .unwrap();
+        }
+
+        let params = Parameters(GetWorkoutHeartRateParams {
+            workout_hash: "wh1".to_string(),
+        });
+        let result = server.get_workout_heart_rate(params).await;
         let parsed: Value = serde_json::from_str(&result).unwrap();
-        let arr = parsed.as_array().unwrap();
-        assert_eq!(arr.len(), 2); // HeartRate and StepCount
+        let series = parsed.as_array().unwrap();
+        // hr4 is outside the workout window
+        assert_eq!(series.len(), 2);
+        assert_eq!(series[0]["minute"], "2024-01-01 10:01:00");
+        assert_eq!(series[0]["avg_bpm"], json!(145.0));
+        assert_eq!(series[0]["sample_count"], json!(2));
+        assert_eq!(series[1]["minute"], "2024-01-01 10:02:00");
+        assert_eq!(series[1]["avg_bpm"], json!(160.0));
     }
 
     #[tokio::test]
-    async fn tool_query_records() {
+    async fn tool_get_workout_heart_rate_no_data() {
         let server = setup_server();
-        let params = Parameters(QueryRecordsParams {
-            record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
-            start_date: None,
-            end_date: None,
-            source_name: None,
-            limit: Some(10),
+        let params = Parameters(GetWorkoutHeartRateParams {
+            workout_hash: "wh1".to_string(),
         });
-        let result = server.query_records(params).await;
+        let result = server.get_workout_heart_rate(params).await;
         let parsed: Value = serde_json::from_str(&result).unwrap();
-        assert_eq!(parsed.as_array().unwrap().len(), 2);
+        assert_eq!(parsed, json!([]));
+
+        let params = Parameters(GetWorkoutHeartRateParams {
+            workout_hash: "nonexistent".to_string(),
+        });
+        let result = server.get_workout_heart_rate(params).await;
+        assert_error(&result, "not_found", "Workout nonexistent not found");
     }
 
     #[tokio::test]
-    async fn tool_query_records_with_filters() {
+    async fn tool_get_heart_rate_zones() {
         let server = setup_server();
-        let params = Parameters(QueryRecordsParams {
-            record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
-            start_date: Some("2024-01-01 08:30:00".to_string()),
-            end_date: None,
-            source_name: Some("Apple Watch".to_string()),
-            limit: None,
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "
+                INSERT INTO records VALUES ('hz1', 'HKQuantityTypeIdentifierHeartRate', 100.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-01 10:00:00', '2024-01-01 10:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('hz2', 'HKQuantityTypeIdentifierHeartRate', 150.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-01 10:10:00', '2024-01-01 10:10:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('hz3', 'HKQuantityTypeIdentifierHeartRate', 185.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-01-01 10:20:00', '2024-01-01 10:20:00', 'imp1', NULL, NULL, NULL, NULL);
+                ",
+            )
+            .unwrap();
+        }
+
+        // Max 200 → boundaries 120/140/160/180
+        let params = Parameters(GetHeartRateZonesParams {
+            workout_hash: "wh1".to_string(),
+            boundaries: None,
+            max_heart_rate: Some(200.0),
         });
-        let result = server.query_records(params).await;
+        let result = server.get_heart_rate_zones(params).await;
         let parsed: Value = serde_json::from_str(&result).unwrap();
-        assert_eq!(parsed.as_array().unwrap().len(), 1);
+        assert_eq!(parsed["boundaries"], json!([120.0, 140.0, 160.0, 180.0]));
+        assert_eq!(parsed["total_seconds"], 1800.0);
+
+        let zones = parsed["zones"].as_array().unwrap();
+        assert_eq!(zones.len(), 5);
+        let seconds: Vec<f64> = zones
+            .iter()
+            .map(|z| z["seconds"].as_f64().unwrap())
+            .collect();
+        assert_eq!(seconds, vec![600.0, 0.0, 600.0, 0.0, 600.0]);
+        assert!((zones[0]["percentage"].as_f64().unwrap() - 100.0 / 3.0).abs() < 1e-9);
+        assert_eq!(zones[2]["min_bpm"], 140.0);
+        assert_eq!(zones[2]["max_bpm"], 160.0);
+
+        // Custom boundaries: two zones split at 160 bpm
+        let params = Parameters(GetHeartRateZonesParams {
+            workout_hash: "wh1".to_string(),
+            boundaries: Some(vec![160.0]),
+            max_heart_rate: None,
+        });
+        let result = server.get_heart_rate_zones(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let seconds: Vec<f64> = parsed["zones"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|z| z["seconds"].as_f64().unwrap())
+            .collect();
+        assert_eq!(seconds, vec![1200.0, 600.0]);
     }
 
     #[tokio::test]
-    async fn tool_get_record_statistics() {
+    async fn tool_get_power_curve() {
         let server = setup_server();
-        let params = Parameters(GetRecordStatisticsParams {
-            record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
-            start_date: None,
-            end_date: None,
-            period: Some("day".to_string()),
+        // wh1 runs 10:00-10:30: 200 W every 5 s, with a 900 W sprint at 10:05:00-10:05:10
+        server
+            .conn
+            .get()
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO records SELECT 'pw' || i, 'HKQuantityTypeIdentifierCyclingPower', \
+                     CASE WHEN i BETWEEN 60 AND 61 THEN 900.0 ELSE 200.0 END, 'W', 'Bike', NULL, NULL, NULL, \
+                     TIMESTAMP '2024-01-01 10:00:00' + INTERVAL (i * 5) SECOND, \
+                     TIMESTAMP '2024-01-01 10:00:00' + INTERVAL (i * 5) SECOND, 'imp1', NULL, NULL, NULL, NULL \
+                 FROM range(360) t(i)",
+            )
+            .unwrap();
+
+        let params = Parameters(GetPowerCurveParams {
+            workout_hash: "wh1".to_string(),
         });
-        let result = server.get_record_statistics(params).await;
+        let result = server.get_power_curve(params).await;
         let parsed: Value = serde_json::from_str(&result).unwrap();
-        assert!(!parsed.as_array().unwrap().is_empty());
+        let curve = parsed.as_array().unwrap();
+        assert_eq!(curve.len(), 4);
+        assert_eq!(curve[0]["duration_secs"], 5);
+        assert_eq!(curve[0]["best_avg_watts"], 900.0);
+        assert_eq!(curve[3]["duration_secs"], 1200);
+        let best_20min = curve[3]["best_avg_watts"].as_f64().unwrap();
+        assert!(best_20min < curve[0]["best_avg_watts"].as_f64().unwrap());
+        assert!((best_20min - (200.0 * 1190.0 + 900.0 * 10.0) / 1200.0).abs() < 1e-9);
+
+        let params = Parameters(GetPowerCurveParams {
+            workout_hash: "missing".to_string(),
+        });
+        let result = server.get_power_curve(params).await;
+        assert_error(&result, "not_found", "Workout missing not found");
+    }
+
+    #[test]
+    fn power_curve_skips_durations_longer_than_the_workout() {
+        // 90 s ride: nothing until 10 s, then 100 W, then 300 W from 70 s
+        let curve = power_curve(&[(10.0, 100.0), (70.0, 300.0)], 90.0, &[5, 60, 300]);
+        assert_eq!(curve.len(), 2);
+        assert_eq!(curve[0], (5, 300.0));
+        assert!((curve[1].1 - (100.0 * 40.0 + 300.0 * 20.0) / 60.0).abs() < 1e-9);
+        assert!(power_curve(&[], 0.0, &[5]).is_empty());
     }
 
     #[tokio::test]
-    async fn tool_list_workouts() {
+    async fn tool_get_heart_rate_zones_no_data() {
         let server = setup_server();
-        let params = Parameters(ListWorkoutsParams {
-            activity_type: None,
-            start_date: None,
-            end_date: None,
-            limit: None,
+        // Seeded HR samples (08:00, 09:00) are outside wh1 (10:00-10:30)
+        let params = Parameters(GetHeartRateZonesParams {
+            workout_hash: "wh1".to_string(),
+            boundaries: None,
+            max_heart_rate: None,
         });
-        let result = server.list_workouts(params).await;
+        let result = server.get_heart_rate_zones(params).await;
         let parsed: Value = serde_json::from_str(&result).unwrap();
-        assert_eq!(parsed.as_array().unwrap().len(), 1);
+        assert_eq!(parsed["total_seconds"], 0.0);
+        for zone in parsed["zones"].as_array().unwrap() {
+            assert_eq!(zone["seconds"], 0.0);
+            assert_eq!(zone["percentage"], 0.0);
+        }
+
+        let params = Parameters(GetHeartRateZonesParams {
+            workout_hash: "missing".to_string(),
+            boundaries: None,
+            max_heart_rate: None,
+        });
+        let result = server.get_heart_rate_zones(params).await;
+        assert_error(&result, "not_found", "Workout missing not found");
     }
 
-    #[tokio::test]
-    async fn tool_get_workout_details() {
-        let server = setup_server();
-        let params = Parameters(GetWorkoutDetailsParams {
-            workout_hash: "wh1".to_string(),
-        });
-        let result = server.get_workout_details(params).await;
+    #[tokio::test]
+    async fn tool_get_active_minutes() {
+        let server = setup_server();
+        {
+            // Max HR 180, so the default threshold is 0.64 * 180 = 115.2 bpm
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "INSERT INTO records VALUES ('ra1', 'HKQuantityTypeIdentifierHeartRate', 130.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-01 10:00:00', '2024-01-01 10:00:00', '2024-01-01 10:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                 INSERT INTO records VALUES ('ra2', 'HKQuantityTypeIdentifierHeartRate', 180.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-01 10:01:10', '2024-01-01 10:01:10', '2024-01-01 10:01:10', 'imp1', NULL, NULL, NULL, NULL);
+                 INSERT INTO records VALUES ('ra3', 'HKQuantityTypeIdentifierHeartRate', 90.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-01 10:02:00', '2024-01-01 10:02:00', '2024-01-01 10:02:00', 'imp1', NULL, NULL, NULL, NULL);
+                 INSERT INTO records VALUES ('ra4', 'HKQuantityTypeIdentifierHeartRate', 120.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-02 07:30:00', '2024-01-02 07:30:00', '2024-01-02 07:30:00', 'imp1', NULL, NULL, NULL, NULL);",
+            )
+            .unwrap();
+        }
+
+        let result = server
+            .get_active_minutes(Parameters(GetActiveMinutesParams {
+                start_date: None,
+                end_date: None,
+                threshold_bpm: None,
+                max_heart_rate: None,
+                intensity: None,
+            }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert!((parsed["threshold_bpm"].as_f64().unwrap() - 115.2).abs() < 1e-9);
+        let days = parsed["days"].as_array().unwrap();
+        assert_eq!(days.len(), 2);
+        // 2024-01-01: 10:00 and 10:01 are above; 08:00, 09:00, and 10:02 are below
+        assert_eq!(days[0]["date"], "2024-01-01");
+        assert_eq!(days[0]["active_minutes"], 2);
+        assert_eq!(days[0]["minutes_with_data"], 5);
+        assert_eq!(days[1]["date"], "2024-01-02");
+        assert_eq!(days[1]["active_minutes"], 1);
+
+        // An explicit threshold overrides the derived one
+        let result = server
+            .get_active_minutes(Parameters(GetActiveMinutesParams {
+                start_date: Some("2024-01-02".to_string()),
+                end_date: None,
+                threshold_bpm: Some(125.0),
+                max_heart_rate: None,
+                intensity: None,
+            }))
+            .await;
         let parsed: Value = serde_json::from_str(&result).unwrap();
-        assert!(parsed.get("workout").unwrap().is_object());
-        assert!(parsed.get("events").unwrap().is_array());
-        assert!(parsed.get("statistics").unwrap().is_array());
-        assert_eq!(parsed.get("has_route").unwrap(), &Value::Bool(true));
+        assert_eq!(parsed["days"][0]["active_minutes"], 0);
+        assert_eq!(parsed["days"][0]["minutes_with_data"], 1);
     }
 
     #[tokio::test]
@@ -719,7 +6534,131 @@ mod tests {
         });
         let result = server.get_activity_summaries(params).await;
         let parsed: Value = serde_json::from_str(&result).unwrap();
-        assert_eq!(parsed.as_array().unwrap().len(), 1);
+        assert_eq!(parsed["rows"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn tool_get_daily_activity_trends() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "INSERT INTO records VALUES ('rs2', 'HKQuantityTypeIdentifierStepCount', 700.0, 'count', 'Apple Watch', '10.0', NULL, '2024-01-01 12:00:00', '2024-01-01 12:00:00', '2024-01-01 12:30:00', 'imp1', NULL, NULL, NULL, NULL);
+                 INSERT INTO records VALUES ('rs3', 'HKQuantityTypeIdentifierStepCount', 4000.0, 'count', 'iPhone', '17.0', NULL, '2024-01-02 09:00:00', '2024-01-02 09:00:00', '2024-01-02 10:00:00', 'imp1', NULL, NULL, NULL, NULL);",
+            )
+            .unwrap();
+            rebuild_daily_stats(&conn).unwrap();
+        }
+
+        let result = server
+            .get_daily_activity_trends(Parameters(GetDailyActivityTrendsParams {
+                start_date: None,
+                end_date: None,
+                limit: None,
+            }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let days = parsed["rows"].as_array().unwrap();
+        assert_eq!(days.len(), 2);
+
+        // Steps only, no rings
+        assert_eq!(days[0]["date"], "2024-01-02");
+        assert_eq!(days[0]["steps"], 4000.0);
+        assert!(days[0].get("active_energy_burned").is_none());
+
+        // Rings and both sources' steps on one row
+        assert_eq!(days[1]["date"], "2024-01-01");
+        assert_eq!(days[1]["active_energy_burned"], 500.0);
+        assert_eq!(days[1]["exercise_minutes"], 30.0);
+        assert_eq!(days[1]["stand_hours"], 10.0);
+        assert_eq!(days[1]["steps"], 2200.0);
+
+        let result = server
+            .get_daily_activity_trends(Parameters(GetDailyActivityTrendsParams {
+                start_date: None,
+                end_date: Some("2024-01-01".to_string()),
+                limit: None,
+            }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["rows"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn tool_generate_weekly_report() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "
+                -- An earlier run to beat, then a longer run and a first-ever ride in the week of Mar 4-10
+                INSERT INTO workouts VALUES ('w_old', 'HKWorkoutActivityTypeRunning', 30.0, 'min', 5.0, 'km', 300.0, 'kcal', 'Apple Watch', NULL, NULL, NULL, '2024-02-01 07:00:00', '2024-02-01 07:30:00', 'imp1', NULL, NULL, 5000.0, 300.0, NULL);
+                INSERT INTO workouts VALUES ('w_run', 'HKWorkoutActivityTypeRunning', 45.0, 'min', 8.0, 'km', 500.0, 'kcal', 'Apple Watch', NULL, NULL, NULL, '2024-03-05 07:00:00', '2024-03-05 07:45:00', 'imp1', NULL, NULL, 8000.0, 500.0, NULL);
+                INSERT INTO workouts VALUES ('w_ride', 'HKWorkoutActivityTypeCycling', 60.0, 'min', 20.0, 'km', 600.0, 'kcal', 'Apple Watch', NULL, NULL, NULL, '2024-03-07 18:00:00', '2024-03-07 19:00:00', 'imp1', NULL, NULL, 20000.0, 600.0, NULL);
+                INSERT INTO records VALUES ('rhr0', 'HKQuantityTypeIdentifierRestingHeartRate', 66.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-02-28 08:00:00', '2024-02-28 08:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('rhr1', 'HKQuantityTypeIdentifierRestingHeartRate', 60.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-03-05 08:00:00', '2024-03-05 08:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                INSERT INTO records VALUES ('rhr2', 'HKQuantityTypeIdentifierRestingHeartRate', 62.0, 'count/min', 'Apple Watch', NULL, NULL, NULL, '2024-03-06 08:00:00', '2024-03-06 08:00:00', 'imp1', NULL, NULL, NULL, NULL);
+                -- The night of Mar 3 belongs to the previous week; the night of Mar 9 is 7h asleep of 8h in bed
+                INSERT INTO records VALUES ('sl0', 'HKCategoryTypeIdentifierSleepAnalysis', NULL, NULL, 'Apple Watch', NULL, NULL, NULL, '2024-03-03 23:00:00', '2024-03-04 07:00:00', 'imp1', NULL, NULL, NULL, 'HKCategoryValueSleepAnalysisAsleepCore');
+                INSERT INTO records VALUES ('sl1', 'HKCategoryTypeIdentifierSleepAnalysis', NULL, NULL, 'Apple Watch', NULL, NULL, NULL, '2024-03-09 23:00:00', '2024-03-10 07:00:00', 'imp1', NULL, NULL, NULL, 'HKCategoryValueSleepAnalysisInBed');
+                INSERT INTO records VALUES ('sl2', 'HKCategoryTypeIdentifierSleepAnalysis', NULL, NULL, 'Apple Watch', NULL, NULL, NULL, '2024-03-09 23:00:00', '2024-03-10 06:00:00', 'imp1', NULL, NULL, NULL, 'HKCategoryValueSleepAnalysisAsleepCore');
+                INSERT INTO activity_summaries VALUES ('2024-03-05', 650.0, 600.0, NULL, NULL, 40.0, 30.0, 12.0, 12.0, 'imp1', NULL, NULL);
+                INSERT INTO activity_summaries VALUES ('2024-03-06', 300.0, 600.0, NULL, NULL, 35.0, 30.0, 12.0, 12.0, 'imp1', NULL, NULL);
+                ",
+            )
+            .unwrap();
+            rebuild_daily_stats(&conn).unwrap();
+        }
+
+        let result = server
+            .generate_weekly_report(Parameters(GenerateWeeklyReportParams {
+                week_ending: "2024-03-10".to_string(),
+            }))
+            .await;
+        let report: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(report["week_start"], "2024-03-04");
+        assert_eq!(report["week_end"], "2024-03-10");
+
+        let workouts = &report["workouts"];
+        assert_eq!(workouts["count"], 2);
+        assert_eq!(workouts["total_distance_m"], 28000.0);
+        assert_eq!(workouts["total_energy_kcal"], 1100.0);
+        assert_eq!(workouts["total_duration_minutes"], 105.0);
+        assert_eq!(workouts["by_activity_type"].as_array().unwrap().len(), 2);
+
+        let rhr = &report["resting_heart_rate"];
+        assert_eq!(rhr["avg_bpm"], 61.0);
+        assert_eq!(rhr["days"], 2);
+        assert_eq!(rhr["previous_week_avg_bpm"], 66.0);
+
+        let sleep = &report["sleep"];
+        assert_eq!(sleep["nights"], 1);
+        assert_eq!(sleep["avg_asleep_hours"], 7.0);
+        assert_eq!(sleep["avg_efficiency"], 0.875);
+
+        let rings = &report["rings"];
+        assert_eq!(rings["days"], 2);
+        assert_eq!(rings["move_closed"], 1);
+        assert_eq!(rings["exercise_closed"], 2);
+        assert_eq!(rings["stand_closed"], 2);
+        assert_eq!(rings["all_closed"], 1);
+
+        // The ride has nothing earlier to beat; the run beats February's on both metrics
+        let records = report["personal_records"].as_array().unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| r["workout_hash"] == "w_run"));
+        assert_eq!(records[0]["metric"], "distance_m");
+        assert_eq!(records[0]["value"], 8000.0);
+        assert_eq!(records[0]["previous_best"], 5000.0);
+        assert_eq!(records[1]["metric"], "duration_seconds");
+        assert_eq!(records[1]["previous_best"], 1800.0);
+
+        let result = server
+            .generate_weekly_report(Parameters(GenerateWeeklyReportParams {
+                week_ending: "last sunday".to_string(),
+            }))
+            .await;
+        assert_error(&result, "invalid_argument", "Invalid week_ending");
     }
 
     #[tokio::test]
@@ -727,10 +6666,89 @@ mod tests {
         let server = setup_server();
         let params = Parameters(GetWorkoutRouteParams {
             workout_hash: "wh1".to_string(),
+            start_time: None,
+            end_time: None,
+            limit: None,
+            offset: None,
         });
         let result = server.get_workout_route(params).await;
         let parsed: Value = serde_json::from_str(&result).unwrap();
-        assert_eq!(parsed.as_array().unwrap().len(), 2);
+        assert_eq!(parsed["rows"].as_array().unwrap().len(), 2);
+        assert_eq!(parsed["truncated"], false);
+        assert_eq!(parsed["limit"], 10_000);
+
+        // A caller's limit goes through the row cap too
+        let params = Parameters(GetWorkoutRouteParams {
+            workout_hash: "wh1".to_string(),
+            start_time: None,
+            end_time: None,
+            limit: Some(50_000),
+            offset: None,
+        });
+        let result = setup_server()
+            .with_config(ServerConfig {
+                tool_max_rows: vec![("get_workout_route".to_string(), 1)],
+                ..Default::default()
+            })
+            .get_workout_route(params)
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["rows"].as_array().unwrap().len(), 1);
+        assert_eq!(parsed["truncated"], true);
+        assert_eq!(parsed["limit"], 1);
+    }
+
+    #[tokio::test]
+    async fn tool_get_workout_route_paging() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "
+                INSERT INTO route_points
+                SELECT 'rp' || (i + 3), 'wh1', 37.7750 + i * 0.0001, -122.4195, 11.0,
+                       TIMESTAMP '2024-01-01 10:00:10' + INTERVAL (i * 5) SECOND, 3.6, 181.0, 4.5, 2.8, 'imp1'
+                FROM range(8) t(i);
+                ",
+            )
+            .unwrap();
+        }
+        let route = |start_time: Option<&str>, limit, offset| {
+            let server = server.clone();
+            let params = Parameters(GetWorkoutRouteParams {
+                workout_hash: "wh1".to_string(),
+                start_time: start_time.map(str::to_string),
+                end_time: None,
+                limit,
+                offset,
+            });
+            async move {
+                let result = server.get_workout_route(params).await;
+                let parsed: Value = serde_json::from_str(&result).unwrap();
+                parsed["rows"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|p| p["timestamp"].as_str().unwrap().to_string())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(route(None, None, None).await.len(), 10);
+        assert_eq!(
+            route(None, Some(3), Some(4)).await,
+            vec![
+                "2024-01-01 10:00:20",
+                "2024-01-01 10:00:25",
+                "2024-01-01 10:00:30"
+            ]
+        );
+        // Paging past the end returns the remainder
+        assert_eq!(route(None, Some(5), Some(8)).await.len(), 2);
+        assert_eq!(
+            route(Some("2024-01-01 10:00:40"), None, None).await,
+            vec!["2024-01-01 10:00:40", "2024-01-01 10:00:45"]
+        );
     }
 
     #[tokio::test]
@@ -745,11 +6763,41 @@ mod tests {
         assert_eq!(parsed.as_array().unwrap().len(), 1);
     }
 
+    #[tokio::test]
+    async fn tool_list_clinical_records() {
+        let server = setup_server();
+        server
+            .conn
+            .get()
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO clinical_records VALUES ('cr1', '2345-7', 'Glucose', 95.0, NULL, 'mg/dL', '2024-01-15 08:30:00', 'City Lab', 'imp1', NULL, NULL);
+                 INSERT INTO clinical_records VALUES ('cr2', '718-7', 'Hemoglobin', 14.1, NULL, 'g/dL', '2023-06-01 09:00:00', 'City Lab', 'imp1', NULL, NULL);",
+            )
+            .unwrap();
+
+        let result = server
+            .list_clinical_records(Parameters(ListClinicalRecordsParams {
+                record_type: None,
+                start_date: Some("2024-01-01".to_string()),
+                end_date: None,
+                limit: None,
+            }))
+            .await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed["rows"].as_array().unwrap();
+        assert_eq!(arr.len(), 1);
+        assert_eq!(arr[0]["record_type"], "2345-7");
+        assert_eq!(arr[0]["value"], 95.0);
+        assert_eq!(arr[0]["unit"], "mg/dL");
+    }
+
     #[tokio::test]
     async fn tool_get_ecg_data() {
         let server = setup_server();
         let params = Parameters(GetEcgDataParams {
             ecg_hash: "ecg1".to_string(),
+            max_samples: None,
         });
         let result = server.get_ecg_data(params).await;
         let parsed: Value = serde_json::from_str(&result).unwrap();
@@ -758,6 +6806,45 @@ mod tests {
             parsed.get("voltages_uv").unwrap().as_array().unwrap().len(),
             3
         );
+        assert!(parsed.get("downsampled").is_none());
+    }
+
+    #[tokio::test]
+    async fn tool_get_ecg_data_max_samples() {
+        let server = setup_server();
+        {
+            let conn = server.conn.get().unwrap();
+            conn.execute_batch(
+                "INSERT INTO ecg_readings VALUES ('ecg2', '2024-01-02 12:00:00', 'Sinus Rhythm', 'Apple Watch', 500.0, NULL, '2.0', 'imp1', NULL, NULL);
+                 INSERT INTO ecg_samples SELECT 'ecg2', i, i * 10.0 FROM range(100) t(i);",
+            )
+            .unwrap();
+        }
+        let get = |max_samples| {
+            server.get_ecg_data(Parameters(GetEcgDataParams {
+                ecg_hash: "ecg2".to_string(),
+                max_samples,
+            }))
+        };
+
+        let parsed: Value = serde_json::from_str(&get(Some(10)).await).unwrap();
+        assert_eq!(parsed["sample_count"], 10);
+        assert_eq!(parsed["downsampled"], true);
+        assert_eq!(parsed["original_sample_count"], 100);
+        assert_eq!(parsed["sample_rate_hz"], 50.0);
+        let voltages = parsed["voltages_uv"].as_array().unwrap();
+        assert_eq!(voltages.len(), 10);
+        assert_eq!(voltages[1], 100.0);
+
+        // Under the cap: unchanged
+        let full = get(None).await;
+        assert_eq!(get(Some(100)).await, full);
+
+        assert_error(
+            &get(Some(0)).await,
+            "invalid_argument",
+            "max_samples must be at least 1",
+        );
     }
 
     #[tokio::test]
@@ -765,31 +6852,218 @@ mod tests {
         let server = setup_server();
         let params = Parameters(RunCustomQueryParams {
             query: "SELECT COUNT(*) as cnt FROM records".to_string(),
+            format: None,
         });
         let result = server.run_custom_query(params).await;
         let parsed: Value = serde_json::from_str(&result).unwrap();
         assert_eq!(parsed.as_array().unwrap()[0].get("cnt").unwrap(), 3);
     }
 
+    #[tokio::test]
+    async fn tool_run_custom_query_ndjson() {
+        let server = setup_server();
+        let query = |format: &str, max_query_rows| {
+            let server = server.clone().with_config(ServerConfig {
+                max_query_rows,
+                ..Default::default()
+            });
+            let format = format.to_string();
+            async move {
+                server
+                    .run_custom_query(Parameters(RunCustomQueryParams {
+                        query: "SELECT record_hash, value FROM records ORDER BY record_hash"
+                            .to_string(),
+                        format: Some(format),
+                    }))
+                    .await
+            }
+        };
+
+        let output = query("ndjson", None).await;
+        let rows: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], json!({"record_hash": "rh1", "value": 72.0}));
+        assert!(output.ends_with('\n'));
+
+        // Capped output ends with a truncation marker line
+        let output = query("ndjson", Some(2)).await;
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2]["truncated"], true);
+
+        let json: Value = serde_json::from_str(&query("json", None).await).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 3);
+        assert_error(
+            &query("csv", None).await,
+            "invalid_argument",
+            "Unknown format",
+        );
+    }
+
     #[tokio::test]
     async fn tool_run_custom_query_with_cte() {
         let server = setup_server();
         let params = Parameters(RunCustomQueryParams {
             query: "WITH t AS (SELECT 1 as n) SELECT n FROM t".to_string(),
+            format: None,
         });
         let result = server.run_custom_query(params).await;
         let parsed: Value = serde_json::from_str(&result).unwrap();
         assert_eq!(parsed.as_array().unwrap().len(), 1);
     }
 
+    #[tokio::test]
+    async fn tool_run_custom_query_caps_rows() {
+        let server = setup_server().with_config(ServerConfig {
+            max_query_rows: Some(2),
+            ..Default::default()
+        });
+        let params = Parameters(RunCustomQueryParams {
+            query: "SELECT record_hash FROM records ORDER BY record_hash;".to_string(),
+            format: None,
+        });
+        let result = server.run_custom_query(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["truncated"], true);
+        assert!(parsed["note"].as_str().unwrap().contains("first 2 rows"));
+        let rows = parsed["rows"].as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["record_hash"], "rh1");
+        assert_eq!(rows[1]["record_hash"], "rh2");
+    }
+
+    #[tokio::test]
+    async fn tool_run_custom_query_streams_large_results() {
+        let query = |max_query_rows| {
+            let server = setup_server().with_config(ServerConfig {
+                max_query_rows,
+                ..Default::default()
+            });
+            async move {
+                server
+                    .run_custom_query(Parameters(RunCustomQueryParams {
+                        query:
+                            "SELECT range AS n, 'row ' || range::VARCHAR AS label FROM range(50000)"
+                                .to_string(),
+                        format: None,
+                    }))
+                    .await
+            }
+        };
+
+        let started = std::time::Instant::now();
+        let output = query(Some(100_000)).await;
+        let elapsed = started.elapsed();
+        // Past PRETTY_MAX_ROWS the array is written compactly
+        assert!(
+            output.starts_with("[{\"label\":\"row 0\",\"n\":0},"),
+            "{}",
+            &output[..80]
+        );
+        let parsed: Value = serde_json::from_str(&output).unwrap();
+        let rows = parsed.as_array().unwrap();
+        assert_eq!(rows.len(), 50_000);
+        assert_eq!(rows[49_999], json!({"n": 49_999, "label": "row 49999"}));
+        assert!(elapsed.as_secs() < 30, "took {:?}", elapsed);
+
+        // Over the default cap: the compact output is wrapped like the pretty one
+        let parsed: Value = serde_json::from_str(&query(None).await).unwrap();
+        assert_eq!(parsed["truncated"], true);
+        assert!(parsed["note"]
+            .as_str()
+            .unwrap()
+            .contains("first 10000 rows"));
+        assert_eq!(
+            parsed["rows"].as_array().unwrap().len(),
+            DEFAULT_MAX_QUERY_ROWS
+        );
+    }
+
+    #[tokio::test]
+    async fn tool_row_caps_lower_requested_limit() {
+        let query = |config: ServerConfig| async move {
+            let params = Parameters(QueryRecordsParams {
+                record_type: "HKQuantityTypeIdentifierHeartRate".to_string(),
+                start_date: None,
+                end_date: None,
+                source_name: None,
+                limit: Some(500),
+                offset: None,
+            });
+            let result = setup_server()
+                .with_config(config)
+                .query_records(params)
+                .await;
+            serde_json::from_str::<Value>(&result).unwrap()
+        };
+
+        // The global cap beats the larger requested limit, and the wrapper says so
+        let parsed = query(ServerConfig {
+            max_rows: Some(1),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(parsed["truncated"], true);
+        assert_eq!(parsed["limit"], 1);
+        let rows = parsed["rows"].as_array().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["record_hash"], "rh2");
+
+        // A per-tool cap replaces the global one; both rows fit, so nothing was cut
+        let parsed = query(ServerConfig {
+            max_rows: Some(1),
+            tool_max_rows: vec![("query_records".to_string(), 5)],
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(parsed["truncated"], false);
+        assert_eq!(parsed["limit"], 5);
+        assert_eq!(parsed["rows"].as_array().unwrap().len(), 2);
+
+        // Within the built-in cap the requested limit stands
+        let parsed = query(ServerConfig::default()).await;
+        assert_eq!(parsed["truncated"], false);
+        assert_eq!(parsed["limit"], 500);
+        assert_eq!(parsed["rows"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn tool_run_custom_query_respects_explicit_limit() {
+        let server = setup_server().with_config(ServerConfig {
+            max_query_rows: Some(2),
+            ..Default::default()
+        });
+        let params = Parameters(RunCustomQueryParams {
+            query: "SELECT record_hash FROM records ORDER BY record_hash LIMIT 1 -- first only"
+                .to_string(),
+            format: None,
+        });
+        let result = server.run_custom_query(params).await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let rows = parsed.as_array().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["record_hash"], "rh1");
+    }
+
     #[tokio::test]
     async fn tool_run_custom_query_rejects_mutation() {
         let server = setup_server();
         let params = Parameters(RunCustomQueryParams {
             query: "DROP TABLE records".to_string(),
+            format: None,
         });
         let result = server.run_custom_query(params).await;
-        assert!(result.starts_with("Error: Query must start with SELECT or WITH"));
+        assert_error(
+            &result,
+            "invalid_argument",
+            "Query must start with SELECT or WITH",
+        );
     }
 
     #[tokio::test]
@@ -797,9 +7071,14 @@ mod tests {
         let server = setup_server();
         let params = Parameters(RunCustomQueryParams {
             query: "INSERT INTO records VALUES ('a','b',1,'c','d',NULL,NULL,NULL,'2024-01-01','2024-01-01','x')".to_string(),
+            format: None,
         });
         let result = server.run_custom_query(params).await;
-        assert!(result.starts_with("Error: Query must start with SELECT or WITH"));
+        assert_error(
+            &result,
+            "invalid_argument",
+            "Query must start with SELECT or WITH",
+        );
     }
 
     #[tokio::test]
@@ -811,6 +7090,29 @@ mod tests {
         assert_eq!(arr.len(), 2); // Apple Watch, iPhone
     }
 
+    #[tokio::test]
+    async fn tool_list_data_sources_with_aliases() {
+        let server = setup_server();
+        server
+            .conn
+            .get()
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO source_aliases VALUES ('Apple%', 'Devices'), ('iPhone', 'Devices'), ('%', 'Other');",
+            )
+            .unwrap();
+        let result = server.list_data_sources().await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        let arr = parsed.as_array().unwrap();
+        assert_eq!(arr.len(), 1);
+        assert_eq!(arr[0]["source_name"], "Devices");
+        assert_eq!(arr[0]["record_count"], 3);
+        assert_eq!(
+            arr[0]["source_names"],
+            serde_json::json!(["Apple Watch", "iPhone"])
+        );
+    }
+
     #[tokio::test]
     async fn tool_get_import_history() {
         let server = setup_server();
@@ -819,6 +7121,65 @@ mod tests {
         assert_eq!(parsed.as_array().unwrap().len(), 1);
     }
 
+    #[tokio::test]
+    async fn tool_diff_imports() {
+        let conn = open_db_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let xml_path = dir.path().join("export.xml");
+        std::fs::write(
+            &xml_path,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<HealthData locale="en_US">
+ <Record type="HKQuantityTypeIdentifierHeartRate" sourceName="Watch" unit="count/min" value="72" startDate="2024-01-01 08:00:00 +0000" endDate="2024-01-01 08:00:00 +0000"/>
+ <Record type="HKQuantityTypeIdentifierStepCount" sourceName="iPhone" unit="count" value="100" startDate="2024-01-01 09:00:00 +0000" endDate="2024-01-01 09:10:00 +0000"/>
+ <Workout workoutActivityType="HKWorkoutActivityTypeRunning" duration="30" durationUnit="min" sourceName="Watch" startDate="2024-01-01 10:00:00 +0000" endDate="2024-01-01 10:30:00 +0000">
+ </Workout>
+</HealthData>"#,
+        )
+        .unwrap();
+        // The same export imported twice, as run_import would: dedup, then log the import
+        for (import_id, imported_at) in [
+            ("imp_a", "2024-02-01 00:00:00"),
+            ("imp_b", "2024-03-01 00:00:00"),
+        ] {
+            crate::import::xml::import_xml(&conn, &xml_path, import_id).unwrap();
+            crate::db::deduplicate_tables(&conn).unwrap();
+            conn.execute(
+                "INSERT INTO imports VALUES (?, '/tmp', ?, 2, 1, 1.0, NULL)",
+                [import_id, imported_at],
+            )
+            .unwrap();
+        }
+        let server = HealthServer::new_in_memory(conn);
+
+        let diff = |a: &str, b: &str| {
+            server.diff_imports(Parameters(DiffImportsParams {
+                import_id_a: a.to_string(),
+                import_id_b: b.to_string(),
+            }))
+        };
+        let parsed: Value = serde_json::from_str(&diff("imp_a", "imp_b").await).unwrap();
+        assert_eq!(
+            parsed["tables"]["records"],
+            json!({"only_a": 0, "only_b": 0, "both": 2})
+        );
+        assert_eq!(
+            parsed["tables"]["workouts"],
+            json!({"only_a": 0, "only_b": 0, "both": 1})
+        );
+        assert_eq!(
+            parsed["tables"]["ecg_readings"],
+            json!({"only_a": 0, "only_b": 0, "both": 0})
+        );
+
+        assert_error(
+            &diff("imp_a", "imp_missing").await,
+            "not_found",
+            "Import imp_missing not found",
+        );
+    }
+
     #[test]
     fn query_to_json_unsigned_int_types() {
         let server = setup_server();
@@ -904,7 +7265,7 @@ mod tests {
             ensure_schema(&conn).unwrap();
         }
 
-        let result = run_server(&db_path, "127.0.0.1", 0, "invalid").await;
+        let result = run_server(&db_path, "127.0.0.1", 0, "invalid", ServerConfig::default()).await;
         assert!(result.is_err());
         assert!(result
             .unwrap_err()
@@ -912,6 +7273,193 @@ mod tests {
             .contains("Unknown transport"));
     }
 
+    #[test]
+    fn validate_server_good_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("test.duckdb");
+        {
+            let conn = crate::db::open_db(&db_path).unwrap();
+            ensure_schema(&conn).unwrap();
+            rebuild_daily_stats(&conn).unwrap();
+        }
+
+        let tool_count = validate_server(&db_path).unwrap();
+        assert!(tool_count > 0);
+    }
+
+    #[test]
+    fn validate_server_missing_db() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(validate_server(&dir.path().join("missing.duckdb")).is_err());
+    }
+
+    #[test]
+    fn validate_server_missing_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("test.duckdb");
+        {
+            // Schema only: daily_record_stats hasn't been built yet
+            let conn = crate::db::open_db(&db_path).unwrap();
+            ensure_schema(&conn).unwrap();
+        }
+
+        let err = validate_server(&db_path).unwrap_err();
+        assert!(err.to_string().contains("daily_record_stats"));
+    }
+
+    /// A file-backed DB with the schema and a CSV next to it, for external-access tests.
+    fn setup_db_with_csv(dir: &Path) -> (PathBuf, String) {
+        let db_path = dir.join("test.duckdb");
+        {
+            let conn = crate::db::open_db(&db_path).unwrap();
+            ensure_schema(&conn).unwrap();
+            rebuild_daily_stats(&conn).unwrap();
+        }
+        let csv_path = dir.join("secret.csv");
+        std::fs::write(&csv_path, "user,password\nalice,hunter2\n").unwrap();
+        let query = format!("SELECT * FROM read_csv('{}')", csv_path.display());
+        (db_path, query)
+    }
+
+    #[tokio::test]
+    async fn restricted_server_rejects_file_access() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db_path, query) = setup_db_with_csv(dir.path());
+
+        let server = HealthServer::new_restricted(&db_path).unwrap();
+        let result = server
+            .run_custom_query(Parameters(RunCustomQueryParams {
+                query,
+                format: None,
+            }))
+            .await;
+        assert_error(&result, "query_failed", "");
+        assert!(!result.contains("hunter2"));
+
+        // Regular queries against the DB still work, and the setting can't be flipped back
+        let result = server
+            .run_custom_query(Parameters(RunCustomQueryParams {
+                query: "SELECT COUNT(*) AS n FROM records".to_string(),
+                format: None,
+            }))
+            .await;
+        assert!(result.contains("\"n\": 0"), "{}", result);
+        let conn = server.conn.get().unwrap();
+        assert!(conn
+            .execute_batch("SET enable_external_access = true")
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn stdio_session_shuts_down_on_eof() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let server = setup_server();
+        let conn = Arc::downgrade(&server.conn);
+        let (mut client_tx, server_rx) = tokio::io::duplex(64 * 1024);
+        let (server_tx, client_rx) = tokio::io::duplex(64 * 1024);
+        let session = tokio::spawn(serve_until_closed(server, (server_rx, server_tx)));
+
+        client_tx
+            .write_all(
+                concat!(
+                    r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{},"clientInfo":{"name":"test","version":"0"}}}"#,
+                    "\n",
+                    r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+                    "\n",
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        BufReader::new(client_rx)
+            .read_line(&mut response)
+            .await
+            .unwrap();
+        assert!(response.contains("\"id\":1"), "{}", response);
+
+        // Closing the client's end is what the parent process exiting looks like
+        drop(client_tx);
+        tokio::time::timeout(std::time::Duration::from_secs(5), session)
+            .await
+            .expect("session did not shut down on EOF")
+            .unwrap()
+            .unwrap();
+        assert!(conn.upgrade().is_none(), "connection still alive");
+    }
+
+    #[tokio::test]
+    async fn restrict_sensitive_hides_denied_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db_path, _) = setup_db_with_csv(dir.path());
+        {
+            let conn = crate::db::open_db(&db_path).unwrap();
+            conn.execute_batch(
+                "INSERT INTO records VALUES ('rs1', 'HKCategoryTypeIdentifierSexualActivity', NULL, NULL, 'iPhone', '17.0', NULL, '2024-01-01 00:00:00', '2024-01-01 22:00:00', '2024-01-01 22:00:00', 'imp1', NULL, NULL, NULL, 'HKCategoryValueNotApplicable');
+                 INSERT INTO records VALUES ('rh1', 'HKQuantityTypeIdentifierHeartRate', 72.0, 'count/min', 'Apple Watch', '10.0', NULL, '2024-01-01 08:00:00', '2024-01-01 08:00:00', '2024-01-01 08:01:00', 'imp1', NULL, NULL, NULL, NULL);
+                 INSERT INTO ecg_readings VALUES ('ecg1', '2024-01-01 12:00:00', 'Sinus Rhythm', 'Apple Watch', 512.0, NULL, '2.0', 'imp1', NULL, NULL);",
+            )
+            .unwrap();
+        }
+
+        let config = ServerConfig {
+            sensitive_denylist: vec![
+                "ecg_readings".to_string(),
+                "HKCategoryTypeIdentifierSexualActivity".to_string(),
+            ],
+            ..Default::default()
+        };
+        let server = HealthServer::open(&db_path, &config, true).unwrap();
+        let query = |q: &str| {
+            server.run_custom_query(Parameters(RunCustomQueryParams {
+                query: q.to_string(),
+                format: None,
+            }))
+        };
+
+        // Denied table and type are rejected by name
+        let result = query("SELECT * FROM ecg_readings").await;
+        assert_error(&result, "restricted", "");
+        let result = query(
+            "SELECT * FROM records WHERE record_type = 'HKCategoryTypeIdentifierSexualActivity'",
+        )
+        .await;
+        assert_error(&result, "restricted", "");
+        let result = query("SELECT * FROM test.main.records").await;
+        assert_error(&result, "restricted", "");
+
+        // Allowed tables still work, without the denied type's rows
+        let result = query("SELECT record_type FROM records").await;
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(
+            parsed,
+            json!([{"record_type": "HKQuantityTypeIdentifierHeartRate"}])
+        );
+        let result = server.list_record_types(all_record_types()).await;
+        assert!(!result.contains("SexualActivity"), "{}", result);
+
+        // Tools built on the denied table are gone
+        assert!(!server.tool_router.has_route("list_ecg_readings"));
+        assert!(!server.tool_router.has_route("get_ecg_data"));
+        assert!(server.tool_router.has_route("query_records"));
+    }
+
+    #[tokio::test]
+    async fn unrestricted_server_allows_file_access() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db_path, query) = setup_db_with_csv(dir.path());
+
+        let server = HealthServer::new(&db_path).unwrap();
+        let result = server
+            .run_custom_query(Parameters(RunCustomQueryParams {
+                query,
+                format: None,
+            }))
+            .await;
+        assert!(result.contains("hunter2"), "{}", result);
+    }
+
     #[test]
     fn debug_impl() {
         let server = setup_server();
@@ -931,7 +7479,7 @@ mod tests {
         });
         let result = server.list_workouts(params).await;
         let parsed: Value = serde_json::from_str(&result).unwrap();
-        assert_eq!(parsed.as_array().unwrap().len(), 1);
+        assert_eq!(parsed["rows"].as_array().unwrap().len(), 1);
     }
 
     #[tokio::test]
@@ -944,7 +7492,7 @@ mod tests {
         });
         let result = server.get_activity_summaries(params).await;
         let parsed: Value = serde_json::from_str(&result).unwrap();
-        assert_eq!(parsed.as_array().unwrap().len(), 1);
+        assert_eq!(parsed["rows"].as_array().unwrap().len(), 1);
     }
 
     #[tokio::test]
@@ -968,6 +7516,10 @@ mod tests {
                 start_date: None,
                 end_date: None,
                 period: Some(period.to_string()),
+                moving_average_window: None,
+                include_annotations: None,
+                value_mode: None,
+                include_extremes: None,
             });
             let result = server.get_record_statistics(params).await;
             let parsed: Value = serde_json::from_str(&result).unwrap();
@@ -0,0 +1,148 @@
+//! A small pool of DuckDB connections to one database, so a slow `run_custom_query` doesn't
+//! make every other tool call of the session wait for it.
+
+use anyhow::Result;
+use duckdb::Connection;
+use std::ops::Deref;
+use std::sync::{Condvar, Mutex};
+
+/// Connections per file-backed session.
+pub const DEFAULT_POOL_SIZE: usize = 4;
+
+/// Connections to one database instance (`Connection::try_clone`), so they share its
+/// settings and data. Each is lent to one caller at a time; callers wait while all are out.
+pub struct ConnectionPool {
+    idle: Mutex<Vec<Connection>>,
+    returned: Condvar,
+    size: usize,
+}
+
+impl ConnectionPool {
+    /// A pool of `conn` plus `size - 1` clones of it.
+    pub fn new(conn: Connection, size: usize) -> Result<Self> {
+        let mut idle = Vec::with_capacity(size.max(1));
+        for _ in 1..size {
+            idle.push(conn.try_clone()?);
+        }
+        idle.push(conn);
+        Ok(Self {
+            size: idle.len(),
+            idle: Mutex::new(idle),
+            returned: Condvar::new(),
+        })
+    }
+
+    /// A pool lending out the single connection `conn`.
+    pub fn single(conn: Connection) -> Self {
+        Self {
+            idle: Mutex::new(vec![conn]),
+            returned: Condvar::new(),
+            size: 1,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Borrow a connection, waiting for one to be returned if all are in use.
+    pub fn get(&self) -> Result<PooledConnection<'_>, String> {
+        let mut idle = self.idle.lock().map_err(|e| e.to_string())?;
+        loop {
+            if let Some(conn) = idle.pop() {
+                return Ok(PooledConnection {
+                    pool: self,
+                    conn: Some(conn),
+                });
+            }
+            idle = self.returned.wait(idle).map_err(|e| e.to_string())?;
+        }
+    }
+
+    /// Run `f` on every connection, e.g. to set up per-connection temp views. Waits until
+    /// all connections are back in the pool.
+    pub fn for_each(&self, f: impl FnMut(&Connection) -> Result<()>) -> Result<()> {
+        let mut idle = self
+            .idle
+            .lock()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        while idle.len() < self.size {
+            idle = self
+                .returned
+                .wait(idle)
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        }
+        idle.iter().try_for_each(f)
+    }
+}
+
+/// A connection borrowed from a `ConnectionPool`; returned to it on drop.
+pub struct PooledConnection<'a> {
+    pool: &'a ConnectionPool,
+    conn: Option<Connection>,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+            .as_ref()
+            .expect("connection is present until drop")
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if let (Some(conn), Ok(mut idle)) = (self.conn.take(), self.pool.idle.lock()) {
+            idle.push(conn);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::open_db_in_memory;
+
+    #[test]
+    fn pooled_connections_share_the_database() {
+        let pool = ConnectionPool::new(open_db_in_memory().unwrap(), 3).unwrap();
+        assert_eq!(pool.size(), 3);
+
+        let a = pool.get().unwrap();
+        let b = pool.get().unwrap();
+        a.execute_batch("CREATE TABLE t AS SELECT 42 AS x").unwrap();
+        let x: i32 = b
+            .query_row("SELECT x FROM t", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(x, 42);
+        drop((a, b));
+
+        let mut visited = 0;
+        pool.for_each(|_| {
+            visited += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(visited, 3);
+    }
+
+    #[test]
+    fn get_waits_for_a_returned_connection() {
+        let pool = ConnectionPool::single(open_db_in_memory().unwrap());
+        let held = pool.get().unwrap();
+        std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| {
+                let conn = pool.get().unwrap();
+                conn.query_row("SELECT 1", [], |row| row.get::<_, i32>(0))
+                    .unwrap()
+            });
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!waiter.is_finished());
+            drop(held);
+            assert_eq!(waiter.join().unwrap(), 1);
+        });
+    }
+}
@@ -0,0 +1,168 @@
+//! Per-client token buckets for the HTTP transport (`--rate-limit N` requests per minute),
+//! so one misbehaving agent can't monopolise a publicly reachable server.
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Most clients tracked with a bucket of their own.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// With the table full, idle clients (full buckets) are dropped at most this often, so a
+/// flood of addresses doesn't make every request scan the table.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Token buckets keyed by client IP. Each holds up to `per_minute` tokens, refilled
+/// continuously at `per_minute` per minute, so short bursts up to the limit are allowed.
+/// New clients arriving while the table is full and can't be pruned yet share one overflow
+/// bucket until the next prune.
+pub struct RateLimiter {
+    per_minute: u32,
+    max_clients: usize,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    clients: HashMap<IpAddr, (f64, Instant)>,
+    overflow: Option<(f64, Instant)>,
+    last_pruned: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self::with_max_clients(per_minute, MAX_TRACKED_CLIENTS)
+    }
+
+    fn with_max_clients(per_minute: u32, max_clients: usize) -> Self {
+        Self {
+            per_minute: per_minute.max(1),
+            max_clients,
+            buckets: Mutex::new(Buckets {
+                clients: HashMap::new(),
+                overflow: None,
+                last_pruned: None,
+            }),
+        }
+    }
+
+    /// Take a token for `client`. On an empty bucket, returns the seconds until the next
+    /// token is available.
+    pub fn check(&self, client: IpAddr) -> Result<(), u64> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: IpAddr, now: Instant) -> Result<(), u64> {
+        let capacity = self.per_minute as f64;
+        let per_second = capacity / 60.0;
+        let refilled = |(tokens, updated): (f64, Instant)| {
+            (tokens + now.duration_since(updated).as_secs_f64() * per_second).min(capacity)
+        };
+
+        let mut guard = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let state = &mut *guard;
+        if !state.clients.contains_key(&client) && state.clients.len() >= self.max_clients {
+            let prune_due = state
+                .last_pruned
+                .is_none_or(|last| now.duration_since(last) >= PRUNE_INTERVAL);
+            if prune_due {
+                state
+                    .clients
+                    .retain(|_, bucket| refilled(*bucket) < capacity);
+                state.last_pruned = Some(now);
+            }
+        }
+        let bucket =
+            if state.clients.contains_key(&client) || state.clients.len() < self.max_clients {
+                state.clients.entry(client).or_insert((capacity, now))
+            } else {
+                state.overflow.get_or_insert((capacity, now))
+            };
+
+        let tokens = refilled(*bucket);
+        if tokens >= 1.0 {
+            *bucket = (tokens - 1.0, now);
+            Ok(())
+        } else {
+            *bucket = (tokens, now);
+            Err(((1.0 - tokens) / per_second).ceil() as u64)
+        }
+    }
+}
+
+/// Middleware answering `429 Too Many Requests` (with `Retry-After`) once the client's
+/// bucket is empty. Clients are told apart by `ConnectInfo`; without it (e.g. in-process
+/// tests) all requests share one bucket.
+pub async fn limit_requests(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+
+    match limiter.check(client) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            "Rate limit exceeded",
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_refill_per_client() {
+        let limiter = RateLimiter::new(2);
+        let (a, b): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let start = Instant::now();
+
+        assert_eq!(limiter.check_at(a, start), Ok(()));
+        assert_eq!(limiter.check_at(a, start), Ok(()));
+        assert_eq!(limiter.check_at(a, start), Err(30));
+        // Other clients have their own bucket
+        assert_eq!(limiter.check_at(b, start), Ok(()));
+        // One token comes back every 30 seconds
+        assert_eq!(limiter.check_at(a, start + Duration::from_secs(30)), Ok(()));
+        assert!(limiter
+            .check_at(a, start + Duration::from_secs(31))
+            .is_err());
+    }
+
+    #[test]
+    fn tracked_clients_are_bounded() {
+        let limiter = RateLimiter::with_max_clients(2, 3);
+        let client = |i: u8| IpAddr::V4(Ipv4Addr::new(10, 0, 0, i));
+        let start = Instant::now();
+
+        // Three clients fill the table; each drains its bucket so none can be pruned
+        for i in 1..=3 {
+            assert_eq!(limiter.check_at(client(i), start), Ok(()));
+            assert_eq!(limiter.check_at(client(i), start), Ok(()));
+        }
+        // Further clients share the overflow bucket instead of growing the table
+        assert_eq!(limiter.check_at(client(4), start), Ok(()));
+        assert_eq!(limiter.check_at(client(5), start), Ok(()));
+        assert_eq!(limiter.check_at(client(6), start), Err(30));
+        assert_eq!(limiter.buckets.lock().unwrap().clients.len(), 3);
+
+        // Once the prune interval has passed, idle clients make room again
+        let later = start + PRUNE_INTERVAL;
+        assert_eq!(limiter.check_at(client(7), later), Ok(()));
+        let state = limiter.buckets.lock().unwrap();
+        assert_eq!(state.clients.len(), 1);
+        assert!(state.clients.contains_key(&client(7)));
+    }
+}
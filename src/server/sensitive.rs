@@ -0,0 +1,458 @@
+//! `serve --restrict-sensitive`: hide a denylist of tables and record types from sessions.
+//!
+//! Denied record types are filtered out by temp views that shadow `records`,
+//! `record_metadata`, `audiogram_points`, and the `*_record_stats` aggregates, so every tool
+//! sees the filtered data.
+//! Denied tables are shadowed by empty views and the tools built on them are removed.
+//! `run_custom_query` additionally rejects statements that name a denied table or type,
+//! or that could reach the base tables behind the views.
+
+use anyhow::Result;
+use duckdb::Connection;
+
+/// Used when `--restrict-sensitive` is given without `--sensitive`: reproductive and sexual
+/// health types, plus the clinical ECG and CDA tables.
+pub const DEFAULT_SENSITIVE: &[&str] = &[
+    "ecg_readings",
+    "ecg_samples",
+    "clinical_records",
+    "HKCategoryTypeIdentifierMenstrualFlow",
+    "HKCategoryTypeIdentifierIntermenstrualBleeding",
+    "HKCategoryTypeIdentifierInfrequentMenstrualCycles",
+    "HKCategoryTypeIdentifierIrregularMenstrualCycles",
+    "HKCategoryTypeIdentifierPersistentIntermenstrualBleeding",
+    "HKCategoryTypeIdentifierProlongedMenstrualPeriods",
+    "HKCategoryTypeIdentifierOvulationTestResult",
+    "HKCategoryTypeIdentifierCervicalMucusQuality",
+    "HKCategoryTypeIdentifierSexualActivity",
+    "HKCategoryTypeIdentifierContraceptive",
+    "HKCategoryTypeIdentifierPregnancy",
+    "HKCategoryTypeIdentifierPregnancyTestResult",
+    "HKCategoryTypeIdentifierProgesteroneTestResult",
+    "HKCategoryTypeIdentifierLactation",
+    "HKCategoryTypeIdentifierBleedingDuringPregnancy",
+    "HKCategoryTypeIdentifierBleedingAfterPregnancy",
+    "HKQuantityTypeIdentifierBasalBodyTemperature",
+];
+
+/// Tables whose rows are filtered when record types are denied.
+const RECORD_TABLES: &[&str] = &[
+    "records",
+    "record_metadata",
+    "audiogram_points",
+    "daily_record_stats",
+    "weekly_record_stats",
+    "monthly_record_stats",
+];
+
+/// Tables each tool reads; a tool is removed when any of them is denied.
+pub const TOOL_TABLES: &[(&str, &[&str])] = &[
+    ("list_record_types", &["records"]),
+    ("describe_data", &["records"]),
+    ("check_unit_consistency", &["records"]),
+    ("query_records", &["records"]),
+    ("get_record_metadata", &["record_metadata"]),
+    ("list_metadata_keys", &["records", "record_metadata"]),
+    ("get_latest_readings", &["records"]),
+    ("query_window", &["records"]),
+    ("find_data_gaps", &["daily_record_stats"]),
+    ("get_audiogram", &["records", "audiogram_points"]),
+    (
+        "get_record_statistics",
+        &[
+            "daily_record_stats",
+            "weekly_record_stats",
+            "monthly_record_stats",
+            "records",
+        ],
+    ),
+    ("compare_periods", &["daily_record_stats"]),
+    ("get_records_aggregated_by_source_and_day", &["records"]),
+    ("find_outliers", &["records"]),
+    ("get_record_histogram", &["records"]),
+    ("get_record_percentiles", &["records"]),
+    ("get_daily_peaks", &["records"]),
+    ("correlate_metrics", &["daily_record_stats"]),
+    ("get_trend", &["daily_record_stats"]),
+    ("get_source_contribution", &["records"]),
+    (
+        "get_glucose_by_meal_context",
+        &["records", "record_metadata"],
+    ),
+    ("get_heart_rate_by_context", &["records", "record_metadata"]),
+    ("get_sleep_quality", &["records"]),
+    ("get_one_rep_max", &["records", "record_metadata"]),
+    ("list_workout_activity_types", &["workouts"]),
+    ("list_workouts", &["workouts"]),
+    (
+        "get_workout_details",
+        &[
+            "workouts",
+            "workout_events",
+            "workout_statistics",
+            "workout_metadata",
+            "route_points",
+        ],
+    ),
+    (
+        "get_last_workout",
+        &[
+            "workouts",
+            "workout_events",
+            "workout_statistics",
+            "workout_metadata",
+            "route_points",
+            "records",
+        ],
+    ),
+    ("get_workout_heart_rate", &["workouts", "records"]),
+    ("get_heart_rate_zones", &["workouts", "records"]),
+    ("get_power_curve", &["workouts", "records"]),
+    ("get_active_minutes", &["records"]),
+    ("get_workout_route", &["route_points"]),
+    ("get_best_effort", &["workouts", "route_points"]),
+    ("get_workout_splits", &["workouts", "route_points"]),
+    ("validate_routes", &["workouts", "route_points"]),
+    ("get_activity_summaries", &["activity_summaries"]),
+    (
+        "generate_weekly_report",
+        &[
+            "workouts",
+            "daily_record_stats",
+            "records",
+            "activity_summaries",
+        ],
+    ),
+    (
+        "get_daily_activity_trends",
+        &["activity_summaries", "daily_record_stats"],
+    ),
+    ("list_ecg_readings", &["ecg_readings"]),
+    ("get_ecg_data", &["ecg_readings", "ecg_samples"]),
+    ("list_clinical_records", &["clinical_records"]),
+    ("list_data_sources", &["records"]),
+    ("get_import_history", &["imports"]),
+    ("diff_imports", &["records", "workouts", "imports"]),
+    ("add_annotation", &["annotations"]),
+    ("list_annotations", &["annotations"]),
+];
+
+/// A `--sensitive` list split into table names and record types. Entries naming one of
+/// `db::BASE_TABLES` are tables; everything else is a record type.
+#[derive(Debug, Clone, Default)]
+pub struct Denylist {
+    tables: Vec<String>,
+    record_types: Vec<String>,
+}
+
+impl Denylist {
+    pub fn new(names: &[String]) -> Self {
+        let (tables, record_types) = names
+            .iter()
+            .map(|n| n.trim())
+            .filter(|n| !n.is_empty())
+            .partition::<Vec<_>, _>(|n| {
+                crate::db::BASE_TABLES
+                    .iter()
+                    .any(|t| t.eq_ignore_ascii_case(n))
+            });
+        Self {
+            tables: tables.iter().map(|t| t.to_lowercase()).collect(),
+            record_types: record_types.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty() && self.record_types.is_empty()
+    }
+
+    /// Tools to remove from the router because they read a denied table.
+    pub fn denied_tools(&self) -> Vec<&'static str> {
+        TOOL_TABLES
+            .iter()
+            .filter(|(_, tables)| tables.iter().any(|t| self.denies_table(t)))
+            .map(|(tool, _)| *tool)
+            .collect()
+    }
+
+    fn denies_table(&self, name: &str) -> bool {
+        self.tables.iter().any(|t| t.eq_ignore_ascii_case(name))
+    }
+
+    fn denies_type(&self, name: &str) -> bool {
+        self.record_types
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(name))
+    }
+
+    /// Tables replaced by temp views, which must only be reachable unqualified.
+    fn shadowed(&self, name: &str) -> bool {
+        self.denies_table(name)
+            || (!self.record_types.is_empty()
+                && RECORD_TABLES.iter().any(|t| t.eq_ignore_ascii_case(name)))
+    }
+}
+
+/// Create the temp views that hide denied data on this connection. Unqualified (and
+/// `main.`-qualified) names resolve to temp views before the database's own tables.
+pub fn install_views(conn: &Connection, denylist: &Denylist) -> Result<()> {
+    let db: String = conn.query_row("SELECT current_database()", [], |row| row.get(0))?;
+    let base = |table: &str| format!("\"{}\".main.{}", db.replace('"', "\"\""), table);
+
+    let mut sql = String::new();
+    if !denylist.record_types.is_empty() {
+        let types = denylist
+            .record_types
+            .iter()
+            .map(|t| format!("'{}'", t.replace('\'', "''")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        sql.push_str(&format!(
+            "CREATE OR REPLACE TEMP VIEW records AS SELECT * FROM {records} \
+               WHERE COALESCE(record_type, '') NOT IN ({types});
+             CREATE OR REPLACE TEMP VIEW record_metadata AS SELECT * FROM {metadata} \
+               WHERE record_hash NOT IN (SELECT record_hash FROM {records} WHERE record_type IN ({types}));
+             CREATE OR REPLACE TEMP VIEW audiogram_points AS SELECT * FROM {audiogram} \
+               WHERE record_hash NOT IN (SELECT record_hash FROM {records} WHERE record_type IN ({types}));
+             CREATE OR REPLACE TEMP VIEW daily_record_stats AS SELECT * FROM {stats} \
+               WHERE COALESCE(record_type, '') NOT IN ({types});
+             CREATE OR REPLACE TEMP VIEW weekly_record_stats AS SELECT * FROM {weekly} \
+               WHERE COALESCE(record_type, '') NOT IN ({types});
+             CREATE OR REPLACE TEMP VIEW monthly_record_stats AS SELECT * FROM {monthly} \
+               WHERE COALESCE(record_type, '') NOT IN ({types});\n",
+            records = base("records"),
+            metadata = base("record_metadata"),
+            audiogram = base("audiogram_points"),
+            stats = base("daily_record_stats"),
+            weekly = base("weekly_record_stats"),
+            monthly = base("monthly_record_stats"),
+        ));
+    }
+    for table in &denylist.tables {
+        sql.push_str(&format!(
+            "CREATE OR REPLACE TEMP VIEW {} AS SELECT * FROM {} WHERE false;\n",
+            table,
+            base(table)
+        ));
+    }
+    conn.execute_batch(&sql)?;
+    Ok(())
+}
+
+/// Reject a `run_custom_query` statement that names a denied table or record type, reaches a
+/// shadowed table through a qualified name (`health.records`), or uses `query()` /
+/// `query_table()` to run SQL hidden in a string.
+pub fn check_query(sql: &str, denylist: &Denylist) -> Result<(), String> {
+    let tokens = tokenize(sql);
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Ident(name) => {
+                if denylist.denies_table(name) {
+                    return Err(format!("Table {} is restricted on this server", name));
+                }
+                if denylist.denies_type(name) {
+                    return Err(format!("Record type {} is restricted on this server", name));
+                }
+                let called = matches!(tokens.get(i + 1), Some(Token::Punct('(')));
+                if called && (name == "query" || name == "query_table") {
+                    return Err(format!(
+                        "{}() is not allowed while sensitive data is restricted",
+                        name
+                    ));
+                }
+                let qualified = i > 0 && matches!(tokens[i - 1], Token::Punct('.'));
+                if qualified && denylist.shadowed(name) {
+                    return Err(format!(
+                        "Refer to {} without a schema or database prefix while sensitive data is restricted",
+                        name
+                    ));
+                }
+            }
+            Token::Str(value) => {
+                if denylist.denies_type(value) {
+                    return Err(format!(
+                        "Record type {} is restricted on this server",
+                        value
+                    ));
+                }
+            }
+            Token::Punct(_) => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// Identifier or keyword, lowercased unless it was double-quoted.
+    Ident(String),
+    /// String literal contents, including dollar-quoted strings.
+    Str(String),
+    Punct(char),
+}
+
+/// Just enough of a SQL lexer to find identifiers and string literals: comments are skipped,
+/// quoting and `''` / `""` escapes are honored, and numbers are dropped.
+fn tokenize(sql: &str) -> Vec<Token> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    // Contents up to the closing `quote`, treating a doubled quote as an escaped one
+    let quoted = |start: usize, quote: char| -> (String, usize) {
+        let mut out = String::new();
+        let mut j = start;
+        while j < chars.len() {
+            if chars[j] == quote {
+                if chars.get(j + 1) == Some(&quote) {
+                    out.push(quote);
+                    j += 2;
+                    continue;
+                }
+                return (out, j + 1);
+            }
+            out.push(chars[j]);
+            j += 1;
+        }
+        (out, j)
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            _ if c.is_whitespace() => i += 1,
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    i += 1;
+                }
+                i += 2;
+            }
+            '\'' => {
+                let (value, next) = quoted(i + 1, '\'');
+                tokens.push(Token::Str(value));
+                i = next;
+            }
+            '"' => {
+                let (value, next) = quoted(i + 1, '"');
+                tokens.push(Token::Ident(value));
+                i = next;
+            }
+            '$' => {
+                // $tag$ ... $tag$ (tag may be empty); anything else is a parameter like $1
+                let tag_end = (i + 1..chars.len())
+                    .find(|&j| !(chars[j].is_alphanumeric() || chars[j] == '_'))
+                    .unwrap_or(chars.len());
+                if chars.get(tag_end) == Some(&'$')
+                    && !chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())
+                {
+                    let delimiter: String = chars[i..=tag_end].iter().collect();
+                    let rest: String = chars[tag_end + 1..].iter().collect();
+                    let (body, consumed) = match rest.find(&delimiter) {
+                        Some(end) => (
+                            &rest[..end],
+                            rest[..end].chars().count() + delimiter.chars().count(),
+                        ),
+                        None => (rest.as_str(), rest.chars().count()),
+                    };
+                    tokens.push(Token::Str(body.to_string()));
+                    i = tag_end + 1 + consumed;
+                } else {
+                    tokens.push(Token::Punct('$'));
+                    i += 1;
+                }
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                tokens.push(Token::Ident(word.to_lowercase()));
+            }
+            _ if c.is_ascii_digit() => {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                    i += 1;
+                }
+            }
+            _ => {
+                tokens.push(Token::Punct(c));
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn denylist() -> Denylist {
+        Denylist::new(&[
+            "ecg_samples".to_string(),
+            "HKCategoryTypeIdentifierSexualActivity".to_string(),
+        ])
+    }
+
+    #[test]
+    fn tokenize_skips_comments_and_unquotes() {
+        assert_eq!(
+            tokenize("SELECT \"Records\", 'it''s' -- records\n/* ecg */ FROM t WHERE x = $1"),
+            vec![
+                Token::Ident("select".to_string()),
+                Token::Ident("Records".to_string()),
+                Token::Punct(','),
+                Token::Str("it's".to_string()),
+                Token::Ident("from".to_string()),
+                Token::Ident("t".to_string()),
+                Token::Ident("where".to_string()),
+                Token::Ident("x".to_string()),
+                Token::Punct('='),
+                Token::Punct('$'),
+            ]
+        );
+        assert_eq!(
+            tokenize("SELECT $q$a'b$q$"),
+            vec![
+                Token::Ident("select".to_string()),
+                Token::Str("a'b".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn check_query_rules() {
+        let d = denylist();
+        assert!(check_query("SELECT * FROM records", &d).is_ok());
+        assert!(check_query("SELECT r.value FROM records r", &d).is_ok());
+        assert!(check_query("SELECT * FROM ecg_readings", &d).is_ok());
+        // Mentions in comments don't count
+        assert!(check_query("SELECT 1 -- ecg_samples", &d).is_ok());
+
+        assert!(check_query("SELECT * FROM ECG_SAMPLES", &d).is_err());
+        assert!(check_query("SELECT * FROM \"ECG_samples\"", &d).is_err());
+        assert!(check_query(
+            "SELECT * FROM records WHERE record_type = 'HKCategoryTypeIdentifierSexualActivity'",
+            &d
+        )
+        .is_err());
+        assert!(check_query("SELECT * FROM health.main.records", &d).is_err());
+        assert!(check_query("SELECT * FROM query_table('x')", &d).is_err());
+    }
+
+    #[test]
+    fn denied_tools_follow_tables() {
+        let d = denylist();
+        assert_eq!(d.denied_tools(), vec!["get_ecg_data"]);
+        assert!(Denylist::new(&[]).is_empty());
+        assert!(Denylist::new(&["imports".to_string()])
+            .denied_tools()
+            .contains(&"get_import_history"));
+    }
+}
@@ -3,6 +3,18 @@ use serde::Deserialize;
 
 // -- Parameter structs for tools that need them --
 
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ListRecordTypesParams {
+    #[schemars(
+        description = "Only types with at least this many records, to hide rarely-populated ones"
+    )]
+    pub min_count: Option<u64>,
+    #[schemars(
+        description = "Only types whose identifier contains this text (case-insensitive), e.g. HeartRate"
+    )]
+    pub name_contains: Option<String>,
+}
+
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct QueryRecordsParams {
     #[schemars(
@@ -17,6 +29,60 @@ pub struct QueryRecordsParams {
     pub source_name: Option<String>,
     #[schemars(description = "Maximum number of results (default 100, max 1000)")]
     pub limit: Option<u32>,
+    #[schemars(
+        description = "Number of results to skip, for paging through results (newest first; default 0)"
+    )]
+    pub offset: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct QueryWindowParams {
+    #[schemars(description = "Start of the window (ISO 8601, e.g. 2024-01-01 14:45:00)")]
+    pub start_date: String,
+    #[schemars(description = "End of the window (ISO 8601, e.g. 2024-01-01 15:15:00)")]
+    pub end_date: String,
+    #[schemars(
+        description = "Only these record types, e.g. [\"HKQuantityTypeIdentifierHeartRate\"] (default: all)"
+    )]
+    pub record_types: Option<Vec<String>>,
+    #[schemars(description = "Maximum number of results (default 100, max 1000)")]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetRecordMetadataParams {
+    #[schemars(description = "The record hash identifier (from query_records)")]
+    pub record_hash: String,
+    #[schemars(description = "Only return this metadata key, e.g. HKMetadataKeyWasUserEntered")]
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetLatestReadingsParams {
+    #[schemars(
+        description = "Only these record types, e.g. [\"HKQuantityTypeIdentifierHeartRate\", \"HKQuantityTypeIdentifierBodyMass\"] (default: all)"
+    )]
+    pub record_types: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ListMetadataKeysParams {
+    #[schemars(
+        description = "Only list keys of this record type, e.g. HKQuantityTypeIdentifierHeartRate"
+    )]
+    pub record_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetAudiogramParams {
+    #[schemars(
+        description = "Record hash of the audiogram (query_records with record_type HKDataTypeIdentifierAudiogram)"
+    )]
+    pub record_hash: String,
+    #[schemars(
+        description = "Hearing level (dB HL) above which a frequency is flagged (default 25, the usual upper limit of normal hearing)"
+    )]
+    pub threshold_db: Option<f64>,
 }
 
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
@@ -29,6 +95,212 @@ pub struct GetRecordStatisticsParams {
     pub end_date: Option<String>,
     #[schemars(description = "Aggregation period: day, week, month, or year (default: day)")]
     pub period: Option<String>,
+    #[schemars(
+        description = "Add a moving_avg column: trailing average of avg_value over this many periods (e.g. 7 with period=day)"
+    )]
+    pub moving_average_window: Option<u32>,
+    #[schemars(
+        description = "Add an annotations array to each period with the annotations (see list_annotations) overlapping it"
+    )]
+    pub include_annotations: Option<bool>,
+    #[schemars(
+        description = "What to aggregate: value (default) or duration_seconds (end_date - start_date of each record, for category records such as mindful sessions)"
+    )]
+    pub value_mode: Option<String>,
+    #[schemars(
+        description = "Add max_value_date and min_value_date: start_date of the record with the period's extreme value (earliest on ties). Slower, as it reads records"
+    )]
+    pub include_extremes: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ComparePeriodsParams {
+    #[schemars(description = "The health record type, e.g. HKQuantityTypeIdentifierStepCount")]
+    pub record_type: String,
+    #[schemars(
+        description = "Granularity for avg_per_period: day, week, month, or year (default: day)"
+    )]
+    pub period: Option<String>,
+    #[schemars(description = "Start of the baseline window (YYYY-MM-DD, inclusive)")]
+    pub period_a_start: String,
+    #[schemars(description = "End of the baseline window (YYYY-MM-DD, inclusive)")]
+    pub period_a_end: String,
+    #[schemars(
+        description = "Start of the window compared to the baseline (YYYY-MM-DD, inclusive)"
+    )]
+    pub period_b_start: String,
+    #[schemars(description = "End of the window compared to the baseline (YYYY-MM-DD, inclusive)")]
+    pub period_b_end: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct FindDataGapsParams {
+    #[schemars(description = "The health record type, e.g. HKQuantityTypeIdentifierHeartRate")]
+    pub record_type: String,
+    #[schemars(
+        description = "First day to check (YYYY-MM-DD, inclusive; default: the type's first day with data)"
+    )]
+    pub start_date: Option<String>,
+    #[schemars(
+        description = "Last day to check (YYYY-MM-DD, inclusive; default: the type's last day with data)"
+    )]
+    pub end_date: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetSourceContributionParams {
+    #[schemars(description = "The health record type, e.g. HKQuantityTypeIdentifierStepCount")]
+    pub record_type: String,
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetRecordsBySourceAndDayParams {
+    #[schemars(description = "The health record type, e.g. HKQuantityTypeIdentifierStepCount")]
+    pub record_type: String,
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct FindOutliersParams {
+    #[schemars(
+        description = "The health record type, e.g. HKQuantityTypeIdentifierRestingHeartRate"
+    )]
+    pub record_type: String,
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+    #[schemars(
+        description = "Flag records more than this many standard deviations from the mean (default 3.0)"
+    )]
+    pub z_threshold: Option<f64>,
+    #[schemars(
+        description = "Maximum number of outliers to return, most extreme first (default 100, max 1000)"
+    )]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetRecordHistogramParams {
+    #[schemars(description = "The health record type, e.g. HKQuantityTypeIdentifierHeartRate")]
+    pub record_type: String,
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+    #[schemars(description = "Number of equal-width buckets (default 20, max 200)")]
+    pub bucket_count: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetRecordPercentilesParams {
+    #[schemars(description = "The health record type, e.g. HKQuantityTypeIdentifierHeartRate")]
+    pub record_type: String,
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+    #[schemars(
+        description = "Percentiles to compute, each between 0 and 100 (default [50, 90, 95, 99], max 20 entries)"
+    )]
+    pub percentiles: Option<Vec<f64>>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetDailyPeaksParams {
+    #[schemars(description = "The health record type, e.g. HKQuantityTypeIdentifierHeartRate")]
+    pub record_type: String,
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+    #[schemars(
+        description = "Maximum number of days to return, newest first (default 30, max 365)"
+    )]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct CorrelateMetricsParams {
+    #[schemars(description = "First record type, e.g. HKQuantityTypeIdentifierRestingHeartRate")]
+    pub record_type_a: String,
+    #[schemars(description = "Second record type, e.g. HKQuantityTypeIdentifierBodyMass")]
+    pub record_type_b: String,
+    #[schemars(description = "Aggregation period: day, week, or month (default: day)")]
+    pub period: Option<String>,
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetTrendParams {
+    #[schemars(description = "The health record type, e.g. HKQuantityTypeIdentifierBodyMass")]
+    pub record_type: String,
+    #[schemars(description = "Aggregation period: day, week, month, or year (default: day)")]
+    pub period: Option<String>,
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+    #[schemars(
+        description = "Number of periods after the last one to project the fitted line over (default 3, max 100)"
+    )]
+    pub forecast_periods: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetGlucoseByMealContextParams {
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetHeartRateByContextParams {
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetSleepQualityParams {
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+    #[schemars(
+        description = "Only use sleep data from this source, e.g. Apple Watch (avoids double counting when several devices track sleep)"
+    )]
+    pub source_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetOneRepMaxParams {
+    #[schemars(
+        description = "Exercise name as logged in record metadata, e.g. Bench Press (case-insensitive)"
+    )]
+    pub exercise: String,
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+    #[schemars(description = "Metadata key holding the exercise name (default: exercise)")]
+    pub exercise_key: Option<String>,
+    #[schemars(description = "Metadata key holding the set weight (default: weight)")]
+    pub weight_key: Option<String>,
+    #[schemars(description = "Metadata key holding the rep count (default: reps)")]
+    pub reps_key: Option<String>,
 }
 
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
@@ -49,6 +321,96 @@ pub struct GetWorkoutDetailsParams {
     pub workout_hash: String,
 }
 
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetLastWorkoutParams {
+    #[schemars(
+        description = "Only consider this workout activity type, e.g. HKWorkoutActivityTypeRunning (default: any)"
+    )]
+    pub activity_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetWorkoutHeartRateParams {
+    #[schemars(description = "The workout hash identifier")]
+    pub workout_hash: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetPowerCurveParams {
+    #[schemars(description = "The workout hash identifier")]
+    pub workout_hash: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetHeartRateZonesParams {
+    #[schemars(description = "The workout hash identifier")]
+    pub workout_hash: String,
+    #[schemars(
+        description = "Ascending zone boundaries in bpm; N boundaries give N+1 zones (below the first, between each pair, at or above the last). Default: 60/70/80/90% of max_heart_rate (5 zones)"
+    )]
+    pub boundaries: Option<Vec<f64>>,
+    #[schemars(
+        description = "Maximum heart rate used for the default boundaries (default: highest heart rate on record)"
+    )]
+    pub max_heart_rate: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetActiveMinutesParams {
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+    #[schemars(
+        description = "Heart rate (bpm) at or above which a minute counts as active. Default: intensity × max_heart_rate"
+    )]
+    pub threshold_bpm: Option<f64>,
+    #[schemars(
+        description = "Maximum heart rate for the default threshold (default: highest heart rate on record)"
+    )]
+    pub max_heart_rate: Option<f64>,
+    #[schemars(
+        description = "Fraction of max heart rate for the default threshold (default 0.64, the lower bound of moderate intensity)"
+    )]
+    pub intensity: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetBestEffortParams {
+    #[schemars(description = "Target distance in meters, e.g. 5000 for a 5K")]
+    pub distance_m: f64,
+    #[schemars(
+        description = "Workout activity type to search (default: HKWorkoutActivityTypeRunning)"
+    )]
+    pub activity_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetWorkoutSplitsParams {
+    #[schemars(description = "The workout hash identifier")]
+    pub workout_hash: String,
+    #[schemars(description = "Split length in meters (default: 1000; 1609.344 for mile splits)")]
+    pub split_distance_m: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ValidateRoutesParams {
+    #[schemars(
+        description = "Only check workouts of this activity type, e.g. HKWorkoutActivityTypeRunning"
+    )]
+    pub activity_type: Option<String>,
+    #[schemars(description = "Only check workouts starting on or after this date (YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(
+        description = "Only check workouts starting before the end of this date (YYYY-MM-DD)"
+    )]
+    pub end_date: Option<String>,
+    #[schemars(
+        description = "Flag segments faster than this many m/s instead of the per-activity default"
+    )]
+    pub max_speed_mps: Option<f64>,
+}
+
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct GetActivitySummariesParams {
     #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
@@ -59,10 +421,42 @@ pub struct GetActivitySummariesParams {
     pub limit: Option<u32>,
 }
 
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetDailyActivityTrendsParams {
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+    #[schemars(description = "Maximum number of days (default 30)")]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GenerateWeeklyReportParams {
+    #[schemars(
+        description = "Last day of the week to report on (YYYY-MM-DD); the week is it and the 6 days before"
+    )]
+    pub week_ending: String,
+}
+
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct GetWorkoutRouteParams {
     #[schemars(description = "The workout hash identifier")]
     pub workout_hash: String,
+    #[schemars(
+        description = "Only points at or after this time (YYYY-MM-DD HH:MM:SS, UTC as in list_workouts)"
+    )]
+    pub start_time: Option<String>,
+    #[schemars(description = "Only points at or before this time (YYYY-MM-DD HH:MM:SS)")]
+    pub end_time: Option<String>,
+    #[schemars(
+        description = "Maximum number of points to return (default: all, up to the row cap of 10,000)"
+    )]
+    pub limit: Option<u32>,
+    #[schemars(
+        description = "Number of points to skip, for paging through a long route in time order (default 0)"
+    )]
+    pub offset: Option<u32>,
 }
 
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
@@ -73,14 +467,68 @@ pub struct ListEcgReadingsParams {
     pub end_date: Option<String>,
 }
 
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ListClinicalRecordsParams {
+    #[schemars(
+        description = "Only this record type: a HealthKit identifier or an observation code such as LOINC 2345-7"
+    )]
+    pub record_type: Option<String>,
+    #[schemars(description = "Start date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "End date filter (ISO 8601 / YYYY-MM-DD)")]
+    pub end_date: Option<String>,
+    #[schemars(description = "Maximum rows to return (default: 100, max: 1000)")]
+    pub limit: Option<u32>,
+}
+
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct GetEcgDataParams {
     #[schemars(description = "The ECG hash identifier")]
     pub ecg_hash: String,
+    #[schemars(
+        description = "Return at most this many samples by keeping every Nth one (default: all, ~15,000 for a 30 s recording)"
+    )]
+    pub max_samples: Option<u32>,
 }
 
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct RunCustomQueryParams {
     #[schemars(description = "A read-only SQL query (must start with SELECT or WITH)")]
     pub query: String,
+    #[schemars(
+        description = "Output format: json (default, one JSON array) or ndjson (one JSON object per line)"
+    )]
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct DiffImportsParams {
+    #[schemars(description = "First import id (from get_import_history)")]
+    pub import_id_a: String,
+    #[schemars(description = "Second import id (from get_import_history)")]
+    pub import_id_b: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct AddAnnotationParams {
+    #[schemars(description = "Short label to group annotations by, e.g. medication or travel")]
+    pub tag: String,
+    #[schemars(description = "First day the annotation applies to (YYYY-MM-DD)")]
+    pub start_date: String,
+    #[schemars(
+        description = "Last day the annotation applies to (YYYY-MM-DD, default: start_date)"
+    )]
+    pub end_date: Option<String>,
+    #[schemars(description = "Free-text note, e.g. started 10 mg in the morning")]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ListAnnotationsParams {
+    #[schemars(description = "Only annotations with this tag")]
+    pub tag: Option<String>,
+    #[schemars(description = "Only annotations ending on or after this date (YYYY-MM-DD)")]
+    pub start_date: Option<String>,
+    #[schemars(description = "Only annotations starting on or before this date (YYYY-MM-DD)")]
+    pub end_date: Option<String>,
 }
@@ -0,0 +1,170 @@
+//! `import --timezone`: the user's timezone, used to decide which calendar day a timestamp
+//! (converted to UTC at import, see `clean_date` in `import/xml.rs`) belongs to when building
+//! `daily_record_stats`, so a workout at 11pm local time isn't counted on the next day.
+
+use anyhow::Result;
+use chrono::{Offset, TimeZone};
+
+/// Granularity of the daylight-saving transition scan; real transitions fall on it.
+const TRANSITION_STEP_SECS: i64 = 15 * 60;
+
+/// Timezone for day bucketing: a fixed offset or an IANA zone with daylight saving time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DayTimezone {
+    /// Seconds east of UTC, from `+02:00`, `-0500`, `+5`, or `UTC`.
+    Fixed(i32),
+    /// IANA zone such as `America/New_York`.
+    Named(chrono_tz::Tz),
+}
+
+impl DayTimezone {
+    /// Parse an IANA name (`Europe/Stockholm`) or a fixed offset (`+02:00`, `-0500`, `+5`,
+    /// `UTC`).
+    pub fn parse(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        if spec.eq_ignore_ascii_case("UTC") || spec == "Z" {
+            return Ok(DayTimezone::Fixed(0));
+        }
+        if let Some(sign) = spec.chars().next().filter(|c| *c == '+' || *c == '-') {
+            return parse_offset(&spec[1..])
+                .map(|secs| DayTimezone::Fixed(if sign == '-' { -secs } else { secs }))
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Invalid UTC offset {:?}; expected e.g. +02:00, -0500, or +5",
+                        spec
+                    )
+                });
+        }
+        spec.parse::<chrono_tz::Tz>()
+            .map(DayTimezone::Named)
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "Unknown timezone {:?}; expected an IANA name like Europe/Stockholm or an offset like +02:00",
+                    spec
+                )
+            })
+    }
+
+    /// Offset from UTC in seconds at `epoch` seconds.
+    fn offset_at(&self, epoch: i64) -> i32 {
+        match self {
+            DayTimezone::Fixed(secs) => *secs,
+            DayTimezone::Named(tz) => chrono::DateTime::from_timestamp(epoch, 0)
+                .map(|utc| {
+                    tz.offset_from_utc_datetime(&utc.naive_utc())
+                        .fix()
+                        .local_minus_utc()
+                })
+                .unwrap_or(0),
+        }
+    }
+
+    /// SQL expression for this timezone's offset in seconds at the UTC timestamp `column`.
+    /// For a named zone, `span` (earliest and latest epoch seconds the column holds) bounds
+    /// the daylight-saving transitions spelled out as a `CASE`.
+    pub fn offset_seconds_sql(&self, column: &str, span: Option<(i64, i64)>) -> String {
+        let Some((start, end)) = span.filter(|_| matches!(self, DayTimezone::Named(_))) else {
+            return self
+                .offset_at(span.map_or(0, |(start, _)| start))
+                .to_string();
+        };
+
+        let mut current = self.offset_at(start);
+        let mut whens = String::new();
+        let mut t = start - start.rem_euclid(TRANSITION_STEP_SECS);
+        while t <= end {
+            t += TRANSITION_STEP_SECS;
+            let offset = self.offset_at(t);
+            if offset != current {
+                let at = chrono::DateTime::from_timestamp(t, 0)
+                    .map(|utc| utc.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default();
+                whens.push_str(&format!(" WHEN {column} < TIMESTAMP '{at}' THEN {current}"));
+                current = offset;
+            }
+        }
+        if whens.is_empty() {
+            current.to_string()
+        } else {
+            format!("(CASE{whens} ELSE {current} END)")
+        }
+    }
+}
+
+impl std::fmt::Display for DayTimezone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DayTimezone::Fixed(secs) => {
+                let sign = if *secs < 0 { '-' } else { '+' };
+                let secs = secs.unsigned_abs();
+                write!(f, "{}{:02}:{:02}", sign, secs / 3600, secs % 3600 / 60)
+            }
+            DayTimezone::Named(tz) => f.write_str(tz.name()),
+        }
+    }
+}
+
+/// `HH:MM`, `HHMM`, or `H[H]` after the sign, as seconds; at most 14 hours.
+fn parse_offset(s: &str) -> Option<i32> {
+    let (hours, minutes) = match s.split_once(':') {
+        Some((h, m)) => (h, m),
+        None if s.len() == 4 => s.split_at(2),
+        None => (s, "0"),
+    };
+    if hours.is_empty() || hours.len() > 2 || !s.chars().all(|c| c.is_ascii_digit() || c == ':') {
+        return None;
+    }
+    let (hours, minutes): (i32, i32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours <= 14 && minutes < 60).then_some(hours * 3600 + minutes * 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_offsets_and_names() {
+        assert_eq!(DayTimezone::parse("UTC").unwrap(), DayTimezone::Fixed(0));
+        assert_eq!(
+            DayTimezone::parse("+02:00").unwrap(),
+            DayTimezone::Fixed(7200)
+        );
+        assert_eq!(
+            DayTimezone::parse("-0530").unwrap(),
+            DayTimezone::Fixed(-19800)
+        );
+        assert_eq!(
+            DayTimezone::parse("-5").unwrap(),
+            DayTimezone::Fixed(-18000)
+        );
+        assert_eq!(
+            DayTimezone::parse("America/New_York").unwrap(),
+            DayTimezone::Named(chrono_tz::America::New_York)
+        );
+        assert!(DayTimezone::parse("+25:00").is_err());
+        assert!(DayTimezone::parse("Mars/Olympus_Mons").is_err());
+
+        assert_eq!(DayTimezone::Fixed(-19800).to_string(), "-05:30");
+        assert_eq!(
+            DayTimezone::Named(chrono_tz::Europe::Stockholm).to_string(),
+            "Europe/Stockholm"
+        );
+    }
+
+    #[test]
+    fn named_zone_spells_out_dst_transitions() {
+        let tz = DayTimezone::parse("America/New_York").unwrap();
+        // 2024-01-01 to 2024-12-31: DST starts 2024-03-10 07:00 UTC, ends 2024-11-03 06:00 UTC
+        let sql = tz.offset_seconds_sql("start_date", Some((1_704_067_200, 1_735_603_200)));
+        assert_eq!(
+            sql,
+            "(CASE WHEN start_date < TIMESTAMP '2024-03-10 07:00:00' THEN -18000 \
+             WHEN start_date < TIMESTAMP '2024-11-03 06:00:00' THEN -14400 ELSE -18000 END)"
+        );
+
+        assert_eq!(
+            DayTimezone::Fixed(3600).offset_seconds_sql("start_date", None),
+            "3600"
+        );
+    }
+}
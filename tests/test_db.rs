@@ -1,4 +1,6 @@
-use apple_health_mcp::db::{open_db, open_db_readonly};
+use apple_health_mcp::db::{
+    open_db, open_db_readonly, open_db_readonly_with_options, open_db_with_options, DbOptions,
+};
 
 #[test]
 fn open_db_creates_file() {
@@ -50,3 +52,37 @@ fn open_db_readonly_rejects_writes() {
     let result = conn.execute_batch("INSERT INTO test VALUES (1)");
     assert!(result.is_err());
 }
+
+#[test]
+fn open_db_applies_db_options() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.duckdb");
+    let setting = |conn: &duckdb::Connection, name: &str| -> String {
+        conn.query_row(
+            &format!("SELECT CAST(current_setting('{}') AS VARCHAR)", name),
+            [],
+            |row| row.get(0),
+        )
+        .unwrap()
+    };
+
+    // Defaults: four threads for the import connection
+    {
+        let conn = open_db(&db_path).unwrap();
+        assert_eq!(setting(&conn, "threads"), "4");
+    }
+
+    let options = DbOptions {
+        threads: Some(2),
+        memory_limit: Some("512MB".to_string()),
+    };
+    {
+        let conn = open_db_with_options(&db_path, &options).unwrap();
+        assert_eq!(setting(&conn, "threads"), "2");
+        // DuckDB reports the limit in its own units (512MB = 488.2 MiB)
+        assert_eq!(setting(&conn, "memory_limit"), "488.2 MiB");
+    }
+
+    let conn = open_db_readonly_with_options(&db_path, &options).unwrap();
+    assert_eq!(setting(&conn, "threads"), "2");
+}
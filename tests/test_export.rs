@@ -0,0 +1,377 @@
+mod common;
+
+use apple_health_mcp::db::open_db_in_memory;
+use apple_health_mcp::export::{
+    dump_database, export_all_ecg_csv, export_ecg_csv, export_records_csv, export_route_gpx,
+    export_sqlite, export_workout_tcx,
+};
+use apple_health_mcp::import::ecg::import_ecg_files;
+use apple_health_mcp::import::gpx::import_gpx_files;
+use apple_health_mcp::import::xml::import_xml;
+use apple_health_mcp::models::ImportStats;
+use std::collections::HashMap;
+
+fn setup_imported_db() -> (duckdb::Connection, tempfile::TempDir) {
+    let conn = common::setup_test_db();
+    let dir = tempfile::tempdir().unwrap();
+    let xml_path = dir.path().join("export.xml");
+    std::fs::write(&xml_path, common::MINIMAL_XML).unwrap();
+    import_xml(&conn, &xml_path, "test_import").unwrap();
+    (conn, dir)
+}
+
+#[test]
+fn export_heart_rate_csv() {
+    let (conn, dir) = setup_imported_db();
+    let out = dir.path().join("heart_rate.csv");
+
+    let rows =
+        export_records_csv(&conn, "HKQuantityTypeIdentifierHeartRate", None, None, &out).unwrap();
+    assert_eq!(rows, 1);
+
+    let csv = std::fs::read_to_string(&out).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "record_type,value,unit,source_name,source_version,device,start_date,end_date"
+    );
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].starts_with("HKQuantityTypeIdentifierHeartRate,72.0,count/min,Apple Watch"));
+}
+
+#[test]
+fn export_with_date_filter() {
+    let (conn, dir) = setup_imported_db();
+    let out = dir.path().join("heart_rate.csv");
+
+    let rows = export_records_csv(
+        &conn,
+        "HKQuantityTypeIdentifierHeartRate",
+        Some("2024-02-01"),
+        None,
+        &out,
+    )
+    .unwrap();
+    assert_eq!(rows, 0);
+
+    // Header is still written for an empty result
+    let csv = std::fs::read_to_string(&out).unwrap();
+    assert_eq!(csv.lines().count(), 1);
+}
+
+#[test]
+fn export_unknown_record_type() {
+    let (conn, dir) = setup_imported_db();
+    let out = dir.path().join("missing.csv");
+
+    let result = export_records_csv(&conn, "HKQuantityTypeIdentifierNope", None, None, &out);
+    assert!(result.unwrap_err().to_string().contains("not found"));
+    assert!(!out.exists());
+}
+
+#[test]
+fn export_route_gpx_round_trip() {
+    let conn = common::setup_test_db();
+    let dir = tempfile::tempdir().unwrap();
+
+    let routes_dir = dir.path().join("workout-routes");
+    std::fs::create_dir_all(&routes_dir).unwrap();
+    std::fs::write(routes_dir.join("route.gpx"), common::MINIMAL_GPX).unwrap();
+    let map = HashMap::from([("/workout-routes/route.gpx".to_string(), "wh1".to_string())]);
+    import_gpx_files(
+        &conn,
+        &routes_dir,
+        "imp1",
+        &map,
+        false,
+        &mut ImportStats::default(),
+    )
+    .unwrap();
+
+    let out_dir = dir.path().join("exported");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let out = out_dir.join("wh1.gpx");
+    let written = export_route_gpx(&conn, "wh1", &out).unwrap();
+    assert_eq!(written, 2);
+
+    let gpx = std::fs::read_to_string(&out).unwrap();
+    assert!(gpx.contains("<time>2024-01-01T10:00:00Z</time>"));
+    assert!(gpx.contains("<speed>3.5</speed>"));
+
+    // Re-import the exported file into a fresh DB
+    let fresh = common::setup_test_db();
+    let mut stats = ImportStats::default();
+    let count =
+        import_gpx_files(&fresh, &out_dir, "imp2", &HashMap::new(), false, &mut stats).unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(stats.gpx_files_failed, 0);
+
+    let (speed, course): (f64, f64) = fresh
+        .query_row(
+            "SELECT speed, course FROM route_points ORDER BY timestamp LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!(speed, 3.5);
+    assert_eq!(course, 180.0);
+}
+
+#[test]
+fn export_route_gpx_no_points() {
+    let conn = common::setup_test_db();
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("none.gpx");
+
+    let result = export_route_gpx(&conn, "missing", &out);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("has no route points"));
+    assert!(!out.exists());
+}
+
+#[test]
+fn dump_database_round_trip() {
+    let (conn, dir) = setup_imported_db();
+    let dump_dir = dir.path().join("dump");
+
+    dump_database(&conn, &dump_dir).unwrap();
+    assert!(dump_dir.join("schema.sql").exists());
+    assert!(dump_dir.join("load.sql").exists());
+
+    let fresh = open_db_in_memory().unwrap();
+    fresh
+        .execute_batch(&format!("IMPORT DATABASE '{}'", dump_dir.display()))
+        .unwrap();
+
+    for table in [
+        "records",
+        "record_metadata",
+        "workouts",
+        "workout_events",
+        "workout_statistics",
+        "workout_metadata",
+        "activity_summaries",
+    ] {
+        let query = format!("SELECT COUNT(*) FROM {}", table);
+        let original: i64 = conn.query_row(&query, [], |row| row.get(0)).unwrap();
+        let restored: i64 = fresh.query_row(&query, [], |row| row.get(0)).unwrap();
+        assert_eq!(original, restored, "row count mismatch for {}", table);
+    }
+}
+
+#[test]
+fn dump_database_refuses_non_empty_dir() {
+    let (conn, dir) = setup_imported_db();
+
+    // dir already contains export.xml
+    let result = dump_database(&conn, dir.path());
+    assert!(result.unwrap_err().to_string().contains("non-empty"));
+}
+
+/// Seed a workout with the minimal GPX route attached, returning its hash.
+fn setup_workout_with_route(conn: &duckdb::Connection, dir: &std::path::Path) -> String {
+    let xml_path = dir.join("export.xml");
+    std::fs::write(&xml_path, common::MINIMAL_XML).unwrap();
+    import_xml(conn, &xml_path, "imp1").unwrap();
+    let workout_hash: String = conn
+        .query_row("SELECT workout_hash FROM workouts", [], |row| row.get(0))
+        .unwrap();
+
+    let routes_dir = dir.join("workout-routes");
+    std::fs::create_dir_all(&routes_dir).unwrap();
+    std::fs::write(routes_dir.join("route.gpx"), common::MINIMAL_GPX).unwrap();
+    let map = HashMap::from([(
+        "/workout-routes/route.gpx".to_string(),
+        workout_hash.clone(),
+    )]);
+    import_gpx_files(
+        conn,
+        &routes_dir,
+        "imp1",
+        &map,
+        false,
+        &mut ImportStats::default(),
+    )
+    .unwrap();
+    workout_hash
+}
+
+/// Count `<Trackpoint>` and `<HeartRateBpm>` elements in a TCX document.
+fn count_tcx_elements(tcx: &str) -> (usize, usize) {
+    let mut reader = quick_xml::Reader::from_str(tcx);
+    let (mut trackpoints, mut heart_rates) = (0, 0);
+    loop {
+        match reader.read_event().unwrap() {
+            quick_xml::events::Event::Start(e) => match e.name().as_ref() {
+                b"Trackpoint" => trackpoints += 1,
+                b"HeartRateBpm" => heart_rates += 1,
+                _ => {}
+            },
+            quick_xml::events::Event::Eof => break,
+            _ => {}
+        }
+    }
+    (trackpoints, heart_rates)
+}
+
+#[test]
+fn export_workout_tcx_with_heart_rate() {
+    let conn = common::setup_test_db();
+    let dir = tempfile::tempdir().unwrap();
+    let workout_hash = setup_workout_with_route(&conn, dir.path());
+
+    // HR sample at the start of the workout (10:00-10:30); the fixture's 08:00 sample is outside it
+    conn.execute_batch(
+        "INSERT INTO records VALUES ('hr_w1', 'HKQuantityTypeIdentifierHeartRate', 148.6, 'count/min', 'Apple Watch', NULL, NULL, NULL, \
+         '2024-01-01 10:00:00', '2024-01-01 10:00:00', 'imp1', NULL, NULL, NULL, NULL);",
+    )
+    .unwrap();
+
+    let out = dir.path().join("workout.tcx");
+    let written = export_workout_tcx(&conn, &workout_hash, &out).unwrap();
+    assert_eq!(written, 2);
+
+    let tcx = std::fs::read_to_string(&out).unwrap();
+    assert_eq!(count_tcx_elements(&tcx), (2, 2));
+    assert!(tcx.contains("<Activity Sport=\"Running\">"));
+    assert!(tcx.contains("<Time>2024-01-01T10:00:05Z</Time>"));
+    assert!(tcx.contains("<HeartRateBpm><Value>149</Value></HeartRateBpm>"));
+}
+
+#[test]
+fn export_workout_tcx_without_heart_rate() {
+    let conn = common::setup_test_db();
+    let dir = tempfile::tempdir().unwrap();
+    let workout_hash = setup_workout_with_route(&conn, dir.path());
+
+    let out = dir.path().join("workout.tcx");
+    export_workout_tcx(&conn, &workout_hash, &out).unwrap();
+
+    let tcx = std::fs::read_to_string(&out).unwrap();
+    assert_eq!(count_tcx_elements(&tcx), (2, 0));
+}
+
+#[test]
+fn export_workout_tcx_unknown_workout() {
+    let conn = common::setup_test_db();
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("none.tcx");
+
+    let err = export_workout_tcx(&conn, "missing", &out).unwrap_err();
+    assert!(err.to_string().contains("not found"));
+    assert!(!out.exists());
+}
+
+/// Import the minimal ECG fixture, returning its hash.
+fn setup_ecg(conn: &duckdb::Connection, dir: &std::path::Path) -> String {
+    let ecg_dir = dir.join("electrocardiograms");
+    std::fs::create_dir_all(&ecg_dir).unwrap();
+    std::fs::write(ecg_dir.join("ecg_2024-06-15.csv"), common::MINIMAL_ECG_CSV).unwrap();
+    import_ecg_files(conn, &ecg_dir, "imp1", &mut ImportStats::default()).unwrap();
+    conn.query_row("SELECT ecg_hash FROM ecg_readings", [], |row| row.get(0))
+        .unwrap()
+}
+
+#[test]
+fn export_ecg_csv_matches_samples() {
+    let conn = common::setup_test_db();
+    let dir = tempfile::tempdir().unwrap();
+    let ecg_hash = setup_ecg(&conn, dir.path());
+
+    let out = dir.path().join("ecg.csv");
+    let written = export_ecg_csv(&conn, &ecg_hash, &out).unwrap();
+    let in_db: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM ecg_samples WHERE ecg_hash = ?",
+            [&ecg_hash],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(written, in_db as u64);
+
+    let csv = std::fs::read_to_string(&out).unwrap();
+    assert!(csv.contains("# classification: Sinus Rhythm\n"));
+    assert!(csv.contains("# sample_rate_hz: 512\n"));
+    let mut data = csv.lines().skip_while(|l| l.starts_with('#'));
+    assert_eq!(data.next(), Some("sample_idx,voltage_uv"));
+    let rows: Vec<&str> = data.collect();
+    assert_eq!(rows.len() as i64, in_db);
+    assert_eq!(rows[2], "2,-50");
+
+    let all_dir = dir.path().join("all");
+    assert_eq!(
+        export_all_ecg_csv(&conn, &all_dir).unwrap(),
+        (1, in_db as u64)
+    );
+    assert!(all_dir.join(format!("{}.csv", ecg_hash)).exists());
+}
+
+#[test]
+fn export_ecg_csv_unknown_hash() {
+    let conn = common::setup_test_db();
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("none.csv");
+
+    let err = export_ecg_csv(&conn, "missing", &out).unwrap_err();
+    assert!(err.to_string().contains("ECG reading missing not found"));
+    assert!(!out.exists());
+}
+
+#[test]
+fn export_sqlite_refuses_to_overwrite() {
+    let (conn, dir) = setup_imported_db();
+    let existing = dir.path().join("existing.sqlite");
+    std::fs::write(&existing, "keep me").unwrap();
+    let err = export_sqlite(&conn, &existing).unwrap_err();
+    assert!(
+        err.to_string().starts_with("Refusing to overwrite"),
+        "{}",
+        err
+    );
+    assert_eq!(std::fs::read_to_string(&existing).unwrap(), "keep me");
+}
+
+#[test]
+fn export_sqlite_copies_tables() {
+    // The extension is downloaded on first use; the copy itself is covered offline by
+    // export.rs's own tests
+    if let Err(e) = open_db_in_memory()
+        .unwrap()
+        .execute_batch("INSTALL sqlite; LOAD sqlite;")
+    {
+        eprintln!(
+            "skipping export_sqlite_copies_tables: can't load DuckDB's sqlite extension: {e}"
+        );
+        return;
+    }
+    let (conn, dir) = setup_imported_db();
+    let out = dir.path().join("health.sqlite");
+    let tables = export_sqlite(&conn, &out).unwrap();
+    assert!(tables > 0);
+
+    // Read the file back through a fresh connection
+    let check = open_db_in_memory().unwrap();
+    check
+        .execute_batch(&format!(
+            "LOAD sqlite; ATTACH '{}' AS exported (TYPE SQLITE, READ_ONLY)",
+            out.display()
+        ))
+        .unwrap();
+    let count = |conn: &duckdb::Connection, table: &str| -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    };
+    assert_eq!(count(&check, "exported.records"), count(&conn, "records"));
+    let start_date: String = check
+        .query_row(
+            "SELECT start_date FROM exported.records ORDER BY start_date LIMIT 1",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert!(start_date.starts_with("2024-"), "{}", start_date);
+}
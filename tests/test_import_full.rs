@@ -1,10 +1,15 @@
 mod common;
 
-use apple_health_mcp::db::{deduplicate_tables, ensure_schema, open_db, rebuild_daily_stats};
+use apple_health_mcp::db::{
+    compact_db, deduplicate_tables, ensure_schema, open_db, purge_import, rebuild_daily_stats,
+};
+use apple_health_mcp::import::downsample::parse_downsample_rule;
 use apple_health_mcp::import::ecg::import_ecg_files;
 use apple_health_mcp::import::gpx::import_gpx_files;
-use apple_health_mcp::import::run_import;
-use apple_health_mcp::import::xml::import_xml;
+use apple_health_mcp::import::watch::ReimportHandler;
+use apple_health_mcp::import::xml::{import_xml, import_xml_reader_checkpointed};
+use apple_health_mcp::import::{run_import, run_import_dirs, ImportOptions};
+use apple_health_mcp::timezone::DayTimezone;
 use std::collections::HashMap;
 
 #[test]
@@ -32,7 +37,7 @@ fn full_import_pipeline() {
     ensure_schema(&conn).unwrap();
 
     // Phase 1: XML
-    let stats = import_xml(&conn, &export_dir.join("export.xml"), "test_import").unwrap();
+    let mut stats = import_xml(&conn, &export_dir.join("export.xml"), "test_import").unwrap();
     assert_eq!(stats.records, 2);
     assert_eq!(stats.workouts, 1);
     assert_eq!(stats.activity_summaries, 1);
@@ -42,7 +47,7 @@ fn full_import_pipeline() {
     assert_eq!(stats.workout_statistics, 1);
 
     // Phase 2: ECG
-    let ecg_count = import_ecg_files(&conn, &ecg_dir, "test_import").unwrap();
+    let ecg_count = import_ecg_files(&conn, &ecg_dir, "test_import", &mut stats).unwrap();
     assert_eq!(ecg_count, 1);
 
     // Phase 3: GPX (need route map)
@@ -58,8 +63,18 @@ fn full_import_pipeline() {
         "/workout-routes/route_2024-01-01.gpx".to_string(),
         workout_hash,
     );
-    let route_count = import_gpx_files(&conn, &gpx_dir, "test_import", &route_map).unwrap();
+    let route_count = import_gpx_files(
+        &conn,
+        &gpx_dir,
+        "test_import",
+        &route_map,
+        false,
+        &mut stats,
+    )
+    .unwrap();
     assert_eq!(route_count, 2);
+    assert_eq!(stats.ecg_files_failed, 0);
+    assert_eq!(stats.gpx_files_failed, 0);
 
     // Phase 4: Deduplicate
     deduplicate_tables(&conn).unwrap();
@@ -152,8 +167,151 @@ fn import_idempotent_with_dedup() {
     assert_eq!(after, 2);
 }
 
-/// Test the top-level `run_import()` which exercises the full pipeline including
-/// `build_workout_route_map()` internally.
+#[test]
+fn purge_import_removes_only_that_import() {
+    let conn = common::setup_test_db();
+    let dir = tempfile::tempdir().unwrap();
+
+    // imp2 repeats imp1's heart-rate record and adds one of its own
+    let second_xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<HealthData locale="en_US">
+ <Record type="HKQuantityTypeIdentifierHeartRate" sourceName="Apple Watch" unit="count/min" value="72" startDate="2024-01-01 08:00:00 +0000" endDate="2024-01-01 08:01:00 +0000">
+  <MetadataEntry key="HKMetadataKeyHeartRateMotionContext" value="1"/>
+ </Record>
+ <Record type="HKQuantityTypeIdentifierBodyMass" sourceName="Scale" unit="kg" value="70" startDate="2024-02-01 07:00:00 +0000" endDate="2024-02-01 07:00:00 +0000"/>
+</HealthData>"#;
+    for (import_id, xml, imported_at) in [
+        ("imp1", common::MINIMAL_XML, "2024-01-02 00:00:00"),
+        ("imp2", second_xml, "2024-02-02 00:00:00"),
+    ] {
+        let xml_path = dir.path().join(format!("{}.xml", import_id));
+        std::fs::write(&xml_path, xml).unwrap();
+        import_xml(&conn, &xml_path, import_id).unwrap();
+        deduplicate_tables(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO imports VALUES (?, ?, ?, 0, 0, 1.0, NULL)",
+            duckdb::params![import_id, dir.path().to_string_lossy(), imported_at],
+        )
+        .unwrap();
+    }
+    rebuild_daily_stats(&conn).unwrap();
+
+    let removed = purge_import(&conn, "imp1").unwrap();
+    assert!(removed > 0);
+
+    let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+    let types: Vec<(String, String, String, String)> = conn
+        .prepare(
+            "SELECT record_type, import_id, first_import_id, last_import_id
+             FROM records ORDER BY record_type",
+        )
+        .unwrap()
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    let imp2 = || "imp2".to_string();
+    assert_eq!(
+        types,
+        vec![
+            (
+                "HKQuantityTypeIdentifierBodyMass".to_string(),
+                imp2(),
+                imp2(),
+                imp2()
+            ),
+            (
+                "HKQuantityTypeIdentifierHeartRate".to_string(),
+                imp2(),
+                imp2(),
+                imp2()
+            ),
+        ]
+    );
+    // The shared record keeps its metadata; imp1-only rows and their children are gone
+    assert_eq!(count("SELECT COUNT(*) FROM record_metadata"), 1);
+    assert_eq!(count("SELECT COUNT(*) FROM workouts"), 0);
+    assert_eq!(count("SELECT COUNT(*) FROM workout_events"), 0);
+    assert_eq!(count("SELECT COUNT(*) FROM workout_statistics"), 0);
+    assert_eq!(count("SELECT COUNT(*) FROM activity_summaries"), 0);
+    assert_eq!(
+        count("SELECT COUNT(*) FROM imports WHERE import_id = 'imp1'"),
+        0
+    );
+    assert_eq!(
+        count("SELECT COUNT(*) FROM daily_record_stats WHERE record_type LIKE '%StepCount'"),
+        0
+    );
+
+    // Nothing left to purge
+    assert!(purge_import(&conn, "imp1").is_err());
+}
+
+#[test]
+fn purge_newer_import_keeps_shared_activity_summary() {
+    let conn = common::setup_test_db();
+    let dir = tempfile::tempdir().unwrap();
+
+    // Both exports carry 2024-01-01; the later one has the day's final ring values
+    let summary = |energy: &str| {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<HealthData locale="en_US">
+ <ActivitySummary dateComponents="2024-01-01" activeEnergyBurned="{energy}" activeEnergyBurnedGoal="600" appleExerciseTime="30" appleExerciseTimeGoal="30" appleStandHours="10" appleStandHoursGoal="12"/>
+</HealthData>"#
+        )
+    };
+    for (import_id, energy, imported_at) in [
+        ("imp1", "400", "2024-01-01 20:00:00"),
+        ("imp2", "550", "2024-01-02 08:00:00"),
+    ] {
+        let xml_path = dir.path().join(format!("{}.xml", import_id));
+        std::fs::write(&xml_path, summary(energy)).unwrap();
+        import_xml(&conn, &xml_path, import_id).unwrap();
+        deduplicate_tables(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO imports VALUES (?, ?, ?, 0, 0, 1.0, NULL)",
+            duckdb::params![import_id, dir.path().to_string_lossy(), imported_at],
+        )
+        .unwrap();
+    }
+
+    let summaries = || -> Vec<(String, f64, String, String, String)> {
+        conn.prepare(
+            "SELECT date_components, active_energy_burned, import_id, first_import_id, last_import_id
+             FROM activity_summaries",
+        )
+        .unwrap()
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+        })
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect()
+    };
+    let row = |import_id: &str, first: &str, last: &str| {
+        (
+            "2024-01-01".to_string(),
+            550.0,
+            import_id.to_string(),
+            first.to_string(),
+            last.to_string(),
+        )
+    };
+    assert_eq!(summaries(), vec![row("imp2", "imp1", "imp2")]);
+
+    // The day survives purging the import whose copy won dedup
+    purge_import(&conn, "imp2").unwrap();
+    assert_eq!(summaries(), vec![row("imp1", "imp1", "imp1")]);
+
+    purge_import(&conn, "imp1").unwrap();
+    assert!(summaries().is_empty());
+}
+
+/// Test the top-level `run_import()` which exercises the full pipeline, including
+/// linking GPX files to workouts via the route map collected during the XML pass.
 #[test]
 fn run_import_end_to_end() {
     let dir = tempfile::tempdir().unwrap();
@@ -175,10 +333,10 @@ fn run_import_end_to_end() {
 
     let db_path = dir.path().join("import_test.duckdb");
 
-    // run_import covers: open_db, ensure_schema, import_xml, build_workout_route_map,
+    // run_import covers: open_db, ensure_schema, import_xml (incl. route map),
     // import_ecg_files, import_gpx_files, deduplicate_tables, rebuild_daily_stats,
     // and the imports table INSERT.
-    run_import(&export_dir, &db_path).unwrap();
+    run_import(&export_dir, &db_path, &ImportOptions::default()).unwrap();
 
     // Verify DB was created and populated
     let conn = open_db(&db_path).unwrap();
@@ -193,11 +351,32 @@ fn run_import_end_to_end() {
         .unwrap();
     assert_eq!(workout_count, 1);
 
+    // Units normalized after dedup (5000 m / 300 kcal in the fixture)
+    let (distance_m, energy_kcal): (f64, f64) = conn
+        .query_row(
+            "SELECT total_distance_m, total_energy_kcal FROM workouts",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!(distance_m, 5000.0);
+    assert_eq!(energy_kcal, 300.0);
+
     let route_count: i64 = conn
         .query_row("SELECT COUNT(*) FROM route_points", [], |row| row.get(0))
         .unwrap();
     assert_eq!(route_count, 2);
 
+    // Route points must be linked to the imported workout
+    let linked_count: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM route_points JOIN workouts USING (workout_hash)",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(linked_count, 2);
+
     let ecg_count: i64 = conn
         .query_row("SELECT COUNT(*) FROM ecg_readings", [], |row| row.get(0))
         .unwrap();
@@ -218,6 +397,246 @@ fn run_import_end_to_end() {
     assert!(daily_count > 0);
 }
 
+/// Re-importing rewrites every table during dedup; compaction afterwards must keep the data
+#[test]
+fn run_import_compacts_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let export_dir = dir.path().join("export");
+    std::fs::create_dir_all(&export_dir).unwrap();
+    std::fs::write(export_dir.join("export.xml"), common::MINIMAL_XML).unwrap();
+
+    let db_path = dir.path().join("compact.duckdb");
+    run_import(&export_dir, &db_path, &ImportOptions::default()).unwrap();
+    run_import(&export_dir, &db_path, &ImportOptions::default()).unwrap();
+
+    let conn = open_db(&db_path).unwrap();
+    compact_db(&conn).unwrap();
+
+    let import_count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM imports", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(import_count, 2);
+    let record_count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM records", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(record_count, 2);
+}
+
+/// Two export directories in one run: one `imports` row each, records deduplicated across them
+#[test]
+fn run_import_merges_export_dirs() {
+    let dir = tempfile::tempdir().unwrap();
+    let export_dirs: Vec<_> = ["alice", "bob"]
+        .iter()
+        .map(|name| {
+            let export_dir = dir.path().join(name);
+            std::fs::create_dir_all(&export_dir).unwrap();
+            std::fs::write(export_dir.join("export.xml"), common::MINIMAL_XML).unwrap();
+            export_dir
+        })
+        .collect();
+
+    let db_path = dir.path().join("merged.duckdb");
+    let stats = run_import_dirs(&export_dirs, &db_path, &ImportOptions::default()).unwrap();
+    assert_eq!(stats.records, 4);
+
+    let conn = open_db(&db_path).unwrap();
+    let mut stmt = conn
+        .prepare("SELECT import_id, export_dir, record_count FROM imports ORDER BY import_id")
+        .unwrap();
+    let imports: Vec<(String, String, i64)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(imports.len(), 2);
+    assert!(imports[0].0.ends_with("_1"));
+    assert!(imports[1].0.ends_with("_2"));
+    assert!(imports[0].1.ends_with("alice"));
+    assert!(imports[1].1.ends_with("bob"));
+    assert_eq!(imports[0].2, 2);
+
+    let record_count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM records", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(record_count, 2);
+}
+
+/// The watch handler reimports once per settled change to export.xml, and the database picks
+/// up records added to the export in between.
+#[test]
+fn watch_reimports_changed_export() {
+    use notify::event::{AccessKind, CreateKind, DataChange, EventKind, ModifyKind};
+
+    let dir = tempfile::tempdir().unwrap();
+    let export_dir = dir.path().join("export");
+    std::fs::create_dir_all(&export_dir).unwrap();
+    let db_path = dir.path().join("watched.duckdb");
+    let xml_path = export_dir.join("export.xml");
+    let event = |kind| notify::Event::new(kind).add_path(xml_path.clone());
+    let record_count = || -> i64 {
+        open_db(&db_path)
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM records", [], |row| row.get(0))
+            .unwrap()
+    };
+
+    let mut handler = ReimportHandler::new(&export_dir, &db_path, &ImportOptions::default());
+    assert!(handler.on_quiet().unwrap().is_none());
+
+    std::fs::write(&xml_path, common::MINIMAL_XML).unwrap();
+    assert!(handler.on_event(&event(EventKind::Create(CreateKind::File))));
+    assert!(handler.on_event(&event(EventKind::Modify(ModifyKind::Data(
+        DataChange::Content
+    )))));
+    let stats = handler.on_quiet().unwrap().expect("export.xml changed");
+    assert_eq!(stats.records, 2);
+    assert_eq!(record_count(), 2);
+
+    // The import reading export.xml, and other files changing, don't trigger another one
+    assert!(!handler.on_event(&event(EventKind::Access(AccessKind::Read))));
+    assert!(!handler.on_event(
+        &notify::Event::new(EventKind::Create(CreateKind::File))
+            .add_path(export_dir.join("notes.txt"))
+    ));
+    assert!(handler.on_quiet().unwrap().is_none());
+
+    let newer = common::MINIMAL_XML.replace(
+        "</HealthData>",
+        r#" <Record type="HKQuantityTypeIdentifierStepCount" sourceName="iPhone" unit="count" value="800" startDate="2024-01-02 09:00:00 +0000" endDate="2024-01-02 09:30:00 +0000"/>
+</HealthData>"#,
+    );
+    std::fs::write(&xml_path, newer).unwrap();
+    assert!(handler.on_event(&event(EventKind::Modify(ModifyKind::Data(
+        DataChange::Content
+    )))));
+    handler.on_quiet().unwrap().expect("export.xml changed");
+    assert_eq!(record_count(), 3);
+}
+
+/// Hands out `data` until `limit` bytes have been read, then panics, like a process killed
+/// partway through export.xml.
+struct DyingReader {
+    data: Vec<u8>,
+    pos: usize,
+    limit: usize,
+}
+
+impl std::io::Read for DyingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.limit {
+            panic!("import killed");
+        }
+        let end = (self.pos + buf.len()).min(self.limit).min(self.data.len());
+        let n = end - self.pos;
+        buf[..n].copy_from_slice(&self.data[self.pos..end]);
+        self.pos = end;
+        Ok(n)
+    }
+}
+
+/// A checkpointed import killed after its first batches resumes from the last resume point:
+/// no record is appended twice, and finishing it with `run_import` keeps the import id.
+#[test]
+fn checkpointed_import_resumes_after_interruption() {
+    let extra: String = (2..=9)
+        .map(|day| {
+            format!(
+                r#" <Record type="HKQuantityTypeIdentifierStepCount" sourceName="iPhone" unit="count" value="{day}00" startDate="2024-01-0{day} 09:00:00 +0000" endDate="2024-01-0{day} 09:30:00 +0000"/>
+"#
+            )
+        })
+        .collect();
+    let xml = common::MINIMAL_XML.replace("</HealthData>", &format!("{extra}</HealthData>"));
+
+    let dir = tempfile::tempdir().unwrap();
+    let export_dir = dir.path().join("export");
+    std::fs::create_dir_all(&export_dir).unwrap();
+    std::fs::write(export_dir.join("export.xml"), &xml).unwrap();
+    let db_path = dir.path().join("checkpoint.duckdb");
+
+    let conn = open_db(&db_path).unwrap();
+    ensure_schema(&conn).unwrap();
+    conn.execute(
+        "INSERT INTO import_progress (import_id, export_dir) VALUES ('import_interrupted', ?)",
+        [export_dir.to_string_lossy().to_string()],
+    )
+    .unwrap();
+    let record_count = |conn: &duckdb::Connection| -> (i64, i64) {
+        conn.query_row(
+            "SELECT COUNT(*), COUNT(DISTINCT record_hash) FROM records",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap()
+    };
+
+    // Killed about two thirds of the way in, with every element committed on its own
+    let dying = DyingReader {
+        data: xml.clone().into_bytes(),
+        pos: 0,
+        limit: xml.len() * 2 / 3,
+    };
+    let killed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        import_xml_reader_checkpointed(
+            &conn,
+            std::io::BufReader::with_capacity(64, dying),
+            "import_interrupted",
+            1,
+        )
+    }));
+    assert!(killed.is_err());
+    let committed: i64 = conn
+        .query_row(
+            "SELECT elements_committed FROM import_progress WHERE import_id = 'import_interrupted'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let (partial, _) = record_count(&conn);
+    assert!(committed > 0);
+    assert!(partial > 0 && partial < 10, "partial import: {partial}");
+
+    // Resuming parses everything again but only appends what wasn't committed
+    let stats = import_xml_reader_checkpointed(
+        &conn,
+        std::io::BufReader::new(xml.as_bytes()),
+        "import_interrupted",
+        1,
+    )
+    .unwrap();
+    assert_eq!(stats.records, 10);
+    assert_eq!(stats.workouts, 1);
+    assert_eq!(record_count(&conn), (10, 10));
+    drop(conn);
+
+    // run_import picks up the unfinished import of this directory and completes it
+    let options = ImportOptions {
+        checkpoint: true,
+        ..Default::default()
+    };
+    let stats = run_import(&export_dir, &db_path, &options).unwrap();
+    assert_eq!(stats.records, 10);
+
+    let conn = open_db(&db_path).unwrap();
+    assert_eq!(record_count(&conn), (10, 10));
+    let (import_id, recorded): (String, i64) = conn
+        .query_row("SELECT import_id, record_count FROM imports", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .unwrap();
+    assert_eq!(import_id, "import_interrupted");
+    assert_eq!(recorded, 10);
+    let workouts: i64 = conn
+        .query_row("SELECT COUNT(*) FROM workouts", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(workouts, 1);
+    let pending: i64 = conn
+        .query_row("SELECT COUNT(*) FROM import_progress", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(pending, 0);
+}
+
 /// Test run_import with no ECG or GPX directories (graceful handling)
 #[test]
 fn run_import_xml_only() {
@@ -228,7 +647,7 @@ fn run_import_xml_only() {
     // No electrocardiograms/ or workout-routes/ directories
 
     let db_path = dir.path().join("xml_only.duckdb");
-    run_import(&export_dir, &db_path).unwrap();
+    run_import(&export_dir, &db_path, &ImportOptions::default()).unwrap();
 
     let conn = open_db(&db_path).unwrap();
     let count: i64 = conn
@@ -236,3 +655,301 @@ fn run_import_xml_only() {
         .unwrap();
     assert_eq!(count, 2);
 }
+
+#[test]
+fn run_import_dry_run_writes_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let export_dir = dir.path().join("export");
+    std::fs::create_dir_all(&export_dir).unwrap();
+    std::fs::write(export_dir.join("export.xml"), common::MINIMAL_XML).unwrap();
+
+    let db_path = dir.path().join("dry_run.duckdb");
+    let options = ImportOptions {
+        dry_run: true,
+        ..Default::default()
+    };
+    let stats = run_import(&export_dir, &db_path, &options).unwrap();
+
+    assert!(!db_path.exists());
+    assert_eq!(stats.workouts, 1);
+    assert_eq!(
+        stats.record_types,
+        vec![
+            ("HKQuantityTypeIdentifierHeartRate".to_string(), 1),
+            ("HKQuantityTypeIdentifierStepCount".to_string(), 1),
+        ]
+    );
+}
+
+/// A phase failing after export.xml has been loaded rolls the whole import back: no rows
+/// and no `imports` entry from it are left behind.
+#[test]
+fn run_import_rolls_back_on_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("rollback.duckdb");
+
+    let good = dir.path().join("good");
+    std::fs::create_dir_all(&good).unwrap();
+    std::fs::write(good.join("export.xml"), common::MINIMAL_XML).unwrap();
+    run_import(&good, &db_path, &ImportOptions::default()).unwrap();
+
+    // A second export with more records, whose ECG phase fails (the directory is a file)
+    let bad = dir.path().join("bad");
+    std::fs::create_dir_all(&bad).unwrap();
+    let extra = common::MINIMAL_XML.replace(
+        "</HealthData>",
+        r#" <Record type="HKQuantityTypeIdentifierBodyMass" sourceName="Scale" unit="kg" value="70" startDate="2024-02-01 07:00:00 +0000" endDate="2024-02-01 07:00:00 +0000"/>
+</HealthData>"#,
+    );
+    std::fs::write(bad.join("export.xml"), extra).unwrap();
+    std::fs::write(bad.join("electrocardiograms"), "not a directory").unwrap();
+    assert!(run_import(&bad, &db_path, &ImportOptions::default()).is_err());
+
+    let conn = open_db(&db_path).unwrap();
+    let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+    assert_eq!(count("SELECT COUNT(*) FROM imports"), 1);
+    assert_eq!(count("SELECT COUNT(*) FROM records"), 2);
+    assert_eq!(
+        count(
+            "SELECT COUNT(*) FROM records WHERE record_type = 'HKQuantityTypeIdentifierBodyMass'"
+        ),
+        0
+    );
+}
+
+/// `run_import` pointed at the `export.zip` from the Health app's share sheet reads the
+/// entries in place and ends up with the same data as the unpacked directory.
+#[test]
+fn run_import_from_zip_archive() {
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    let dir = tempfile::tempdir().unwrap();
+    let zip_path = dir.path().join("export.zip");
+    {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        for (name, content) in [
+            ("apple_health_export/export.xml", common::MINIMAL_XML),
+            (
+                "apple_health_export/electrocardiograms/ecg_2024.csv",
+                common::MINIMAL_ECG_CSV,
+            ),
+            (
+                "apple_health_export/workout-routes/route_2024-01-01.gpx",
+                common::MINIMAL_GPX,
+            ),
+        ] {
+            zip.start_file(name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    let db_path = dir.path().join("zip_test.duckdb");
+    run_import(&zip_path, &db_path, &ImportOptions::default()).unwrap();
+
+    let conn = open_db(&db_path).unwrap();
+    let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+    assert_eq!(count("SELECT COUNT(*) FROM records"), 2);
+    assert_eq!(count("SELECT COUNT(*) FROM workouts"), 1);
+    assert_eq!(count("SELECT COUNT(*) FROM activity_summaries"), 1);
+    assert_eq!(count("SELECT COUNT(*) FROM ecg_readings"), 1);
+    assert_eq!(count("SELECT COUNT(*) FROM ecg_samples"), 5);
+    assert_eq!(count("SELECT COUNT(*) FROM route_points"), 2);
+    assert_eq!(
+        count("SELECT COUNT(*) FROM route_points JOIN workouts USING (workout_hash)"),
+        2
+    );
+    assert_eq!(count("SELECT COUNT(*) FROM imports"), 1);
+}
+
+/// `--downsample HeartRate=1m` collapses dense heart rate samples to one averaged record per
+/// minute while other types keep full resolution.
+#[test]
+fn run_import_downsamples_heart_rate() {
+    let dir = tempfile::tempdir().unwrap();
+    let export_dir = dir.path().join("export");
+    std::fs::create_dir_all(&export_dir).unwrap();
+
+    // 12 heart rate samples 10 s apart (two minutes) and 12 step samples at the same times
+    let mut xml =
+        String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<HealthData locale=\"en_US\">\n");
+    for i in 0..12 {
+        let ts = format!("2024-01-01 08:{:02}:{:02} +0000", i / 6, (i % 6) * 10);
+        xml.push_str(&format!(
+            "  <Record type=\"HKQuantityTypeIdentifierHeartRate\" sourceName=\"Apple Watch\" unit=\"count/min\" \
+             startDate=\"{ts}\" endDate=\"{ts}\" value=\"{}\">\n    \
+             <MetadataEntry key=\"HKMetadataKeyHeartRateMotionContext\" value=\"0\"/>\n  </Record>\n",
+            60 + i
+        ));
+        xml.push_str(&format!(
+            "  <Record type=\"HKQuantityTypeIdentifierStepCount\" sourceName=\"iPhone\" unit=\"count\" \
+             startDate=\"{ts}\" endDate=\"{ts}\" value=\"10\"/>\n"
+        ));
+    }
+    xml.push_str("</HealthData>\n");
+    std::fs::write(export_dir.join("export.xml"), xml).unwrap();
+
+    let db_path = dir.path().join("downsample.duckdb");
+    let options = ImportOptions {
+        downsample: vec![parse_downsample_rule("HKQuantityTypeIdentifierHeartRate=1m").unwrap()],
+        ..Default::default()
+    };
+    run_import(&export_dir, &db_path, &options).unwrap();
+
+    let conn = open_db(&db_path).unwrap();
+    let heart_rates: Vec<(String, f64)> = conn
+        .prepare(
+            "SELECT CAST(start_date AS VARCHAR), value FROM records \
+             WHERE record_type = 'HKQuantityTypeIdentifierHeartRate' ORDER BY start_date",
+        )
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<std::result::Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        heart_rates,
+        vec![
+            ("2024-01-01 08:00:00".to_string(), 62.5),
+            ("2024-01-01 08:01:00".to_string(), 68.5),
+        ]
+    );
+
+    let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+    assert_eq!(
+        count(
+            "SELECT COUNT(*) FROM records WHERE record_type = 'HKQuantityTypeIdentifierStepCount'"
+        ),
+        12
+    );
+    // Metadata of the collapsed samples is dropped along with them
+    assert_eq!(count("SELECT COUNT(*) FROM record_metadata"), 0);
+}
+
+/// export.xml dates carry the device's UTC offset: they are stored as UTC, and `--timezone`
+/// puts a late evening record back on its local day.
+#[test]
+fn run_import_converts_offsets_and_buckets_local_days() {
+    let dir = tempfile::tempdir().unwrap();
+    let export_dir = dir.path().join("export");
+    std::fs::create_dir_all(&export_dir).unwrap();
+
+    let record = |start: &str, end: &str| {
+        format!(
+            "  <Record type=\"HKQuantityTypeIdentifierStepCount\" sourceName=\"iPhone\" unit=\"count\" \
+             startDate=\"{start}\" endDate=\"{end}\" value=\"100\"/>\n"
+        )
+    };
+    let xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<HealthData locale=\"en_US\">\n{}{}</HealthData>\n",
+        // 22:30 EST and 23:30 EDT, both on the next day in UTC
+        record("2024-01-01 22:30:00 -0500", "2024-01-01 22:31:00 -0500"),
+        record("2024-07-01 23:30:00 -0400", "2024-07-01 23:31:00 -0400"),
+    );
+    std::fs::write(export_dir.join("export.xml"), xml).unwrap();
+
+    let db_path = dir.path().join("timezone.duckdb");
+    let options = ImportOptions {
+        timezone: Some(DayTimezone::parse("America/New_York").unwrap()),
+        ..Default::default()
+    };
+    run_import(&export_dir, &db_path, &options).unwrap();
+
+    let conn = open_db(&db_path).unwrap();
+    let strings = |sql: &str| -> Vec<String> {
+        conn.prepare(sql)
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap()
+    };
+    assert_eq!(
+        strings("SELECT CAST(start_date AS VARCHAR) FROM records ORDER BY start_date"),
+        ["2024-01-02 03:30:00", "2024-07-02 03:30:00"]
+    );
+    assert_eq!(
+        strings("SELECT CAST(date AS VARCHAR) FROM daily_record_stats ORDER BY date"),
+        ["2024-01-01", "2024-07-01"]
+    );
+}
+
+/// Tiny `--batch-size` values flush after nearly every row, serially and in parallel; no row
+/// may be lost or doubled at a flush boundary.
+#[test]
+fn run_import_small_batch_size_keeps_all_rows() {
+    let dir = tempfile::tempdir().unwrap();
+    let export_dir = dir.path().join("export");
+    std::fs::create_dir_all(&export_dir).unwrap();
+
+    let mut xml =
+        String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<HealthData locale=\"en_US\">\n");
+    for i in 0..20 {
+        let ts = format!("2024-01-01 08:{:02}:00 +0000", i);
+        xml.push_str(&format!(
+            "  <Record type=\"HKQuantityTypeIdentifierHeartRate\" sourceName=\"Apple Watch\" unit=\"count/min\" \
+             startDate=\"{ts}\" endDate=\"{ts}\" value=\"{}\">\n    \
+             <MetadataEntry key=\"HKMetadataKeyHeartRateMotionContext\" value=\"1\"/>\n  </Record>\n",
+            60 + i
+        ));
+    }
+    xml.push_str("</HealthData>\n");
+    std::fs::write(export_dir.join("export.xml"), xml).unwrap();
+
+    for (batch_size, parse_threads) in [(1, 1), (7, 1), (1, 3)] {
+        let db_path = dir
+            .path()
+            .join(format!("batch_{}_{}.duckdb", batch_size, parse_threads));
+        let options = ImportOptions {
+            batch_size: std::num::NonZeroUsize::new(batch_size),
+            parse_threads,
+            ..Default::default()
+        };
+        run_import(&export_dir, &db_path, &options).unwrap();
+
+        let conn = open_db(&db_path).unwrap();
+        let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(
+            count("SELECT COUNT(*) FROM records"),
+            20,
+            "batch {}",
+            batch_size
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM record_metadata"),
+            20,
+            "batch {}",
+            batch_size
+        );
+    }
+}
+
+#[test]
+fn run_import_writes_summary_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let export_dir = dir.path().join("export");
+    std::fs::create_dir_all(&export_dir).unwrap();
+    std::fs::write(export_dir.join("export.xml"), common::MINIMAL_XML).unwrap();
+
+    let db_path = dir.path().join("summary.duckdb");
+    let summary_path = dir.path().join("summary.json");
+    let options = ImportOptions {
+        summary_json: Some(summary_path.clone()),
+        ..Default::default()
+    };
+    run_import(&export_dir, &db_path, &options).unwrap();
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&summary_path).unwrap()).unwrap();
+    assert_eq!(summary["records"], 2);
+    assert_eq!(summary["workouts"], 1);
+    assert!(summary["import_id"]
+        .as_str()
+        .unwrap()
+        .starts_with("import_"));
+    assert!(summary["duration_secs"].as_f64().unwrap() >= 0.0);
+    assert_eq!(summary["table_rows"]["records"], 2);
+    assert_eq!(summary["table_rows"]["imports"], 1);
+    assert!(summary.get("workout_routes").is_none());
+}
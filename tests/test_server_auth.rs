@@ -0,0 +1,76 @@
+use apple_health_mcp::db::{ensure_schema, open_db, rebuild_daily_stats};
+use apple_health_mcp::server::{build_http_router, ServerConfig};
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use tower::ServiceExt;
+
+const INITIALIZE_BODY: &str = r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{},"clientInfo":{"name":"test","version":"1.0"}}}"#;
+
+fn create_db(dir: &tempfile::TempDir) -> std::path::PathBuf {
+    let db_path = dir.path().join("test.duckdb");
+    let conn = open_db(&db_path).unwrap();
+    ensure_schema(&conn).unwrap();
+    rebuild_daily_stats(&conn).unwrap();
+    db_path
+}
+
+fn token_config() -> ServerConfig {
+    ServerConfig {
+        auth_token: Some("s3cret".to_string()),
+        ..Default::default()
+    }
+}
+
+fn initialize_request(auth: Option<&str>) -> Request<Body> {
+    let mut builder = Request::builder()
+        .method("POST")
+        .uri("/mcp")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCEPT, "application/json, text/event-stream");
+    if let Some(value) = auth {
+        builder = builder.header(header::AUTHORIZATION, value);
+    }
+    builder.body(Body::from(INITIALIZE_BODY)).unwrap()
+}
+
+#[tokio::test]
+async fn auth_rejects_missing_header() {
+    let dir = tempfile::tempdir().unwrap();
+    let router = build_http_router(&create_db(&dir), &token_config());
+
+    let response = router.oneshot(initialize_request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn auth_rejects_wrong_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let router = build_http_router(&create_db(&dir), &token_config());
+
+    let response = router
+        .oneshot(initialize_request(Some("Bearer wrong")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn auth_accepts_correct_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let router = build_http_router(&create_db(&dir), &token_config());
+
+    let response = router
+        .oneshot(initialize_request(Some("Bearer s3cret")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn no_token_configured_allows_requests() {
+    let dir = tempfile::tempdir().unwrap();
+    let router = build_http_router(&create_db(&dir), &ServerConfig::default());
+
+    let response = router.oneshot(initialize_request(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
@@ -0,0 +1,390 @@
+use apple_health_mcp::db::{ensure_schema, open_db, rebuild_daily_stats};
+use apple_health_mcp::server::{build_http_router, build_multi_http_router, ServerConfig};
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use serde_json::Value;
+use tower::ServiceExt;
+
+fn health_request() -> Request<Body> {
+    Request::builder()
+        .method("GET")
+        .uri("/health")
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn body_json(response: axum::response::Response) -> Value {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+#[tokio::test]
+async fn health_ok() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.duckdb");
+    {
+        let conn = open_db(&db_path).unwrap();
+        ensure_schema(&conn).unwrap();
+        rebuild_daily_stats(&conn).unwrap();
+    }
+
+    let router = build_http_router(&db_path, &ServerConfig::default());
+    let response = router.oneshot(health_request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = body_json(response).await;
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["db_path"], db_path.display().to_string());
+}
+
+#[tokio::test]
+async fn health_unavailable_without_db() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("missing.duckdb");
+
+    let router = build_http_router(&db_path, &ServerConfig::default());
+    let response = router.oneshot(health_request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let body = body_json(response).await;
+    assert_eq!(body["status"], "error");
+}
+
+#[tokio::test]
+async fn health_does_not_require_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.duckdb");
+    {
+        let conn = open_db(&db_path).unwrap();
+        ensure_schema(&conn).unwrap();
+        rebuild_daily_stats(&conn).unwrap();
+    }
+
+    let config = ServerConfig {
+        auth_token: Some("s3cret".to_string()),
+        ..Default::default()
+    };
+    let router = build_http_router(&db_path, &config);
+    let response = router.oneshot(health_request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn rate_limit_rejects_excess_requests() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.duckdb");
+    {
+        let conn = open_db(&db_path).unwrap();
+        ensure_schema(&conn).unwrap();
+        rebuild_daily_stats(&conn).unwrap();
+    }
+
+    let config = ServerConfig {
+        rate_limit: Some(3),
+        ..Default::default()
+    };
+    let router = build_http_router(&db_path, &config);
+    let mcp_request = || {
+        Request::builder()
+            .method("POST")
+            .uri("/mcp")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, "application/json, text/event-stream")
+            .body(Body::from(
+                r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{},"clientInfo":{"name":"test","version":"1.0"}}}"#,
+            ))
+            .unwrap()
+    };
+
+    for _ in 0..3 {
+        let response = router.clone().oneshot(mcp_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let response = router.clone().oneshot(mcp_request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+    // The health probe is not limited
+    let response = router.oneshot(health_request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn cors_preflight_allows_configured_origin() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.duckdb");
+    {
+        let conn = open_db(&db_path).unwrap();
+        ensure_schema(&conn).unwrap();
+        rebuild_daily_stats(&conn).unwrap();
+    }
+    let preflight = |origin: &str| {
+        Request::builder()
+            .method("OPTIONS")
+            .uri("/mcp")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "authorization,content-type",
+            )
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    // Preflights carry no token, so they must get through with auth enabled
+    let config = ServerConfig {
+        auth_token: Some("s3cret".to_string()),
+        cors_origins: vec!["https://dashboard.example.com".to_string()],
+        ..Default::default()
+    };
+    let router = build_http_router(&db_path, &config);
+    let response = router
+        .clone()
+        .oneshot(preflight("https://dashboard.example.com"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://dashboard.example.com"
+    );
+
+    let response = router
+        .oneshot(preflight("https://evil.example.com"))
+        .await
+        .unwrap();
+    assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+    // Without --cors-origin there is no CORS layer at all
+    let router = build_http_router(&db_path, &ServerConfig::default());
+    let response = router
+        .oneshot(preflight("https://dashboard.example.com"))
+        .await
+        .unwrap();
+    assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+}
+
+#[tokio::test]
+async fn metrics_count_tool_calls() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.duckdb");
+    {
+        let conn = open_db(&db_path).unwrap();
+        ensure_schema(&conn).unwrap();
+        rebuild_daily_stats(&conn).unwrap();
+    }
+
+    let config = ServerConfig {
+        metrics: true,
+        ..Default::default()
+    };
+    let router = build_http_router(&db_path, &config);
+    let mcp_request = |session: Option<&str>, body: &'static str| {
+        let mut builder = Request::builder()
+            .method("POST")
+            .uri("/mcp")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, "application/json, text/event-stream");
+        if let Some(session) = session {
+            builder = builder.header("mcp-session-id", session);
+        }
+        builder.body(Body::from(body)).unwrap()
+    };
+
+    let response = router
+        .clone()
+        .oneshot(mcp_request(
+            None,
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{},"clientInfo":{"name":"test","version":"1.0"}}}"#,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let session = response.headers()["mcp-session-id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let response = router
+        .clone()
+        .oneshot(mcp_request(
+            Some(&session),
+            r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let response = router
+        .clone()
+        .oneshot(mcp_request(
+            Some(&session),
+            r#"{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"list_record_types","arguments":{}}}"#,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // The SSE stream ends once the tool's response has been sent
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert!(String::from_utf8_lossy(&bytes).contains(r#""id":2"#));
+
+    let response = router
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri("/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(text.contains("mcp_tool_calls_total"));
+    assert!(text.contains(r#"mcp_tool_calls_by_tool_total{tool="list_record_types"}"#));
+    assert!(text.contains("mcp_tool_duration_seconds_bucket"));
+}
+
+#[tokio::test]
+async fn metrics_route_needs_flag() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.duckdb");
+
+    let router = build_http_router(&db_path, &ServerConfig::default());
+    let response = router
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri("/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn multiple_databases_routed_by_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut dbs = Vec::new();
+    for (name, record_type) in [
+        ("alice", "HKQuantityTypeIdentifierStepCount"),
+        ("bob", "HKQuantityTypeIdentifierHeartRate"),
+    ] {
+        let db_path = dir.path().join(format!("{}.duckdb", name));
+        let conn = open_db(&db_path).unwrap();
+        ensure_schema(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO records (record_hash, record_type, value, unit, source_name, creation_date, \
+             start_date, end_date, import_id) VALUES ('h1', ?, 1.0, 'count', 'iPhone', \
+             '2024-01-01 08:00:00', '2024-01-01 08:00:00', '2024-01-01 08:01:00', 'imp1')",
+            [record_type],
+        )
+        .unwrap();
+        rebuild_daily_stats(&conn).unwrap();
+        dbs.push((name.to_string(), db_path));
+    }
+
+    let router = build_multi_http_router(&dbs, &ServerConfig::default());
+    let mcp_request = |uri: &str, session: Option<&str>, body: &'static str| {
+        let mut builder = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, "application/json, text/event-stream");
+        if let Some(session) = session {
+            builder = builder.header("mcp-session-id", session);
+        }
+        builder.body(Body::from(body)).unwrap()
+    };
+
+    for (uri, expected, other) in [
+        (
+            "/mcp/alice",
+            "HKQuantityTypeIdentifierStepCount",
+            "HKQuantityTypeIdentifierHeartRate",
+        ),
+        (
+            "/mcp/bob",
+            "HKQuantityTypeIdentifierHeartRate",
+            "HKQuantityTypeIdentifierStepCount",
+        ),
+    ] {
+        let response = router
+            .clone()
+            .oneshot(mcp_request(
+                uri,
+                None,
+                r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{},"clientInfo":{"name":"test","version":"1.0"}}}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let session = response.headers()["mcp-session-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let response = router
+            .clone()
+            .oneshot(mcp_request(
+                uri,
+                Some(&session),
+                r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let response = router
+            .clone()
+            .oneshot(mcp_request(
+                uri,
+                Some(&session),
+                r#"{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"list_record_types","arguments":{}}}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(text.contains(expected), "{uri}: {text}");
+        assert!(!text.contains(other), "{uri}: {text}");
+    }
+
+    // The unnamed endpoint isn't mounted
+    let response = router
+        .clone()
+        .oneshot(mcp_request(
+            "/mcp",
+            None,
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{},"clientInfo":{"name":"test","version":"1.0"}}}"#,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = router.oneshot(health_request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_json(response).await;
+    assert_eq!(body["status"], "ok");
+    assert_eq!(
+        body["databases"]["bob"]["db_path"],
+        dbs[1].1.display().to_string()
+    );
+}
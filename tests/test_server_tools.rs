@@ -32,12 +32,28 @@ fn server_new_with_file_db() {
     assert_eq!(cnt, 2);
 }
 
+#[test]
+fn server_new_rejects_outdated_schema() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("old.duckdb");
+    {
+        let conn = open_db(&db_path).unwrap();
+        conn.execute_batch("CREATE TABLE records (record_hash VARCHAR);")
+            .unwrap();
+    }
+
+    let err = HealthServer::new(&db_path).unwrap_err().to_string();
+    assert!(err.contains("missing tables"));
+    assert!(err.contains("workouts"));
+    assert!(err.contains("re-run `apple-health-mcp import`"));
+}
+
 #[test]
 fn server_in_memory_query() {
     let conn = open_db_in_memory().unwrap();
     ensure_schema(&conn).unwrap();
     conn.execute_batch(
-        "INSERT INTO records VALUES ('rh1', 'HKQuantityTypeIdentifierHeartRate', 72.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-01 08:00:00', '2024-01-01 08:01:00', 'imp1');",
+        "INSERT INTO records VALUES ('rh1', 'HKQuantityTypeIdentifierHeartRate', 72.0, 'count/min', 'Watch', NULL, NULL, NULL, '2024-01-01 08:00:00', '2024-01-01 08:01:00', 'imp1', NULL, NULL, NULL, NULL);",
     ).unwrap();
     rebuild_daily_stats(&conn).unwrap();
 
@@ -98,12 +114,12 @@ fn server_queries_all_tables() {
     ensure_schema(&conn).unwrap();
     conn.execute_batch(
         "
-        INSERT INTO records VALUES ('rh1', 'HeartRate', 72.0, 'bpm', 'Watch', NULL, NULL, NULL, '2024-01-01 08:00:00', '2024-01-01 08:01:00', 'imp1');
-        INSERT INTO workouts VALUES ('wh1', 'Running', 1800.0, 'sec', 5000.0, 'm', 300.0, 'kcal', 'Watch', NULL, NULL, NULL, '2024-01-01 10:00:00', '2024-01-01 10:30:00', 'imp1');
-        INSERT INTO activity_summaries VALUES ('2024-01-01', 500.0, 600.0, 45.0, 30.0, 30.0, 30.0, 10.0, 12.0, 'imp1');
-        INSERT INTO ecg_readings VALUES ('ecg1', '2024-01-01 12:00:00', 'Normal', 'Watch', 512.0, NULL, '2.0', 'imp1');
+        INSERT INTO records VALUES ('rh1', 'HeartRate', 72.0, 'bpm', 'Watch', NULL, NULL, NULL, '2024-01-01 08:00:00', '2024-01-01 08:01:00', 'imp1', NULL, NULL, NULL, NULL);
+        INSERT INTO workouts VALUES ('wh1', 'Running', 1800.0, 'sec', 5000.0, 'm', 300.0, 'kcal', 'Watch', NULL, NULL, NULL, '2024-01-01 10:00:00', '2024-01-01 10:30:00', 'imp1', NULL, NULL, NULL, NULL, NULL);
+        INSERT INTO activity_summaries VALUES ('2024-01-01', 500.0, 600.0, 45.0, 30.0, 30.0, 30.0, 10.0, 12.0, 'imp1', NULL, NULL);
+        INSERT INTO ecg_readings VALUES ('ecg1', '2024-01-01 12:00:00', 'Normal', 'Watch', 512.0, NULL, '2.0', 'imp1', NULL, NULL);
         INSERT INTO route_points VALUES ('rp1', 'wh1', 37.7749, -122.4194, 10.5, '2024-01-01 10:00:00', 3.5, 180.0, 5.0, 3.0, 'imp1');
-        INSERT INTO imports VALUES ('imp1', '/tmp', '2024-01-01 00:00:00', 1, 1, 1.0);
+        INSERT INTO imports VALUES ('imp1', '/tmp', '2024-01-01 00:00:00', 1, 1, 1.0, NULL);
         ",
     )
     .unwrap();